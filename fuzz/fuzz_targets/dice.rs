@@ -0,0 +1,31 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rand::{rngs::StdRng, SeedableRng};
+
+// The bot is a binary crate, so pull the dice module in directly
+#[path = "../../src/dice.rs"]
+#[allow(dead_code)]
+mod dice;
+#[path = "../../src/odds.rs"]
+#[allow(dead_code)]
+mod odds;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(source) = std::str::from_utf8(data) else {
+        return;
+    };
+    let mut rng = StdRng::seed_from_u64(data.len() as u64);
+    match dice::Expression::parse(source) {
+        Ok(expression) => {
+            if let Ok(roll) = expression.roll(&mut rng) {
+                for term in roll.terms.iter() {
+                    if let dice::TermResult::Dice { dice, successes: false, total, .. } = term {
+                        assert_eq!(*total, dice.iter().filter(|die| die.kept).map(|die| die.value).sum::<i64>());
+                    }
+                }
+            }
+        },
+        Err(e) => assert!(e.position <= source.chars().count(), "error position {} is past the end of {source:?}", e.position),
+    }
+});
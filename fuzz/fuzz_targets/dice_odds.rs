@@ -0,0 +1,39 @@
+#![no_main]
+
+use std::time::{Duration, Instant};
+use libfuzzer_sys::fuzz_target;
+
+// The bot is a binary crate, so pull the dice module in directly
+#[path = "../../src/dice.rs"]
+#[allow(dead_code)]
+mod dice;
+#[path = "../../src/odds.rs"]
+#[allow(dead_code)]
+mod odds;
+
+/// Longest `!odds` is allowed to take on any expression before it gives up
+const TIME_BUDGET: Duration = Duration::from_secs(5);
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(source) = std::str::from_utf8(data) else {
+        return;
+    };
+    let Ok(expression) = dice::Expression::parse(source) else {
+        return;
+    };
+    let start = Instant::now();
+    let distribution = expression.distribution();
+    let elapsed = start.elapsed();
+    assert!(elapsed < TIME_BUDGET, "Working out the odds of {source:?} took {elapsed:?}");
+
+    let Ok(distribution) = distribution else {
+        return;
+    };
+    let total: f64 = distribution.outcomes().values().sum();
+    assert!((total - 1.0).abs() < 1e-6, "The odds of {source:?} add up to {total}");
+    if let Ok((low, high)) = expression.bounds() {
+        for outcome in distribution.outcomes().keys() {
+            assert!((low..=high).contains(outcome), "{source:?} can give {outcome}, outside of {low} to {high}");
+        }
+    }
+});
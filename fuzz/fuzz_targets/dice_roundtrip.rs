@@ -0,0 +1,38 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rand::{rngs::StdRng, SeedableRng};
+
+// The bot is a binary crate, so pull the dice module in directly
+#[path = "../../src/dice.rs"]
+#[allow(dead_code)]
+mod dice;
+#[path = "../../src/odds.rs"]
+#[allow(dead_code)]
+mod odds;
+
+// Anything that parses should roll the same way when it's parsed again from its own breakdown
+fuzz_target!(|data: &[u8]| {
+    let Ok(source) = std::str::from_utf8(data) else {
+        return;
+    };
+    let Ok(expression) = dice::Expression::parse(source) else {
+        return;
+    };
+    let seed = data.len() as u64;
+    let Ok(roll) = expression.roll(&mut StdRng::seed_from_u64(seed)) else {
+        return;
+    };
+    let reparsed = dice::Expression::parse(&roll.expression)
+        .unwrap_or_else(|e| panic!("{:?} parsed but its breakdown {:?} didn't: {e}", source, roll.expression));
+    let rerolled = reparsed.roll(&mut StdRng::seed_from_u64(seed)).expect("The same roll can't fail the second time");
+    assert_eq!(roll.total, rerolled.total, "{source:?} rolled differently after being parsed again");
+    assert_eq!(roll.label, rerolled.label);
+
+    // Each dice term stands on its own as an expression too
+    for term in roll.terms.iter() {
+        if let dice::TermResult::Dice { expression, .. } = term {
+            dice::Expression::parse(expression).unwrap_or_else(|e| panic!("The term {expression:?} of {source:?} didn't parse: {e}"));
+        }
+    }
+});
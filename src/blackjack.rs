@@ -0,0 +1,678 @@
+use std::{fmt::Display, hash::Hash};
+use anyhow::{Context, Result};
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::cards::{format_cards, Card, Shoe};
+use crate::roulette::RouletteState;
+
+/// Most hands a single seat may split into
+const MAX_HANDS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlackjackPayout {
+    ThreeToTwo,
+    SixToFive
+}
+
+impl BlackjackPayout {
+    /// Winnings (excluding the returned stake) for a natural blackjack
+    pub fn winnings(&self, bet: u128) -> u128 {
+        match self {
+            BlackjackPayout::ThreeToTwo => bet * 3 / 2,
+            BlackjackPayout::SixToFive => bet * 6 / 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BlackjackRules {
+    pub decks: u8,
+    /// Whether the dealer hits on a soft 17 (H17) or stands (S17)
+    pub dealer_hits_soft_17: bool,
+    pub blackjack_payout: BlackjackPayout
+}
+
+impl Default for BlackjackRules {
+    fn default() -> Self {
+        BlackjackRules { decks: 6, dealer_hits_soft_17: false, blackjack_payout: BlackjackPayout::ThreeToTwo }
+    }
+}
+
+impl Display for BlackjackRules {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let dealer_rule = if self.dealer_hits_soft_17 { "H17" } else { "S17" };
+        let payout = match self.blackjack_payout {
+            BlackjackPayout::ThreeToTwo => "3:2",
+            BlackjackPayout::SixToFive => "6:5",
+        };
+        write!(f, "{} decks, {dealer_rule}, blackjack pays {payout}", self.decks)
+    }
+}
+
+impl BlackjackRules {
+    /// Parse rule words like `h17 6:5 8`, keeping the current value for anything left out
+    pub fn apply_string(&mut self, s: &str) -> Result<()> {
+        for word in s.split_whitespace() {
+            match word.to_lowercase().as_str() {
+                "h17" => self.dealer_hits_soft_17 = true,
+                "s17" => self.dealer_hits_soft_17 = false,
+                "3:2" => self.blackjack_payout = BlackjackPayout::ThreeToTwo,
+                "6:5" => self.blackjack_payout = BlackjackPayout::SixToFive,
+                other => {
+                    let decks = other.parse::<u8>().context(format!("Unrecognized blackjack rule: '{other}'"))?;
+                    if decks == 0 || decks > 8 {
+                        anyhow::bail!("The shoe must hold between 1 and 8 decks");
+                    }
+                    self.decks = decks;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Blackjack value of a set of cards, and whether an ace is counted as 11
+pub fn hand_value(cards: &[Card]) -> (u8, bool) {
+    let mut total: u8 = 0;
+    let mut has_ace = false;
+    for card in cards {
+        total += card.rank.min(10);
+        has_ace |= card.is_ace();
+    }
+    if has_ace && total + 10 <= 21 {
+        (total + 10, true)
+    }
+    else {
+        (total, false)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Hand {
+    pub cards: Vec<Card>,
+    pub bet: u128,
+    pub doubled: bool,
+    pub from_split: bool,
+    pub finished: bool
+}
+
+impl Hand {
+    fn new(bet: u128) -> Self {
+        Hand { cards: vec![], bet, doubled: false, from_split: false, finished: false }
+    }
+
+    pub fn value(&self) -> u8 {
+        hand_value(&self.cards).0
+    }
+
+    pub fn is_blackjack(&self) -> bool {
+        !self.from_split && self.cards.len() == 2 && self.value() == 21
+    }
+
+    pub fn is_bust(&self) -> bool {
+        self.value() > 21
+    }
+
+    pub fn can_split(&self) -> bool {
+        self.cards.len() == 2 && self.cards[0].rank.min(10) == self.cards[1].rank.min(10)
+    }
+}
+
+impl Display for Hand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (value, soft) = hand_value(&self.cards);
+        let soft = if soft && value < 21 { "soft " } else { "" };
+        write!(f, "{} ({soft}{value}) bet {}", format_cards(&self.cards), self.bet)?;
+        if self.doubled {
+            write!(f, " doubled")?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Seat<T> {
+    pub player_id: T,
+    pub hands: Vec<Hand>,
+    pub insurance: Option<u128>,
+    active_hand: usize
+}
+
+impl<T> Seat<T> {
+    fn is_done(&self) -> bool {
+        self.active_hand >= self.hands.len()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Players are joining with their opening bets
+    Betting,
+    /// The dealer shows an ace and players may take insurance
+    Insurance,
+    PlayerTurns,
+    Finished
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandOutcome {
+    Blackjack,
+    Win,
+    Push,
+    Lose,
+    Bust
+}
+
+#[derive(Debug, Clone)]
+pub struct HandResult<T> {
+    pub player_id: T,
+    pub hand: Hand,
+    pub outcome: HandOutcome,
+    /// Everything paid back for this hand minus what the hand (and any insurance) cost the player
+    pub net: i128
+}
+
+pub struct BlackjackTable<T> {
+    rules: BlackjackRules,
+    shoe: Shoe,
+    rng: StdRng,
+    seats: Vec<Seat<T>>,
+    dealer: Vec<Card>,
+    phase: Phase,
+    current_seat: usize,
+    results: Vec<HandResult<T>>,
+    /// Goes up every time play moves on, so a turn timer knows whether the player it was waiting on acted
+    action_id: u64,
+    pub deal_scheduled: bool
+}
+
+impl<T> BlackjackTable<T> where T: Display + Eq + Hash + Clone + Ord {
+    pub fn new(rules: BlackjackRules) -> Self {
+        Self::with_rng(rules, StdRng::from_entropy())
+    }
+
+    /// Create a table that deals from a specific RNG, e.g. a seeded one
+    pub fn with_rng(rules: BlackjackRules, mut rng: StdRng) -> Self {
+        let shoe = Shoe::new(rules.decks, &mut rng);
+        BlackjackTable {
+            rules,
+            shoe,
+            rng,
+            seats: vec![],
+            dealer: vec![],
+            phase: Phase::Betting,
+            current_seat: 0,
+            results: vec![],
+            action_id: 0,
+            deal_scheduled: false
+        }
+    }
+
+    pub fn rules(&self) -> BlackjackRules {
+        self.rules
+    }
+
+    /// Change the rules, which reshuffles the shoe. Only allowed between rounds
+    pub fn set_rules(&mut self, rules: BlackjackRules) -> Result<()> {
+        if self.phase == Phase::Finished {
+            self.start_new_round();
+        }
+        if self.phase != Phase::Betting || !self.seats.is_empty() {
+            anyhow::bail!("The rules can only be changed between rounds");
+        }
+        self.rules = rules;
+        self.shoe = Shoe::new(rules.decks, &mut self.rng);
+        Ok(())
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    pub fn seats(&self) -> &[Seat<T>] {
+        &self.seats
+    }
+
+    pub fn results(&self) -> &[HandResult<T>] {
+        &self.results
+    }
+
+    /// The dealer's cards, with the hole card hidden until players have acted
+    pub fn dealer_cards(&self) -> String {
+        match self.phase {
+            Phase::Finished => {
+                let (value, _) = hand_value(&self.dealer);
+                format!("{} ({value})", format_cards(&self.dealer))
+            },
+            _ => match self.dealer.first() {
+                Some(up_card) => format!("{up_card} 🂠"),
+                None => String::new(),
+            },
+        }
+    }
+
+    /// The player whose turn it is, if players are acting
+    pub fn current_player(&self) -> Option<&T> {
+        match self.phase {
+            Phase::PlayerTurns => self.seats.get(self.current_seat).map(|seat| &seat.player_id),
+            _ => None,
+        }
+    }
+
+    pub fn action_id(&self) -> u64 {
+        self.action_id
+    }
+
+    /// Stand the current hand for a player whose turn timer ran out.
+    /// Returns whether anything happened, which it won't if they acted in time
+    pub fn time_out(&mut self, action_id: u64, bank: &mut RouletteState<T>) -> Result<bool> {
+        if action_id != self.action_id || self.phase != Phase::PlayerTurns {
+            return Ok(false);
+        }
+        let player_id = self.seats[self.current_seat].player_id.clone();
+        self.stand(player_id, bank)?;
+        Ok(true)
+    }
+
+    /// Take a seat for the next round, staking `bet` from the player's balance
+    pub fn join(&mut self, player_id: T, bet: u128, bank: &mut RouletteState<T>) -> Result<()> {
+        if self.phase == Phase::Finished {
+            self.start_new_round();
+        }
+        if self.phase != Phase::Betting {
+            anyhow::bail!("A hand is already in progress, wait for the next round");
+        }
+        if bet == 0 {
+            anyhow::bail!("You cannot place a bet with a value of zero!");
+        }
+        if self.seats.iter().any(|seat| seat.player_id == player_id) {
+            anyhow::bail!("You already have a seat at this table");
+        }
+        bank.withdraw(player_id.clone(), bet)?;
+        self.seats.push(Seat { player_id, hands: vec![Hand::new(bet)], insurance: None, active_hand: 0 });
+        Ok(())
+    }
+
+    fn start_new_round(&mut self) {
+        self.seats.clear();
+        self.dealer.clear();
+        self.results.clear();
+        self.current_seat = 0;
+        self.phase = Phase::Betting;
+    }
+
+    fn draw(&mut self) -> Card {
+        self.shoe.draw(&mut self.rng)
+    }
+
+    /// Deal the opening cards to every seated player and the dealer
+    pub fn deal(&mut self, bank: &mut RouletteState<T>) -> Result<()> {
+        if self.phase != Phase::Betting {
+            anyhow::bail!("Cards have already been dealt");
+        }
+        if self.seats.is_empty() {
+            anyhow::bail!("Nobody has joined the table");
+        }
+        // Reshuffle once three quarters of the shoe has been played
+        if self.shoe.remaining() < self.shoe.size() / 4 {
+            self.shoe.shuffle(&mut self.rng);
+        }
+        for _ in 0..2 {
+            for seat in 0..self.seats.len() {
+                let card = self.draw();
+                self.seats[seat].hands[0].cards.push(card);
+            }
+            let card = self.draw();
+            self.dealer.push(card);
+        }
+        if self.dealer[0].is_ace() {
+            self.phase = Phase::Insurance;
+            Ok(())
+        }
+        else {
+            self.finish_insurance(bank)
+        }
+    }
+
+    /// Insure against a dealer blackjack for half of the opening bet
+    pub fn insure(&mut self, player_id: T, bank: &mut RouletteState<T>) -> Result<()> {
+        if self.phase != Phase::Insurance {
+            anyhow::bail!("Insurance is only offered while the dealer shows an ace");
+        }
+        let seat = self.seats.iter_mut().find(|seat| seat.player_id == player_id)
+            .context("You don't have a seat at this table")?;
+        if seat.insurance.is_some() {
+            anyhow::bail!("You have already taken insurance");
+        }
+        let cost = seat.hands[0].bet / 2;
+        if cost == 0 {
+            anyhow::bail!("Your bet is too small to insure");
+        }
+        bank.withdraw(player_id, cost)?;
+        seat.insurance = Some(cost);
+        Ok(())
+    }
+
+    /// Close the insurance window, peeking for a dealer blackjack
+    pub fn finish_insurance(&mut self, bank: &mut RouletteState<T>) -> Result<()> {
+        if !matches!(self.phase, Phase::Betting | Phase::Insurance) || self.dealer.len() != 2 {
+            anyhow::bail!("There is no insurance to settle right now");
+        }
+        if hand_value(&self.dealer).0 == 21 {
+            self.settle(bank);
+            return Ok(());
+        }
+        self.phase = Phase::PlayerTurns;
+        self.current_seat = 0;
+        self.skip_finished_hands(bank);
+        Ok(())
+    }
+
+    fn current_hand(&mut self, player_id: &T) -> Result<&mut Hand> {
+        if self.phase != Phase::PlayerTurns {
+            anyhow::bail!("It is not time to play hands");
+        }
+        let seat = &mut self.seats[self.current_seat];
+        if seat.player_id != *player_id {
+            anyhow::bail!("It is not your turn");
+        }
+        Ok(&mut seat.hands[seat.active_hand])
+    }
+
+    pub fn hit(&mut self, player_id: T, bank: &mut RouletteState<T>) -> Result<()> {
+        self.current_hand(&player_id)?;
+        let card = self.draw();
+        let hand = self.current_hand(&player_id)?;
+        hand.cards.push(card);
+        if hand.value() >= 21 {
+            hand.finished = true;
+        }
+        self.skip_finished_hands(bank);
+        Ok(())
+    }
+
+    pub fn stand(&mut self, player_id: T, bank: &mut RouletteState<T>) -> Result<()> {
+        self.current_hand(&player_id)?.finished = true;
+        self.skip_finished_hands(bank);
+        Ok(())
+    }
+
+    /// Double the bet, take exactly one more card and stand
+    pub fn double(&mut self, player_id: T, bank: &mut RouletteState<T>) -> Result<()> {
+        let hand = self.current_hand(&player_id)?;
+        if hand.cards.len() != 2 {
+            anyhow::bail!("You can only double down on your first two cards");
+        }
+        let bet = hand.bet;
+        bank.withdraw(player_id.clone(), bet)?;
+        let card = self.draw();
+        let hand = self.current_hand(&player_id)?;
+        hand.bet += bet;
+        hand.doubled = true;
+        hand.cards.push(card);
+        hand.finished = true;
+        self.skip_finished_hands(bank);
+        Ok(())
+    }
+
+    /// Split a pair into two hands, staking the same bet again
+    pub fn split(&mut self, player_id: T, bank: &mut RouletteState<T>) -> Result<()> {
+        let hand = self.current_hand(&player_id)?;
+        if !hand.can_split() {
+            anyhow::bail!("You can only split a pair");
+        }
+        let bet = hand.bet;
+        let seat = &self.seats[self.current_seat];
+        if seat.hands.len() >= MAX_HANDS {
+            anyhow::bail!("You cannot split into more than {MAX_HANDS} hands");
+        }
+        bank.withdraw(player_id, bet)?;
+
+        let first_card = self.draw();
+        let second_card = self.draw();
+        let seat = &mut self.seats[self.current_seat];
+        let active = seat.active_hand;
+        let split_card = seat.hands[active].cards.pop().expect("A pair has two cards");
+        let splitting_aces = split_card.is_ace();
+        let mut new_hand = Hand::new(bet);
+        new_hand.cards = vec![split_card, second_card];
+        new_hand.from_split = true;
+        let hand = &mut seat.hands[active];
+        hand.cards.push(first_card);
+        hand.from_split = true;
+        // Split aces only get one card each
+        if splitting_aces {
+            hand.finished = true;
+            new_hand.finished = true;
+        }
+        seat.hands.insert(active + 1, new_hand);
+        self.skip_finished_hands(bank);
+        Ok(())
+    }
+
+    /// Move past any hands that can no longer act, and let the dealer play once everyone is done
+    fn skip_finished_hands(&mut self, bank: &mut RouletteState<T>) {
+        self.action_id += 1;
+        while self.current_seat < self.seats.len() {
+            let seat = &mut self.seats[self.current_seat];
+            while !seat.is_done() {
+                let hand = &mut seat.hands[seat.active_hand];
+                if hand.value() >= 21 {
+                    hand.finished = true;
+                }
+                if !hand.finished {
+                    return;
+                }
+                seat.active_hand += 1;
+            }
+            self.current_seat += 1;
+        }
+        self.play_dealer();
+        self.settle(bank);
+    }
+
+    fn play_dealer(&mut self) {
+        let anyone_standing = self.seats.iter()
+            .flat_map(|seat| seat.hands.iter())
+            .any(|hand| !hand.is_bust() && !hand.is_blackjack());
+        if !anyone_standing {
+            return;
+        }
+        loop {
+            let (value, soft) = hand_value(&self.dealer);
+            let hits = value < 17 || (value == 17 && soft && self.rules.dealer_hits_soft_17);
+            if !hits {
+                break;
+            }
+            let card = self.draw();
+            self.dealer.push(card);
+        }
+    }
+
+    fn settle(&mut self, bank: &mut RouletteState<T>) {
+        let dealer_value = hand_value(&self.dealer).0;
+        let dealer_blackjack = self.dealer.len() == 2 && dealer_value == 21;
+        let mut results = vec![];
+        for seat in self.seats.iter() {
+            let insurance = seat.insurance.unwrap_or(0);
+            let insurance_payout = if dealer_blackjack { insurance * 3 } else { 0 };
+            for (index, hand) in seat.hands.iter().enumerate() {
+                let (outcome, payout) = if hand.is_bust() {
+                    (HandOutcome::Bust, 0)
+                }
+                else if hand.is_blackjack() && !dealer_blackjack {
+                    (HandOutcome::Blackjack, hand.bet + self.rules.blackjack_payout.winnings(hand.bet))
+                }
+                else if dealer_blackjack {
+                    if hand.is_blackjack() {
+                        (HandOutcome::Push, hand.bet)
+                    }
+                    else {
+                        (HandOutcome::Lose, 0)
+                    }
+                }
+                else if dealer_value > 21 || hand.value() > dealer_value {
+                    (HandOutcome::Win, hand.bet * 2)
+                }
+                else if hand.value() == dealer_value {
+                    (HandOutcome::Push, hand.bet)
+                }
+                else {
+                    (HandOutcome::Lose, 0)
+                };
+                // Insurance is settled alongside the first hand
+                let (payout, cost) = if index == 0 {
+                    (payout + insurance_payout, hand.bet + insurance)
+                }
+                else {
+                    (payout, hand.bet)
+                };
+                if payout > 0 {
+                    if let Err(e) = bank.deposit(seat.player_id.clone(), payout) {
+                        println!("Failed to pay out blackjack hand to {}: {e}", seat.player_id);
+                    }
+                }
+                results.push(HandResult {
+                    player_id: seat.player_id.clone(),
+                    hand: hand.clone(),
+                    outcome,
+                    net: payout as i128 - cost as i128
+                });
+            }
+        }
+        self.results = results;
+        self.phase = Phase::Finished;
+        self.deal_scheduled = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cards::Suit;
+
+    const PLAYER: u64 = 1;
+
+    /// A table with one player betting 100, dealing `ranks` in order.
+    /// Cards are dealt to the player, the dealer's up card, the player, the dealer's hole card, then as drawn
+    fn table(rules: BlackjackRules, ranks: &[u8]) -> (BlackjackTable<u64>, RouletteState<u64>) {
+        let mut table = BlackjackTable::with_rng(rules, StdRng::seed_from_u64(1));
+        let cards: Vec<Card> = ranks.iter().map(|rank| Card::new(*rank, Suit::Spades)).collect();
+        table.shoe = Shoe::stacked(&cards);
+        let mut bank = RouletteState::new();
+        bank.register_player(PLAYER, "player");
+        table.join(PLAYER, 100, &mut bank).unwrap();
+        (table, bank)
+    }
+
+    fn outcomes(table: &BlackjackTable<u64>) -> Vec<(HandOutcome, i128)> {
+        table.results().iter().map(|result| (result.outcome, result.net)).collect()
+    }
+
+    fn rules(dealer_hits_soft_17: bool, blackjack_payout: BlackjackPayout) -> BlackjackRules {
+        BlackjackRules { decks: 1, dealer_hits_soft_17, blackjack_payout }
+    }
+
+    #[test]
+    fn dealer_soft_17() {
+        // The player stands on 18 against the dealer's 6 and ace, with a 4 to come
+        let cards = [10, 6, 8, 1, 4];
+        let (mut table, mut bank) = table(rules(false, BlackjackPayout::ThreeToTwo), &cards);
+        table.deal(&mut bank).unwrap();
+        table.stand(PLAYER, &mut bank).unwrap();
+        assert_eq!(outcomes(&table), vec![(HandOutcome::Win, 100)]);
+
+        let (mut table, mut bank) = self::table(rules(true, BlackjackPayout::ThreeToTwo), &cards);
+        table.deal(&mut bank).unwrap();
+        table.stand(PLAYER, &mut bank).unwrap();
+        assert_eq!(table.dealer.len(), 3);
+        assert_eq!(outcomes(&table), vec![(HandOutcome::Lose, -100)]);
+    }
+
+    #[test]
+    fn blackjack_payouts() {
+        for (payout, net) in [(BlackjackPayout::ThreeToTwo, 150), (BlackjackPayout::SixToFive, 120)] {
+            let (mut table, mut bank) = table(rules(false, payout), &[1, 10, 13, 7]);
+            table.deal(&mut bank).unwrap();
+            assert_eq!(table.phase(), Phase::Finished);
+            assert_eq!(outcomes(&table), vec![(HandOutcome::Blackjack, net)]);
+            assert_eq!(bank.get_balance(PLAYER).unwrap(), (1000 + net) as u128);
+        }
+    }
+
+    #[test]
+    fn split_aces_get_one_card() {
+        let (mut table, mut bank) = table(BlackjackRules::default(), &[1, 10, 1, 7, 13, 5]);
+        table.deal(&mut bank).unwrap();
+        table.split(PLAYER, &mut bank).unwrap();
+        // Both hands are done, so the dealer has already played
+        assert_eq!(table.phase(), Phase::Finished);
+        let hands: Vec<usize> = table.results().iter().map(|result| result.hand.cards.len()).collect();
+        assert_eq!(hands, vec![2, 2]);
+        // 21 after a split is a plain win, not a blackjack
+        assert_eq!(outcomes(&table), vec![(HandOutcome::Win, 100), (HandOutcome::Lose, -100)]);
+    }
+
+    #[test]
+    fn resplit_limit() {
+        let (mut table, mut bank) = table(BlackjackRules::default(), &[8, 10, 8, 7, 8, 8, 8, 8, 8, 8]);
+        table.deal(&mut bank).unwrap();
+        for _ in 1..MAX_HANDS {
+            table.split(PLAYER, &mut bank).unwrap();
+        }
+        assert_eq!(table.seats()[0].hands.len(), MAX_HANDS);
+        assert!(table.split(PLAYER, &mut bank).is_err());
+        assert_eq!(bank.get_balance(PLAYER).unwrap(), 1000 - 100 * MAX_HANDS as u128);
+    }
+
+    #[test]
+    fn insurance_pays_when_the_dealer_peeks_blackjack() {
+        let (mut table, mut bank) = table(BlackjackRules::default(), &[10, 1, 9, 13]);
+        table.deal(&mut bank).unwrap();
+        assert_eq!(table.phase(), Phase::Insurance);
+        table.insure(PLAYER, &mut bank).unwrap();
+        table.finish_insurance(&mut bank).unwrap();
+        assert_eq!(outcomes(&table), vec![(HandOutcome::Lose, 0)]);
+        assert_eq!(bank.get_balance(PLAYER).unwrap(), 1000);
+    }
+
+    #[test]
+    fn insurance_is_lost_without_a_dealer_blackjack() {
+        let (mut table, mut bank) = table(BlackjackRules::default(), &[10, 1, 9, 7]);
+        table.deal(&mut bank).unwrap();
+        table.insure(PLAYER, &mut bank).unwrap();
+        table.finish_insurance(&mut bank).unwrap();
+        assert_eq!(table.phase(), Phase::PlayerTurns);
+        table.stand(PLAYER, &mut bank).unwrap();
+        assert_eq!(outcomes(&table), vec![(HandOutcome::Win, 50)]);
+    }
+
+    #[test]
+    fn dealer_peeks_under_a_ten() {
+        let (mut table, mut bank) = table(BlackjackRules::default(), &[9, 10, 9, 1]);
+        table.deal(&mut bank).unwrap();
+        assert_eq!(table.phase(), Phase::Finished);
+        assert_eq!(outcomes(&table), vec![(HandOutcome::Lose, -100)]);
+    }
+
+    #[test]
+    fn double_after_split() {
+        let (mut table, mut bank) = table(BlackjackRules::default(), &[8, 10, 8, 7, 3, 2, 10, 9]);
+        table.deal(&mut bank).unwrap();
+        table.split(PLAYER, &mut bank).unwrap();
+        table.double(PLAYER, &mut bank).unwrap();
+        table.double(PLAYER, &mut bank).unwrap();
+        assert_eq!(outcomes(&table), vec![(HandOutcome::Win, 200), (HandOutcome::Win, 200)]);
+        assert_eq!(bank.get_balance(PLAYER).unwrap(), 1400);
+    }
+
+    #[test]
+    fn turn_timeout_stands() {
+        let (mut table, mut bank) = table(BlackjackRules::default(), &[10, 10, 8, 7]);
+        table.deal(&mut bank).unwrap();
+        let action_id = table.action_id();
+        assert!(!table.time_out(action_id - 1, &mut bank).unwrap());
+        assert!(table.time_out(action_id, &mut bank).unwrap());
+        assert_eq!(outcomes(&table), vec![(HandOutcome::Win, 100)]);
+        assert!(!table.time_out(action_id, &mut bank).unwrap());
+    }
+}
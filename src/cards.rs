@@ -0,0 +1,128 @@
+use std::fmt::Display;
+use rand::{seq::SliceRandom, Rng};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Suit {
+    Clubs,
+    Diamonds,
+    Hearts,
+    Spades
+}
+
+impl Suit {
+    pub const ALL: [Suit; 4] = [Suit::Clubs, Suit::Diamonds, Suit::Hearts, Suit::Spades];
+
+    pub fn symbol(&self) -> char {
+        match self {
+            Suit::Clubs => '♣',
+            Suit::Diamonds => '♦',
+            Suit::Hearts => '♥',
+            Suit::Spades => '♠',
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Card {
+    /// 1 for an ace, 11 to 13 for jack, queen and king
+    pub rank: u8,
+    pub suit: Suit
+}
+
+impl Card {
+    pub fn new(rank: u8, suit: Suit) -> Self {
+        Card { rank, suit }
+    }
+
+    pub fn is_ace(&self) -> bool {
+        self.rank == 1
+    }
+
+    pub fn rank_name(&self) -> &'static str {
+        match self.rank {
+            1 => "A",
+            2 => "2",
+            3 => "3",
+            4 => "4",
+            5 => "5",
+            6 => "6",
+            7 => "7",
+            8 => "8",
+            9 => "9",
+            10 => "10",
+            11 => "J",
+            12 => "Q",
+            13 => "K",
+            _ => "?",
+        }
+    }
+}
+
+impl Display for Card {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.rank_name(), self.suit.symbol())
+    }
+}
+
+/// A freshly ordered 52 card deck
+pub fn standard_deck() -> Vec<Card> {
+    let mut deck = Vec::with_capacity(52);
+    for suit in Suit::ALL {
+        for rank in 1..=13 {
+            deck.push(Card::new(rank, suit));
+        }
+    }
+    deck
+}
+
+/// One or more decks shuffled together
+pub struct Shoe {
+    decks: u8,
+    cards: Vec<Card>
+}
+
+impl Shoe {
+    pub fn new(decks: u8, rng: &mut impl Rng) -> Self {
+        let mut shoe = Shoe { decks: decks.max(1), cards: vec![] };
+        shoe.shuffle(rng);
+        shoe
+    }
+
+    /// Put every card back in the shoe and shuffle it
+    pub fn shuffle(&mut self, rng: &mut impl Rng) {
+        self.cards.clear();
+        for _ in 0..self.decks {
+            self.cards.extend(standard_deck());
+        }
+        self.cards.shuffle(rng);
+    }
+
+    /// A one deck shoe that deals `cards` in order before the rest of the deck, for testing
+    #[cfg(test)]
+    pub fn stacked(cards: &[Card]) -> Self {
+        let mut shoe = Shoe { decks: 1, cards: standard_deck() };
+        shoe.cards.extend(cards.iter().rev());
+        shoe
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.cards.len()
+    }
+
+    pub fn size(&self) -> usize {
+        self.decks as usize * 52
+    }
+
+    /// Draw the next card, reshuffling if the shoe has run dry
+    pub fn draw(&mut self, rng: &mut impl Rng) -> Card {
+        if self.cards.is_empty() {
+            self.shuffle(rng);
+        }
+        self.cards.pop().expect("A freshly shuffled shoe is never empty")
+    }
+}
+
+pub fn format_cards(cards: &[Card]) -> String {
+    let cards: Vec<String> = cards.iter().map(|card| card.to_string()).collect();
+    cards.join(" ")
+}
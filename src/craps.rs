@@ -0,0 +1,561 @@
+use std::{fmt::Display, hash::Hash, str::FromStr};
+use anyhow::{Context, Result};
+use rand::Rng;
+
+use crate::roulette::RouletteState;
+
+/// Numbers that can become a point
+const POINT_NUMBERS: [u8; 6] = [4, 5, 6, 8, 9, 10];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrapsBet {
+    PassLine,
+    DontPass,
+    /// A come bet, which travels to its own point after its first roll
+    Come { point: Option<u8> },
+    DontCome { point: Option<u8> },
+    /// Odds behind a pass line bet
+    PassOdds,
+    DontPassOdds,
+    /// Odds behind a come bet that has travelled to `point`
+    ComeOdds { point: u8 },
+    DontComeOdds { point: u8 },
+    Place { number: u8 },
+    Field,
+    Hardway { number: u8 }
+}
+
+impl Display for CrapsBet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CrapsBet::PassLine => write!(f, "Pass line"),
+            CrapsBet::DontPass => write!(f, "Don't pass"),
+            CrapsBet::Come { point: None } => write!(f, "Come"),
+            CrapsBet::Come { point: Some(point) } => write!(f, "Come on {point}"),
+            CrapsBet::DontCome { point: None } => write!(f, "Don't come"),
+            CrapsBet::DontCome { point: Some(point) } => write!(f, "Don't come on {point}"),
+            CrapsBet::PassOdds => write!(f, "Pass odds"),
+            CrapsBet::DontPassOdds => write!(f, "Don't pass odds"),
+            CrapsBet::ComeOdds { point } => write!(f, "Come odds on {point}"),
+            CrapsBet::DontComeOdds { point } => write!(f, "Don't come odds on {point}"),
+            CrapsBet::Place { number } => write!(f, "Place {number}"),
+            CrapsBet::Field => write!(f, "Field"),
+            CrapsBet::Hardway { number } => write!(f, "Hard {number}"),
+        }
+    }
+}
+
+/// Winnings paid per unit staked, as a ratio
+fn true_odds(point: u8) -> (u128, u128) {
+    match point {
+        4 | 10 => (2, 1),
+        5 | 9 => (3, 2),
+        _ => (6, 5),
+    }
+}
+
+fn parse_number(word: Option<&&str>, allowed: &[u8], bet_name: &str) -> Result<u8> {
+    let word = word.context(format!("Which number do you want to bet {bet_name} on?"))?;
+    let number = u8::from_str(word).context(format!("Unable to parse number for {bet_name} bet: '{word}'"))?;
+    if !allowed.contains(&number) {
+        anyhow::bail!("You can't bet {bet_name} on {number}. Choose one of {allowed:?}");
+    }
+    Ok(number)
+}
+
+impl CrapsBet {
+    pub fn from_string(s: &str) -> Result<Self> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let bet = match words.first().map(|word| word.to_lowercase()).as_deref() {
+            Some("pass") => CrapsBet::PassLine,
+            Some("dontpass") => CrapsBet::DontPass,
+            Some("come") => CrapsBet::Come { point: None },
+            Some("dontcome") => CrapsBet::DontCome { point: None },
+            Some("place") => CrapsBet::Place { number: parse_number(words.get(1), &POINT_NUMBERS, "place")? },
+            Some("field") => CrapsBet::Field,
+            Some("hard") => CrapsBet::Hardway { number: parse_number(words.get(1), &[4, 6, 8, 10], "hard")? },
+            Some("odds") => match words.get(1).map(|word| word.to_lowercase()).as_deref() {
+                Some("pass") => CrapsBet::PassOdds,
+                Some("dontpass") => CrapsBet::DontPassOdds,
+                Some("come") => CrapsBet::ComeOdds { point: parse_number(words.get(2), &POINT_NUMBERS, "come odds")? },
+                Some("dontcome") => CrapsBet::DontComeOdds { point: parse_number(words.get(2), &POINT_NUMBERS, "don't come odds")? },
+                _ => anyhow::bail!("Odds go behind 'pass', 'dontpass', 'come <point>' or 'dontcome <point>'"),
+            },
+            Some(other) => anyhow::bail!("Unrecognized bet type: '{other}'"),
+            None => anyhow::bail!("Invalid bet command: '{s}'"),
+        };
+        Ok(bet)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PlayerCrapsBet<T> {
+    pub player_id: T,
+    pub bet: CrapsBet,
+    pub amount: u128
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BetResult {
+    /// Won, paying back this much including the stake
+    Win(u128),
+    Lose,
+    /// Returned to the player
+    Push,
+    /// A come bet travelled to its point
+    Moved(u8)
+}
+
+#[derive(Debug, Clone)]
+pub struct BetOutcome<T> {
+    pub player_id: T,
+    pub bet: CrapsBet,
+    pub amount: u128,
+    pub result: BetResult
+}
+
+#[derive(Debug, Clone)]
+pub struct CrapsRoll<T> {
+    pub dice: (u8, u8),
+    pub shooter: Option<T>,
+    /// The point before this roll, `None` for a come out roll
+    pub point: Option<u8>,
+    pub new_point: Option<u8>,
+    pub seven_out: bool,
+    pub outcomes: Vec<BetOutcome<T>>
+}
+
+impl<T> CrapsRoll<T> {
+    pub fn total(&self) -> u8 {
+        self.dice.0 + self.dice.1
+    }
+}
+
+pub struct CrapsState<T> {
+    bets: Vec<PlayerCrapsBet<T>>,
+    point: Option<u8>,
+    /// Players in the order they will shoot
+    shooters: Vec<T>,
+    shooter: usize,
+    can_change_bets: bool,
+    pub roll_scheduled: bool
+}
+
+impl<T> CrapsState<T> where T: Display + Eq + Hash + Clone + Ord {
+    pub fn new() -> Self {
+        CrapsState {
+            bets: vec![],
+            point: None,
+            shooters: vec![],
+            shooter: 0,
+            can_change_bets: true,
+            roll_scheduled: false
+        }
+    }
+
+    pub fn point(&self) -> Option<u8> {
+        self.point
+    }
+
+    pub fn shooter(&self) -> Option<&T> {
+        self.shooters.get(self.shooter)
+    }
+
+    pub fn get_bets(&self, player_id: &T) -> Vec<PlayerCrapsBet<T>> {
+        self.bets.iter().filter(|bet| bet.player_id == *player_id).cloned().collect()
+    }
+
+    fn has_bet(&self, player_id: &T, wanted: impl Fn(&CrapsBet) -> bool) -> bool {
+        self.bets.iter().any(|bet| bet.player_id == *player_id && wanted(&bet.bet))
+    }
+
+    /// Stake `amount` from the player's balance on a bet
+    pub fn bet(&mut self, player_id: T, bet: CrapsBet, amount: u128, bank: &mut RouletteState<T>) -> Result<()> {
+        if !self.can_change_bets {
+            anyhow::bail!("The dice are already rolling, wait for the next roll");
+        }
+        if amount == 0 {
+            anyhow::bail!("You cannot place a bet with a value of zero!");
+        }
+        match bet {
+            CrapsBet::PassLine | CrapsBet::DontPass if self.point.is_some() => {
+                anyhow::bail!("Line bets can only be made on the come out roll. Try a come bet instead");
+            },
+            CrapsBet::Come { .. } | CrapsBet::DontCome { .. } if self.point.is_none() => {
+                anyhow::bail!("Come bets can only be made once a point is set. Try the pass line instead");
+            },
+            CrapsBet::PassOdds if self.point.is_none() || !self.has_bet(&player_id, |bet| *bet == CrapsBet::PassLine) => {
+                anyhow::bail!("Pass odds need a pass line bet with a point set");
+            },
+            CrapsBet::DontPassOdds if self.point.is_none() || !self.has_bet(&player_id, |bet| *bet == CrapsBet::DontPass) => {
+                anyhow::bail!("Don't pass odds need a don't pass bet with a point set");
+            },
+            CrapsBet::ComeOdds { point } if !self.has_bet(&player_id, |bet| *bet == CrapsBet::Come { point: Some(point) }) => {
+                anyhow::bail!("You don't have a come bet on {point}");
+            },
+            CrapsBet::DontComeOdds { point } if !self.has_bet(&player_id, |bet| *bet == CrapsBet::DontCome { point: Some(point) }) => {
+                anyhow::bail!("You don't have a don't come bet on {point}");
+            },
+            _ => {},
+        }
+        bank.withdraw(player_id.clone(), amount)?;
+        if !self.shooters.contains(&player_id) {
+            self.shooters.push(player_id.clone());
+        }
+        println!("Player {player_id} placed craps bet {bet} for {amount}");
+        self.bets.push(PlayerCrapsBet { player_id, bet, amount });
+        Ok(())
+    }
+
+    /// Take a bet off the table and back into the player's balance. Line and come bets can't be taken down
+    /// once they're made, and field bets are settled every roll. Returns how much was returned
+    pub fn take_down(&mut self, player_id: &T, bet: CrapsBet, bank: &mut RouletteState<T>) -> Result<u128> {
+        if !self.can_change_bets {
+            anyhow::bail!("The dice are already rolling, wait for the next roll");
+        }
+        match bet {
+            CrapsBet::Place { .. } | CrapsBet::Hardway { .. } | CrapsBet::PassOdds | CrapsBet::DontPassOdds
+                | CrapsBet::ComeOdds { .. } | CrapsBet::DontComeOdds { .. } => {},
+            _ => anyhow::bail!("Only place bets, hardways and odds can be taken down"),
+        }
+        let amount: u128 = self.bets.iter()
+            .filter(|player_bet| player_bet.player_id == *player_id && player_bet.bet == bet)
+            .map(|player_bet| player_bet.amount)
+            .sum();
+        if amount == 0 {
+            anyhow::bail!("You don't have a {bet} bet");
+        }
+        bank.deposit(player_id.clone(), amount)?;
+        self.bets.retain(|player_bet| player_bet.player_id != *player_id || player_bet.bet != bet);
+        println!("Player {player_id} took down craps bet {bet} for {amount}");
+        Ok(amount)
+    }
+
+    pub fn lock_bets(&mut self) {
+        self.can_change_bets = false;
+    }
+
+    /// Resolve a single bet against a roll. Returns `None` if the bet stays on the table unchanged
+    fn resolve(&self, bet: CrapsBet, amount: u128, dice: (u8, u8)) -> Option<BetResult> {
+        let total = dice.0 + dice.1;
+        let win = |(numerator, denominator): (u128, u128)| Some(BetResult::Win(amount + amount * numerator / denominator));
+        let even_money = win((1, 1));
+        match bet {
+            CrapsBet::PassLine => match (self.point, total) {
+                (None, 7 | 11) => even_money,
+                (None, 2 | 3 | 12) => Some(BetResult::Lose),
+                (Some(_), 7) => Some(BetResult::Lose),
+                (Some(point), total) if total == point => even_money,
+                _ => None,
+            },
+            CrapsBet::DontPass => match (self.point, total) {
+                (None, 2 | 3) => even_money,
+                (None, 12) => Some(BetResult::Push),
+                (None, 7 | 11) => Some(BetResult::Lose),
+                (Some(_), 7) => even_money,
+                (Some(point), total) if total == point => Some(BetResult::Lose),
+                _ => None,
+            },
+            CrapsBet::Come { point: None } => match total {
+                7 | 11 => even_money,
+                2 | 3 | 12 => Some(BetResult::Lose),
+                total => Some(BetResult::Moved(total)),
+            },
+            CrapsBet::DontCome { point: None } => match total {
+                2 | 3 => even_money,
+                12 => Some(BetResult::Push),
+                7 | 11 => Some(BetResult::Lose),
+                total => Some(BetResult::Moved(total)),
+            },
+            CrapsBet::Come { point: Some(point) } => match total {
+                7 => Some(BetResult::Lose),
+                total if total == point => even_money,
+                _ => None,
+            },
+            CrapsBet::DontCome { point: Some(point) } => match total {
+                7 => even_money,
+                total if total == point => Some(BetResult::Lose),
+                _ => None,
+            },
+            CrapsBet::PassOdds => match (self.point, total) {
+                (Some(_), 7) => Some(BetResult::Lose),
+                (Some(point), total) if total == point => win(true_odds(point)),
+                _ => None,
+            },
+            CrapsBet::DontPassOdds => match (self.point, total) {
+                (Some(point), 7) => {
+                    let (numerator, denominator) = true_odds(point);
+                    win((denominator, numerator))
+                },
+                (Some(point), total) if total == point => Some(BetResult::Lose),
+                _ => None,
+            },
+            // Odds on come bets are off on the come out roll, so they're returned if it decides the come bet
+            CrapsBet::ComeOdds { point } => match (self.point, total) {
+                (None, 7) => Some(BetResult::Push),
+                (None, total) if total == point => Some(BetResult::Push),
+                (Some(_), 7) => Some(BetResult::Lose),
+                (Some(_), total) if total == point => win(true_odds(point)),
+                _ => None,
+            },
+            CrapsBet::DontComeOdds { point } => match total {
+                7 => {
+                    let (numerator, denominator) = true_odds(point);
+                    win((denominator, numerator))
+                },
+                total if total == point => Some(BetResult::Lose),
+                _ => None,
+            },
+            // Place bets and hardways are off on the come out roll
+            CrapsBet::Place { .. } | CrapsBet::Hardway { .. } if self.point.is_none() => None,
+            CrapsBet::Place { number } => match total {
+                7 => Some(BetResult::Lose),
+                total if total == number => match number {
+                    4 | 10 => win((9, 5)),
+                    5 | 9 => win((7, 5)),
+                    _ => win((7, 6)),
+                },
+                _ => None,
+            },
+            CrapsBet::Field => match total {
+                2 => win((2, 1)),
+                12 => win((3, 1)),
+                3 | 4 | 9 | 10 | 11 => even_money,
+                _ => Some(BetResult::Lose),
+            },
+            CrapsBet::Hardway { number } => match total {
+                7 => Some(BetResult::Lose),
+                total if total == number && dice.0 == dice.1 => match number {
+                    4 | 10 => win((7, 1)),
+                    _ => win((9, 1)),
+                },
+                total if total == number => Some(BetResult::Lose),
+                _ => None,
+            },
+        }
+    }
+
+    /// Roll the dice, settle every bet it decides and move the point and shooter along
+    pub fn roll(&mut self, bank: &mut RouletteState<T>, rng: &mut impl Rng) -> CrapsRoll<T> {
+        let dice = (rng.gen_range(1..=6), rng.gen_range(1..=6));
+        self.settle_roll(dice, bank)
+    }
+
+    /// Settle a known roll of the dice
+    pub fn settle_roll(&mut self, dice: (u8, u8), bank: &mut RouletteState<T>) -> CrapsRoll<T> {
+        let total = dice.0 + dice.1;
+        let shooter = self.shooter().cloned();
+        let point = self.point;
+        println!("Craps roll: {} + {} = {total}", dice.0, dice.1);
+
+        let mut outcomes = vec![];
+        let mut remaining_bets = vec![];
+        for player_bet in self.bets.iter() {
+            let result = match self.resolve(player_bet.bet, player_bet.amount, dice) {
+                Some(result) => result,
+                None => {
+                    remaining_bets.push(player_bet.clone());
+                    continue;
+                }
+            };
+            let payout = match result {
+                BetResult::Win(payout) => payout,
+                BetResult::Push => player_bet.amount,
+                BetResult::Lose => 0,
+                BetResult::Moved(point) => {
+                    let bet = match player_bet.bet {
+                        CrapsBet::DontCome { .. } => CrapsBet::DontCome { point: Some(point) },
+                        _ => CrapsBet::Come { point: Some(point) },
+                    };
+                    remaining_bets.push(PlayerCrapsBet { bet, ..player_bet.clone() });
+                    0
+                },
+            };
+            if payout > 0 {
+                if let Err(e) = bank.deposit(player_bet.player_id.clone(), payout) {
+                    println!("Failed to pay out craps bet to {}: {e}", player_bet.player_id);
+                }
+            }
+            outcomes.push(BetOutcome {
+                player_id: player_bet.player_id.clone(),
+                bet: player_bet.bet,
+                amount: player_bet.amount,
+                result
+            });
+        }
+        self.bets = remaining_bets;
+
+        let mut seven_out = false;
+        self.point = match (point, total) {
+            (None, total) if POINT_NUMBERS.contains(&total) => Some(total),
+            (Some(_), 7) => {
+                seven_out = true;
+                None
+            },
+            (Some(point), total) if total == point => None,
+            (point, _) => point,
+        };
+        if seven_out && !self.shooters.is_empty() {
+            self.shooter = (self.shooter + 1) % self.shooters.len();
+        }
+
+        self.can_change_bets = true;
+        self.roll_scheduled = false;
+        CrapsRoll { dice, shooter, point, new_point: self.point, seven_out, outcomes }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAYER: u64 = 1;
+
+    fn table() -> (CrapsState<u64>, RouletteState<u64>) {
+        let mut bank = RouletteState::new();
+        bank.register_player(PLAYER, "player");
+        (CrapsState::new(), bank)
+    }
+
+    fn bet(craps: &mut CrapsState<u64>, bank: &mut RouletteState<u64>, bet: &str, amount: u128) {
+        craps.bet(PLAYER, CrapsBet::from_string(bet).unwrap(), amount, bank).unwrap();
+    }
+
+    fn results(roll: &CrapsRoll<u64>) -> Vec<(CrapsBet, BetResult)> {
+        roll.outcomes.iter().map(|outcome| (outcome.bet, outcome.result)).collect()
+    }
+
+    #[test]
+    fn come_out_rolls() {
+        let (mut craps, mut bank) = table();
+        bet(&mut craps, &mut bank, "pass", 10);
+        bet(&mut craps, &mut bank, "dontpass", 10);
+        let roll = craps.settle_roll((5, 6), &mut bank);
+        assert_eq!(results(&roll), [(CrapsBet::PassLine, BetResult::Win(20)), (CrapsBet::DontPass, BetResult::Lose)]);
+
+        bet(&mut craps, &mut bank, "pass", 10);
+        bet(&mut craps, &mut bank, "dontpass", 10);
+        let roll = craps.settle_roll((6, 6), &mut bank);
+        assert_eq!(results(&roll), [(CrapsBet::PassLine, BetResult::Lose), (CrapsBet::DontPass, BetResult::Push)]);
+
+        bet(&mut craps, &mut bank, "pass", 10);
+        let roll = craps.settle_roll((2, 4), &mut bank);
+        assert!(roll.outcomes.is_empty());
+        assert_eq!(craps.point(), Some(6));
+    }
+
+    #[test]
+    fn making_the_point_pays_odds() {
+        let (mut craps, mut bank) = table();
+        let start = bank.get_balance(PLAYER).unwrap();
+        bet(&mut craps, &mut bank, "pass", 10);
+        craps.settle_roll((1, 3), &mut bank);
+        bet(&mut craps, &mut bank, "odds pass", 10);
+        craps.settle_roll((5, 6), &mut bank);
+        let roll = craps.settle_roll((2, 2), &mut bank);
+        assert_eq!(results(&roll), [(CrapsBet::PassLine, BetResult::Win(20)), (CrapsBet::PassOdds, BetResult::Win(30))]);
+        assert_eq!(craps.point(), None);
+        assert!(!roll.seven_out);
+        assert_eq!(bank.get_balance(PLAYER).unwrap(), start + 30);
+    }
+
+    #[test]
+    fn seven_out() {
+        let (mut craps, mut bank) = table();
+        bet(&mut craps, &mut bank, "pass", 10);
+        bet(&mut craps, &mut bank, "dontpass", 10);
+        craps.settle_roll((4, 5), &mut bank);
+        bet(&mut craps, &mut bank, "odds dontpass", 30);
+        bet(&mut craps, &mut bank, "place 8", 12);
+        let roll = craps.settle_roll((3, 4), &mut bank);
+        assert!(roll.seven_out);
+        assert_eq!(results(&roll), [
+            (CrapsBet::PassLine, BetResult::Lose),
+            (CrapsBet::DontPass, BetResult::Win(20)),
+            (CrapsBet::DontPassOdds, BetResult::Win(50)),
+            (CrapsBet::Place { number: 8 }, BetResult::Lose)
+        ]);
+    }
+
+    #[test]
+    fn come_bets_travel() {
+        let (mut craps, mut bank) = table();
+        bet(&mut craps, &mut bank, "pass", 10);
+        craps.settle_roll((3, 3), &mut bank);
+        bet(&mut craps, &mut bank, "come", 10);
+        let roll = craps.settle_roll((4, 6), &mut bank);
+        assert_eq!(results(&roll), [(CrapsBet::Come { point: None }, BetResult::Moved(10))]);
+        bet(&mut craps, &mut bank, "odds come 10", 10);
+        let roll = craps.settle_roll((5, 5), &mut bank);
+        assert_eq!(results(&roll), [
+            (CrapsBet::Come { point: Some(10) }, BetResult::Win(20)),
+            (CrapsBet::ComeOdds { point: 10 }, BetResult::Win(30))
+        ]);
+    }
+
+    #[test]
+    fn come_odds_are_off_on_the_come_out() {
+        let (mut craps, mut bank) = table();
+        let start = bank.get_balance(PLAYER).unwrap();
+        bet(&mut craps, &mut bank, "pass", 10);
+        craps.settle_roll((3, 3), &mut bank);
+        bet(&mut craps, &mut bank, "come", 10);
+        craps.settle_roll((2, 2), &mut bank);
+        bet(&mut craps, &mut bank, "odds come 4", 10);
+        // Making the point of 6 leaves the come bet and its odds on 4 through the come out roll
+        craps.settle_roll((1, 5), &mut bank);
+        assert_eq!(craps.point(), None);
+        let roll = craps.settle_roll((3, 4), &mut bank);
+        assert_eq!(results(&roll), [
+            (CrapsBet::Come { point: Some(4) }, BetResult::Lose),
+            (CrapsBet::ComeOdds { point: 4 }, BetResult::Push)
+        ]);
+        // Won 10 on the pass line and lost the 10 come bet
+        assert_eq!(bank.get_balance(PLAYER).unwrap(), start);
+    }
+
+    #[test]
+    fn place_bets_and_hardways() {
+        let (mut craps, mut bank) = table();
+        bet(&mut craps, &mut bank, "pass", 10);
+        craps.settle_roll((1, 4), &mut bank);
+        bet(&mut craps, &mut bank, "place 6", 12);
+        bet(&mut craps, &mut bank, "hard 8", 10);
+        bet(&mut craps, &mut bank, "hard 6", 10);
+        let roll = craps.settle_roll((2, 4), &mut bank);
+        assert_eq!(results(&roll), [
+            (CrapsBet::Place { number: 6 }, BetResult::Win(26)),
+            (CrapsBet::Hardway { number: 6 }, BetResult::Lose)
+        ]);
+        let roll = craps.settle_roll((4, 4), &mut bank);
+        assert_eq!(results(&roll), [(CrapsBet::Hardway { number: 8 }, BetResult::Win(100))]);
+    }
+
+    #[test]
+    fn field() {
+        let (mut craps, mut bank) = table();
+        for (dice, result) in [((1, 1), BetResult::Win(30)), ((6, 6), BetResult::Win(40)), ((4, 5), BetResult::Win(20)), ((3, 4), BetResult::Lose)] {
+            bet(&mut craps, &mut bank, "field", 10);
+            let roll = craps.settle_roll(dice, &mut bank);
+            assert_eq!(results(&roll), [(CrapsBet::Field, result)], "{dice:?}");
+        }
+    }
+
+    #[test]
+    fn taking_down_place_bets() {
+        let (mut craps, mut bank) = table();
+        bet(&mut craps, &mut bank, "pass", 10);
+        craps.settle_roll((4, 4), &mut bank);
+        let before = bank.get_balance(PLAYER).unwrap();
+        bet(&mut craps, &mut bank, "place 5", 10);
+        bet(&mut craps, &mut bank, "place 5", 5);
+        assert_eq!(craps.take_down(&PLAYER, CrapsBet::Place { number: 5 }, &mut bank).unwrap(), 15);
+        assert_eq!(bank.get_balance(PLAYER).unwrap(), before);
+        assert!(craps.take_down(&PLAYER, CrapsBet::Place { number: 5 }, &mut bank).is_err());
+        assert!(craps.take_down(&PLAYER, CrapsBet::PassLine, &mut bank).is_err());
+
+        bet(&mut craps, &mut bank, "place 9", 10);
+        craps.lock_bets();
+        assert!(craps.take_down(&PLAYER, CrapsBet::Place { number: 9 }, &mut bank).is_err());
+    }
+}
@@ -0,0 +1,335 @@
+use std::{collections::BTreeMap, fmt::Display, hash::Hash};
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+use crate::roulette::RouletteState;
+
+/// Multipliers are kept in hundredths, so 100 is 1.00x
+pub const ONE_X: u64 = 100;
+
+/// Lowest multiplier a player can cash out at, or set an automatic cash out at
+const MIN_CASH_OUT: u64 = 101;
+
+/// Work out a round's crash point from its server seed.
+/// The first 52 bits of HMAC-SHA256(seed, round) give a uniform `r` in [0, 1),
+/// and the crash point is `(1 - edge) / (1 - r)`, so every cash out target has the same expected return.
+/// Anything under 1.00x busts immediately
+pub fn crash_point(server_seed: &[u8], round: u64, house_edge_bps: u32) -> u64 {
+    let mut mac = Hmac::<Sha256>::new_from_slice(server_seed).expect("HMAC accepts keys of any length");
+    mac.update(round.to_string().as_bytes());
+    let hash = mac.finalize().into_bytes();
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&hash[..8]);
+    let r = (u64::from_be_bytes(bytes) >> 12) as u128;
+    let scale: u128 = 1 << 52;
+    let numerator = (10_000 - house_edge_bps.min(10_000) as u128) * scale;
+    let denominator = 100 * (scale - r);
+    ((numerator / denominator) as u64).max(ONE_X)
+}
+
+/// Hex SHA-256 of the server seed, published before a round so the seed can't be swapped later
+pub fn seed_commitment(server_seed: &[u8]) -> String {
+    hex::encode(Sha256::digest(server_seed))
+}
+
+pub fn format_multiplier(multiplier: u64) -> String {
+    format!("{}.{:02}x", multiplier / 100, multiplier % 100)
+}
+
+/// Format a house edge in basis points as a percentage, like `1.50%`
+pub fn format_house_edge(house_edge_bps: u32) -> String {
+    format!("{}.{:02}%", house_edge_bps / 100, house_edge_bps % 100)
+}
+
+/// Parse a house edge percentage like `1.5` or `2%` into basis points
+pub fn parse_house_edge(s: &str) -> Result<u32> {
+    // Percentages parse the same way as multipliers, in hundredths
+    let house_edge = parse_multiplier(s.trim().trim_end_matches('%'))?;
+    if house_edge >= 10_000 {
+        anyhow::bail!("The house edge must be under 100%");
+    }
+    Ok(house_edge as u32)
+}
+
+/// Parse multipliers like `2`, `2.5x` or `1.75` into hundredths
+pub fn parse_multiplier(s: &str) -> Result<u64> {
+    let s = s.trim().trim_end_matches(['x', 'X']);
+    let (whole, fraction) = s.split_once('.').unwrap_or((s, ""));
+    if fraction.len() > 2 {
+        anyhow::bail!("Multipliers only go to two decimal places: '{s}'");
+    }
+    let whole = whole.parse::<u64>().context(format!("Invalid multiplier: '{s}'"))?;
+    let fraction = if fraction.is_empty() { 0 } else { format!("{fraction:0<2}").parse::<u64>().context(format!("Invalid multiplier: '{s}'"))? };
+    whole.checked_mul(100).and_then(|whole| whole.checked_add(fraction)).with_context(|| format!("Multiplier is too large: '{s}'"))
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CrashBet {
+    pub amount: u128,
+    /// Cash out automatically once the multiplier reaches this
+    pub auto_cash_out: Option<u64>,
+    pub cashed_out_at: Option<u64>,
+    /// What was paid back on cashing out
+    pub payout: u128
+}
+
+impl CrashBet {
+    /// Cash out at `multiplier`, leaving the bet alone if the payout doesn't fit
+    fn cash_out(&mut self, multiplier: u64) -> Result<u128> {
+        let payout = self.amount.checked_mul(multiplier as u128).context("That payout is too large for the bank")? / ONE_X as u128;
+        self.cashed_out_at = Some(multiplier);
+        self.payout = payout;
+        Ok(payout)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrashPhase {
+    Idle,
+    Betting,
+    Running,
+}
+
+#[derive(Debug, Clone)]
+pub struct CrashSummary<T> {
+    pub round: u64,
+    pub crash_point: u64,
+    /// The house edge the crash point was worked out with, in basis points
+    pub house_edge_bps: u32,
+    /// Hex encoded seed, revealed so players can check the crash point
+    pub server_seed: String,
+    pub bets: Vec<(T, CrashBet)>
+}
+
+pub enum CrashTick<T> {
+    Climbing {
+        multiplier: u64
+    },
+    Crashed(CrashSummary<T>)
+}
+
+pub struct CrashGame<T> {
+    /// House edge in basis points, so 100 is 1%
+    house_edge_bps: u32,
+    phase: CrashPhase,
+    round: u64,
+    server_seed: [u8; 32],
+    crash_point: u64,
+    multiplier: u64,
+    bets: BTreeMap<T, CrashBet>
+}
+
+impl<T> CrashGame<T> where T: Display + Eq + Hash + Clone + Ord {
+    pub fn new(house_edge_bps: u32) -> Self {
+        CrashGame {
+            house_edge_bps,
+            phase: CrashPhase::Idle,
+            round: 0,
+            server_seed: [0; 32],
+            crash_point: ONE_X,
+            multiplier: ONE_X,
+            bets: Default::default()
+        }
+    }
+
+    pub fn phase(&self) -> CrashPhase {
+        self.phase
+    }
+
+    pub fn round(&self) -> u64 {
+        self.round
+    }
+
+    pub fn multiplier(&self) -> u64 {
+        self.multiplier
+    }
+
+    pub fn set_house_edge(&mut self, house_edge_bps: u32) -> Result<()> {
+        if self.phase != CrashPhase::Idle {
+            anyhow::bail!("The house edge can only be changed between rounds");
+        }
+        if house_edge_bps >= 10_000 {
+            anyhow::bail!("The house edge must be under 100%");
+        }
+        self.house_edge_bps = house_edge_bps;
+        Ok(())
+    }
+
+    /// Commitment to this round's server seed
+    pub fn commitment(&self) -> String {
+        seed_commitment(&self.server_seed)
+    }
+
+    pub fn bets(&self) -> &BTreeMap<T, CrashBet> {
+        &self.bets
+    }
+
+    /// Stake on the next round, opening betting with a fresh seed if nobody has bet yet.
+    /// Returns whether this bet opened the betting window
+    pub fn bet(&mut self, player_id: T, amount: u128, auto_cash_out: Option<u64>, bank: &mut RouletteState<T>, rng: &mut impl Rng) -> Result<bool> {
+        if self.phase == CrashPhase::Running {
+            anyhow::bail!("The rocket has already launched, wait for the next round");
+        }
+        if amount == 0 {
+            anyhow::bail!("You cannot place a bet with a value of zero!");
+        }
+        if self.bets.contains_key(&player_id) {
+            anyhow::bail!("You have already bet on this round");
+        }
+        if let Some(auto_cash_out) = auto_cash_out {
+            if auto_cash_out < MIN_CASH_OUT {
+                anyhow::bail!("Automatic cash outs must be at least {}", format_multiplier(MIN_CASH_OUT));
+            }
+        }
+        bank.withdraw(player_id.clone(), amount)?;
+        let opened = self.phase == CrashPhase::Idle;
+        if opened {
+            self.round += 1;
+            rng.fill(&mut self.server_seed);
+            self.crash_point = crash_point(&self.server_seed, self.round, self.house_edge_bps);
+            self.phase = CrashPhase::Betting;
+        }
+        self.bets.insert(player_id, CrashBet { amount, auto_cash_out, cashed_out_at: None, payout: 0 });
+        Ok(opened)
+    }
+
+    /// Close betting and launch at 1.00x
+    pub fn launch(&mut self) -> Result<()> {
+        if self.phase != CrashPhase::Betting {
+            anyhow::bail!("There is no round waiting to launch");
+        }
+        self.phase = CrashPhase::Running;
+        self.multiplier = ONE_X;
+        Ok(())
+    }
+
+    /// Climb to `multiplier`, paying out automatic cash outs on the way, or crash if it is past the crash point
+    pub fn advance(&mut self, multiplier: u64, bank: &mut RouletteState<T>) -> Result<CrashTick<T>> {
+        if self.phase != CrashPhase::Running {
+            anyhow::bail!("No round is running");
+        }
+        let reached = multiplier.max(self.multiplier).min(self.crash_point);
+        for (player_id, bet) in self.bets.iter_mut() {
+            match (bet.auto_cash_out, bet.cashed_out_at) {
+                (Some(target), None) if target <= reached => {
+                    let payout = bet.cash_out(target)?;
+                    bank.deposit(player_id.clone(), payout)?;
+                },
+                _ => {},
+            }
+        }
+        self.multiplier = reached;
+        if multiplier >= self.crash_point {
+            return Ok(CrashTick::Crashed(self.finish()));
+        }
+        Ok(CrashTick::Climbing { multiplier: self.multiplier })
+    }
+
+    /// Cash out at the current multiplier
+    pub fn cash_out(&mut self, player_id: T, bank: &mut RouletteState<T>) -> Result<u128> {
+        if self.phase != CrashPhase::Running {
+            anyhow::bail!("No round is running");
+        }
+        if self.multiplier < MIN_CASH_OUT {
+            anyhow::bail!("Wait for lift off before cashing out");
+        }
+        let multiplier = self.multiplier;
+        let bet = self.bets.get_mut(&player_id).context("You didn't bet on this round")?;
+        if bet.cashed_out_at.is_some() {
+            anyhow::bail!("You have already cashed out");
+        }
+        let payout = bet.cash_out(multiplier)?;
+        bank.deposit(player_id, payout)?;
+        Ok(payout)
+    }
+
+    fn finish(&mut self) -> CrashSummary<T> {
+        let summary = CrashSummary {
+            round: self.round,
+            crash_point: self.crash_point,
+            house_edge_bps: self.house_edge_bps,
+            server_seed: hex::encode(self.server_seed),
+            bets: std::mem::take(&mut self.bets).into_iter().collect()
+        };
+        self.phase = CrashPhase::Idle;
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    const SEED: &[u8] = b"provably fair";
+
+    #[test]
+    fn known_crash_points() {
+        // Round 1 hashes to r = 0.16826, so the crash point is (1 - edge) / 0.83174
+        assert_eq!(crash_point(SEED, 1, 0), 120);
+        assert_eq!(crash_point(SEED, 1, 100), 119);
+        assert_eq!(crash_point(SEED, 2, 100), 168);
+        assert_eq!(crash_point(SEED, 3, 100), 172);
+        // Round 160 hashes to r = 0.00368, under the 1% edge, so it busts straight away
+        assert_eq!(crash_point(SEED, 160, 100), ONE_X);
+        // and a large enough edge makes round 1 bust too
+        assert_eq!(crash_point(SEED, 1, 5_000), ONE_X);
+    }
+
+    #[test]
+    fn instant_crashes_pay_nothing() {
+        let mut game: CrashGame<u64> = CrashGame::new(9_999);
+        let mut bank = RouletteState::new();
+        bank.register_player(1, "player");
+        let balance = bank.get_balance(1).unwrap();
+        game.bet(1, 10, Some(MIN_CASH_OUT), &mut bank, &mut StdRng::seed_from_u64(0)).unwrap();
+        game.launch().unwrap();
+        assert!(game.cash_out(1, &mut bank).is_err());
+        match game.advance(MIN_CASH_OUT, &mut bank).unwrap() {
+            CrashTick::Crashed(summary) => {
+                assert_eq!(summary.crash_point, ONE_X);
+                assert_eq!(summary.bets[0].1.cashed_out_at, None);
+            },
+            CrashTick::Climbing { .. } => panic!("a 99.99% edge should crash at 1.00x"),
+        }
+        assert_eq!(bank.get_balance(1).unwrap(), balance - 10);
+        assert_eq!(game.phase(), CrashPhase::Idle);
+    }
+
+    #[test]
+    fn payouts_that_overflow() {
+        let mut bet = CrashBet { amount: u128::MAX / 2, auto_cash_out: None, cashed_out_at: None, payout: 0 };
+        assert!(bet.cash_out(250).is_err());
+        assert_eq!(bet.cashed_out_at, None);
+        let mut bet = CrashBet { amount: 40, auto_cash_out: None, cashed_out_at: None, payout: 0 };
+        assert_eq!(bet.cash_out(250).unwrap(), 100);
+        assert_eq!(bet.cashed_out_at, Some(250));
+    }
+
+    #[test]
+    fn multipliers() {
+        assert_eq!(parse_multiplier("2").unwrap(), 200);
+        assert_eq!(parse_multiplier("2.5x").unwrap(), 250);
+        assert_eq!(parse_multiplier("1.75").unwrap(), 175);
+        assert_eq!(parse_multiplier(" 3X ").unwrap(), 300);
+        assert!(parse_multiplier("1.234").is_err());
+        assert!(parse_multiplier("1.-5").is_err());
+        assert!(parse_multiplier("-2").is_err());
+        assert!(parse_multiplier("x").is_err());
+        assert!(parse_multiplier("184467440737095517").is_err());
+        assert_eq!(format_multiplier(parse_multiplier("1.5").unwrap()), "1.50x");
+    }
+
+    #[test]
+    fn house_edges() {
+        assert_eq!(parse_house_edge("1.5").unwrap(), 150);
+        assert_eq!(parse_house_edge("2%").unwrap(), 200);
+        assert_eq!(parse_house_edge("0").unwrap(), 0);
+        assert_eq!(parse_house_edge("99.99").unwrap(), 9_999);
+        assert!(parse_house_edge("100").is_err());
+        assert!(parse_house_edge("250%").is_err());
+        assert_eq!(format_house_edge(150), "1.50%");
+    }
+}
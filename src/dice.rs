@@ -0,0 +1,1263 @@
+use std::{collections::BTreeMap, fmt::Display};
+use rand::Rng;
+
+use crate::odds::{Budget, Distribution};
+
+/// Most dice one term can roll, not counting explosions
+const MAX_DICE: i64 = 1_000;
+
+/// Most sides a die can have
+const MAX_SIDES: i64 = 1_000_000;
+
+/// How many times one die can explode or be rerolled before we give up
+const MAX_REROLLS: usize = 100;
+
+/// How deep parentheses can nest
+const MAX_DEPTH: usize = 32;
+
+/// Most sides a die can have when working out odds
+const MAX_ODDS_SIDES: i64 = 10_000;
+
+/// Most steps spent working out the odds of a whole expression
+const MAX_ODDS_WORK: usize = 20_000_000;
+
+/// Explosions less likely than this are left out when working out odds
+const NEGLIGIBLE_PROBABILITY: f64 = 1e-15;
+
+/// Where and why an expression couldn't be parsed or rolled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiceError {
+    /// Index of the offending character
+    pub position: usize,
+    pub message: String
+}
+
+impl DiceError {
+    fn new(position: usize, message: impl Into<String>) -> Self {
+        DiceError { position, message: message.into() }
+    }
+
+    /// The expression with a caret under where it went wrong
+    pub fn pointer(&self, expression: &str) -> String {
+        format!("{expression}\n{}^", " ".repeat(self.position))
+    }
+}
+
+impl Display for DiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (at character {})", self.message, self.position + 1)
+    }
+}
+
+impl std::error::Error for DiceError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compare {
+    Equal,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual
+}
+
+/// A test applied to each die, like the `<2` in `r<2`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub compare: Compare,
+    pub value: i64
+}
+
+impl Condition {
+    pub fn matches(&self, value: i64) -> bool {
+        match self.compare {
+            Compare::Equal => value == self.value,
+            Compare::Less => value < self.value,
+            Compare::LessEqual => value <= self.value,
+            Compare::Greater => value > self.value,
+            Compare::GreaterEqual => value >= self.value,
+        }
+    }
+
+    /// Whether every face of a die passes, which would explode or reroll forever
+    fn matches_every_face(&self, sides: i64) -> bool {
+        (1..=sides).all(|face| self.matches(face))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Keep {
+    Highest(i64),
+    Lowest(i64),
+    DropHighest(i64),
+    DropLowest(i64)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Explode {
+    /// Each die that passes rolls another die
+    Explode(Condition),
+    /// Each die that passes rolls again and adds to itself
+    Compound(Condition)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reroll {
+    /// Reroll until the die no longer passes
+    Always(Condition),
+    Once(Condition)
+}
+
+#[derive(Debug, Clone)]
+struct DiceTerm {
+    /// The term as written, like `4d6kh3`
+    text: String,
+    /// Where the term starts
+    position: usize,
+    count: i64,
+    sides: i64,
+    /// Fudge dice are rolled as d3s and shifted down to -1, 0 or +1
+    fudge: bool,
+    keep: Option<Keep>,
+    explode: Option<Explode>,
+    reroll: Option<Reroll>,
+    min: Option<i64>,
+    max: Option<i64>,
+    /// Count dice that pass instead of adding them up, like the `>=8` in `8d10>=8`
+    success: Option<Condition>,
+    /// Dice that pass take away a success, like the `f1` in `8d10>=8f1`
+    failure: Option<Condition>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Number(i64),
+    Dice(DiceTerm),
+    /// Negation, and where the minus sign is
+    Negate(Box<Expr>, usize),
+    Binary {
+        operator: Operator,
+        /// Where the operator is, for division by zero and overflow errors
+        position: usize,
+        left: Box<Expr>,
+        right: Box<Expr>
+    },
+    Labelled(Box<Expr>, String)
+}
+
+/// A single die as it landed
+#[derive(Debug, Clone, Copy)]
+pub struct DieResult {
+    pub sides: u32,
+    pub value: i64,
+    /// Fudge dice show -1, 0 or +1
+    pub fudge: bool,
+    /// Whether the die counts towards the total
+    pub kept: bool,
+    /// When counting successes, 1 for a success, -1 for a failure and 0 for neither
+    pub score: Option<i64>
+}
+
+impl DieResult {
+    pub fn is_max(&self) -> bool {
+        match self.fudge {
+            true => self.value == 1,
+            false => self.value == self.sides as i64,
+        }
+    }
+
+    pub fn is_min(&self) -> bool {
+        match self.fudge {
+            true => self.value == -1,
+            false => self.value == 1,
+        }
+    }
+
+    /// The face as shown on the die, which is a symbol for fudge dice
+    fn face(&self) -> String {
+        match (self.fudge, self.value) {
+            (true, 1) => "+".into(),
+            (true, 0) => "0".into(),
+            (true, _) => "-".into(),
+            (false, value) => value.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum TermResult {
+    Dice {
+        /// The term as written, like `3d6`
+        expression: String,
+        label: Option<String>,
+        dice: Vec<DieResult>,
+        /// Whether `total` is a count of successes rather than a sum
+        successes: bool,
+        total: i64
+    },
+    Modifier(i64)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Critical {
+    Success,
+    Failure
+}
+
+/// Everything that went into a roll, term by term
+#[derive(Debug, Clone)]
+pub struct RollBreakdown {
+    pub expression: String,
+    /// What the whole roll was labelled with, like `attack` in `1d20+5 [attack]`
+    pub label: Option<String>,
+    pub terms: Vec<TermResult>,
+    pub total: i64
+}
+
+impl RollBreakdown {
+    /// Whether any of the dice were fudge dice, so the total belongs on the FATE ladder
+    pub fn is_fate(&self) -> bool {
+        self.terms.iter().any(|term| match term {
+            TermResult::Dice { dice, .. } => dice.iter().any(|die| die.fudge),
+            TermResult::Modifier(_) => false,
+        })
+    }
+
+    /// One line version of the roll, like "`1d20+4` → **17** (1d20: 13)"
+    pub fn summary(&self) -> String {
+        let dice: Vec<String> = self.terms.iter()
+            .filter_map(|term| match term {
+                TermResult::Dice { expression, dice, .. } => Some(format!("{expression}: {}", format_dice(dice))),
+                TermResult::Modifier(_) => None,
+            })
+            .collect();
+        let total = match self.is_fate() {
+            true => fate_ladder(self.total),
+            false => self.total.to_string(),
+        };
+        let critical = match self.critical() {
+            Some(Critical::Success) => " 🎉",
+            Some(Critical::Failure) => " 💀",
+            None => "",
+        };
+        format!("`{}` → **{total}**{critical} ({})", self.expression, dice.join("; "))
+    }
+
+    /// A natural 20 or natural 1 on a kept d20 that was added up rather than counted
+    pub fn critical(&self) -> Option<Critical> {
+        let d20s: Vec<&DieResult> = self.terms.iter()
+            .flat_map(|term| match term {
+                TermResult::Dice { dice, successes: false, .. } => dice.iter().collect(),
+                _ => vec![],
+            })
+            .filter(|die| die.kept && die.sides == 20)
+            .collect();
+        if d20s.iter().any(|die| die.is_max()) {
+            Some(Critical::Success)
+        }
+        else if d20s.iter().any(|die| die.is_min()) {
+            Some(Critical::Failure)
+        }
+        else {
+            None
+        }
+    }
+}
+
+/// Where a total sits on the FATE ladder, like "Great (+4)"
+pub fn fate_ladder(total: i64) -> String {
+    let adjective = match total {
+        9.. => "Beyond Legendary",
+        8 => "Legendary",
+        7 => "Epic",
+        6 => "Fantastic",
+        5 => "Superb",
+        4 => "Great",
+        3 => "Good",
+        2 => "Fair",
+        1 => "Average",
+        0 => "Mediocre",
+        -1 => "Poor",
+        -2 => "Terrible",
+        -3 => "Catastrophic",
+        _ => "Horrifying",
+    };
+    format!("{adjective} ({total:+})")
+}
+
+/// Faces separated by commas, with dropped dice struck through.
+/// Successes are in bold and failures in italics, or the highest and lowest faces are in bold when nothing is being counted
+pub fn format_dice(dice: &[DieResult]) -> String {
+    let faces: Vec<String> = dice.iter()
+        .map(|die| {
+            if !die.kept {
+                format!("~~{}~~", die.face())
+            }
+            else if let Some(score) = die.score {
+                match score {
+                    1.. => format!("**{}**", die.face()),
+                    0 => die.face(),
+                    _ => format!("*{}*", die.face()),
+                }
+            }
+            else if die.is_max() || die.is_min() {
+                format!("**{}**", die.face())
+            }
+            else {
+                die.face()
+            }
+        })
+        .collect();
+    faces.join(", ")
+}
+
+/// A parsed dice expression, which can be rolled any number of times
+#[derive(Debug, Clone)]
+pub struct Expression {
+    source: String,
+    /// A label at the very end, which names the whole roll
+    label: Option<String>,
+    root: Expr
+}
+
+impl Expression {
+    pub fn parse(source: &str) -> Result<Self, DiceError> {
+        let mut parser = Parser { chars: source.chars().collect(), position: 0, depth: 0, trailing_label: None };
+        let root = parser.expression()?;
+        parser.skip_whitespace();
+        if let Some(c) = parser.peek() {
+            return Err(DiceError::new(parser.position, format!("Unexpected '{c}'")));
+        }
+        Ok(Expression { source: source.trim().to_string(), label: parser.trailing_label, root })
+    }
+
+    pub fn roll(&self, rng: &mut impl Rng) -> Result<RollBreakdown, DiceError> {
+        let mut terms = vec![];
+        let total = evaluate(&self.root, Some(1), None, &mut terms, rng)?;
+        Ok(RollBreakdown { expression: self.source.clone(), label: self.label.clone(), terms, total })
+    }
+
+    /// Every total the expression can roll and how likely each is, worked out exactly rather than by rolling
+    pub fn distribution(&self) -> Result<Distribution, DiceError> {
+        distribution(&self.root, &mut Budget::new(MAX_ODDS_WORK))
+    }
+
+    /// The lowest and highest totals the expression could roll, without working out how likely anything is
+    pub fn bounds(&self) -> Result<(i64, i64), DiceError> {
+        bounds(&self.root)
+    }
+}
+
+/// Expressions wrapped in double square brackets, like the `1d20+4` in `I attack [[1d20+4]]`
+pub fn find_inline_rolls(text: &str) -> Vec<&str> {
+    let mut rolls = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find("[[") {
+        let after = &rest[start + 2..];
+        let Some(mut end) = after.find("]]") else {
+            break;
+        };
+        // A label can end right before the closing brackets, like `[[1d20 [attack]]]`
+        while after[end + 2..].starts_with(']') {
+            end += 1;
+        }
+        rolls.push(after[..end].trim());
+        rest = &after[end + 2..];
+    }
+    rolls
+}
+
+/// Parse and roll an expression in one go
+pub fn roll(source: &str, rng: &mut impl Rng) -> Result<RollBreakdown, DiceError> {
+    Expression::parse(source)?.roll(rng)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+    depth: usize,
+    trailing_label: Option<String>
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.position += 1;
+        }
+    }
+
+    /// Consume `text` if it comes next, ignoring case
+    fn eat(&mut self, text: &str) -> bool {
+        let matches = text.chars().enumerate()
+            .all(|(offset, c)| self.chars.get(self.position + offset).is_some_and(|next| next.eq_ignore_ascii_case(&c)));
+        if matches {
+            self.position += text.chars().count();
+        }
+        matches
+    }
+
+    fn number(&mut self) -> Result<i64, DiceError> {
+        let start = self.position;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.position += 1;
+        }
+        if start == self.position {
+            return match self.peek() {
+                Some(c) => Err(DiceError::new(start, format!("Expected a number but found '{c}'"))),
+                None => Err(DiceError::new(start, "Expected a number but the expression ended")),
+            };
+        }
+        let digits: String = self.chars[start..self.position].iter().collect();
+        digits.parse::<i64>().map_err(|_| DiceError::new(start, "Number is too large"))
+    }
+
+    fn expression(&mut self) -> Result<Expr, DiceError> {
+        let mut left = self.product()?;
+        loop {
+            self.skip_whitespace();
+            let position = self.position;
+            let operator = match self.peek() {
+                Some('+') => Operator::Add,
+                Some('-') => Operator::Subtract,
+                _ => return Ok(left),
+            };
+            self.position += 1;
+            let right = self.product()?;
+            left = Expr::Binary { operator, position, left: Box::new(left), right: Box::new(right) };
+        }
+    }
+
+    fn product(&mut self) -> Result<Expr, DiceError> {
+        let mut left = self.unary()?;
+        loop {
+            self.skip_whitespace();
+            let position = self.position;
+            let operator = match self.peek() {
+                Some('*') => Operator::Multiply,
+                Some('/') => Operator::Divide,
+                _ => return Ok(left),
+            };
+            self.position += 1;
+            let right = self.unary()?;
+            left = Expr::Binary { operator, position, left: Box::new(left), right: Box::new(right) };
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, DiceError> {
+        self.skip_whitespace();
+        let position = self.position;
+        if self.eat("-") {
+            return self.nested(|parser| parser.unary()).map(|expr| Expr::Negate(Box::new(expr), position));
+        }
+        if self.eat("+") {
+            return self.nested(|parser| parser.unary());
+        }
+        let atom = self.atom()?;
+        self.label(atom)
+    }
+
+    /// Parse something one level deeper, so deeply nested input can't overflow the stack
+    fn nested(&mut self, parse: impl FnOnce(&mut Self) -> Result<Expr, DiceError>) -> Result<Expr, DiceError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(DiceError::new(self.position, "Expression is nested too deeply"));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// A `[label]` following a term. A label at the end of the whole expression names the roll instead
+    fn label(&mut self, expr: Expr) -> Result<Expr, DiceError> {
+        self.skip_whitespace();
+        let start = self.position;
+        if !self.eat("[") {
+            return Ok(expr);
+        }
+        while self.peek().is_some_and(|c| c != ']') {
+            self.position += 1;
+        }
+        if self.peek().is_none() {
+            return Err(DiceError::new(start, "Label is missing its closing ']'"));
+        }
+        let label = self.chars[start + 1..self.position].iter().collect::<String>().trim().to_string();
+        self.position += 1;
+        let end = self.position;
+        self.skip_whitespace();
+        if self.depth == 0 && self.peek().is_none() {
+            self.trailing_label = Some(label);
+            return Ok(expr);
+        }
+        self.position = end;
+        Ok(Expr::Labelled(Box::new(expr), label))
+    }
+
+    fn atom(&mut self) -> Result<Expr, DiceError> {
+        self.skip_whitespace();
+        let start = self.position;
+        match self.peek() {
+            Some('(') => {
+                self.position += 1;
+                let expr = self.nested(|parser| parser.expression())?;
+                self.skip_whitespace();
+                if !self.eat(")") {
+                    return Err(DiceError::new(start, "Opening '(' is never closed"));
+                }
+                Ok(expr)
+            },
+            Some('d') | Some('D') => self.dice(start, 1),
+            Some(c) if c.is_ascii_digit() => {
+                let number = self.number()?;
+                if matches!(self.peek(), Some('d') | Some('D')) {
+                    self.dice(start, number)
+                }
+                else {
+                    Ok(Expr::Number(number))
+                }
+            },
+            Some(c) => Err(DiceError::new(start, format!("Unexpected '{c}'"))),
+            None => Err(DiceError::new(start, "Expected a number or dice but the expression ended")),
+        }
+    }
+
+    fn dice(&mut self, start: usize, count: i64) -> Result<Expr, DiceError> {
+        if count > MAX_DICE {
+            return Err(DiceError::new(start, format!("You can roll at most {MAX_DICE} dice at once")));
+        }
+        // Skip the d
+        self.position += 1;
+        let sides_position = self.position;
+        let fudge = self.eat("f");
+        let sides = if fudge {
+            3
+        }
+        else if self.eat("%") {
+            100
+        }
+        else {
+            self.number()?
+        };
+        if !(1..=MAX_SIDES).contains(&sides) {
+            return Err(DiceError::new(sides_position, format!("Dice must have between 1 and {MAX_SIDES} sides")));
+        }
+        let mut term = DiceTerm {
+            text: String::new(),
+            position: start,
+            count,
+            sides,
+            fudge,
+            keep: None,
+            explode: None,
+            reroll: None,
+            min: None,
+            max: None,
+            success: None,
+            failure: None
+        };
+        self.dice_modifiers(&mut term)?;
+        term.text = self.chars[start..self.position].iter().collect();
+        Ok(Expr::Dice(term))
+    }
+
+    fn dice_modifiers(&mut self, term: &mut DiceTerm) -> Result<(), DiceError> {
+        loop {
+            let position = self.position;
+            if self.eat("kl") {
+                set_once(&mut term.keep, Keep::Lowest(self.number()?), position)?;
+            }
+            else if self.eat("kh") || self.eat("k") {
+                set_once(&mut term.keep, Keep::Highest(self.number()?), position)?;
+            }
+            else if self.eat("dh") {
+                set_once(&mut term.keep, Keep::DropHighest(self.number()?), position)?;
+            }
+            else if self.eat("dl") || self.eat("d") {
+                set_once(&mut term.keep, Keep::DropLowest(self.number()?), position)?;
+            }
+            else if self.eat("!!") {
+                let condition = self.condition(Condition { compare: Compare::Equal, value: term.sides })?;
+                set_once(&mut term.explode, Explode::Compound(condition), position)?;
+            }
+            else if self.eat("!") {
+                let condition = self.condition(Condition { compare: Compare::Equal, value: term.sides })?;
+                set_once(&mut term.explode, Explode::Explode(condition), position)?;
+            }
+            else if self.eat("ro") {
+                let condition = self.condition(Condition { compare: Compare::Equal, value: 1 })?;
+                set_once(&mut term.reroll, Reroll::Once(condition), position)?;
+            }
+            else if self.eat("r") {
+                let condition = self.condition(Condition { compare: Compare::Equal, value: 1 })?;
+                set_once(&mut term.reroll, Reroll::Always(condition), position)?;
+            }
+            else if self.eat("min") {
+                set_once(&mut term.min, self.number()?, position)?;
+            }
+            else if self.eat("max") {
+                set_once(&mut term.max, self.number()?, position)?;
+            }
+            else if matches!(self.peek(), Some('>') | Some('<') | Some('=')) {
+                let condition = self.condition(Condition { compare: Compare::GreaterEqual, value: term.sides })?;
+                set_once(&mut term.success, condition, position)?;
+            }
+            else if self.eat("f") {
+                let condition = self.condition(Condition { compare: Compare::Equal, value: 1 })?;
+                set_once(&mut term.failure, condition, position)?;
+            }
+            else {
+                break;
+            }
+        }
+
+        let position = self.position;
+        match term.explode {
+            Some(Explode::Explode(condition)) | Some(Explode::Compound(condition)) if condition.matches_every_face(term.sides) => {
+                return Err(DiceError::new(position, "Every face would explode, so the roll would never end"));
+            },
+            _ => {},
+        }
+        if let Some(Reroll::Always(condition)) = term.reroll {
+            if condition.matches_every_face(term.sides) {
+                return Err(DiceError::new(position, "Every face would be rerolled, so the roll would never end"));
+            }
+        }
+        if let (Some(min), Some(max)) = (term.min, term.max) {
+            if min > max {
+                return Err(DiceError::new(position, "The minimum is higher than the maximum"));
+            }
+        }
+        if term.fudge && (term.explode.is_some() || term.reroll.is_some() || term.min.is_some() || term.max.is_some() || term.success.is_some()) {
+            return Err(DiceError::new(position, "Fudge dice can only be kept or dropped"));
+        }
+        if term.failure.is_some() && term.success.is_none() {
+            return Err(DiceError::new(position, "Failures can only be counted alongside a success target, like `8d10>=8f1`"));
+        }
+        Ok(())
+    }
+
+    /// An optional comparison like `<2`, `>=5` or `3`, falling back to `default` if there is none
+    fn condition(&mut self, default: Condition) -> Result<Condition, DiceError> {
+        let compare = if self.eat(">=") {
+            Compare::GreaterEqual
+        }
+        else if self.eat("<=") {
+            Compare::LessEqual
+        }
+        else if self.eat(">") {
+            Compare::Greater
+        }
+        else if self.eat("<") {
+            Compare::Less
+        }
+        else if self.eat("=") || self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            Compare::Equal
+        }
+        else {
+            return Ok(default);
+        };
+        Ok(Condition { compare, value: self.number()? })
+    }
+}
+
+fn set_once<V>(slot: &mut Option<V>, value: V, position: usize) -> Result<(), DiceError> {
+    if slot.is_some() {
+        return Err(DiceError::new(position, "This kind of modifier can only be used once per term"));
+    }
+    *slot = Some(value);
+    Ok(())
+}
+
+/// Evaluate an expression, recording each dice term it rolls.
+/// `sign` is the sign of the expression if it's directly part of the top level sum, so constants there can be listed as modifiers
+fn evaluate(expr: &Expr, sign: Option<i64>, label: Option<&str>, terms: &mut Vec<TermResult>, rng: &mut impl Rng) -> Result<i64, DiceError> {
+    match expr {
+        Expr::Number(number) => {
+            if let Some(sign) = sign {
+                terms.push(TermResult::Modifier(sign * number));
+            }
+            Ok(*number)
+        },
+        Expr::Dice(term) => {
+            let dice = roll_term(term, rng);
+            let successes = term.success.is_some();
+            let total = match successes {
+                true => dice.iter().filter_map(|die| die.score).sum(),
+                false => dice.iter().filter(|die| die.kept).map(|die| die.value).sum(),
+            };
+            terms.push(TermResult::Dice { expression: term.text.clone(), label: label.map(String::from), dice, successes, total });
+            Ok(total)
+        },
+        Expr::Negate(inner, position) => {
+            let value = evaluate(inner, sign.map(|sign| -sign), label, terms, rng)?;
+            value.checked_neg().ok_or_else(|| DiceError::new(*position, "The result is too large"))
+        },
+        Expr::Labelled(inner, label) => evaluate(inner, sign, Some(label), terms, rng),
+        Expr::Binary { operator, position, left, right } => {
+            let (left_sign, right_sign) = match operator {
+                Operator::Add => (sign, sign),
+                Operator::Subtract => (sign, sign.map(|sign| -sign)),
+                Operator::Multiply | Operator::Divide => (None, None),
+            };
+            let left = evaluate(left, left_sign, label, terms, rng)?;
+            let right = evaluate(right, right_sign, label, terms, rng)?;
+            let result = match operator {
+                Operator::Add => left.checked_add(right),
+                Operator::Subtract => left.checked_sub(right),
+                Operator::Multiply => left.checked_mul(right),
+                Operator::Divide if right == 0 => return Err(DiceError::new(*position, "Can't divide by zero")),
+                Operator::Divide => left.checked_div_euclid(right),
+            };
+            result.ok_or_else(|| DiceError::new(*position, "The result is too large"))
+        },
+    }
+}
+
+fn roll_face(sides: i64, reroll: Option<Reroll>, rng: &mut impl Rng, dice: &mut Vec<DieResult>) -> i64 {
+    let mut value = rng.gen_range(1..=sides);
+    let (condition, limit) = match reroll {
+        Some(Reroll::Always(condition)) => (condition, MAX_REROLLS),
+        Some(Reroll::Once(condition)) => (condition, 1),
+        None => return value,
+    };
+    for _ in 0..limit {
+        if !condition.matches(value) {
+            break;
+        }
+        // The die that was rerolled stays visible but doesn't count
+        dice.push(DieResult { sides: sides as u32, value, fudge: false, kept: false, score: None });
+        value = rng.gen_range(1..=sides);
+    }
+    value
+}
+
+fn roll_term(term: &DiceTerm, rng: &mut impl Rng) -> Vec<DieResult> {
+    let clamp = |value: i64| value.max(term.min.unwrap_or(i64::MIN)).min(term.max.unwrap_or(i64::MAX));
+    let mut dice = vec![];
+    let mut rolled = vec![];
+    for _ in 0..term.count {
+        let mut value = roll_face(term.sides, term.reroll, rng, &mut dice);
+        match term.explode {
+            Some(Explode::Explode(condition)) => {
+                rolled.push(clamp(value));
+                for _ in 0..MAX_REROLLS {
+                    if !condition.matches(value) {
+                        break;
+                    }
+                    value = roll_face(term.sides, term.reroll, rng, &mut dice);
+                    rolled.push(clamp(value));
+                }
+            },
+            Some(Explode::Compound(condition)) => {
+                let mut total = value;
+                for _ in 0..MAX_REROLLS {
+                    if !condition.matches(value) {
+                        break;
+                    }
+                    value = roll_face(term.sides, term.reroll, rng, &mut dice);
+                    total += value;
+                }
+                rolled.push(clamp(total));
+            },
+            None if term.fudge => rolled.push(value - 2),
+            None => rolled.push(clamp(value)),
+        }
+    }
+
+    // Work out which of the rolled dice are kept, lowest first
+    let mut order: Vec<usize> = (0..rolled.len()).collect();
+    order.sort_by_key(|index| rolled[*index]);
+    let kept_range = match term.keep {
+        Some(Keep::Highest(n)) => order.len().saturating_sub(n as usize)..order.len(),
+        Some(Keep::Lowest(n)) => 0..order.len().min(n as usize),
+        Some(Keep::DropHighest(n)) => 0..order.len().saturating_sub(n as usize),
+        Some(Keep::DropLowest(n)) => order.len().min(n as usize)..order.len(),
+        None => 0..order.len(),
+    };
+    let mut kept = vec![false; rolled.len()];
+    for index in order[kept_range].iter() {
+        kept[*index] = true;
+    }
+    let score = |value: i64| match (term.success, term.failure) {
+        (Some(success), _) if success.matches(value) => Some(1),
+        (Some(_), Some(failure)) if failure.matches(value) => Some(-1),
+        (Some(_), _) => Some(0),
+        (None, _) => None,
+    };
+    dice.extend(rolled.iter().zip(kept).map(|(value, kept)| DieResult {
+        sides: term.sides as u32,
+        value: *value,
+        fudge: term.fudge,
+        kept,
+        score: if kept { score(*value) } else { None }
+    }));
+    dice
+}
+
+/// Work out the distribution of an expression, combining its parts as if each were rolled independently
+fn distribution(expr: &Expr, budget: &mut Budget) -> Result<Distribution, DiceError> {
+    match expr {
+        Expr::Number(number) => Ok(Distribution::constant(*number)),
+        Expr::Dice(term) => term_distribution(term, budget).map_err(|message| DiceError::new(term.position, message)),
+        Expr::Negate(inner, position) => distribution(inner, budget)?
+            .map(i64::checked_neg)
+            .map_err(|message| DiceError::new(*position, message)),
+        Expr::Labelled(inner, _) => distribution(inner, budget),
+        Expr::Binary { operator, position, left, right } => {
+            let left = distribution(left, budget)?;
+            let right = distribution(right, budget)?;
+            let result = match operator {
+                Operator::Add => left.combine(&right, i64::checked_add, budget),
+                Operator::Subtract => left.combine(&right, i64::checked_sub, budget),
+                Operator::Multiply => left.combine(&right, i64::checked_mul, budget),
+                Operator::Divide if right.outcomes().contains_key(&0) => Err("This could divide by zero".into()),
+                Operator::Divide => left.combine(&right, i64::checked_div_euclid, budget),
+            };
+            result.map_err(|message| DiceError::new(*position, message))
+        },
+    }
+}
+
+/// How likely each face of a single die is, once any rerolls are done
+fn face_distribution(sides: i64, reroll: Option<Reroll>) -> Distribution {
+    let face = 1.0 / sides as f64;
+    let (condition, once) = match reroll {
+        Some(Reroll::Always(condition)) => (condition, false),
+        Some(Reroll::Once(condition)) => (condition, true),
+        None => return Distribution::from_weights((1..=sides).map(|value| (value, face))),
+    };
+    let rerolled = (1..=sides).filter(|value| condition.matches(*value)).count() as f64 * face;
+    Distribution::from_weights((1..=sides).map(|value| {
+        let kept = if condition.matches(value) { 0.0 } else { face };
+        match once {
+            // Either the first roll stands, or it was rerolled into this face
+            true => (value, kept + rerolled * face),
+            // Rerolling until it doesn't pass is the same as only rolling faces that don't
+            false => (value, kept / (1.0 - rerolled)),
+        }
+    }))
+}
+
+/// Roll a die, and again for as long as `condition` passes, adding up `add` of every face.
+/// Chains too unlikely to matter are left out
+fn chain_distribution(faces: &Distribution, condition: Condition, add: impl Fn(i64) -> i64, budget: &mut Budget) -> Result<Distribution, String> {
+    let mut finished = vec![];
+    let mut active = BTreeMap::from([(0i64, 1.0)]);
+    for depth in 0..=MAX_REROLLS {
+        let mut next: BTreeMap<i64, f64> = BTreeMap::new();
+        for (total, probability) in active.iter() {
+            budget.spend(faces.outcomes().len(), "These dice explode into too many possible results to work out the odds")?;
+            for (face, face_probability) in faces.outcomes().iter() {
+                let total = total.checked_add(add(*face)).ok_or("The result could be too large")?;
+                if depth < MAX_REROLLS && condition.matches(*face) {
+                    *next.entry(total).or_insert(0.0) += probability * face_probability;
+                }
+                else {
+                    finished.push((total, probability * face_probability));
+                }
+            }
+        }
+        next.retain(|_, probability| *probability > NEGLIGIBLE_PROBABILITY);
+        if next.is_empty() {
+            break;
+        }
+        active = next;
+    }
+    Ok(Distribution::from_weights(finished))
+}
+
+/// The distribution of adding up `score` of the `keep` dice that come first in `values` out of `count`,
+/// where `values` is every value a die can show in the order they're kept
+fn kept_distribution(values: &[(i64, f64)], count: usize, keep: usize, score: impl Fn(i64) -> i64, budget: &mut Budget) -> Result<Distribution, String> {
+    // Pascal's triangle, for the ways of picking which dice show each value
+    let mut choose = vec![vec![1.0f64]];
+    for n in 1..=count {
+        let previous = &choose[n - 1];
+        let row = (0..=n).map(|k| if k == 0 || k == n { 1.0 } else { previous[k - 1] + previous[k] }).collect();
+        choose.push(row);
+    }
+
+    // Go through the values in the order they're kept, tracking how many dice show one of them so far and the kept total
+    let mut states: Vec<BTreeMap<i64, f64>> = vec![BTreeMap::new(); count + 1];
+    states[0].insert(0, 1.0);
+    for (value, probability) in values.iter() {
+        let mut next: Vec<BTreeMap<i64, f64>> = vec![BTreeMap::new(); count + 1];
+        for (placed, totals) in states.iter().enumerate() {
+            let remaining = count - placed;
+            budget.spend(totals.len() * (remaining + 1), "There are too many dice to work out the odds of keeping some of them")?;
+            for (total, total_probability) in totals.iter() {
+                for showing in 0..=remaining {
+                    let kept = showing.min(keep.saturating_sub(placed)) as i64;
+                    let total = kept.checked_mul(score(*value)).and_then(|score| total.checked_add(score))
+                        .ok_or("The result could be too large")?;
+                    let chance = total_probability * choose[remaining][showing] * probability.powi(showing as i32);
+                    if chance > 0.0 {
+                        *next[placed + showing].entry(total).or_insert(0.0) += chance;
+                    }
+                }
+            }
+        }
+        states = next;
+    }
+    Ok(Distribution::from_weights(states[count].iter().map(|(total, probability)| (*total, *probability))))
+}
+
+fn term_distribution(term: &DiceTerm, budget: &mut Budget) -> Result<Distribution, String> {
+    if term.sides > MAX_ODDS_SIDES {
+        return Err(format!("Dice with more than {MAX_ODDS_SIDES} sides have too many results to work out the odds"));
+    }
+    let clamp = |value: i64| value.max(term.min.unwrap_or(i64::MIN)).min(term.max.unwrap_or(i64::MAX));
+    let score = |value: i64| match (term.success, term.failure) {
+        (Some(success), _) if success.matches(value) => 1,
+        (Some(_), Some(failure)) if failure.matches(value) => -1,
+        (Some(_), _) => 0,
+        (None, _) => value,
+    };
+    let faces = face_distribution(term.sides, term.reroll);
+
+    // What each die shows once it's finished exploding and been clamped
+    let die = match term.explode {
+        Some(Explode::Explode(condition)) => {
+            if term.keep.is_some() {
+                return Err("Can't work out the odds of keeping or dropping dice that explode".into());
+            }
+            // Each explosion is its own die, so score them one at a time
+            let chain = chain_distribution(&faces, condition, |face| score(clamp(face)), budget)?;
+            return chain.repeat_sum(term.count, budget);
+        },
+        Some(Explode::Compound(condition)) => chain_distribution(&faces, condition, |face| face, budget)?.map(|total| Some(clamp(total)))?,
+        None if term.fudge => faces.map(|face| Some(face - 2))?,
+        None => faces.map(|face| Some(clamp(face)))?,
+    };
+
+    let count = term.count as usize;
+    let (keep, highest_first) = match term.keep {
+        None => return die.map(|value| Some(score(value)))?.repeat_sum(term.count, budget),
+        Some(Keep::Highest(n)) => (n as usize, true),
+        Some(Keep::Lowest(n)) => (n as usize, false),
+        Some(Keep::DropHighest(n)) => (count.saturating_sub(n as usize), false),
+        Some(Keep::DropLowest(n)) => (count.saturating_sub(n as usize), true),
+    };
+    let mut values: Vec<(i64, f64)> = die.outcomes().iter().map(|(value, probability)| (*value, *probability)).collect();
+    if highest_first {
+        values.reverse();
+    }
+    kept_distribution(&values, count, keep.min(count), score, budget)
+}
+
+/// The lowest and highest values of an expression, worked out from the lowest and highest of each part
+fn bounds(expr: &Expr) -> Result<(i64, i64), DiceError> {
+    let too_large = |position: usize| DiceError::new(position, "The result could be too large");
+    match expr {
+        Expr::Number(number) => Ok((*number, *number)),
+        Expr::Dice(term) => term_bounds(term).ok_or_else(|| too_large(term.position)),
+        Expr::Negate(inner, position) => {
+            let (low, high) = bounds(inner)?;
+            high.checked_neg().zip(low.checked_neg()).ok_or_else(|| too_large(*position))
+        },
+        Expr::Labelled(inner, _) => bounds(inner),
+        Expr::Binary { operator, position, left, right } => {
+            let (left_low, left_high) = bounds(left)?;
+            let (right_low, right_high) = bounds(right)?;
+            let corners = |op: fn(i64, i64) -> Option<i64>| -> Option<(i64, i64)> {
+                let values = [op(left_low, right_low)?, op(left_low, right_high)?, op(left_high, right_low)?, op(left_high, right_high)?];
+                Some((*values.iter().min()?, *values.iter().max()?))
+            };
+            let result = match operator {
+                Operator::Add => left_low.checked_add(right_low).zip(left_high.checked_add(right_high)),
+                Operator::Subtract => left_low.checked_sub(right_high).zip(left_high.checked_sub(right_low)),
+                Operator::Multiply => corners(i64::checked_mul),
+                Operator::Divide if (right_low..=right_high).contains(&0) => return Err(DiceError::new(*position, "This could divide by zero")),
+                // Euclidean division only ever moves one way as either side grows, so the extremes are at the corners
+                Operator::Divide => corners(i64::checked_div_euclid),
+            };
+            result.ok_or_else(|| too_large(*position))
+        },
+    }
+}
+
+/// The lowest and highest totals of one dice term, or `None` if they don't fit
+fn term_bounds(term: &DiceTerm) -> Option<(i64, i64)> {
+    let clamp = |value: i64| value.max(term.min.unwrap_or(i64::MIN)).min(term.max.unwrap_or(i64::MAX));
+    // Faces that are always rerolled can't come up, barring running out of rerolls
+    let (face_low, face_high) = match term.reroll {
+        Some(Reroll::Always(condition)) => (
+            (1..=term.sides).find(|face| !condition.matches(*face)).unwrap_or(1),
+            (1..=term.sides).rev().find(|face| !condition.matches(*face)).unwrap_or(term.sides),
+        ),
+        _ => (1, term.sides),
+    };
+    let chain = MAX_REROLLS as i64 + 1;
+
+    // What each counted die can show, and how many dice there can be before keeping or dropping any
+    let (die_low, die_high, dice_low, dice_high) = match term.explode {
+        Some(Explode::Explode(_)) => (clamp(face_low), clamp(face_high), term.count, term.count.checked_mul(chain)?),
+        Some(Explode::Compound(_)) => (clamp(face_low), clamp(face_high.checked_mul(chain)?), term.count, term.count),
+        None if term.fudge => (-1, 1, term.count, term.count),
+        None => (clamp(face_low), clamp(face_high), term.count, term.count),
+    };
+    let (score_low, score_high) = match (term.success, term.failure) {
+        (Some(_), Some(_)) => (-1, 1),
+        (Some(_), None) => (0, 1),
+        (None, _) => (die_low, die_high),
+    };
+    let kept = |dice: i64| match term.keep {
+        Some(Keep::Highest(n)) | Some(Keep::Lowest(n)) => dice.min(n),
+        Some(Keep::DropHighest(n)) | Some(Keep::DropLowest(n)) => dice.saturating_sub(n).max(0),
+        None => dice,
+    };
+    let (kept_low, kept_high) = (kept(dice_low), kept(dice_high));
+    let low = score_low.checked_mul(kept_low)?.min(score_low.checked_mul(kept_high)?);
+    let high = score_high.checked_mul(kept_low)?.max(score_high.checked_mul(kept_high)?);
+    Some((low, high))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Seeds each expression is rolled with, enough for rare faces like a natural 20 to come up
+    const SEEDS: u64 = 500;
+
+    fn rolls(source: &str) -> Vec<RollBreakdown> {
+        let expression = Expression::parse(source).unwrap_or_else(|e| panic!("{source} didn't parse: {e}"));
+        (0..SEEDS).map(|seed| expression.roll(&mut StdRng::seed_from_u64(seed)).unwrap()).collect()
+    }
+
+    /// The dice of the only dice term in a roll
+    fn dice(roll: &RollBreakdown) -> &[DieResult] {
+        let mut terms = roll.terms.iter().filter_map(|term| match term {
+            TermResult::Dice { dice, .. } => Some(dice.as_slice()),
+            TermResult::Modifier(_) => None,
+        });
+        let dice = terms.next().expect("The roll has a dice term");
+        assert!(terms.next().is_none(), "The roll has more than one dice term");
+        dice
+    }
+
+    fn kept(dice: &[DieResult]) -> Vec<i64> {
+        dice.iter().filter(|die| die.kept).map(|die| die.value).collect()
+    }
+
+    #[test]
+    fn totals_stay_in_range() {
+        let cases: &[(&str, i64, i64)] = &[
+            ("1d20", 1, 20),
+            ("d%", 1, 100),
+            ("3d6+2", 5, 20),
+            ("4d6kh3", 3, 18),
+            ("4d6dl1", 3, 18),
+            ("2d20kl1", 1, 20),
+            ("4dF", -4, 4),
+            ("1d6r<2", 2, 6),
+            ("4d6min3", 12, 24),
+            ("4d6max4", 4, 16),
+            ("8d10>=8", 0, 8),
+            ("8d10>=8f1", -8, 8),
+            ("d6!", 1, 6 * (MAX_REROLLS as i64 + 1)),
+            ("d10!!", 1, 10 * (MAX_REROLLS as i64 + 1)),
+            ("-1d4", -4, -1),
+            ("(1d4+1)*2", 4, 10),
+        ];
+        for (source, low, high) in cases {
+            let bounds = Expression::parse(source).unwrap().bounds().unwrap();
+            assert_eq!(bounds, (*low, *high), "bounds of {source}");
+            for roll in rolls(source) {
+                assert!((*low..=*high).contains(&roll.total), "{source} rolled {}", roll.total);
+            }
+        }
+    }
+
+    #[test]
+    fn keeping_and_dropping() {
+        let cases: &[(&str, usize, usize, bool)] = &[
+            // Expression, dice rolled, dice kept, whether the highest are kept
+            ("4d6kh3", 4, 3, true),
+            ("4d6k3", 4, 3, true),
+            ("4d6dl1", 4, 3, true),
+            ("4d6d1", 4, 3, true),
+            ("2d20kl1", 2, 1, false),
+            ("3d8dh1", 3, 2, false),
+        ];
+        for (source, count, keep, highest) in cases {
+            for roll in rolls(source) {
+                let dice = dice(&roll);
+                assert_eq!(dice.len(), *count, "{source}");
+                let mut values: Vec<i64> = dice.iter().map(|die| die.value).collect();
+                values.sort();
+                let expected = match highest {
+                    true => &values[count - keep..],
+                    false => &values[..*keep],
+                };
+                let mut kept = kept(dice);
+                kept.sort();
+                assert_eq!(kept, expected, "{source}");
+                assert_eq!(roll.total, kept.iter().sum::<i64>(), "{source}");
+            }
+        }
+    }
+
+    #[test]
+    fn exploding() {
+        let mut exploded = false;
+        for roll in rolls("d6!") {
+            let dice = dice(&roll);
+            // Every 6 rolls another die, and only the last die can be under 6
+            for (index, die) in dice.iter().enumerate() {
+                assert_eq!(die.value == 6, index + 1 < dice.len());
+            }
+            exploded |= dice.len() > 1;
+            assert_eq!(roll.total, kept(dice).iter().sum::<i64>());
+        }
+        assert!(exploded);
+
+        let mut compounded = false;
+        for roll in rolls("d10!!") {
+            // Compounding adds to the same die rather than rolling new ones
+            assert_eq!(dice(&roll).len(), 1);
+            assert_ne!(roll.total % 10, 0);
+            compounded |= roll.total > 10;
+        }
+        assert!(compounded);
+
+        let rolls = rolls("d6!>=5");
+        assert!(rolls.iter().any(|roll| dice(roll).first().is_some_and(|die| die.value == 5) && dice(roll).len() > 1));
+    }
+
+    #[test]
+    fn rerolling() {
+        for roll in rolls("1d6r<2") {
+            let dice = dice(&roll);
+            assert!(dice.iter().all(|die| die.kept != (die.value < 2)));
+        }
+        let mut rerolled = false;
+        for roll in rolls("2d6ro1") {
+            let dice = dice(&roll);
+            // Each die can only be rerolled once, so at most two dice are thrown away
+            assert!(dice.len() <= 4);
+            assert_eq!(kept(dice).len(), 2);
+            assert!(dice.iter().filter(|die| !die.kept).all(|die| die.value == 1));
+            rerolled |= dice.len() > 2;
+        }
+        assert!(rerolled);
+        assert!(rolls("1d6ro1").iter().any(|roll| roll.total == 1));
+    }
+
+    #[test]
+    fn min_and_max() {
+        for roll in rolls("4d6min3max5") {
+            assert!(kept(dice(&roll)).iter().all(|value| (3..=5).contains(value)));
+        }
+    }
+
+    #[test]
+    fn counting_successes() {
+        for roll in rolls("8d10>=8f1") {
+            let dice = dice(&roll);
+            let successes = dice.iter().filter(|die| die.value >= 8).count() as i64;
+            let failures = dice.iter().filter(|die| die.value == 1).count() as i64;
+            assert_eq!(roll.total, successes - failures);
+        }
+    }
+
+    #[test]
+    fn precedence_and_parentheses() {
+        let cases: &[(&str, i64)] = &[
+            ("2+3*4", 14),
+            ("10-2-3", 5),
+            ("2*3+1d1", 7),
+            ("(2+3)*4", 20),
+            ("2*(1d1+1)", 4),
+            ("-2*3", -6),
+            ("7/2", 3),
+            ("-7/2", -4),
+            ("--3", 3),
+            ("((((1))))", 1),
+            (" 3d1 + 2 ", 5),
+        ];
+        let mut rng = StdRng::seed_from_u64(0);
+        for (source, total) in cases {
+            assert_eq!(roll(source, &mut rng).unwrap().total, *total, "{source}");
+        }
+    }
+
+    #[test]
+    fn labels() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let labels = |roll: &RollBreakdown| -> Vec<Option<String>> {
+            roll.terms.iter()
+                .filter_map(|term| match term {
+                    TermResult::Dice { label, .. } => Some(label.clone()),
+                    TermResult::Modifier(_) => None,
+                })
+                .collect()
+        };
+
+        // A label at the very end names the whole roll
+        let trailing = roll("1d20+5 [attack]", &mut rng).unwrap();
+        assert_eq!(trailing.label.as_deref(), Some("attack"));
+        assert_eq!(labels(&trailing), vec![None]);
+
+        let inline = roll("1d8 [slashing] + 2d6 [fire] + 3", &mut rng).unwrap();
+        assert_eq!(inline.label, None);
+        assert_eq!(labels(&inline), vec![Some("slashing".to_string()), Some("fire".to_string())]);
+
+        // Even after other labels, the last one is the roll's
+        let last = roll("1d8 [slashing] + 2d6 [fire]", &mut rng).unwrap();
+        assert_eq!(last.label.as_deref(), Some("fire"));
+        assert_eq!(labels(&last), vec![Some("slashing".to_string()), None]);
+
+        let both = roll("(1d8 [slashing] + 1d6 [fire]) [damage]", &mut rng).unwrap();
+        assert_eq!(both.label.as_deref(), Some("damage"));
+        assert_eq!(labels(&both), vec![Some("slashing".to_string()), Some("fire".to_string())]);
+    }
+
+    #[test]
+    fn error_positions() {
+        let cases: &[(&str, usize, &str)] = &[
+            ("1d", 2, "Expected a number but the expression ended"),
+            ("2+", 2, "Expected a number or dice but the expression ended"),
+            ("3 x", 2, "Unexpected 'x'"),
+            ("(1+2", 0, "Opening '(' is never closed"),
+            ("1d0", 2, "Dice must have between 1 and 1000000 sides"),
+            ("1001d6", 0, "You can roll at most 1000 dice at once"),
+            ("1d6kh1kl1", 6, "This kind of modifier can only be used once per term"),
+            ("1d6!<7", 6, "Every face would explode, so the roll would never end"),
+            ("1d6r<7", 6, "Every face would be rerolled, so the roll would never end"),
+            ("4d6min5max2", 11, "The minimum is higher than the maximum"),
+            ("4dF!", 4, "Fudge dice can only be kept or dropped"),
+            ("8d10f1", 6, "Failures can only be counted alongside a success target, like `8d10>=8f1`"),
+            ("1d6 [fire", 4, "Label is missing its closing ']'"),
+            ("99999999999999999999", 0, "Number is too large"),
+        ];
+        for (source, position, message) in cases {
+            let error = Expression::parse(source).unwrap_err();
+            assert_eq!((error.position, error.message.as_str()), (*position, *message), "{source}");
+        }
+
+        let error = roll("1 + 5/0", &mut StdRng::seed_from_u64(0)).unwrap_err();
+        assert_eq!((error.position, error.message.as_str()), (5, "Can't divide by zero"));
+        assert!(Expression::parse(&"(".repeat(MAX_DEPTH + 1)).is_err());
+    }
+
+    #[test]
+    fn carets() {
+        let error = Expression::parse("2d6 + x").unwrap_err();
+        assert_eq!(error.pointer("2d6 + x"), "2d6 + x\n      ^");
+        assert_eq!(error.to_string(), "Unexpected 'x' (at character 7)");
+
+        let error = Expression::parse("1d").unwrap_err();
+        assert_eq!(error.pointer("1d"), "1d\n  ^");
+    }
+}
@@ -0,0 +1,178 @@
+use std::{fmt::Display, hash::Hash, str::FromStr};
+use anyhow::{Context, Result};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::roulette::{RouletteState, SavedBalance};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoinSide {
+    Heads,
+    Tails
+}
+
+impl CoinSide {
+    pub fn flip(rng: &mut impl Rng) -> Self {
+        if rng.gen_bool(0.5) { CoinSide::Heads } else { CoinSide::Tails }
+    }
+}
+
+impl FromStr for CoinSide {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "heads" | "h" => Ok(CoinSide::Heads),
+            "tails" | "t" => Ok(CoinSide::Tails),
+            _ => anyhow::bail!("Call 'heads' or 'tails', not '{s}'"),
+        }
+    }
+}
+
+impl Display for CoinSide {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CoinSide::Heads => write!(f, "Heads"),
+            CoinSide::Tails => write!(f, "Tails"),
+        }
+    }
+}
+
+/// Flip a coin against the house, paying double the stake on a correct call.
+/// Returns the side that landed and the payout
+pub fn coin_flip<T>(player_id: T, call: CoinSide, amount: u128, bank: &mut RouletteState<T>, rng: &mut impl Rng) -> Result<(CoinSide, u128)>
+where T: Display + Eq + Hash + Clone + Ord {
+    if amount == 0 {
+        anyhow::bail!("You cannot place a bet with a value of zero!");
+    }
+    bank.withdraw(player_id.clone(), amount)?;
+    let landed = CoinSide::flip(rng);
+    let payout = if landed == call { amount * 2 } else { 0 };
+    if payout > 0 {
+        bank.deposit(player_id, payout)?;
+    }
+    Ok((landed, payout))
+}
+
+/// A challenge whose stake is held in escrow until it is accepted, declined or expires
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingDuel<T> {
+    pub challenger: T,
+    pub challenger_name: String,
+    pub challenged: T,
+    pub amount: u128,
+    /// Unix timestamp after which the challenge lapses
+    pub expires_at: u64
+}
+
+#[derive(Debug, Clone)]
+pub struct DuelResult<T> {
+    pub duel: PendingDuel<T>,
+    pub landed: CoinSide,
+    pub winner: T,
+    pub payout: u128,
+    pub rake: u128
+}
+
+pub struct DuelLedger<T> {
+    duels: Vec<PendingDuel<T>>,
+    /// Cut of the pot the house keeps, in basis points
+    rake_bps: u32
+}
+
+impl<T> DuelLedger<T> where T: Display + Eq + Hash + Clone + Ord {
+    pub fn new() -> Self {
+        DuelLedger { duels: vec![], rake_bps: 0 }
+    }
+
+    pub fn pending(&self) -> &[PendingDuel<T>] {
+        &self.duels
+    }
+
+    pub fn set_rake(&mut self, rake_bps: u32) -> Result<()> {
+        if rake_bps > 5_000 {
+            anyhow::bail!("The rake can't be more than 50%");
+        }
+        self.rake_bps = rake_bps;
+        Ok(())
+    }
+
+    /// Challenge another player, moving the challenger's stake into escrow
+    pub fn challenge(&mut self, challenger: T, challenger_name: &str, challenged: T, amount: u128, expires_at: u64, bank: &mut RouletteState<T>) -> Result<()> {
+        if challenger == challenged {
+            anyhow::bail!("You can't duel yourself");
+        }
+        if amount == 0 {
+            anyhow::bail!("You cannot duel for nothing!");
+        }
+        if self.duels.iter().any(|duel| duel.challenger == challenger || duel.challenged == challenged) {
+            anyhow::bail!("Either you already have a challenge out, or they already have one to answer");
+        }
+        bank.withdraw(challenger.clone(), amount)?;
+        println!("Player {challenger_name} (id={challenger}) challenged {challenged} to a duel for {amount}");
+        self.duels.push(PendingDuel { challenger, challenger_name: challenger_name.into(), challenged, amount, expires_at });
+        Ok(())
+    }
+
+    fn take_duel(&mut self, matches: impl Fn(&PendingDuel<T>) -> bool) -> Option<PendingDuel<T>> {
+        let index = self.duels.iter().position(matches)?;
+        Some(self.duels.remove(index))
+    }
+
+    /// Accept the challenge waiting for `challenged`, matching the stake and flipping for the pot
+    pub fn accept(&mut self, challenged: T, bank: &mut RouletteState<T>, rng: &mut impl Rng) -> Result<DuelResult<T>> {
+        let index = self.duels.iter().position(|duel| duel.challenged == challenged)
+            .context("Nobody has challenged you to a duel")?;
+        bank.withdraw(challenged.clone(), self.duels[index].amount)?;
+        let duel = self.duels.remove(index);
+
+        let landed = CoinSide::flip(rng);
+        // The challenger is always heads
+        let winner = match landed {
+            CoinSide::Heads => duel.challenger.clone(),
+            CoinSide::Tails => duel.challenged.clone(),
+        };
+        let pot = duel.amount * 2;
+        let rake = pot * self.rake_bps as u128 / 10_000;
+        let payout = pot - rake;
+        bank.deposit(winner.clone(), payout)?;
+        Ok(DuelResult { duel, landed, winner, payout, rake })
+    }
+
+    /// Turn down a challenge, returning the challenger's stake
+    pub fn decline(&mut self, challenged: T, bank: &mut RouletteState<T>) -> Result<PendingDuel<T>> {
+        let duel = self.take_duel(|duel| duel.challenged == challenged).context("Nobody has challenged you to a duel")?;
+        bank.deposit(duel.challenger.clone(), duel.amount)?;
+        Ok(duel)
+    }
+
+    /// Withdraw your own challenge and get the stake back
+    pub fn cancel(&mut self, challenger: T, bank: &mut RouletteState<T>) -> Result<PendingDuel<T>> {
+        let duel = self.take_duel(|duel| duel.challenger == challenger).context("You don't have a challenge out")?;
+        bank.deposit(duel.challenger.clone(), duel.amount)?;
+        Ok(duel)
+    }
+
+    /// Refund every challenge that has expired by `now`
+    pub fn expire(&mut self, now: u64, bank: &mut RouletteState<T>) -> Vec<PendingDuel<T>> {
+        let (expired, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.duels).into_iter().partition(|duel| duel.expires_at <= now);
+        self.duels = pending;
+        for duel in expired.iter() {
+            if let Err(e) = bank.deposit(duel.challenger.clone(), duel.amount) {
+                println!("Failed to refund expired duel to {}: {e}", duel.challenger);
+            }
+        }
+        expired
+    }
+}
+
+/// Count the stakes held for open challenges in the challengers' saved balances, so that they're given back
+/// if the bot restarts before the challenges are settled
+pub fn save_escrow<T: PartialEq>(duels: &[PendingDuel<T>], saved: &mut [SavedBalance<T>]) {
+    for duel in duels {
+        match saved.iter_mut().find(|saved| saved.player_id == duel.challenger) {
+            Some(saved) => saved.balance += duel.amount,
+            None => println!("Not saving {}'s {} stake, who has no balance", duel.challenger_name, duel.amount),
+        }
+    }
+}
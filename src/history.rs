@@ -0,0 +1,244 @@
+use std::{collections::HashMap, hash::Hash};
+use serde::{Deserialize, Serialize};
+
+use crate::dice::{self, DieResult, RollBreakdown};
+use crate::pools::PoolResult;
+
+/// Most rolls kept for one session, after which the oldest are forgotten
+const MAX_SESSION_ROLLS: usize = 2_000;
+
+/// A roll that's been made but not yet recorded against anyone
+#[derive(Debug, Clone)]
+pub struct Rolled {
+    pub expression: String,
+    pub dice: Vec<DieResult>,
+    pub result: String,
+    /// Whether the dice show numbers from 1 up to their sides, so they say how lucky the roll was
+    pub numeric: bool
+}
+
+impl Rolled {
+    pub fn from_breakdown(roll: &RollBreakdown) -> Self {
+        let dice = roll.terms.iter()
+            .flat_map(|term| match term {
+                dice::TermResult::Dice { dice, .. } => dice.clone(),
+                dice::TermResult::Modifier(_) => vec![],
+            })
+            .collect();
+        let result = match roll.is_fate() {
+            true => dice::fate_ladder(roll.total),
+            false => roll.total.to_string(),
+        };
+        Rolled { expression: roll.expression.clone(), dice, result, numeric: true }
+    }
+
+    pub fn from_pool(expression: &str, roll: &PoolResult) -> Self {
+        Rolled { expression: expression.into(), dice: roll.dice.clone(), result: roll.outcome.clone(), numeric: roll.numeric }
+    }
+}
+
+/// A single die as it was recorded
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RecordedDie {
+    pub sides: u32,
+    pub value: i64,
+    pub fudge: bool,
+    /// Whether it counted, rather than being dropped or rerolled
+    pub kept: bool,
+    /// Whether it shows a number from 1 up to its sides, so it counts towards luck.
+    /// Percentile digits and narrative symbols don't
+    pub numeric: bool
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RollRecord<T> {
+    pub player: T,
+    pub player_name: String,
+    pub expression: String,
+    pub dice: Vec<RecordedDie>,
+    pub result: String,
+    /// Unix timestamp of the roll
+    pub timestamp: u64
+}
+
+impl<T> RollRecord<T> {
+    pub fn new(player: T, player_name: &str, rolled: Rolled, timestamp: u64) -> Self {
+        let dice = rolled.dice.iter()
+            .map(|die| RecordedDie { sides: die.sides, value: die.value, fudge: die.fudge, kept: die.kept, numeric: rolled.numeric })
+            .collect();
+        RollRecord { player, player_name: player_name.into(), expression: rolled.expression, dice, result: rolled.result, timestamp }
+    }
+
+    /// The dice as a list, with dropped dice struck out
+    pub fn describe_dice(&self) -> String {
+        let faces: Vec<String> = self.dice.iter()
+            .map(|die| {
+                let face = match (die.fudge, die.value) {
+                    (true, 1) => "+".to_string(),
+                    (true, -1) => "-".to_string(),
+                    (true, _) => "0".to_string(),
+                    (false, value) => value.to_string(),
+                };
+                if die.kept { face } else { format!("~~{face}~~") }
+            })
+            .collect();
+        faces.join(", ")
+    }
+}
+
+/// How lucky one player has been this session
+#[derive(Debug, Clone)]
+pub struct Luck<T> {
+    pub player: T,
+    pub player_name: String,
+    pub rolls: usize,
+    pub dice: usize,
+    /// How high their dice landed on average, from 0 for always the lowest face to 100 for always the highest.
+    /// 50 is average
+    pub average: f64,
+    pub d20s: usize,
+    pub natural_20s: usize,
+    pub natural_1s: usize
+}
+
+/// Every roll made in a channel since its session started
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session<T> {
+    /// Unix timestamp of when the session started
+    pub started_at: u64,
+    rolls: Vec<RollRecord<T>>
+}
+
+impl<T> Session<T> where T: Eq + Hash + Clone {
+    pub fn new(now: u64) -> Self {
+        Session { started_at: now, rolls: vec![] }
+    }
+
+    pub fn rolls(&self) -> &[RollRecord<T>] {
+        &self.rolls
+    }
+
+    pub fn record(&mut self, record: RollRecord<T>) {
+        self.rolls.push(record);
+        if self.rolls.len() > MAX_SESSION_ROLLS {
+            self.rolls.remove(0);
+        }
+    }
+
+    /// The most recent `count` rolls, oldest first, optionally only by one player
+    pub fn last(&self, count: usize, player: Option<&T>) -> Vec<&RollRecord<T>> {
+        let mut rolls: Vec<&RollRecord<T>> = self.rolls.iter().rev()
+            .filter(|record| player.is_none_or(|player| record.player == *player))
+            .take(count)
+            .collect();
+        rolls.reverse();
+        rolls
+    }
+
+    /// Luck for everyone who's rolled, luckiest first. Fudge dice, d1s and dice that aren't numbered don't count towards the average
+    pub fn luck(&self) -> Vec<Luck<T>> {
+        let mut players: Vec<Luck<T>> = vec![];
+        let mut totals: HashMap<T, f64> = HashMap::new();
+        for record in self.rolls.iter() {
+            let index = match players.iter().position(|luck| luck.player == record.player) {
+                Some(index) => index,
+                None => {
+                    players.push(Luck {
+                        player: record.player.clone(),
+                        player_name: record.player_name.clone(),
+                        rolls: 0,
+                        dice: 0,
+                        average: 50.0,
+                        d20s: 0,
+                        natural_20s: 0,
+                        natural_1s: 0
+                    });
+                    players.len() - 1
+                },
+            };
+            let luck = &mut players[index];
+            luck.rolls += 1;
+            for die in record.dice.iter().filter(|die| die.numeric && !die.fudge && die.sides > 1) {
+                luck.dice += 1;
+                *totals.entry(record.player.clone()).or_insert(0.0) += (die.value - 1) as f64 / (die.sides - 1) as f64;
+                if die.sides == 20 {
+                    luck.d20s += 1;
+                    match die.value {
+                        20 => luck.natural_20s += 1,
+                        1 => luck.natural_1s += 1,
+                        _ => {},
+                    }
+                }
+            }
+        }
+        for luck in players.iter_mut().filter(|luck| luck.dice > 0) {
+            luck.average = totals[&luck.player] / luck.dice as f64 * 100.0;
+        }
+        players.sort_by(|a, b| b.average.total_cmp(&a.average));
+        players
+    }
+
+    /// The session as a Markdown table, for pasting into a wiki
+    pub fn to_markdown(&self, title: &str) -> String {
+        let mut markdown = format!(
+            "# {title}\n\nStarted {}, {} roll{}\n\n| Time | Player | Roll | Dice | Result |\n|---|---|---|---|---|\n",
+            format_timestamp(self.started_at),
+            self.rolls.len(),
+            if self.rolls.len() == 1 { "" } else { "s" }
+        );
+        let escape = |text: &str| text.replace('|', "\\|");
+        for record in self.rolls.iter() {
+            markdown += &format!(
+                "| {} | {} | `{}` | {} | **{}** |\n",
+                format_timestamp(record.timestamp),
+                escape(&record.player_name),
+                escape(&record.expression).replace('`', "'"),
+                escape(&record.describe_dice()),
+                escape(&record.result)
+            );
+        }
+        markdown
+    }
+}
+
+/// A Unix timestamp as a UTC date and time, like `2024-05-04 19:30:00 UTC`
+pub fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86_400) as i64;
+    let seconds = timestamp % 86_400;
+    // Days since 1970 to a civil date, from Howard Hinnant's date algorithms
+    let shifted = days + 719_468;
+    let era = shifted.div_euclid(146_097);
+    let day_of_era = shifted.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC", seconds / 3_600, seconds / 60 % 60, seconds % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rolled(sides: u32, values: &[i64], numeric: bool) -> Rolled {
+        let dice = values.iter()
+            .map(|value| DieResult { sides, value: *value, fudge: false, kept: true, score: None })
+            .collect();
+        Rolled { expression: "test".into(), dice, result: "test".into(), numeric }
+    }
+
+    #[test]
+    fn luck_only_counts_numbered_dice() {
+        let mut session = Session::new(0);
+        session.record(RollRecord::new(1, "player", rolled(20, &[20, 1], true), 0));
+        // Percentile digits from 0 to 9 and narrative face indexes don't say anything about luck
+        session.record(RollRecord::new(1, "player", rolled(10, &[0, 0], false), 0));
+        session.record(RollRecord::new(1, "player", rolled(8, &[1, 1, 1], false), 0));
+        let luck = session.luck();
+        assert_eq!(luck.len(), 1);
+        assert_eq!((luck[0].rolls, luck[0].dice, luck[0].d20s), (3, 2, 2));
+        assert_eq!(luck[0].average, 50.0);
+    }
+}
@@ -0,0 +1,523 @@
+use anyhow::{Context, Result};
+use rand::Rng;
+
+/// Most combatants one encounter can have
+const MAX_COMBATANTS: usize = 50;
+
+/// Most NPCs that can be added at once, like `!init add Goblin +2 6`
+pub const MAX_NPCS_AT_ONCE: u32 = 20;
+
+/// Something affecting a combatant, like being poisoned
+#[derive(Debug, Clone)]
+pub struct Condition {
+    pub name: String,
+    /// When it wears off, as the id of the combatant whose turn it ends before and the round of that turn.
+    /// `None` lasts until it's removed
+    pub ends: Option<(u32, u32)>
+}
+
+#[derive(Debug, Clone)]
+pub struct Combatant<T> {
+    /// Stays the same as the turn order changes
+    pub id: u32,
+    pub name: String,
+    /// The player controlling them, or `None` for the GM's NPCs
+    pub player: Option<T>,
+    pub modifier: i64,
+    /// The d20 rolled, or `None` if the GM set their initiative directly
+    pub roll: Option<i64>,
+    pub initiative: i64,
+    /// Rolled when they join, to break ties that the modifier doesn't
+    tiebreak: u32,
+    /// Waiting to come back in with `!rejoin`. Their turns are skipped until then
+    pub delaying: bool,
+    /// What they're waiting to do, used with `!trigger` before their next turn
+    pub readied: Option<String>,
+    pub conditions: Vec<Condition>
+}
+
+impl<T> Combatant<T> {
+    fn order_key(&self) -> (i64, i64, u32) {
+        (self.initiative, self.modifier, self.tiebreak)
+    }
+}
+
+/// A condition that wore off when the turn moved on
+#[derive(Debug, Clone)]
+pub struct Expiry {
+    pub combatant: String,
+    pub condition: String
+}
+
+/// What happened when the turn moved on
+#[derive(Debug, Clone, Default)]
+pub struct TurnChange {
+    pub new_round: bool,
+    pub expired: Vec<Expiry>,
+    /// Who was skipped because they're delaying
+    pub skipped: Vec<String>,
+    /// A readied action that lapsed because its turn came round again
+    pub lapsed: Option<String>
+}
+
+/// A fight in one channel, run by whoever started it
+#[derive(Debug, Clone)]
+pub struct Encounter<T> {
+    pub gm: Option<T>,
+    /// The turn order, which is only sorted when someone joins
+    combatants: Vec<Combatant<T>>,
+    turn: usize,
+    /// 0 until the first turn
+    pub round: u32,
+    next_id: u32
+}
+
+impl<T> Encounter<T> where T: Eq + Clone {
+    pub fn new() -> Self {
+        Encounter { gm: None, combatants: vec![], turn: 0, round: 0, next_id: 0 }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.gm.is_some()
+    }
+
+    pub fn has_started(&self) -> bool {
+        self.round > 0
+    }
+
+    pub fn combatants(&self) -> &[Combatant<T>] {
+        &self.combatants
+    }
+
+    /// Whose turn it is, once the first round has started
+    pub fn current(&self) -> Option<&Combatant<T>> {
+        if self.has_started() { self.combatants.get(self.turn) } else { None }
+    }
+
+    /// Who goes after the current combatant, not counting anyone delaying
+    pub fn up_next(&self) -> Option<&Combatant<T>> {
+        let count = self.combatants.len();
+        (1..=count)
+            .map(|offset| &self.combatants[(self.turn + offset) % count])
+            .find(|combatant| !combatant.delaying)
+    }
+
+    pub fn start(&mut self, gm: T) -> Result<()> {
+        if self.is_running() {
+            anyhow::bail!("There's already an encounter running in this channel. End it with `!init end` first");
+        }
+        *self = Encounter::new();
+        self.gm = Some(gm);
+        Ok(())
+    }
+
+    pub fn end(&mut self) -> Result<()> {
+        if !self.is_running() {
+            anyhow::bail!("There's no encounter running in this channel");
+        }
+        *self = Encounter::new();
+        Ok(())
+    }
+
+    /// Find a combatant by name, or the start of one
+    pub fn find(&self, name: &str) -> Result<usize> {
+        let name = name.to_lowercase();
+        if let Some(index) = self.combatants.iter().position(|combatant| combatant.name.to_lowercase() == name) {
+            return Ok(index);
+        }
+        let matches: Vec<usize> = (0..self.combatants.len())
+            .filter(|index| self.combatants[*index].name.to_lowercase().starts_with(&name))
+            .collect();
+        match matches.as_slice() {
+            [index] => Ok(*index),
+            [] => anyhow::bail!("There's nobody called '{name}' in this encounter"),
+            _ => {
+                let names: Vec<&str> = matches.iter().map(|index| self.combatants[*index].name.as_str()).collect();
+                anyhow::bail!("'{name}' could be any of {}", names.join(", "))
+            },
+        }
+    }
+
+    pub fn find_player(&self, player: &T) -> Result<usize> {
+        self.combatants.iter().position(|combatant| combatant.player.as_ref() == Some(player))
+            .context("You're not in this encounter. Join with `!init join <modifier>`")
+    }
+
+    /// Roll initiative for a combatant and add them in order.
+    /// Anyone joining after their place in the round has passed acts from the next round
+    fn add(&mut self, name: String, player: Option<T>, modifier: i64, rng: &mut impl Rng) -> Result<&Combatant<T>> {
+        if !self.is_running() {
+            anyhow::bail!("There's no encounter running in this channel. Start one with `!init start`");
+        }
+        if self.combatants.len() >= MAX_COMBATANTS {
+            anyhow::bail!("An encounter can only have {MAX_COMBATANTS} combatants");
+        }
+        if self.combatants.iter().any(|combatant| combatant.name.eq_ignore_ascii_case(&name)) {
+            anyhow::bail!("There's already someone called '{name}' in this encounter");
+        }
+        let roll: i64 = rng.gen_range(1..=20);
+        let initiative = roll.checked_add(modifier).context("That modifier is too large")?;
+        let combatant = Combatant {
+            id: self.next_id,
+            name,
+            player,
+            modifier,
+            roll: Some(roll),
+            initiative,
+            tiebreak: rng.gen(),
+            delaying: false,
+            readied: None,
+            conditions: vec![]
+        };
+        self.next_id += 1;
+        let index = self.combatants.iter().position(|other| other.order_key() < combatant.order_key()).unwrap_or(self.combatants.len());
+        if self.has_started() && index <= self.turn {
+            self.turn += 1;
+        }
+        self.combatants.insert(index, combatant);
+        Ok(&self.combatants[index])
+    }
+
+    pub fn join(&mut self, player: T, name: &str, modifier: i64, rng: &mut impl Rng) -> Result<&Combatant<T>> {
+        if self.combatants.iter().any(|combatant| combatant.player.as_ref() == Some(&player)) {
+            anyhow::bail!("You've already rolled initiative in this encounter");
+        }
+        self.add(name.into(), Some(player), modifier, rng)
+    }
+
+    /// Add `count` NPCs, numbering them if there's more than one
+    pub fn add_npcs(&mut self, name: &str, modifier: i64, count: u32, rng: &mut impl Rng) -> Result<Vec<Combatant<T>>> {
+        let name = name.trim();
+        if name.is_empty() {
+            anyhow::bail!("NPCs need a name");
+        }
+        if !(1..=MAX_NPCS_AT_ONCE).contains(&count) {
+            anyhow::bail!("You can add between 1 and {MAX_NPCS_AT_ONCE} NPCs at once");
+        }
+        let mut added = vec![];
+        for number in 1..=count {
+            let npc_name = if count == 1 { name.to_string() } else { format!("{name} {number}") };
+            added.push(self.add(npc_name, None, modifier, rng)?.clone());
+        }
+        Ok(added)
+    }
+
+    /// Set someone's initiative directly, moving them to their new place in the order
+    pub fn set_initiative(&mut self, name: &str, initiative: i64) -> Result<()> {
+        let index = self.find(name)?;
+        if self.has_started() && index == self.turn {
+            anyhow::bail!("You can't move someone during their own turn");
+        }
+        let mut combatant = self.remove_at(index);
+        combatant.initiative = initiative;
+        combatant.roll = None;
+        let index = self.combatants.iter().position(|other| other.order_key() < combatant.order_key()).unwrap_or(self.combatants.len());
+        if self.has_started() && index <= self.turn {
+            self.turn += 1;
+        }
+        self.combatants.insert(index, combatant);
+        Ok(())
+    }
+
+    /// Take someone out of the order, keeping the current turn where it is
+    fn remove_at(&mut self, index: usize) -> Combatant<T> {
+        if index < self.turn {
+            self.turn -= 1;
+        }
+        self.combatants.remove(index)
+    }
+
+    /// Remove someone from the fight. Conditions that would have ended on their turn end on the next one instead
+    pub fn remove(&mut self, name: &str) -> Result<Combatant<T>> {
+        let index = self.find(name)?;
+        if self.has_started() && index == self.turn {
+            anyhow::bail!("It's {}'s turn. Use `!next` before removing them", self.combatants[index].name);
+        }
+        let removed = self.remove_at(index);
+        if let Some(next) = self.combatants.get(index).or_else(|| self.combatants.first()) {
+            let (next_id, wrapped) = (next.id, index >= self.combatants.len());
+            for condition in self.combatants.iter_mut().flat_map(|combatant| combatant.conditions.iter_mut()) {
+                if let Some((id, round)) = condition.ends.as_mut().filter(|(id, _)| *id == removed.id) {
+                    *id = next_id;
+                    if wrapped {
+                        *round += 1;
+                    }
+                }
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Remove conditions that end as `id`'s turn in the current round starts
+    fn expire(&mut self, id: u32) -> Vec<Expiry> {
+        let round = self.round;
+        let mut expired = vec![];
+        for combatant in self.combatants.iter_mut() {
+            combatant.conditions.retain(|condition| match condition.ends {
+                Some((ends_id, ends_round)) if ends_id == id && ends_round <= round => {
+                    expired.push(Expiry { combatant: combatant.name.clone(), condition: condition.name.clone() });
+                    false
+                },
+                _ => true,
+            });
+        }
+        expired
+    }
+
+    /// Move on to the next turn, skipping anyone delaying, or start the first round
+    pub fn next_turn(&mut self) -> Result<TurnChange> {
+        if !self.is_running() {
+            anyhow::bail!("There's no encounter running in this channel. Start one with `!init start`");
+        }
+        if self.combatants.is_empty() {
+            anyhow::bail!("Nobody has rolled initiative yet");
+        }
+        if self.combatants.iter().all(|combatant| combatant.delaying) {
+            anyhow::bail!("Everyone is delaying. Someone has to `!rejoin` first");
+        }
+        let mut change = TurnChange::default();
+        if self.has_started() {
+            self.turn += 1;
+        }
+        else {
+            self.round = 1;
+            self.turn = 0;
+            change.new_round = true;
+        }
+        loop {
+            if self.turn >= self.combatants.len() {
+                self.turn = 0;
+                self.round += 1;
+                change.new_round = true;
+            }
+            let id = self.combatants[self.turn].id;
+            change.expired.extend(self.expire(id));
+            let combatant = &mut self.combatants[self.turn];
+            if !combatant.delaying {
+                change.lapsed = combatant.readied.take();
+                return Ok(change);
+            }
+            change.skipped.push(combatant.name.clone());
+            self.turn += 1;
+        }
+    }
+
+    /// Check that it's `index`'s turn
+    fn check_turn(&self, index: usize) -> Result<()> {
+        if !self.has_started() || index != self.turn {
+            anyhow::bail!("You can only do that on your own turn");
+        }
+        Ok(())
+    }
+
+    /// Hold the current turn to act later in the round. Moves on to the next turn
+    pub fn delay(&mut self, index: usize) -> Result<TurnChange> {
+        self.check_turn(index)?;
+        if self.combatants.iter().filter(|combatant| !combatant.delaying).count() < 2 {
+            anyhow::bail!("There's nobody else left to take a turn");
+        }
+        self.combatants[index].delaying = true;
+        self.next_turn()
+    }
+
+    /// Bring a delaying combatant back in. The current turn ends and theirs starts, and they keep this place in the order
+    pub fn rejoin(&mut self, index: usize) -> Result<TurnChange> {
+        if !self.combatants[index].delaying {
+            anyhow::bail!("{} isn't delaying", self.combatants[index].name);
+        }
+        let mut combatant = self.remove_at(index);
+        combatant.delaying = false;
+        // Take the initiative of whoever they come in after, so anyone joining later slots in around them sensibly
+        combatant.initiative = self.combatants[self.turn].initiative;
+        combatant.modifier = self.combatants[self.turn].modifier;
+        combatant.tiebreak = self.combatants[self.turn].tiebreak.saturating_sub(1);
+        let id = combatant.id;
+        self.turn += 1;
+        self.combatants.insert(self.turn, combatant);
+        Ok(TurnChange { expired: self.expire(id), ..Default::default() })
+    }
+
+    /// Prepare an action to take before the combatant's next turn. Moves on to the next turn
+    pub fn ready(&mut self, index: usize, action: &str) -> Result<TurnChange> {
+        self.check_turn(index)?;
+        let action = action.trim();
+        if action.is_empty() {
+            anyhow::bail!("Say what you're readying, like `!ready attack the first goblin through the door`");
+        }
+        self.combatants[index].readied = Some(action.into());
+        self.next_turn()
+    }
+
+    /// Use a readied action, returning what it was
+    pub fn trigger(&mut self, index: usize) -> Result<String> {
+        let combatant = &mut self.combatants[index];
+        combatant.readied.take().context(format!("{} doesn't have an action readied", combatant.name))
+    }
+
+    /// Give someone a condition lasting `rounds` rounds, counted from the current turn.
+    /// With no duration it lasts until it's removed
+    pub fn add_condition(&mut self, index: usize, name: &str, rounds: Option<u32>) -> Result<()> {
+        let name = name.trim().to_lowercase();
+        if name.is_empty() {
+            anyhow::bail!("Conditions need a name");
+        }
+        let ends = match rounds {
+            Some(0) => anyhow::bail!("Conditions have to last at least one round"),
+            Some(rounds) => {
+                let current = self.current().context("Durations count from the current turn, so start the fight with `!next` first")?;
+                Some((current.id, self.round.checked_add(rounds).context("That's too many rounds")?))
+            },
+            None => None,
+        };
+        let combatant = &mut self.combatants[index];
+        combatant.conditions.retain(|condition| condition.name != name);
+        combatant.conditions.push(Condition { name, ends });
+        Ok(())
+    }
+
+    pub fn remove_condition(&mut self, index: usize, name: &str) -> Result<()> {
+        let name = name.trim().to_lowercase();
+        let combatant = &mut self.combatants[index];
+        let position = combatant.conditions.iter().position(|condition| condition.name == name)
+            .context(format!("{} isn't {name}", combatant.name))?;
+        combatant.conditions.remove(position);
+        Ok(())
+    }
+
+    /// The name of the combatant with this id
+    pub fn name_of(&self, id: u32) -> Option<&str> {
+        self.combatants.iter().find(|combatant| combatant.id == id).map(|combatant| combatant.name.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// A started encounter with NPCs at fixed initiatives, given as name, modifier and initiative
+    fn encounter(combatants: &[(&str, i64, i64)]) -> Encounter<u64> {
+        let mut encounter = Encounter::new();
+        encounter.start(0).unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+        for (name, modifier, initiative) in combatants {
+            encounter.add_npcs(name, *modifier, 1, &mut rng).unwrap();
+            encounter.set_initiative(name, *initiative).unwrap();
+        }
+        encounter
+    }
+
+    fn order(encounter: &Encounter<u64>) -> Vec<&str> {
+        encounter.combatants().iter().map(|combatant| combatant.name.as_str()).collect()
+    }
+
+    fn current(encounter: &Encounter<u64>) -> (&str, u32) {
+        (encounter.current().unwrap().name.as_str(), encounter.round)
+    }
+
+    fn expired(change: &TurnChange) -> Vec<&str> {
+        change.expired.iter().map(|expiry| expiry.condition.as_str()).collect()
+    }
+
+    #[test]
+    fn ties_go_to_the_higher_modifier() {
+        let encounter = encounter(&[("Archer", 1, 15), ("Brute", 5, 15), ("Cleric", 3, 20), ("Druid", 3, 15)]);
+        assert_eq!(order(&encounter), ["Cleric", "Brute", "Druid", "Archer"]);
+    }
+
+    #[test]
+    fn conditions_expire_at_the_start_of_the_turn_they_were_given_on() {
+        let mut encounter = encounter(&[("A", 0, 20), ("B", 0, 15), ("C", 0, 10)]);
+        encounter.next_turn().unwrap();
+        encounter.next_turn().unwrap();
+        assert_eq!(current(&encounter), ("B", 1));
+        let c = encounter.find("C").unwrap();
+        encounter.add_condition(c, "Poisoned", Some(2)).unwrap();
+
+        // C, A and B in round 2, then C and A in round 3
+        for expected in [("C", 1), ("A", 2), ("B", 2), ("C", 2), ("A", 3)] {
+            let change = encounter.next_turn().unwrap();
+            assert!(change.expired.is_empty(), "{expected:?}");
+            assert_eq!(current(&encounter), expected);
+        }
+        let change = encounter.next_turn().unwrap();
+        assert_eq!(current(&encounter), ("B", 3));
+        assert_eq!(expired(&change), ["poisoned"]);
+        assert_eq!(change.expired[0].combatant, "C");
+    }
+
+    #[test]
+    fn removing_someone_moves_their_conditions_to_the_next_turn() {
+        let mut encounter = encounter(&[("A", 0, 20), ("B", 0, 15), ("C", 0, 10)]);
+        for _ in 0..3 {
+            encounter.next_turn().unwrap();
+        }
+        assert_eq!(current(&encounter), ("C", 1));
+        let a = encounter.find("A").unwrap();
+        encounter.add_condition(a, "blessed", Some(1)).unwrap();
+        encounter.next_turn().unwrap();
+        assert_eq!(current(&encounter), ("A", 2));
+
+        // C was last in the order, so the condition now ends as A's turn starts in the next round
+        encounter.remove("C").unwrap();
+        let a = encounter.find("A").unwrap();
+        let a_id = encounter.combatants()[a].id;
+        assert_eq!(encounter.combatants()[a].conditions[0].ends, Some((a_id, 3)));
+        assert!(encounter.next_turn().unwrap().expired.is_empty());
+        assert_eq!(current(&encounter), ("B", 2));
+        let change = encounter.next_turn().unwrap();
+        assert_eq!(current(&encounter), ("A", 3));
+        assert_eq!(expired(&change), ["blessed"]);
+    }
+
+    #[test]
+    fn delaying_and_rejoining() {
+        let mut encounter = encounter(&[("A", 0, 20), ("B", 0, 15), ("C", 0, 10)]);
+        encounter.next_turn().unwrap();
+        let a = encounter.find("A").unwrap();
+        encounter.delay(a).unwrap();
+        assert_eq!(current(&encounter), ("B", 1));
+
+        // A comes back in right after B and keeps that place
+        let a = encounter.find("A").unwrap();
+        encounter.rejoin(a).unwrap();
+        assert_eq!(current(&encounter), ("A", 1));
+        assert_eq!(order(&encounter), ["B", "A", "C"]);
+        encounter.next_turn().unwrap();
+        assert_eq!(current(&encounter), ("C", 1));
+        encounter.next_turn().unwrap();
+        assert_eq!(current(&encounter), ("B", 2));
+
+        // Anyone still delaying when their turn comes round again is skipped
+        let b = encounter.find("B").unwrap();
+        let change = encounter.delay(b).unwrap();
+        assert_eq!(current(&encounter), ("A", 2));
+        assert!(change.skipped.is_empty());
+        encounter.next_turn().unwrap();
+        let change = encounter.next_turn().unwrap();
+        assert_eq!(current(&encounter), ("A", 3));
+        assert_eq!(change.skipped, ["B"]);
+    }
+
+    #[test]
+    fn joining_mid_round() {
+        let mut encounter = encounter(&[("A", 0, 20), ("C", 0, 10)]);
+        encounter.next_turn().unwrap();
+        encounter.next_turn().unwrap();
+        assert_eq!(current(&encounter), ("C", 1));
+        let mut rng = StdRng::seed_from_u64(0);
+
+        // Too fast to act this round, since their place has passed
+        encounter.join(1, "Fast", 100, &mut rng).unwrap();
+        assert_eq!(order(&encounter), ["Fast", "A", "C"]);
+        assert_eq!(current(&encounter), ("C", 1));
+        // Still to come this round
+        encounter.join(2, "Slow", -100, &mut rng).unwrap();
+        assert!(encounter.join(2, "Slower", -100, &mut rng).is_err());
+
+        encounter.next_turn().unwrap();
+        assert_eq!(current(&encounter), ("Slow", 1));
+        encounter.next_turn().unwrap();
+        assert_eq!(current(&encounter), ("Fast", 2));
+    }
+}
@@ -0,0 +1,191 @@
+use std::{fmt::Display, hash::Hash};
+use anyhow::{Context, Result};
+use rand::{seq::index::sample, Rng};
+use serde::{Deserialize, Serialize};
+
+use crate::roulette::RouletteState;
+
+/// How many numbers are on a ticket, and drawn
+pub const NUMBERS_PER_TICKET: usize = 3;
+
+/// Numbers are picked from 1 up to this
+pub const HIGHEST_NUMBER: u8 = 15;
+
+/// Most tickets one purchase can buy
+const MAX_TICKETS_PER_PURCHASE: u32 = 100;
+
+/// Longest time between draws, a year
+const MAX_DRAW_INTERVAL_HOURS: u64 = 24 * 365;
+
+/// Unix timestamp of the draw `hours` after `now`
+fn draw_after(now: u64, hours: u64) -> Result<u64> {
+    hours.checked_mul(3600)
+        .and_then(|seconds| now.checked_add(seconds))
+        .context("The next draw would be too far in the future")
+}
+
+fn pick_numbers(rng: &mut impl Rng) -> Vec<u8> {
+    let mut numbers: Vec<u8> = sample(rng, HIGHEST_NUMBER as usize, NUMBERS_PER_TICKET)
+        .into_iter()
+        .map(|index| index as u8 + 1)
+        .collect();
+    numbers.sort_unstable();
+    numbers
+}
+
+pub fn format_numbers(numbers: &[u8]) -> String {
+    let numbers: Vec<String> = numbers.iter().map(|number| format!("{number:02}")).collect();
+    numbers.join("-")
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ticket<T> {
+    pub owner: T,
+    pub owner_name: String,
+    pub numbers: Vec<u8>
+}
+
+#[derive(Debug, Clone)]
+pub struct DrawResult<T> {
+    pub round: u64,
+    pub numbers: Vec<u8>,
+    pub tickets_sold: usize,
+    /// Winning tickets and what each was paid
+    pub winners: Vec<(Ticket<T>, u128)>,
+    /// What the jackpot is now, which is everything if nobody won
+    pub jackpot: u128
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LotteryState<T> {
+    pub ticket_price: u128,
+    pub jackpot: u128,
+    /// Hours between draws
+    pub draw_interval_hours: u64,
+    /// Unix timestamp of the next draw
+    pub next_draw: u64,
+    pub round: u64,
+    tickets: Vec<Ticket<T>>
+}
+
+impl<T> LotteryState<T> where T: Display + Eq + Hash + Clone + Ord {
+    pub fn new(now: u64) -> Self {
+        let draw_interval_hours = 24 * 7;
+        LotteryState {
+            ticket_price: 10,
+            jackpot: 0,
+            draw_interval_hours,
+            next_draw: draw_after(now, draw_interval_hours).unwrap_or(u64::MAX),
+            round: 1,
+            tickets: vec![]
+        }
+    }
+
+    pub fn tickets(&self) -> &[Ticket<T>] {
+        &self.tickets
+    }
+
+    pub fn tickets_for(&self, owner: &T) -> Vec<&Ticket<T>> {
+        self.tickets.iter().filter(|ticket| ticket.owner == *owner).collect()
+    }
+
+    pub fn is_due(&self, now: u64) -> bool {
+        now >= self.next_draw
+    }
+
+    /// Draw every `hours` hours, starting that long from now
+    pub fn set_schedule(&mut self, hours: u64, now: u64) -> Result<()> {
+        if !(1..=MAX_DRAW_INTERVAL_HOURS).contains(&hours) {
+            anyhow::bail!("Draws must be between an hour and {} days apart", MAX_DRAW_INTERVAL_HOURS / 24);
+        }
+        self.next_draw = draw_after(now, hours)?;
+        self.draw_interval_hours = hours;
+        Ok(())
+    }
+
+    pub fn set_ticket_price(&mut self, ticket_price: u128) -> Result<()> {
+        if ticket_price == 0 {
+            anyhow::bail!("Tickets can't be free");
+        }
+        if !self.tickets.is_empty() {
+            anyhow::bail!("The ticket price can only change before anyone has bought into this draw");
+        }
+        self.ticket_price = ticket_price;
+        Ok(())
+    }
+
+    /// Buy quick pick tickets, adding their price to the jackpot
+    pub fn buy(&mut self, owner: T, owner_name: &str, count: u32, bank: &mut RouletteState<T>, rng: &mut impl Rng) -> Result<Vec<Ticket<T>>> {
+        if count == 0 || count > MAX_TICKETS_PER_PURCHASE {
+            anyhow::bail!("You can buy between 1 and {MAX_TICKETS_PER_PURCHASE} tickets at a time");
+        }
+        let cost = self.ticket_price.checked_mul(count as u128).context("Those tickets cost more than anyone could have")?;
+        let jackpot = self.jackpot.checked_add(cost).context("The jackpot can't get any bigger, wait for the draw")?;
+        bank.withdraw(owner.clone(), cost)?;
+        self.jackpot = jackpot;
+        let tickets: Vec<Ticket<T>> = (0..count)
+            .map(|_| Ticket { owner: owner.clone(), owner_name: owner_name.into(), numbers: pick_numbers(rng) })
+            .collect();
+        self.tickets.extend(tickets.iter().cloned());
+        println!("Player {owner_name} (id={owner}) bought {count} lottery ticket(s) for {cost}");
+        Ok(tickets)
+    }
+
+    /// Draw the winning numbers, split the jackpot between matching tickets or roll it over,
+    /// and schedule the next draw
+    pub fn draw(&mut self, bank: &mut RouletteState<T>, rng: &mut impl Rng, now: u64) -> Result<DrawResult<T>> {
+        let next_draw = draw_after(now, self.draw_interval_hours)?;
+        let numbers = pick_numbers(rng);
+        let tickets = std::mem::take(&mut self.tickets);
+        let tickets_sold = tickets.len();
+        let winning_tickets: Vec<Ticket<T>> = tickets.into_iter().filter(|ticket| ticket.numbers == numbers).collect();
+
+        let mut winners = vec![];
+        if !winning_tickets.is_empty() {
+            let share = self.jackpot / winning_tickets.len() as u128;
+            let mut remainder = self.jackpot % winning_tickets.len() as u128;
+            for ticket in winning_tickets {
+                let prize = share + std::mem::take(&mut remainder);
+                bank.register_player(ticket.owner.clone(), &ticket.owner_name);
+                if let Err(e) = bank.deposit(ticket.owner.clone(), prize) {
+                    println!("Failed to pay lottery prize to {}: {e}", ticket.owner);
+                }
+                winners.push((ticket, prize));
+            }
+            self.jackpot = 0;
+        }
+
+        let result = DrawResult { round: self.round, numbers, tickets_sold, winners, jackpot: self.jackpot };
+        self.round += 1;
+        self.next_draw = next_draw;
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schedule_bounds() {
+        let mut lottery: LotteryState<u64> = LotteryState::new(1_000);
+        assert!(lottery.set_schedule(0, 1_000).is_err());
+        assert!(lottery.set_schedule(u64::MAX, 1_000).is_err());
+        assert!(lottery.set_schedule(MAX_DRAW_INTERVAL_HOURS + 1, 1_000).is_err());
+        assert!(lottery.set_schedule(24, u64::MAX).is_err());
+        assert_eq!(lottery.draw_interval_hours, 24 * 7);
+
+        lottery.set_schedule(MAX_DRAW_INTERVAL_HOURS, 1_000).unwrap();
+        assert_eq!(lottery.next_draw, 1_000 + MAX_DRAW_INTERVAL_HOURS * 3600);
+    }
+
+    #[test]
+    fn ticket_costs_that_overflow() {
+        let mut lottery: LotteryState<u64> = LotteryState::new(0);
+        let mut bank = RouletteState::new();
+        bank.register_player(1, "player");
+        lottery.set_ticket_price(u128::MAX / 2).unwrap();
+        assert!(lottery.buy(1, "player", 3, &mut bank, &mut rand::thread_rng()).is_err());
+        assert_eq!(lottery.jackpot, 0);
+    }
+}
@@ -0,0 +1,198 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// Most macros one player, or the server, can save
+const MAX_MACROS: usize = 50;
+
+/// Longest expression a macro can hold
+const MAX_EXPRESSION_LENGTH: usize = 500;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RollMacro<T> {
+    /// Who saved it, or `None` for macros shared by the whole server
+    pub owner: Option<T>,
+    pub name: String,
+    pub expression: String
+}
+
+/// A named number substituted into rolls, like `@str`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Variable<T> {
+    pub owner: T,
+    pub name: String,
+    pub value: i64
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MacroBook<T> {
+    macros: Vec<RollMacro<T>>,
+    variables: Vec<Variable<T>>
+}
+
+fn validate_name(name: &str) -> Result<String> {
+    let name = name.to_lowercase();
+    if name.is_empty() || name.len() > 32 || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        anyhow::bail!("Names can only use letters, numbers, '-' and '_', and be up to 32 characters long");
+    }
+    Ok(name)
+}
+
+impl<T> MacroBook<T> where T: Eq + Clone {
+    pub fn new() -> Self {
+        MacroBook { macros: vec![], variables: vec![] }
+    }
+
+    /// Save a macro for `owner`, or for the server if there's no owner, replacing any with the same name
+    pub fn save(&mut self, owner: Option<T>, name: &str, expression: &str) -> Result<()> {
+        let name = validate_name(name)?;
+        let expression = expression.trim();
+        if expression.is_empty() || expression.len() > MAX_EXPRESSION_LENGTH {
+            anyhow::bail!("Macros must be between 1 and {MAX_EXPRESSION_LENGTH} characters long");
+        }
+        match self.macros.iter_mut().find(|roll_macro| roll_macro.owner == owner && roll_macro.name == name) {
+            Some(roll_macro) => roll_macro.expression = expression.into(),
+            None => {
+                if self.macros.iter().filter(|roll_macro| roll_macro.owner == owner).count() >= MAX_MACROS {
+                    anyhow::bail!("You can only save {MAX_MACROS} macros");
+                }
+                self.macros.push(RollMacro { owner, name, expression: expression.into() });
+            },
+        }
+        Ok(())
+    }
+
+    pub fn delete(&mut self, owner: Option<T>, name: &str) -> Result<()> {
+        let name = name.to_lowercase();
+        let index = self.macros.iter().position(|roll_macro| roll_macro.owner == owner && roll_macro.name == name)
+            .context(format!("There's no macro called '{name}'"))?;
+        self.macros.remove(index);
+        Ok(())
+    }
+
+    /// Macros saved by `owner`, or shared by the server if there's no owner
+    pub fn macros(&self, owner: Option<&T>) -> Vec<&RollMacro<T>> {
+        self.macros.iter().filter(|roll_macro| roll_macro.owner.as_ref() == owner).collect()
+    }
+
+    /// Find a macro by name, preferring the player's own over the server's
+    pub fn lookup(&self, owner: &T, name: &str) -> Option<&RollMacro<T>> {
+        let name = name.to_lowercase();
+        self.macros.iter()
+            .filter(|roll_macro| roll_macro.name == name)
+            .find(|roll_macro| roll_macro.owner.as_ref() == Some(owner))
+            .or_else(|| self.macros.iter().find(|roll_macro| roll_macro.name == name && roll_macro.owner.is_none()))
+    }
+
+    pub fn set_variable(&mut self, owner: T, name: &str, value: i64) -> Result<()> {
+        let name = validate_name(name)?;
+        match self.variables.iter_mut().find(|variable| variable.owner == owner && variable.name == name) {
+            Some(variable) => variable.value = value,
+            None => self.variables.push(Variable { owner, name, value }),
+        }
+        Ok(())
+    }
+
+    pub fn unset_variable(&mut self, owner: &T, name: &str) -> Result<()> {
+        let name = name.to_lowercase();
+        let index = self.variables.iter().position(|variable| variable.owner == *owner && variable.name == name)
+            .context(format!("You don't have a variable called '@{name}'"))?;
+        self.variables.remove(index);
+        Ok(())
+    }
+
+    pub fn variables(&self, owner: &T) -> Vec<&Variable<T>> {
+        self.variables.iter().filter(|variable| variable.owner == *owner).collect()
+    }
+
+    /// Turn what a player asked to roll into an expression, replacing a macro name with its
+    /// expression and `@variables` with their values. Variables the player hasn't set are looked up with `fallback`
+    pub fn expand(&self, owner: &T, input: &str, fallback: impl Fn(&str) -> Option<i64>) -> Result<String> {
+        let input = input.trim();
+        let expression = match self.lookup(owner, input) {
+            Some(roll_macro) => roll_macro.expression.as_str(),
+            None => input,
+        };
+
+        let mut expanded = String::new();
+        let mut chars = expression.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '@' {
+                expanded.push(c);
+                continue;
+            }
+            let mut name = String::new();
+            while let Some(c) = chars.peek().filter(|c| c.is_ascii_alphanumeric() || **c == '_' || **c == '-') {
+                name.push(c.to_ascii_lowercase());
+                chars.next();
+            }
+            let value = self.variables.iter()
+                .find(|variable| variable.owner == *owner && variable.name == name)
+                .map(|variable| variable.value)
+                .or_else(|| fallback(&name))
+                .context(format!("You haven't set '@{name}'. Set it with `!macro set {name} <value>`"))?;
+            if value < 0 {
+                expanded.push_str(&format!("({value})"));
+            }
+            else {
+                expanded.push_str(&value.to_string());
+            }
+        }
+        Ok(expanded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_sheet(_: &str) -> Option<i64> {
+        None
+    }
+
+    #[test]
+    fn personal_macros_come_first() {
+        let mut book: MacroBook<u64> = MacroBook::new();
+        book.save(None, "Attack", "1d20+2").unwrap();
+        book.save(Some(1), "attack", "1d20+7").unwrap();
+        assert_eq!(book.expand(&1, "ATTACK", no_sheet).unwrap(), "1d20+7");
+        assert_eq!(book.expand(&2, "attack", no_sheet).unwrap(), "1d20+2");
+        // Anything that isn't a macro is rolled as written
+        assert_eq!(book.expand(&1, " 2d6 ", no_sheet).unwrap(), "2d6");
+    }
+
+    #[test]
+    fn variables() {
+        let mut book: MacroBook<u64> = MacroBook::new();
+        book.set_variable(1, "STR", 3).unwrap();
+        book.set_variable(1, "dex", -1).unwrap();
+        book.save(Some(1), "hit", "1d20+@str+@Dex").unwrap();
+        assert_eq!(book.expand(&1, "hit", no_sheet).unwrap(), "1d20+3+(-1)");
+        assert_eq!(book.expand(&1, "1d20-@dex", no_sheet).unwrap(), "1d20-(-1)");
+        assert!(book.set_variable(1, "two words", 1).is_err());
+    }
+
+    #[test]
+    fn sheet_fallback() {
+        let mut book: MacroBook<u64> = MacroBook::new();
+        book.set_variable(1, "str", 3).unwrap();
+        let sheet = |name: &str| match name {
+            "str" => Some(4),
+            "wis" => Some(-2),
+            _ => None,
+        };
+        // Variables the player set win over their sheet
+        assert_eq!(book.expand(&1, "1d20+@str+@wis", sheet).unwrap(), "1d20+3+(-2)");
+        assert_eq!(book.expand(&2, "1d20+@str", sheet).unwrap(), "1d20+4");
+    }
+
+    #[test]
+    fn unset_variables() {
+        let mut book: MacroBook<u64> = MacroBook::new();
+        book.set_variable(1, "str", 3).unwrap();
+        let error = book.expand(&2, "1d20+@str", no_sheet).unwrap_err();
+        assert_eq!(error.to_string(), "You haven't set '@str'. Set it with `!macro set str <value>`");
+        book.unset_variable(&1, "str").unwrap();
+        assert!(book.expand(&1, "1d20+@str", no_sheet).is_err());
+        assert!(book.unset_variable(&1, "str").is_err());
+    }
+}
@@ -1,36 +1,78 @@
+mod blackjack;
+mod cards;
+mod crash;
+mod craps;
+mod dice;
+mod duel;
+mod history;
+mod initiative;
+mod lottery;
+mod macros;
+mod odds;
+mod poker;
+mod pools;
 mod roulette;
 mod search;
+mod sheets;
+mod shop;
+mod slots;
+mod stats;
+mod tables;
 
-use std::{collections::{HashMap, HashSet}, fmt::format, io::Read, sync::Arc};
+use std::{collections::{HashMap, HashSet}, fmt::format, io::Read, sync::Arc, time::Duration};
 
-use roulette::RouletteState;
+use rand::seq::SliceRandom;
+
+use blackjack::{BlackjackTable, HandOutcome, Phase};
+use crash::{CrashGame, CrashPhase, CrashTick};
+use craps::{BetResult, CrapsBet, CrapsState};
+use dice::{Compare, Condition, Critical, Expression, RollBreakdown, TermResult};
+use duel::{CoinSide, DuelLedger};
+use history::{Rolled, RollRecord, Session};
+use initiative::{Combatant, Encounter, TurnChange};
+use lottery::LotteryState;
+use macros::MacroBook;
+use poker::{PokerTable, Street};
+use pools::{Pool, PoolResult, Tier};
+use roulette::{RouletteState, SavedBalance};
+use sheets::{CharacterBook, Sheet};
+use shop::Shop;
+use slots::SlotMachine;
+use stats::{Method, PointBuy};
+use tables::{Format, RandomTable, TableLibrary};
 use songbird::{
-    input::Input, Event, EventContext, EventHandler as VoiceEventHandler, SerenityInit, TrackEvent,
+    input::{Input, Restartable}, tracks::{PlayMode, TrackHandle, TrackQueue}, Event, EventContext, EventHandler as VoiceEventHandler, SerenityInit, TrackEvent,
 };
 
 use serenity::{
     async_trait,
+    futures::StreamExt,
     client::{Client, Context, EventHandler},
     framework::{
         standard::{
             help_commands,
-            macros::{command, group, help},
+            macros::{command, group, help, hook},
             Args, CommandGroup, CommandResult, HelpOptions,
         },
         StandardFramework,
     },
     http::Http,
     model::{
-        channel::Message,
+        application::{
+            component::ButtonStyle,
+            interaction::{message_component::MessageComponentInteraction, Interaction, InteractionResponseType},
+        },
+        channel::{AttachmentType, Message},
         gateway::Ready,
-        prelude::{ChannelId, UserId},
+        prelude::{ChannelId, GuildId, RoleId, UserId},
     },
     prelude::*,
+    utils::Colour,
     Result as SerenityResult,
 };
 
 #[group]
-#[commands(milk, join, leave, fuckoff, play, skip, queue, roll, rbet, rbets, rbalance, rclearlast, rclearall)]
+#[commands(milk, join, leave, fuckoff, play, skip, queue, roll, rbet, rbets, rbalance, rclearlast, rclearall, bjbet, bjhit, bjstand, bjdouble, bjsplit, bjinsure, bjtable, bjrules, pksit, pkleave, pkdeal, pkcheck, pkcall, pkraise, pkallin, pkfold, pktable, pkcards, pkblinds, slots, cbet, cbets, ctakedown, crash, cashout, crashverify, crashedge, coinflip, duel, accept, decline, duelcancel, duelrake, lottery, shop, buy, sell, inventory, odds, inlinerolls, macro_command, r, groll, broll, gmrole, init, next, delay, rejoin, ready, trigger, condition, sheet, campaign, check, save, hp, rolls, luck, session, table, stats, remove, move_song, shuffle, clear, skipto, dedupe, pause, resume, stop, seek, forward, rewind, volume, loop_mode, nowplaying)]
 struct General;
 
 struct Handler;
@@ -41,12 +83,24 @@ impl EventHandler for Handler {
         println!("Connected as {}", ready.user.name);
     }
 
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        if let Interaction::MessageComponent(component) = interaction {
+            if component.data.custom_id == CRASH_CASH_OUT_BUTTON {
+                crash_cash_out_button(&ctx, &component).await;
+            }
+        }
+    }
+
     async fn message(&self, ctx: Context, msg: Message) {
         if msg.content.to_lowercase().contains("cringe") {
             if let Err(why) = msg.reply(&ctx.http, "Gay").await {
                 println!("Error sending message: {:?}", why);
             }
         }
+        // Never answer bots, including ourselves, so rolls can't set each other off
+        if !msg.author.bot && msg.content.contains("[[") {
+            inline_rolls(&ctx, &msg).await;
+        }
     }
 }
 
@@ -69,11 +123,69 @@ impl VoiceEventHandler for SongPlayNotifier {
     }
 }
 
+/// Keeps each song looping as it starts while the server is looping one track
+struct TrackLooper {
+    guild_id: GuildId,
+    data: Arc<RwLock<TypeMap>>,
+}
+
+#[async_trait]
+impl VoiceEventHandler for TrackLooper {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let looping = match self.data.read().await.get::<LoopData>() {
+            Some(loop_data) => loop_data.guild_modes.get(&self.guild_id) == Some(&LoopMode::Track),
+            None => false,
+        };
+        if let (true, EventContext::Track(tracks)) = (looping, ctx) {
+            for (_, track) in tracks.iter() {
+                let _ = track.enable_loop();
+            }
+        }
+        None
+    }
+}
+
+/// Puts each song back at the end of the queue when it finishes, while the server is looping the queue
+struct QueueLooper {
+    guild_id: GuildId,
+    url: String,
+    ctx: Context,
+    msg: Message,
+}
+
+#[async_trait]
+impl VoiceEventHandler for QueueLooper {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if get_loop_mode(&self.ctx, self.guild_id).await != LoopMode::Queue {
+            return None;
+        }
+        if let EventContext::Track(tracks) = ctx {
+            for (_, track) in tracks.iter() {
+                if track.typemap().read().await.contains_key::<RemovedFromQueue>() {
+                    return None;
+                }
+            }
+        }
+        let manager = songbird::get(&self.ctx).await?;
+        manager.get(self.guild_id)?;
+        enqueue_song(&self.ctx, &self.msg, self.guild_id, &self.url).await;
+        None
+    }
+}
+
+/// Marks a song that was taken out of the queue, rather than finishing or being skipped
+struct RemovedFromQueue;
+
+impl TypeMapKey for RemovedFromQueue {
+    type Value = ();
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let framework = StandardFramework::new()
         .configure(|c| c.prefix("!")) // set the bot's prefix to "~"
         .help(&HELP)
+        .after(after_command)
         .group(&GENERAL_GROUP);
 
     // Login with a bot token from the environment
@@ -88,6 +200,53 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .await
         .expect("Error creating client");
 
+    {
+        let mut data = client.data.write().await;
+        data.insert::<SlotsData>(Arc::new(SlotMachine::load("slots.json").expect("Invalid slot machine in slots.json")));
+        let builtin_tables = TableLibrary::load_directory(TABLES_DIR).unwrap_or_else(|e| {
+            println!("Couldn't read the tables directory, so there won't be any built in tables: {e}");
+            TableLibrary::new()
+        });
+        data.insert::<BuiltinTablesData>(Arc::new(builtin_tables));
+    }
+
+    if let Err(e) = load_balances(&client.data).await {
+        println!("Failed to load balances: {e}");
+    }
+
+    if let Err(e) = load_lotteries(&client.data).await {
+        println!("Failed to load lotteries: {e}");
+    }
+    if let Err(e) = load_shops(&client.data).await {
+        println!("Failed to load shops: {e}");
+    }
+    if let Err(e) = load_inline_roll_settings(&client.data).await {
+        println!("Failed to load inline roll settings: {e}");
+    }
+    if let Err(e) = load_macros(&client.data).await {
+        println!("Failed to load macros: {e}");
+    }
+    if let Err(e) = load_gm_roles(&client.data).await {
+        println!("Failed to load GM roles: {e}");
+    }
+    if let Err(e) = load_characters(&client.data).await {
+        println!("Failed to load character sheets: {e}");
+    }
+    if let Err(e) = load_sessions(&client.data).await {
+        println!("Failed to load roll history: {e}");
+    }
+    if let Err(e) = load_guild_tables(&client.data).await {
+        println!("Failed to load uploaded tables: {e}");
+    }
+    if let Err(e) = load_reroll_thresholds(&client.data).await {
+        println!("Failed to load reroll thresholds: {e}");
+    }
+    if let Err(e) = load_volumes(&client.data).await {
+        println!("Failed to load volumes: {e}");
+    }
+    tokio::spawn(run_lottery_draws(client.cache_and_http.http.clone(), client.data.clone()));
+    tokio::spawn(run_balance_saves(client.data.clone()));
+
     // start listening for events by starting a single shard
     if let Err(why) = client.start().await {
         println!("An error occurred while running the client: {:?}", why);
@@ -184,8 +343,9 @@ async fn fuckoff(ctx: &Context, msg: &Message) -> CommandResult {
 
 async fn get_source(ctx: &Context, msg: &Message, url: &str) -> Option<Input> {
     println!("Attempting to get source with url: {url}");
-    match songbird::ytdl(url).await {
-        Ok(source) => Some(source),
+    // Restartable sources can be seeked and looped, and are only downloaded once they're about to play
+    match Restartable::ytdl(url.to_string(), true).await {
+        Ok(source) => Some(source.into()),
         Err(err) => {
             check_msg(
                 msg.channel_id
@@ -197,6 +357,51 @@ async fn get_source(ctx: &Context, msg: &Message, url: &str) -> Option<Input> {
     }
 }
 
+/// Resolve a song and add it to the back of the server's queue at the server's volume.
+/// Nothing is queued if the bot isn't in a voice channel there
+async fn enqueue_song(ctx: &Context, msg: &Message, guild_id: GuildId, url: &str) -> Option<TrackHandle> {
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let volume = get_default_volume(ctx, guild_id).await;
+    let source = get_source(ctx, msg, url).await?;
+    let handler_lock = manager.get(guild_id)?;
+    let mut handler = handler_lock.lock().await;
+    if !handler.is_deaf() {
+        let _ = handler.deafen(true).await;
+    }
+    // handler.play_source(source);
+    let track_handle = handler.enqueue_source(source);
+    let _ = track_handle.set_volume(volume as f32 / 100.0);
+    let _ = track_handle.add_event(
+        Event::Track(TrackEvent::Play),
+        SongPlayNotifier {
+            artist: track_handle.metadata().artist.clone().unwrap_or("Unknown Artist".into()),
+            title: track_handle.metadata().title.clone().unwrap_or("Unknown Title".into()),
+            channel_id: msg.channel_id,
+            http: ctx.http.clone(),
+        },
+    );
+    let _ = track_handle.add_event(
+        Event::Track(TrackEvent::Play),
+        TrackLooper {
+            guild_id,
+            data: ctx.data.clone(),
+        },
+    );
+    let _ = track_handle.add_event(
+        Event::Track(TrackEvent::End),
+        QueueLooper {
+            guild_id,
+            url: url.to_string(),
+            ctx: ctx.clone(),
+            msg: msg.clone(),
+        },
+    );
+    Some(track_handle)
+}
+
 #[command]
 #[aliases("p")]
 #[only_in(guilds)]
@@ -239,35 +444,18 @@ async fn play(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
             .await,
     );
     if let Some(url) = search::search(song).await {
-        if let Some(source) = get_source(ctx, msg, &url).await {
-            if let Some(handler_lock) = manager.get(guild_id) {
-                let mut handler = handler_lock.lock().await;
-                if !handler.is_deaf() {
-                    let _ = handler.deafen(true).await;
-                }
-                // handler.play_source(source);
-                let track_handle = handler.enqueue_source(source);
-                let _ = track_handle.add_event(
-                    Event::Track(TrackEvent::Play),
-                    SongPlayNotifier {
-                        artist: track_handle.metadata().artist.clone().unwrap_or("Unknown Artist".into()),
-                        title: track_handle.metadata().title.clone().unwrap_or("Unknown Title".into()),
-                        channel_id: msg.channel_id,
-                        http: ctx.http.clone(),
-                    },
-                );
-                check_msg(
-                    msg.channel_id
-                        .say(&ctx.http, format!("💿 Queued {}", track_handle.metadata().title.clone().unwrap_or("Unknown Title".into())))
-                        .await,
-                );
-            } else {
-                check_msg(
-                    msg.channel_id
-                        .say(&ctx.http, "❌ Not in a voice channel")
-                        .await,
-                );
-            }
+        if manager.get(guild_id).is_none() {
+            check_msg(
+                msg.channel_id
+                    .say(&ctx.http, "❌ Not in a voice channel")
+                    .await,
+            );
+        } else if let Some(track_handle) = enqueue_song(ctx, msg, guild_id, &url).await {
+            check_msg(
+                msg.channel_id
+                    .say(&ctx.http, format!("💿 Queued {}", track_handle.metadata().title.clone().unwrap_or("Unknown Title".into())))
+                    .await,
+            );
         }
     } else {
         check_msg(
@@ -343,22 +531,10 @@ async fn queue(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
 
     if let Some(handler_lock) = manager.get(guild_id) {
         let mut handler = handler_lock.lock().await;
-        let queue = handler.queue();
-        let mut response = String::new();
-        for (pos, track) in queue.current_queue().iter().enumerate() {
-            let title = track
-                .metadata()
-                .title
-                .clone()
-                .unwrap_or("Unknown title".into());
-            let artist = track
-                .metadata()
-                .artist
-                .clone()
-                .unwrap_or("Unknown artist".into());
-            response += &format!("\n  {}) [{}] {}", pos + 1, &artist, &title);
-        }
-        reply(ctx, msg, format!("💿💿 Songs in queue 💿💿{}", response)).await;
+        let listing = queue_listing(handler.queue());
+        drop(handler);
+        let mode = get_loop_mode(ctx, guild_id).await;
+        reply(ctx, msg, truncate_message(format!("🔁 Loop: {}\n{listing}", mode.describe()))).await;
     } else {
         check_msg(
             msg.channel_id
@@ -370,229 +546,4446 @@ async fn queue(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
     Ok(())
 }
 
-#[command]
-#[only_in(guilds)]
-async fn roll(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
-    let roll_expression = args.rest();
-    if let Ok(result) = d20::roll_dice(roll_expression) {
-        reply(&ctx, &msg, format!("You rolled: {}", result.to_string())).await;
-    } else {
-        reply(&ctx, &msg, format!("Invalid roll expression")).await;
+/// Every song in a queue, numbered from 1 for the one playing now
+fn queue_listing(queue: &TrackQueue) -> String {
+    let mut response = String::new();
+    for (pos, track) in queue.current_queue().iter().enumerate() {
+        let title = track
+            .metadata()
+            .title
+            .clone()
+            .unwrap_or("Unknown title".into());
+        let artist = track
+            .metadata()
+            .artist
+            .clone()
+            .unwrap_or("Unknown artist".into());
+        response += &format!("\n  {}) [{}] {}", pos + 1, &artist, &title);
     }
-    Ok(())
+    if response.is_empty() {
+        response = "\n  Nothing".into();
+    }
+    truncate_message(format!("💿💿 Songs in queue 💿💿{}", response))
 }
 
-struct RouletteData {
-    channel_state: HashMap<ChannelId, Arc<Mutex<RouletteState<UserId>>>>
+/// The queue of the server the message was sent in, if the bot is in a voice channel there
+async fn guild_queue(ctx: &Context, msg: &Message) -> Option<TrackQueue> {
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let handler_lock = manager.get(msg.guild_id?)?;
+    let handler = handler_lock.lock().await;
+    Some(handler.queue().clone())
 }
 
-impl TypeMapKey for RouletteData {
-    type Value = RouletteData;
+/// Stop songs that have been taken out of the queue, marking them so they aren't put back when looping the queue
+async fn stop_removed(tracks: Vec<TrackHandle>) {
+    for track in tracks {
+        track.typemap().write().await.insert::<RemovedFromQueue>(());
+        let _ = track.stop();
+    }
 }
 
-#[command]
-#[only_in(guilds)]
-async fn rbet(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
-    let mut ctx_data = ctx.data.write().await;
-    let roulette_data = ctx_data.entry::<RouletteData>().or_insert(RouletteData{ channel_state: Default::default() });
-    let roulette_state = roulette_data.channel_state.entry(msg.channel_id).or_insert(
-        Arc::new(Mutex::new(RouletteState::new()))
-    );
-    let player_id = msg.author.id;
-    let player_name = msg.author.name.clone();
-    let mut roulette_state_mut = roulette_state.lock().await;
-    roulette_state_mut.register_player(player_id, &player_name);
-    let current_balance = roulette_state_mut.get_balance(player_id);
-    if let Ok(current_balance) = current_balance {
-        if let Ok(current_bets) = roulette_state_mut.get_bets(player_id) {
-            if current_balance == 0 && current_bets.len() == 0 {
-                let set_money_result = roulette_state_mut.set_balance(player_id, 5);
-                if set_money_result.is_ok() {
-                    reply(ctx, msg, format!("```\nYou seem broke. Since I feel sorry for you, have 5 money units...\n```")).await;
-                }
+/// Stop every song before a position in the queue and play the one there. Returns how many were skipped
+async fn skip_to(queue: &TrackQueue, position: usize) -> usize {
+    let skipped: Vec<_> = queue.modify_queue(|tracks| {
+        let skipped = tracks.drain(..(position - 1).min(tracks.len())).collect();
+        // Whatever's now at the front has to be started by hand, as the queue only moves on when its front track ends
+        while let Some(track) = tracks.front() {
+            if track.play().is_ok() {
+                break;
             }
+            tracks.pop_front();
         }
+        skipped
+    });
+    let count = skipped.len();
+    stop_removed(skipped.iter().map(|track| track.handle()).collect()).await;
+    count
+}
+
+/// Reads a 1-based queue position that isn't the song playing now, checking it's in the queue
+fn queued_position(args: &mut Args, queue: &TrackQueue) -> Result<usize, String> {
+    let position = args.single::<usize>().map_err(|_| "Give the song's number from `!queue`".to_string())?;
+    match position {
+        _ if queue.len() < 2 => Err("There's nothing queued after the song playing now".into()),
+        2.. if position <= queue.len() => Ok(position),
+        _ => Err(format!("Choose a song from 2 to {}, as 1 is playing now", queue.len())),
     }
-    let bet_result = roulette_state_mut.play_bet_command(player_id, args.rest());
-    if let Err(e) = bet_result {
-        reply(ctx, msg, format!("Betting failed:\n{}", e.to_string())).await;
-    }
-    let current_bets = roulette_state_mut.get_bets(player_id);
-    match current_bets {
-        Ok(current_bets) => {
-            let bets: Vec<String> = current_bets.into_iter().map(|bet| format!("- {bet:?}")).collect();
-            reply(ctx, msg, format!("Current bets:\n```\n{}\n```", bets.join("\n"))).await;
-        },
-        Err(e) => {
-            reply(ctx, msg, format!("Unable to get current bets: {}", e.to_string())).await;
+}
+
+/// Remove a song from the queue by its number in `!queue`
+#[command]
+#[only_in(guilds)]
+async fn remove(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let Some(queue) = guild_queue(ctx, msg).await else {
+        reply(ctx, msg, "❌ Not in a voice channel").await;
+        return Ok(());
+    };
+    if args.current() == Some("1") {
+        if skip_to(&queue, 2).await == 0 {
+            reply(ctx, msg, "❌ No song to remove").await;
         }
+        else {
+            reply(ctx, msg, format!("→ Removed the song playing now\n{}", queue_listing(&queue))).await;
+        }
+        return Ok(());
     }
-    let current_balance = roulette_state_mut.get_balance(player_id);
-    match current_balance {
-        Ok(current_balance) => {
-            reply(ctx, msg, format!("```\nYour new balance is {current_balance}\n```")).await;
-        },
+    let position = match queued_position(&mut args, &queue) {
+        Ok(position) => position,
         Err(e) => {
-            reply(ctx, msg, format!("Unable to get current balance: {}", e.to_string())).await;
+            reply(ctx, msg, format!("❌ {e}")).await;
+            return Ok(());
         }
-    }
-
-    if !roulette_state_mut.spin_scheduled {
-        roulette_state_mut.spin_scheduled = true;
-        let _ = msg.channel_id.say(ctx, "```\nWheel will stop spinning in 25 seconds. Place your bets!\n```").await;
-        let http = ctx.http.clone();
-        let roulette_state = roulette_state.clone();
-        let channel_id = msg.channel_id.clone();
-        tokio::spawn(async move {
-            tokio::time::sleep(tokio::time::Duration::from_secs(15)).await;
-            let _ = channel_id.say(&http, "```\nWheel will stop spinning in 10 seconds. Finalize your bets!\n```").await;
-            tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
-            let mut roulette_state = roulette_state.lock().await;
-            roulette_state.lock_bets();
-            let _ = channel_id.say(&http, "Bets have been finalized!").await;
-            let spin_result = roulette_state.spin();
-            let payouts: Vec<String> = spin_result.payouts.into_iter().map(
-                |(player_id, (payout, balance))| format!("- {player_id}: {payout} (new balance: {balance})")
-            ).collect();
-            let payouts = payouts.join("\n");
-            let color = match spin_result.result {
-                0 => "Green",
-                _ => {
-                    if roulette::is_red(spin_result.result) {
-                        "Red"
-                    }
-                    else {
-                        "Black"
-                    }
-                }
-            };
-            let _ = channel_id.say(&http, format!("**Landed on {}** (**{color}**)\nPayouts:\n```\n{payouts}\n```", spin_result.result)).await;
-        });
+    };
+    if let Some(track) = queue.dequeue(position - 1) {
+        stop_removed(vec![track.handle()]).await;
+        let title = track.metadata().title.clone().unwrap_or("Unknown title".into());
+        reply(ctx, msg, format!("🗑️ Removed {title}\n{}", queue_listing(&queue))).await;
     }
     Ok(())
 }
 
-#[command]
+/// Move a song to another place in the queue, like `!move 5 2` to play song 5 next
+#[command("move")]
 #[only_in(guilds)]
-async fn rclearlast(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
-    let mut ctx_data = ctx.data.write().await;
-    let roulette_data = ctx_data.entry::<RouletteData>().or_insert(RouletteData{ channel_state: Default::default() });
-    let roulette_state = roulette_data.channel_state.entry(msg.channel_id).or_insert(
-        Arc::new(Mutex::new(RouletteState::new()))
-    );
-    let player_id = msg.author.id;
-    let player_name = msg.author.name.clone();
-    let mut roulette_state_mut = roulette_state.lock().await;
-    roulette_state_mut.register_player(player_id, &player_name);
-    let bet_result = roulette_state_mut.clear_last_bet(player_id);
-    if let Err(e) = bet_result {
-        reply(ctx, msg, format!("```\nUnable to clear last bet:\n{}\n```", e.to_string())).await;
-    }
-    let current_bets = roulette_state_mut.get_bets(player_id);
-    match current_bets {
-        Ok(current_bets) => {
-            let bets: Vec<String> = current_bets.into_iter().map(|bet| format!("- {bet:?}")).collect();
-            reply(ctx, msg, format!("Current bets:\n```\n{}\n```", bets.join("\n"))).await;
-        },
+async fn move_song(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let Some(queue) = guild_queue(ctx, msg).await else {
+        reply(ctx, msg, "❌ Not in a voice channel").await;
+        return Ok(());
+    };
+    let positions = queued_position(&mut args, &queue).and_then(|from| Ok((from, queued_position(&mut args, &queue)?)));
+    let (from, to) = match positions {
+        Ok(positions) => positions,
         Err(e) => {
-            reply(ctx, msg, format!("Unable to get current bets: {}", e.to_string())).await;
+            reply(ctx, msg, format!("❌ {e}\nUsage: `!move <from> <to>`")).await;
+            return Ok(());
         }
-    }
-    let current_balance = roulette_state_mut.get_balance(player_id);
-    match current_balance {
-        Ok(current_balance) => {
-            reply(ctx, msg, format!("```\nYour new balance is {current_balance}\n```")).await;
-        },
-        Err(e) => {
-            reply(ctx, msg, format!("Unable to get current balance: {}", e.to_string())).await;
+    };
+    queue.modify_queue(|tracks| {
+        if let Some(track) = tracks.remove(from - 1) {
+            tracks.insert(to - 1, track);
         }
-    }
+    });
+    reply(ctx, msg, format!("↕️ Moved song {from} to {to}\n{}", queue_listing(&queue))).await;
     Ok(())
 }
 
+/// Shuffle every song after the one playing now
 #[command]
 #[only_in(guilds)]
-async fn rclearall(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
-    let mut ctx_data = ctx.data.write().await;
-    let roulette_data = ctx_data.entry::<RouletteData>().or_insert(RouletteData{ channel_state: Default::default() });
-    let roulette_state = roulette_data.channel_state.entry(msg.channel_id).or_insert(
-        Arc::new(Mutex::new(RouletteState::new()))
-    );
-    let player_id = msg.author.id;
-    let player_name = msg.author.name.clone();
-    let mut roulette_state_mut = roulette_state.lock().await;
-    roulette_state_mut.register_player(player_id, &player_name);
-    let bet_result = roulette_state_mut.clear_all_bets(player_id);
-    if let Err(e) = bet_result {
-        reply(ctx, msg, format!("```\nUnable to clear all bets:\n{}\n```", e.to_string())).await;
-    }
-    let current_bets = roulette_state_mut.get_bets(player_id);
-    match current_bets {
-        Ok(current_bets) => {
-            let bets: Vec<String> = current_bets.into_iter().map(|bet| format!("- {bet:?}")).collect();
-            reply(ctx, msg, format!("Current bets:\n```\n{}\n```", bets.join("\n"))).await;
-        },
-        Err(e) => {
-            reply(ctx, msg, format!("Unable to get current bets: {}", e.to_string())).await;
-        }
-    }
-    let current_balance = roulette_state_mut.get_balance(player_id);
-    match current_balance {
-        Ok(current_balance) => {
-            reply(ctx, msg, format!("```\nYour new balance is {current_balance}\n```")).await;
-        },
-        Err(e) => {
-            reply(ctx, msg, format!("Unable to get current balance: {}", e.to_string())).await;
+async fn shuffle(ctx: &Context, msg: &Message) -> CommandResult {
+    let Some(queue) = guild_queue(ctx, msg).await else {
+        reply(ctx, msg, "❌ Not in a voice channel").await;
+        return Ok(());
+    };
+    queue.modify_queue(|tracks| {
+        if let Some((_, upcoming)) = tracks.make_contiguous().split_first_mut() {
+            upcoming.shuffle(&mut rand::thread_rng());
         }
-    }
+    });
+    reply(ctx, msg, format!("🔀 Shuffled the queue\n{}", queue_listing(&queue))).await;
     Ok(())
 }
 
+/// Remove every song after the one playing now
 #[command]
 #[only_in(guilds)]
-async fn rbalance(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
-    let mut ctx_data = ctx.data.write().await;
-    let roulette_data = ctx_data.entry::<RouletteData>().or_insert(RouletteData{ channel_state: Default::default() });
-    let roulette_state = roulette_data.channel_state.entry(msg.channel_id).or_insert(
-        Arc::new(Mutex::new(RouletteState::new()))
-    );
-    let player_id = msg.author.id;
-    let player_name = msg.author.name.clone();
-    let mut roulette_state_mut = roulette_state.lock().await;
-    roulette_state_mut.register_player(player_id, &player_name);
-    let current_balance = roulette_state_mut.get_balance(player_id);
-    match current_balance {
+async fn clear(ctx: &Context, msg: &Message) -> CommandResult {
+    let Some(queue) = guild_queue(ctx, msg).await else {
+        reply(ctx, msg, "❌ Not in a voice channel").await;
+        return Ok(());
+    };
+    let removed: Vec<_> = queue.modify_queue(|tracks| tracks.drain(1.min(tracks.len())..).collect());
+    stop_removed(removed.iter().map(|track| track.handle()).collect()).await;
+    reply(ctx, msg, format!("🗑️ Removed {} song{}\n{}", removed.len(), if removed.len() == 1 { "" } else { "s" }, queue_listing(&queue))).await;
+    Ok(())
+}
+
+/// Skip straight to a song by its number in `!queue`, dropping everything before it
+#[command]
+#[only_in(guilds)]
+async fn skipto(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let Some(queue) = guild_queue(ctx, msg).await else {
+        reply(ctx, msg, "❌ Not in a voice channel").await;
+        return Ok(());
+    };
+    let position = match queued_position(&mut args, &queue) {
+        Ok(position) => position,
+        Err(e) => {
+            reply(ctx, msg, format!("❌ {e}")).await;
+            return Ok(());
+        }
+    };
+    let skipped = skip_to(&queue, position).await;
+    reply(ctx, msg, format!("→ Skipped {skipped} song{}\n{}", if skipped == 1 { "" } else { "s" }, queue_listing(&queue))).await;
+    Ok(())
+}
+
+/// What makes two tracks the same song: where they came from, or their title if that's unknown
+fn track_key(track: &TrackHandle) -> Option<String> {
+    let metadata = track.metadata();
+    metadata.source_url.clone().or_else(|| metadata.title.clone())
+}
+
+/// Remove songs that are already further up the queue
+#[command]
+#[only_in(guilds)]
+async fn dedupe(ctx: &Context, msg: &Message) -> CommandResult {
+    let Some(queue) = guild_queue(ctx, msg).await else {
+        reply(ctx, msg, "❌ Not in a voice channel").await;
+        return Ok(());
+    };
+    let removed = queue.modify_queue(|tracks| {
+        let mut seen = HashSet::new();
+        let mut removed = vec![];
+        let mut index = 0;
+        while index < tracks.len() {
+            if track_key(&tracks[index]).is_some_and(|key| !seen.insert(key)) {
+                removed.extend(tracks.remove(index));
+            }
+            else {
+                index += 1;
+            }
+        }
+        removed
+    });
+    stop_removed(removed.iter().map(|track| track.handle()).collect()).await;
+    reply(ctx, msg, format!("🧹 Removed {} duplicate{}\n{}", removed.len(), if removed.len() == 1 { "" } else { "s" }, queue_listing(&queue))).await;
+    Ok(())
+}
+
+/// The volume each server plays songs at, as a percentage
+struct VolumeData {
+    guild_volumes: HashMap<GuildId, u16>
+}
+
+impl TypeMapKey for VolumeData {
+    type Value = VolumeData;
+}
+
+const VOLUMES_FILE: &str = "volumes.json";
+
+/// Volume for servers that haven't chosen one, as a percentage
+const DEFAULT_VOLUME: u16 = 100;
+
+const MAX_VOLUME: u16 = 200;
+
+async fn load_volumes(data: &Arc<RwLock<TypeMap>>) -> anyhow::Result<()> {
+    let json = match std::fs::read_to_string(VOLUMES_FILE) {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let volumes: HashMap<u64, u16> = serde_json::from_str(&json)?;
+    let guild_volumes = volumes.into_iter().map(|(guild_id, volume)| (GuildId(guild_id), volume)).collect();
+    data.write().await.insert::<VolumeData>(VolumeData { guild_volumes });
+    Ok(())
+}
+
+async fn get_default_volume(ctx: &Context, guild_id: GuildId) -> u16 {
+    match ctx.data.read().await.get::<VolumeData>() {
+        Some(data) => data.guild_volumes.get(&guild_id).copied().unwrap_or(DEFAULT_VOLUME),
+        None => DEFAULT_VOLUME,
+    }
+}
+
+/// Read a position in a song like `1:23`, `1:02:03` or `83` seconds
+fn parse_position(input: &str) -> Option<Duration> {
+    let mut seconds: u64 = 0;
+    for part in input.trim().split(':') {
+        if part.is_empty() || !part.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        seconds = seconds.checked_mul(60)?.checked_add(part.parse().ok()?)?;
+    }
+    Some(Duration::from_secs(seconds))
+}
+
+/// A position in a song like `1:23`, or `1:02:03` past an hour
+fn format_position(position: Duration) -> String {
+    let seconds = position.as_secs();
+    match seconds / 3600 {
+        0 => format!("{}:{:02}", seconds / 60, seconds % 60),
+        hours => format!("{hours}:{:02}:{:02}", seconds / 60 % 60, seconds % 60),
+    }
+}
+
+/// The song playing now in the message's server
+async fn current_track(ctx: &Context, msg: &Message) -> Result<TrackHandle, &'static str> {
+    let queue = guild_queue(ctx, msg).await.ok_or("❌ Not in a voice channel")?;
+    queue.current().ok_or("❌ Nothing is playing")
+}
+
+/// Move the song playing now to a new position, working out where from how far through it is
+async fn seek_current(ctx: &Context, msg: &Message, position: impl FnOnce(Duration) -> Duration) {
+    let track = match current_track(ctx, msg).await {
+        Ok(track) => track,
+        Err(e) => {
+            reply(ctx, msg, e).await;
+            return;
+        }
+    };
+    let current = match track.get_info().await {
+        Ok(info) => info.position,
+        Err(_) => {
+            reply(ctx, msg, "❌ Nothing is playing").await;
+            return;
+        }
+    };
+    let position = position(current);
+    match track.seek_time(position) {
+        Ok(()) => reply(ctx, msg, format!("⏩ Now at {}", format_position(position))).await,
+        Err(e) => reply(ctx, msg, format!("❌ Couldn't seek: {e}")).await,
+    }
+}
+
+#[command]
+#[only_in(guilds)]
+async fn pause(ctx: &Context, msg: &Message) -> CommandResult {
+    match current_track(ctx, msg).await.map(|track| track.pause()) {
+        Ok(Ok(())) => reply(ctx, msg, "⏸️ Paused").await,
+        Ok(Err(e)) => reply(ctx, msg, format!("❌ Couldn't pause: {e}")).await,
+        Err(e) => reply(ctx, msg, e).await,
+    }
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn resume(ctx: &Context, msg: &Message) -> CommandResult {
+    match current_track(ctx, msg).await.map(|track| track.play()) {
+        Ok(Ok(())) => reply(ctx, msg, "▶️ Resumed").await,
+        Ok(Err(e)) => reply(ctx, msg, format!("❌ Couldn't resume: {e}")).await,
+        Err(e) => reply(ctx, msg, e).await,
+    }
+    Ok(())
+}
+
+/// Stop playing and clear the queue, but stay in the voice channel
+#[command]
+#[only_in(guilds)]
+async fn stop(ctx: &Context, msg: &Message) -> CommandResult {
+    match guild_queue(ctx, msg).await {
+        Some(queue) => {
+            stop_removed(queue.current_queue()).await;
+            queue.stop();
+            reply(ctx, msg, "⏹️ Stopped and cleared the queue").await;
+        },
+        None => reply(ctx, msg, "❌ Not in a voice channel").await,
+    }
+    Ok(())
+}
+
+/// Jump to a point in the song playing now, like `!seek 1:23`
+#[command]
+#[only_in(guilds)]
+async fn seek(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    match parse_position(args.rest()) {
+        Some(position) => seek_current(ctx, msg, |_| position).await,
+        None => reply(ctx, msg, "Usage: `!seek 1:23`").await,
+    }
+    Ok(())
+}
+
+/// Skip ahead in the song playing now by some seconds, like `!forward 30`
+#[command]
+#[only_in(guilds)]
+async fn forward(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    match parse_position(args.rest()) {
+        Some(offset) => seek_current(ctx, msg, |current| current + offset).await,
+        None => reply(ctx, msg, "Usage: `!forward 30`").await,
+    }
+    Ok(())
+}
+
+/// Go back in the song playing now by some seconds, like `!rewind 15`
+#[command]
+#[only_in(guilds)]
+async fn rewind(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    match parse_position(args.rest()) {
+        Some(offset) => seek_current(ctx, msg, |current| current.saturating_sub(offset)).await,
+        None => reply(ctx, msg, "Usage: `!rewind 15`").await,
+    }
+    Ok(())
+}
+
+/// Set the volume from 0 to 200%, which the server keeps for later songs too
+#[command]
+#[only_in(guilds)]
+async fn volume(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let volume = match args.single::<String>() {
+        Ok(volume) => match volume.trim_end_matches('%').parse::<u16>() {
+            Ok(volume) if volume <= MAX_VOLUME => volume,
+            _ => {
+                reply(ctx, msg, format!("Usage: `!volume <0-{MAX_VOLUME}>`")).await;
+                return Ok(());
+            }
+        },
+        Err(_) => {
+            reply(ctx, msg, format!("🔊 Volume is {}%", get_default_volume(ctx, guild_id).await)).await;
+            return Ok(());
+        }
+    };
+    if let Some(queue) = guild_queue(ctx, msg).await {
+        for track in queue.current_queue() {
+            let _ = track.set_volume(volume as f32 / 100.0);
+        }
+    }
+    let volumes: HashMap<u64, u16> = {
+        let mut ctx_data = ctx.data.write().await;
+        let volume_data = ctx_data.entry::<VolumeData>().or_insert(VolumeData{ guild_volumes: Default::default() });
+        volume_data.guild_volumes.insert(guild_id, volume);
+        volume_data.guild_volumes.iter().map(|(guild_id, volume)| (guild_id.0, *volume)).collect()
+    };
+    if let Err(e) = save_json(VOLUMES_FILE, &volumes) {
+        println!("Failed to save volumes: {e}");
+    }
+    reply(ctx, msg, format!("🔊 Volume set to {volume}%")).await;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LoopMode {
+    Off,
+    /// Play the same song over and over
+    Track,
+    /// Put each song back at the end of the queue when it finishes
+    Queue,
+}
+
+impl LoopMode {
+    fn describe(&self) -> &'static str {
+        match self {
+            LoopMode::Off => "off",
+            LoopMode::Track => "this song",
+            LoopMode::Queue => "the whole queue",
+        }
+    }
+}
+
+/// How each server is looping its music
+struct LoopData {
+    guild_modes: HashMap<GuildId, LoopMode>
+}
+
+impl TypeMapKey for LoopData {
+    type Value = LoopData;
+}
+
+async fn get_loop_mode(ctx: &Context, guild_id: GuildId) -> LoopMode {
+    match ctx.data.read().await.get::<LoopData>() {
+        Some(loop_data) => loop_data.guild_modes.get(&guild_id).copied().unwrap_or(LoopMode::Off),
+        None => LoopMode::Off,
+    }
+}
+
+/// Loop the song playing now with `!loop track`, every song in the queue with `!loop queue`, or stop looping with `!loop off`
+#[command("loop")]
+#[only_in(guilds)]
+async fn loop_mode(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let mode = match args.single::<String>().map(|mode| mode.to_lowercase()).as_deref() {
+        Ok("track" | "song" | "one") => LoopMode::Track,
+        Ok("queue" | "all") => LoopMode::Queue,
+        Ok("off" | "none") => LoopMode::Off,
+        Ok(_) => {
+            reply(ctx, msg, "Usage: `!loop track`, `!loop queue` or `!loop off`").await;
+            return Ok(());
+        },
+        Err(_) => {
+            reply(ctx, msg, format!("🔁 Loop: {}", get_loop_mode(ctx, guild_id).await.describe())).await;
+            return Ok(());
+        }
+    };
+    if let Ok(track) = current_track(ctx, msg).await {
+        let looped = match mode {
+            LoopMode::Track => track.enable_loop(),
+            LoopMode::Off | LoopMode::Queue => track.disable_loop(),
+        };
+        if let Err(e) = looped {
+            reply(ctx, msg, format!("❌ Couldn't change looping: {e}")).await;
+            return Ok(());
+        }
+    }
+    {
+        let mut ctx_data = ctx.data.write().await;
+        let loop_data = ctx_data.entry::<LoopData>().or_insert(LoopData{ guild_modes: Default::default() });
+        loop_data.guild_modes.insert(guild_id, mode);
+    }
+    reply(ctx, msg, format!("🔁 Loop: {}", mode.describe())).await;
+    Ok(())
+}
+
+/// Show the song playing now, how far through it is, the volume and the loop mode
+#[command]
+#[aliases("np")]
+#[only_in(guilds)]
+async fn nowplaying(ctx: &Context, msg: &Message) -> CommandResult {
+    let track = match current_track(ctx, msg).await {
+        Ok(track) => track,
+        Err(e) => {
+            reply(ctx, msg, e).await;
+            return Ok(());
+        }
+    };
+    let Ok(info) = track.get_info().await else {
+        reply(ctx, msg, "❌ Nothing is playing").await;
+        return Ok(());
+    };
+    let metadata = track.metadata();
+    let position = match metadata.duration {
+        Some(duration) => format!("{} / {}", format_position(info.position), format_position(duration)),
+        None => format_position(info.position),
+    };
+    let state = match info.playing {
+        PlayMode::Pause => "⏸️ Paused",
+        _ => "▶️ Playing",
+    };
+    let mode = get_loop_mode(ctx, msg.guild_id.unwrap()).await;
+    reply(ctx, msg, format!(
+        "🎵 [{}] {}\n{state} {position}\n🔊 {:.0}%  🔁 Loop: {}",
+        metadata.artist.clone().unwrap_or("Unknown artist".into()),
+        metadata.title.clone().unwrap_or("Unknown title".into()),
+        info.volume * 100.0,
+        mode.describe()
+    )).await;
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn roll(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    roll_and_reply(ctx, msg, args.rest()).await;
+    Ok(())
+}
+
+/// Roll an expression or dice pool and reply with the result.
+/// Several rolls separated by `;` are answered together, one line each
+async fn roll_and_reply(ctx: &Context, msg: &Message, roll_expression: &str) {
+    let parts: Vec<&str> = roll_expression.split(';').map(str::trim).filter(|part| !part.is_empty()).collect();
+    if parts.len() > 1 {
+        let (lines, rolled): (Vec<String>, Vec<Option<Rolled>>) = parts.into_iter().take(MAX_INLINE_ROLLS).map(roll_line).unzip();
+        reply(ctx, msg, lines.join("\n")).await;
+        record_rolls(ctx, msg, rolled.into_iter().flatten().collect()).await;
+        return;
+    }
+    if let Some(pool) = Pool::parse(roll_expression) {
+        match pool {
+            Ok(pool) => {
+                let result = pool.roll(&mut rand::thread_rng());
+                send_pool_roll(ctx, msg, roll_expression, &result).await;
+                record_rolls(ctx, msg, vec![Rolled::from_pool(roll_expression, &result)]).await;
+            },
+            Err(e) => reply(ctx, msg, format!("Invalid dice pool: {e}")).await,
+        }
+        return;
+    }
+    let result = dice::roll(roll_expression, &mut rand::thread_rng());
+    match result {
+        Ok(result) => {
+            send_roll(ctx, msg, &result).await;
+            record_rolls(ctx, msg, vec![Rolled::from_breakdown(&result)]).await;
+        },
+        Err(e) => reply(ctx, msg, format!("Invalid roll expression: {}\n```\n{}\n```", e.message, e.pointer(roll_expression))).await,
+    }
+}
+
+/// Channels where inline `[[rolls]]` have been turned off
+struct InlineRollData {
+    disabled: HashSet<ChannelId>
+}
+
+impl TypeMapKey for InlineRollData {
+    type Value = InlineRollData;
+}
+
+const INLINE_ROLLS_FILE: &str = "inline_rolls.json";
+
+/// Most inline rolls answered from one message
+const MAX_INLINE_ROLLS: usize = 10;
+
+/// Most characters Discord allows in one message
+const MAX_MESSAGE_LENGTH: usize = 2000;
+
+async fn load_inline_roll_settings(data: &Arc<RwLock<TypeMap>>) -> anyhow::Result<()> {
+    let json = match std::fs::read_to_string(INLINE_ROLLS_FILE) {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let disabled: Vec<u64> = serde_json::from_str(&json)?;
+    let disabled = disabled.into_iter().map(ChannelId).collect();
+    data.write().await.insert::<InlineRollData>(InlineRollData { disabled });
+    Ok(())
+}
+
+/// Roll one expression or dice pool as a single line of text, along with the roll to record if it worked
+fn roll_line(expression: &str) -> (String, Option<Rolled>) {
+    if let Some(pool) = Pool::parse(expression) {
+        return match pool {
+            Ok(pool) => {
+                let result = pool.roll(&mut rand::thread_rng());
+                let rolled = Rolled::from_pool(expression, &result);
+                let dice = result.detail.unwrap_or_else(|| dice::format_dice(&result.dice)).replace('\n', "; ");
+                (format!("`{expression}` → **{}** ({dice})", result.outcome), Some(rolled))
+            },
+            Err(e) => (format!("`{expression}` → invalid dice pool: {e}"), None),
+        };
+    }
+    match dice::roll(expression, &mut rand::thread_rng()) {
+        Ok(result) => (result.summary(), Some(Rolled::from_breakdown(&result))),
+        Err(e) => (format!("`{expression}` → invalid roll: {e}"), None),
+    }
+}
+
+/// Split a target like the `>= 14` off the end of `3d6+2 >= 14`.
+/// Anything that already parses as a whole is left alone, so `8d10>=8` still counts successes
+fn split_odds_target(input: &str) -> (&str, Option<Condition>) {
+    let input = input.trim();
+    if Expression::parse(input).is_ok() {
+        return (input, None);
+    }
+    let without_number = input.trim_end_matches(|c: char| c.is_ascii_digit());
+    let Ok(value) = input[without_number.len()..].parse::<i64>() else {
+        return (input, None);
+    };
+    let (without_number, value) = match without_number.strip_suffix('-') {
+        Some(rest) => (rest, -value),
+        None => (without_number, value),
+    };
+    let rest = without_number.trim_end();
+    let operators = [(">=", Compare::GreaterEqual), ("<=", Compare::LessEqual), (">", Compare::Greater), ("<", Compare::Less), ("=", Compare::Equal)];
+    for (operator, compare) in operators {
+        if let Some(expression) = rest.strip_suffix(operator) {
+            return (expression.trim(), Some(Condition { compare, value }));
+        }
+    }
+    (input, None)
+}
+
+#[command]
+#[only_in(guilds)]
+async fn odds(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let (expression, target) = split_odds_target(args.rest());
+    if expression.is_empty() {
+        reply(ctx, msg, "Usage: `!odds <expression> [>= target]`, e.g. `!odds 3d6+2 >= 14`").await;
+        return Ok(());
+    }
+    // Working out the odds can take a while, so keep it off the async runtime
+    let source = expression.to_string();
+    let distribution = tokio::task::spawn_blocking(move || Expression::parse(&source).and_then(|parsed| parsed.distribution())).await?;
+    let distribution = match distribution {
+        Ok(distribution) => distribution,
+        Err(e) => {
+            reply(ctx, msg, format!("Can't work out the odds: {}\n```\n{}\n```", e.message, e.pointer(expression))).await;
+            return Ok(());
+        },
+    };
+
+    let mut response = format!(
+        "`{expression}`\nMean **{:.2}**, standard deviation **{:.2}**\n",
+        distribution.mean(),
+        distribution.standard_deviation()
+    );
+    if let Some(target) = target {
+        let symbol = match target.compare {
+            Compare::Equal => "=",
+            Compare::Less => "<",
+            Compare::LessEqual => "≤",
+            Compare::Greater => ">",
+            Compare::GreaterEqual => "≥",
+        };
+        let chance = distribution.probability(|value| target.matches(value));
+        response.push_str(&format!("Chance of {symbol} {}: **{:.2}%**\n", target.value, chance * 100.0));
+    }
+    let matches_target = |value: i64| target.is_some_and(|target| target.matches(value));
+    let histogram = distribution.histogram(target.map(|_| &matches_target as &dyn Fn(i64) -> bool));
+    response.push_str(&format!("```\n{histogram}\n```"));
+    reply(ctx, msg, response).await;
+    Ok(())
+}
+
+/// Answer every `[[roll]]` in a message, Roll20 style
+async fn inline_rolls(ctx: &Context, msg: &Message) {
+    let disabled = match ctx.data.read().await.get::<InlineRollData>() {
+        Some(inline_roll_data) => inline_roll_data.disabled.contains(&msg.channel_id),
+        None => false,
+    };
+    if disabled {
+        return;
+    }
+    let (lines, rolled): (Vec<String>, Vec<Option<Rolled>>) = dice::find_inline_rolls(&msg.content).into_iter()
+        .filter(|expression| !expression.is_empty())
+        .take(MAX_INLINE_ROLLS)
+        .map(roll_line)
+        .unzip();
+    if lines.is_empty() {
+        return;
+    }
+    reply(ctx, msg, truncate_message(lines.join("\n"))).await;
+    record_rolls(ctx, msg, rolled.into_iter().flatten().collect()).await;
+}
+
+/// Turn inline `[[rolls]]` on or off in this channel with `!inlinerolls on` or `!inlinerolls off`
+#[command]
+#[only_in(guilds)]
+async fn inlinerolls(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    if !is_admin(ctx, msg).await {
+        reply(ctx, msg, "❌ Only administrators can change inline rolls").await;
+        return Ok(());
+    }
+    let enabled = match args.rest().trim().to_lowercase().as_str() {
+        "on" => true,
+        "off" => false,
+        _ => {
+            reply(ctx, msg, "Usage: `!inlinerolls on` or `!inlinerolls off`").await;
+            return Ok(());
+        }
+    };
+    let disabled: Vec<u64> = {
+        let mut ctx_data = ctx.data.write().await;
+        let inline_roll_data = ctx_data.entry::<InlineRollData>().or_insert(InlineRollData{ disabled: Default::default() });
+        if enabled {
+            inline_roll_data.disabled.remove(&msg.channel_id);
+        }
+        else {
+            inline_roll_data.disabled.insert(msg.channel_id);
+        }
+        inline_roll_data.disabled.iter().map(|channel_id| channel_id.0).collect()
+    };
+    if let Err(e) = save_json(INLINE_ROLLS_FILE, &disabled) {
+        println!("Failed to save inline roll settings: {e}");
+    }
+    let state = if enabled { "on" } else { "off" };
+    reply(ctx, msg, format!("```\nInline rolls are now {state} in this channel\n```")).await;
+    Ok(())
+}
+
+/// Most fields Discord allows in one embed
+const MAX_EMBED_FIELDS: usize = 25;
+
+/// Most characters Discord allows in one embed field
+const MAX_FIELD_LENGTH: usize = 1024;
+
+fn truncate_field(value: String) -> String {
+    if value.chars().count() <= MAX_FIELD_LENGTH {
+        return value;
+    }
+    value.chars().take(MAX_FIELD_LENGTH - 1).collect::<String>() + "…"
+}
+
+fn truncate_message(value: String) -> String {
+    if value.chars().count() <= MAX_MESSAGE_LENGTH {
+        return value;
+    }
+    value.chars().take(MAX_MESSAGE_LENGTH - 1).collect::<String>() + "…"
+}
+
+/// Reply with an embed showing every die and modifier that went into a roll
+async fn send_roll(ctx: &Context, msg: &Message, roll: &RollBreakdown) {
+    let label = roll.label.as_ref().map(|label| format!("{label}: ")).unwrap_or_default();
+    let total = match roll.is_fate() {
+        true => dice::fate_ladder(roll.total),
+        false => roll.total.to_string(),
+    };
+    let (title, colour) = match roll.critical() {
+        Some(Critical::Success) => (format!("🎉 Critical success! {label}{total}"), Colour::DARK_GREEN),
+        Some(Critical::Failure) => (format!("💀 Critical failure! {label}{total}"), Colour::RED),
+        None => (format!("🎲 {label}{total}"), Colour::BLURPLE),
+    };
+    let fields: Vec<(String, String, bool)> = roll.terms.iter()
+        .take(MAX_EMBED_FIELDS)
+        .map(|term| match term {
+            TermResult::Dice { expression, label, dice, successes, total } => {
+                let name = match label {
+                    Some(label) => format!("{expression} ({label})"),
+                    None => expression.clone(),
+                };
+                let total = match successes {
+                    true => format!("{total} success{}", if *total == 1 { "" } else { "es" }),
+                    false => total.to_string(),
+                };
+                (name, truncate_field(format!("{} = {total}", dice::format_dice(dice))), true)
+            },
+            TermResult::Modifier(modifier) => ("Modifier".into(), format!("{modifier:+}"), true),
+        })
+        .collect();
+    check_msg(msg.channel_id.send_message(&ctx.http, |m| {
+        m.reference_message(msg).embed(|e| {
+            e.title(title)
+                .description(format!("{} rolled `{}`", msg.author.name, roll.expression))
+                .fields(fields)
+                .footer(|f| f.text(format!("Total: {}", roll.total)))
+                .colour(colour)
+        })
+    }).await);
+}
+
+/// Reply with an embed showing a dice pool's dice and what they mean in its system
+async fn send_pool_roll(ctx: &Context, msg: &Message, pool: &str, result: &PoolResult) {
+    let colour = match result.tier {
+        Tier::Critical => Colour::DARK_GREEN,
+        Tier::Success => Colour::BLURPLE,
+        Tier::Partial => Colour::GOLD,
+        Tier::Failure => Colour::LIGHT_GREY,
+        Tier::Disaster => Colour::RED,
+    };
+    check_msg(msg.channel_id.send_message(&ctx.http, |m| {
+        m.reference_message(msg).embed(|e| {
+            e.title(format!("🎲 {}", result.outcome))
+                .description(format!("{} rolled `{pool}`", msg.author.name))
+                .field("Dice", truncate_field(result.detail.clone().unwrap_or_else(|| dice::format_dice(&result.dice))), false)
+                .footer(|f| f.text(result.system))
+                .colour(colour)
+        })
+    }).await);
+}
+
+struct RouletteData {
+    channel_state: HashMap<ChannelId, Arc<Mutex<RouletteState<UserId>>>>
+}
+
+impl TypeMapKey for RouletteData {
+    type Value = RouletteData;
+}
+
+/// Where every channel's balances are kept between restarts
+const BALANCES_FILE: &str = "balances.json";
+
+/// The balances as they were last written, so they're only written again when something changed
+struct SavedBalancesData;
+
+impl TypeMapKey for SavedBalancesData {
+    type Value = String;
+}
+
+/// Write every channel's balances to the balances file if they've changed since last time.
+/// Stakes held for open duels are saved as part of the challengers' balances, so they're given back after a restart.
+/// Don't call this while holding a roulette state or duel ledger lock
+async fn save_balances(data: &Arc<RwLock<TypeMap>>) {
+    let (states, ledgers) = {
+        let ctx_data = data.read().await;
+        let states: Vec<(ChannelId, Arc<Mutex<RouletteState<UserId>>>)> = match ctx_data.get::<RouletteData>() {
+            Some(roulette_data) => roulette_data.channel_state.iter().map(|(channel_id, state)| (*channel_id, state.clone())).collect(),
+            None => return,
+        };
+        let ledgers: HashMap<ChannelId, Arc<Mutex<DuelLedger<UserId>>>> = match ctx_data.get::<DuelData>() {
+            Some(duel_data) => duel_data.channel_state.clone(),
+            None => HashMap::new(),
+        };
+        (states, ledgers)
+    };
+    let mut balances: HashMap<u64, Vec<SavedBalance<UserId>>> = HashMap::new();
+    for (channel_id, state) in states {
+        // Lock the channel's duels along with its balances, so a stake is saved in exactly one of them
+        let state = state.lock().await;
+        let mut saved = state.saved_balances();
+        if let Some(ledger) = ledgers.get(&channel_id) {
+            duel::save_escrow(ledger.lock().await.pending(), &mut saved);
+        }
+        drop(state);
+        if !saved.is_empty() {
+            balances.insert(channel_id.0, saved);
+        }
+    }
+    let json = match serde_json::to_string_pretty(&balances) {
+        Ok(json) => json,
+        Err(e) => {
+            println!("Failed to save balances: {e}");
+            return;
+        }
+    };
+    // Hold the write lock while writing, so two saves can't finish out of order
+    let mut ctx_data = data.write().await;
+    if ctx_data.get::<SavedBalancesData>() == Some(&json) {
+        return;
+    }
+    match write_atomically(BALANCES_FILE, &json).await {
+        Ok(()) => {
+            ctx_data.insert::<SavedBalancesData>(json);
+        },
+        Err(e) => println!("Failed to save balances: {e}"),
+    }
+}
+
+async fn load_balances(data: &Arc<RwLock<TypeMap>>) -> anyhow::Result<()> {
+    let json = match std::fs::read_to_string(BALANCES_FILE) {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let balances: HashMap<u64, Vec<SavedBalance<UserId>>> = serde_json::from_str(&json)?;
+    let channel_state = balances.into_iter()
+        .map(|(channel_id, saved)| {
+            let mut state = RouletteState::new();
+            state.restore_balances(saved);
+            (ChannelId(channel_id), Arc::new(Mutex::new(state)))
+        })
+        .collect();
+    let mut ctx_data = data.write().await;
+    ctx_data.insert::<RouletteData>(RouletteData { channel_state });
+    ctx_data.insert::<SavedBalancesData>(json);
+    Ok(())
+}
+
+/// Save balances after every command, since most games pay out or take stakes as they're played
+#[hook]
+async fn after_command(ctx: &Context, _msg: &Message, _command_name: &str, _result: CommandResult) {
+    save_balances(&ctx.data).await;
+}
+
+/// Save balances changed outside of commands, like by scheduled spins and crash rounds, every few seconds
+async fn run_balance_saves(data: Arc<RwLock<TypeMap>>) {
+    loop {
+        tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
+        save_balances(&data).await;
+    }
+}
+
+#[command]
+#[only_in(guilds)]
+async fn rbet(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let mut ctx_data = ctx.data.write().await;
+    let roulette_data = ctx_data.entry::<RouletteData>().or_insert(RouletteData{ channel_state: Default::default() });
+    let roulette_state = roulette_data.channel_state.entry(msg.channel_id).or_insert(
+        Arc::new(Mutex::new(RouletteState::new()))
+    );
+    let player_id = msg.author.id;
+    let player_name = msg.author.name.clone();
+    let mut roulette_state_mut = roulette_state.lock().await;
+    roulette_state_mut.register_player(player_id, &player_name);
+    let current_balance = roulette_state_mut.get_balance(player_id);
+    if let Ok(current_balance) = current_balance {
+        if let Ok(current_bets) = roulette_state_mut.get_bets(player_id) {
+            if current_balance == 0 && current_bets.len() == 0 {
+                let set_money_result = roulette_state_mut.set_balance(player_id, 5);
+                if set_money_result.is_ok() {
+                    reply(ctx, msg, format!("```\nYou seem broke. Since I feel sorry for you, have 5 money units...\n```")).await;
+                }
+            }
+        }
+    }
+    let bet_result = roulette_state_mut.play_bet_command(player_id, args.rest());
+    if let Err(e) = bet_result {
+        reply(ctx, msg, format!("Betting failed:\n{}", e.to_string())).await;
+    }
+    let current_bets = roulette_state_mut.get_bets(player_id);
+    match current_bets {
+        Ok(current_bets) => {
+            let bets: Vec<String> = current_bets.into_iter().map(|bet| format!("- {bet:?}")).collect();
+            reply(ctx, msg, format!("Current bets:\n```\n{}\n```", bets.join("\n"))).await;
+        },
+        Err(e) => {
+            reply(ctx, msg, format!("Unable to get current bets: {}", e.to_string())).await;
+        }
+    }
+    let current_balance = roulette_state_mut.get_balance(player_id);
+    match current_balance {
+        Ok(current_balance) => {
+            reply(ctx, msg, format!("```\nYour new balance is {current_balance}\n```")).await;
+        },
+        Err(e) => {
+            reply(ctx, msg, format!("Unable to get current balance: {}", e.to_string())).await;
+        }
+    }
+
+    if !roulette_state_mut.spin_scheduled {
+        roulette_state_mut.spin_scheduled = true;
+        let _ = msg.channel_id.say(ctx, "```\nWheel will stop spinning in 25 seconds. Place your bets!\n```").await;
+        let http = ctx.http.clone();
+        let roulette_state = roulette_state.clone();
+        let channel_id = msg.channel_id.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(tokio::time::Duration::from_secs(15)).await;
+            let _ = channel_id.say(&http, "```\nWheel will stop spinning in 10 seconds. Finalize your bets!\n```").await;
+            tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
+            let mut roulette_state = roulette_state.lock().await;
+            roulette_state.lock_bets();
+            let _ = channel_id.say(&http, "Bets have been finalized!").await;
+            let spin_result = roulette_state.spin();
+            let payouts: Vec<String> = spin_result.outcomes.into_iter().map(|outcome| {
+                let winnings = match outcome.net {
+                    net if net > 0 => format!("won **{net}**"),
+                    net if net < 0 => format!("lost {}", -net),
+                    _ => "broke even".into(),
+                };
+                let winning_bets: Vec<String> = outcome.winning_bets.iter()
+                    .map(|bet| format!("{:?} ({})", bet.bet(), bet.amount()))
+                    .collect();
+                let winning_bets = if winning_bets.is_empty() {
+                    String::new()
+                } else {
+                    format!(" on {}", winning_bets.join(", "))
+                };
+                format!("- {} {winnings}{winning_bets} (new balance: {})", outcome.player_id.mention(), outcome.balance)
+            }).collect();
+            let payouts = payouts.join("\n");
+            let color = match spin_result.result {
+                0 => "Green",
+                _ => {
+                    if roulette::is_red(spin_result.result) {
+                        "Red"
+                    }
+                    else {
+                        "Black"
+                    }
+                }
+            };
+            let _ = channel_id.say(&http, format!("**Landed on {}** (**{color}**)\nPayouts:\n{payouts}", spin_result.result)).await;
+        });
+    }
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn rclearlast(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let mut ctx_data = ctx.data.write().await;
+    let roulette_data = ctx_data.entry::<RouletteData>().or_insert(RouletteData{ channel_state: Default::default() });
+    let roulette_state = roulette_data.channel_state.entry(msg.channel_id).or_insert(
+        Arc::new(Mutex::new(RouletteState::new()))
+    );
+    let player_id = msg.author.id;
+    let player_name = msg.author.name.clone();
+    let mut roulette_state_mut = roulette_state.lock().await;
+    roulette_state_mut.register_player(player_id, &player_name);
+    let bet_result = roulette_state_mut.clear_last_bet(player_id);
+    if let Err(e) = bet_result {
+        reply(ctx, msg, format!("```\nUnable to clear last bet:\n{}\n```", e.to_string())).await;
+    }
+    let current_bets = roulette_state_mut.get_bets(player_id);
+    match current_bets {
+        Ok(current_bets) => {
+            let bets: Vec<String> = current_bets.into_iter().map(|bet| format!("- {bet:?}")).collect();
+            reply(ctx, msg, format!("Current bets:\n```\n{}\n```", bets.join("\n"))).await;
+        },
+        Err(e) => {
+            reply(ctx, msg, format!("Unable to get current bets: {}", e.to_string())).await;
+        }
+    }
+    let current_balance = roulette_state_mut.get_balance(player_id);
+    match current_balance {
+        Ok(current_balance) => {
+            reply(ctx, msg, format!("```\nYour new balance is {current_balance}\n```")).await;
+        },
+        Err(e) => {
+            reply(ctx, msg, format!("Unable to get current balance: {}", e.to_string())).await;
+        }
+    }
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn rclearall(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let mut ctx_data = ctx.data.write().await;
+    let roulette_data = ctx_data.entry::<RouletteData>().or_insert(RouletteData{ channel_state: Default::default() });
+    let roulette_state = roulette_data.channel_state.entry(msg.channel_id).or_insert(
+        Arc::new(Mutex::new(RouletteState::new()))
+    );
+    let player_id = msg.author.id;
+    let player_name = msg.author.name.clone();
+    let mut roulette_state_mut = roulette_state.lock().await;
+    roulette_state_mut.register_player(player_id, &player_name);
+    let bet_result = roulette_state_mut.clear_all_bets(player_id);
+    if let Err(e) = bet_result {
+        reply(ctx, msg, format!("```\nUnable to clear all bets:\n{}\n```", e.to_string())).await;
+    }
+    let current_bets = roulette_state_mut.get_bets(player_id);
+    match current_bets {
+        Ok(current_bets) => {
+            let bets: Vec<String> = current_bets.into_iter().map(|bet| format!("- {bet:?}")).collect();
+            reply(ctx, msg, format!("Current bets:\n```\n{}\n```", bets.join("\n"))).await;
+        },
+        Err(e) => {
+            reply(ctx, msg, format!("Unable to get current bets: {}", e.to_string())).await;
+        }
+    }
+    let current_balance = roulette_state_mut.get_balance(player_id);
+    match current_balance {
+        Ok(current_balance) => {
+            reply(ctx, msg, format!("```\nYour new balance is {current_balance}\n```")).await;
+        },
+        Err(e) => {
+            reply(ctx, msg, format!("Unable to get current balance: {}", e.to_string())).await;
+        }
+    }
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn rbalance(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let mut ctx_data = ctx.data.write().await;
+    let roulette_data = ctx_data.entry::<RouletteData>().or_insert(RouletteData{ channel_state: Default::default() });
+    let roulette_state = roulette_data.channel_state.entry(msg.channel_id).or_insert(
+        Arc::new(Mutex::new(RouletteState::new()))
+    );
+    let player_id = msg.author.id;
+    let player_name = msg.author.name.clone();
+    let mut roulette_state_mut = roulette_state.lock().await;
+    roulette_state_mut.register_player(player_id, &player_name);
+    let current_balance = roulette_state_mut.get_balance(player_id);
+    match current_balance {
         Ok(current_balance) => {
             reply(ctx, msg, format!("```\nYour balance is {current_balance}\n```")).await;
         },
         Err(e) => {
-            reply(ctx, msg, format!("Unable to get current balance: {}", e.to_string())).await;
+            reply(ctx, msg, format!("Unable to get current balance: {}", e.to_string())).await;
+        }
+    }
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn rbets(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let mut ctx_data = ctx.data.write().await;
+    let roulette_data = ctx_data.entry::<RouletteData>().or_insert(RouletteData{ channel_state: Default::default() });
+    let roulette_state = roulette_data.channel_state.entry(msg.channel_id).or_insert(
+        Arc::new(Mutex::new(RouletteState::new()))
+    );
+    let player_id = msg.author.id;
+    let player_name = msg.author.name.clone();
+    let mut roulette_state_mut = roulette_state.lock().await;
+    roulette_state_mut.register_player(player_id, &player_name);
+    let current_bets = roulette_state_mut.get_bets(player_id);
+    match current_bets {
+        Ok(current_bets) => {
+            let bets: Vec<String> = current_bets.into_iter().map(|bet| format!("- {bet:?}")).collect();
+            reply(ctx, msg, format!("Current bets:\n```\n{}\n```", bets.join("\n"))).await;
+        },
+        Err(e) => {
+            reply(ctx, msg, format!("Unable to get current bets: {}", e.to_string())).await;
+        }
+    }
+    Ok(())
+}
+
+/// Get the roulette state for a channel, which also holds the balances every other game bets with
+async fn get_roulette_state(ctx: &Context, channel_id: ChannelId) -> Arc<Mutex<RouletteState<UserId>>> {
+    let mut ctx_data = ctx.data.write().await;
+    let roulette_data = ctx_data.entry::<RouletteData>().or_insert(RouletteData{ channel_state: Default::default() });
+    roulette_data.channel_state.entry(channel_id).or_insert(
+        Arc::new(Mutex::new(RouletteState::new()))
+    ).clone()
+}
+
+struct BlackjackData {
+    channel_state: HashMap<ChannelId, Arc<Mutex<BlackjackTable<UserId>>>>
+}
+
+impl TypeMapKey for BlackjackData {
+    type Value = BlackjackData;
+}
+
+async fn get_blackjack_table(ctx: &Context, channel_id: ChannelId) -> Arc<Mutex<BlackjackTable<UserId>>> {
+    let mut ctx_data = ctx.data.write().await;
+    let blackjack_data = ctx_data.entry::<BlackjackData>().or_insert(BlackjackData{ channel_state: Default::default() });
+    blackjack_data.channel_state.entry(channel_id).or_insert(
+        Arc::new(Mutex::new(BlackjackTable::new(Default::default())))
+    ).clone()
+}
+
+/// How long a blackjack player has to act before their hand stands
+const BLACKJACK_TURN_SECONDS: u64 = 60;
+
+fn blackjack_status(table: &BlackjackTable<UserId>) -> String {
+    let mut status = format!("**Dealer:** {}", table.dealer_cards());
+    if table.phase() == Phase::Finished {
+        for result in table.results() {
+            let outcome = match result.outcome {
+                HandOutcome::Blackjack => "Blackjack!",
+                HandOutcome::Win => "Win",
+                HandOutcome::Push => "Push",
+                HandOutcome::Lose => "Lose",
+                HandOutcome::Bust => "Bust",
+            };
+            let net = match result.net {
+                net if net > 0 => format!("+{net}"),
+                net => net.to_string(),
+            };
+            status += &format!("\n- {}: `{}` **{outcome}** ({net})", result.player_id.mention(), result.hand);
+        }
+        return status;
+    }
+    for seat in table.seats() {
+        let hands: Vec<String> = seat.hands.iter().map(|hand| format!("`{hand}`")).collect();
+        let insurance = match seat.insurance {
+            Some(insurance) => format!(" (insured for {insurance})"),
+            None => String::new(),
+        };
+        status += &format!("\n- {}: {}{insurance}", seat.player_id.mention(), hands.join(" | "));
+    }
+    match table.phase() {
+        Phase::Insurance => status += "\nThe dealer shows an ace. Use `!bjinsure` to take insurance!",
+        Phase::PlayerTurns => {
+            if let Some(player_id) = table.current_player() {
+                status += &format!(
+                    "\nIt's {}'s turn with {BLACKJACK_TURN_SECONDS} seconds to act: `!bjhit`, `!bjstand`, `!bjdouble` or `!bjsplit`",
+                    player_id.mention()
+                );
+            }
+        },
+        _ => {},
+    }
+    status
+}
+
+/// Stand whoever is to act if they take too long, for as long as the hand keeps timing out
+fn schedule_blackjack_timer(http: Arc<Http>, channel_id: ChannelId, roulette_state: Arc<Mutex<RouletteState<UserId>>>, blackjack_table: Arc<Mutex<BlackjackTable<UserId>>>, action_id: u64) {
+    tokio::spawn(async move {
+        let mut action_id = action_id;
+        loop {
+            tokio::time::sleep(tokio::time::Duration::from_secs(BLACKJACK_TURN_SECONDS)).await;
+            let mut roulette_state = roulette_state.lock().await;
+            let mut table = blackjack_table.lock().await;
+            let timed_out_player = table.current_player().copied();
+            match table.time_out(action_id, &mut roulette_state) {
+                Ok(true) => {},
+                Ok(false) => return,
+                Err(e) => {
+                    println!("Failed to time out blackjack player: {e}");
+                    return;
+                }
+            }
+            if let Some(player_id) = timed_out_player {
+                let _ = channel_id.say(&http, format!("⏰ {} ran out of time and stands\n{}", player_id.mention(), blackjack_status(&table))).await;
+            }
+            if table.phase() != Phase::PlayerTurns {
+                return;
+            }
+            action_id = table.action_id();
+        }
+    });
+}
+
+#[command]
+#[only_in(guilds)]
+async fn bjbet(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let amount = match args.rest().trim().parse::<u128>() {
+        Ok(amount) => amount,
+        Err(_) => {
+            reply(ctx, msg, "Usage: `!bjbet <amount>`").await;
+            return Ok(());
+        }
+    };
+    let roulette_state = get_roulette_state(ctx, msg.channel_id).await;
+    let blackjack_table = get_blackjack_table(ctx, msg.channel_id).await;
+    let mut roulette_state_mut = roulette_state.lock().await;
+    let mut table = blackjack_table.lock().await;
+    roulette_state_mut.register_player(msg.author.id, &msg.author.name);
+    if let Err(e) = table.join(msg.author.id, amount, &mut roulette_state_mut) {
+        reply(ctx, msg, format!("Couldn't join the table:\n{e}")).await;
+        return Ok(());
+    }
+    if let Ok(balance) = roulette_state_mut.get_balance(msg.author.id) {
+        reply(ctx, msg, format!("```\nYou bet {amount}. Your new balance is {balance}\n```")).await;
+    }
+
+    if !table.deal_scheduled {
+        table.deal_scheduled = true;
+        let _ = msg.channel_id.say(ctx, format!("```\nDealing blackjack in 20 seconds ({}). Place your bets with !bjbet!\n```", table.rules())).await;
+        let http = ctx.http.clone();
+        let roulette_state = roulette_state.clone();
+        let blackjack_table = blackjack_table.clone();
+        let channel_id = msg.channel_id;
+        tokio::spawn(async move {
+            tokio::time::sleep(tokio::time::Duration::from_secs(20)).await;
+            {
+                let mut roulette_state_mut = roulette_state.lock().await;
+                let mut table = blackjack_table.lock().await;
+                if let Err(e) = table.deal(&mut roulette_state_mut) {
+                    let _ = channel_id.say(&http, format!("Couldn't deal: {e}")).await;
+                    return;
+                }
+                let _ = channel_id.say(&http, blackjack_status(&table)).await;
+                if table.phase() == Phase::PlayerTurns {
+                    schedule_blackjack_timer(http.clone(), channel_id, roulette_state.clone(), blackjack_table.clone(), table.action_id());
+                }
+                if table.phase() != Phase::Insurance {
+                    return;
+                }
+            }
+            tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
+            let mut roulette_state_mut = roulette_state.lock().await;
+            let mut table = blackjack_table.lock().await;
+            if let Err(e) = table.finish_insurance(&mut roulette_state_mut) {
+                let _ = channel_id.say(&http, format!("Couldn't settle insurance: {e}")).await;
+                return;
+            }
+            let _ = channel_id.say(&http, blackjack_status(&table)).await;
+            if table.phase() == Phase::PlayerTurns {
+                schedule_blackjack_timer(http.clone(), channel_id, roulette_state.clone(), blackjack_table.clone(), table.action_id());
+            }
+        });
+    }
+    Ok(())
+}
+
+enum BlackjackAction {
+    Hit,
+    Stand,
+    Double,
+    Split,
+    Insure
+}
+
+async fn blackjack_action(ctx: &Context, msg: &Message, action: BlackjackAction) -> CommandResult {
+    let roulette_state = get_roulette_state(ctx, msg.channel_id).await;
+    let blackjack_table = get_blackjack_table(ctx, msg.channel_id).await;
+    let mut roulette_state_mut = roulette_state.lock().await;
+    let mut table = blackjack_table.lock().await;
+    roulette_state_mut.register_player(msg.author.id, &msg.author.name);
+    let player_id = msg.author.id;
+    let result = match action {
+        BlackjackAction::Hit => table.hit(player_id, &mut roulette_state_mut),
+        BlackjackAction::Stand => table.stand(player_id, &mut roulette_state_mut),
+        BlackjackAction::Double => table.double(player_id, &mut roulette_state_mut),
+        BlackjackAction::Split => table.split(player_id, &mut roulette_state_mut),
+        BlackjackAction::Insure => table.insure(player_id, &mut roulette_state_mut),
+    };
+    match result {
+        Ok(()) => {
+            check_msg(msg.channel_id.say(&ctx.http, blackjack_status(&table)).await);
+            if table.phase() == Phase::PlayerTurns && !matches!(action, BlackjackAction::Insure) {
+                schedule_blackjack_timer(ctx.http.clone(), msg.channel_id, roulette_state.clone(), blackjack_table.clone(), table.action_id());
+            }
+        },
+        Err(e) => reply(ctx, msg, format!("```\n{e}\n```")).await,
+    }
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn bjhit(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    blackjack_action(ctx, msg, BlackjackAction::Hit).await
+}
+
+#[command]
+#[only_in(guilds)]
+async fn bjstand(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    blackjack_action(ctx, msg, BlackjackAction::Stand).await
+}
+
+#[command]
+#[only_in(guilds)]
+async fn bjdouble(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    blackjack_action(ctx, msg, BlackjackAction::Double).await
+}
+
+#[command]
+#[only_in(guilds)]
+async fn bjsplit(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    blackjack_action(ctx, msg, BlackjackAction::Split).await
+}
+
+#[command]
+#[only_in(guilds)]
+async fn bjinsure(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    blackjack_action(ctx, msg, BlackjackAction::Insure).await
+}
+
+#[command]
+#[only_in(guilds)]
+async fn bjtable(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let blackjack_table = get_blackjack_table(ctx, msg.channel_id).await;
+    let table = blackjack_table.lock().await;
+    reply(ctx, msg, format!("Rules: {}\n{}", table.rules(), blackjack_status(&table))).await;
+    Ok(())
+}
+
+/// Change the table rules, e.g. `!bjrules h17 6:5 8`
+#[command]
+#[only_in(guilds)]
+async fn bjrules(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let blackjack_table = get_blackjack_table(ctx, msg.channel_id).await;
+    let mut table = blackjack_table.lock().await;
+    let mut rules = table.rules();
+    let result = rules.apply_string(args.rest()).and_then(|_| table.set_rules(rules));
+    match result {
+        Ok(()) => reply(ctx, msg, format!("```\nBlackjack rules: {}\n```", table.rules())).await,
+        Err(e) => reply(ctx, msg, format!("Unable to change rules:\n{e}")).await,
+    }
+    Ok(())
+}
+
+struct PokerData {
+    channel_state: HashMap<ChannelId, Arc<Mutex<PokerTable<UserId>>>>
+}
+
+impl TypeMapKey for PokerData {
+    type Value = PokerData;
+}
+
+/// How long a poker player has to act before they are checked or folded
+const POKER_TURN_SECONDS: u64 = 60;
+
+async fn get_poker_table(ctx: &Context, channel_id: ChannelId) -> Arc<Mutex<PokerTable<UserId>>> {
+    let mut ctx_data = ctx.data.write().await;
+    let poker_data = ctx_data.entry::<PokerData>().or_insert(PokerData{ channel_state: Default::default() });
+    poker_data.channel_state.entry(channel_id).or_insert(
+        Arc::new(Mutex::new(PokerTable::new(5, 10)))
+    ).clone()
+}
+
+fn poker_status(table: &PokerTable<UserId>) -> String {
+    let mut status = String::new();
+    if table.street() == Street::Waiting {
+        if let Some(last_hand) = table.last_hand() {
+            status += &format!("**Board:** {}", poker::describe_cards(&last_hand.board));
+            for (player_id, hole_cards, value) in last_hand.shown.iter() {
+                status += &format!("\n- {} shows `{}`: {value}", player_id.mention(), poker::describe_cards(hole_cards));
+            }
+            for (index, pot) in last_hand.pots.iter().enumerate() {
+                let pot_name = if index == 0 { "Main pot".to_string() } else { format!("Side pot {index}") };
+                let winners: Vec<String> = pot.winners.iter().map(|winner| winner.mention().to_string()).collect();
+                let hand = match &pot.hand {
+                    Some(hand) => format!(" with {}", hand.category),
+                    None => String::new(),
+                };
+                status += &format!("\n{pot_name} of {} goes to {}{hand}", pot.amount, winners.join(", "));
+            }
+            status += "\n";
+        }
+        let (small_blind, big_blind) = table.blinds();
+        status += &format!("Waiting for the next hand (blinds {small_blind}/{big_blind}). Use `!pkdeal` to start");
+        for seat in table.seats() {
+            status += &format!("\n- {}: {} chips", seat.player_id.mention(), seat.stack);
+        }
+        return status;
+    }
+
+    status += &format!("**Board:** {} | **Pot:** {}", poker::describe_cards(table.board()), table.pot());
+    for seat in table.seats() {
+        let mut state = String::new();
+        if table.button_player() == Some(&seat.player_id) {
+            state += " (D)";
+        }
+        if !seat.in_hand {
+            state += " sitting out";
+        }
+        else if seat.folded {
+            state += " folded";
+        }
+        else if seat.all_in {
+            state += " all in";
+        }
+        status += &format!("\n- {}{state}: {} chips, bet {}", seat.player_id.mention(), seat.stack, seat.street_bet);
+    }
+    if let Some(player_id) = table.current_player() {
+        status += &format!(
+            "\nIt's {}'s turn ({} to call) with {POKER_TURN_SECONDS} seconds to act: `!pkcheck`, `!pkcall`, `!pkraise <to>`, `!pkallin` or `!pkfold`",
+            player_id.mention(),
+            table.current_bet()
+        );
+    }
+    status
+}
+
+/// Send a player their hole cards in a direct message
+async fn send_hole_cards(http: &Arc<Http>, channel_id: ChannelId, table: &PokerTable<UserId>, player_id: UserId) {
+    let hole_cards = match table.hole_cards(&player_id) {
+        Some(hole_cards) => poker::describe_cards(hole_cards),
+        None => return,
+    };
+    let dm_result = match player_id.create_dm_channel(http).await {
+        Ok(dm_channel) => dm_channel.say(http, format!("Your hole cards in <#{channel_id}>: **{hole_cards}**")).await,
+        Err(e) => Err(e),
+    };
+    if dm_result.is_err() {
+        let _ = channel_id.say(http, format!("{} I couldn't DM you your cards. Please allow direct messages from server members", player_id.mention())).await;
+    }
+}
+
+/// Check or fold whoever is to act if they take too long, for as long as the hand keeps timing out
+fn schedule_poker_timer(http: Arc<Http>, channel_id: ChannelId, poker_table: Arc<Mutex<PokerTable<UserId>>>, action_id: u64) {
+    tokio::spawn(async move {
+        let mut action_id = action_id;
+        loop {
+            tokio::time::sleep(tokio::time::Duration::from_secs(POKER_TURN_SECONDS)).await;
+            let mut table = poker_table.lock().await;
+            let timed_out_player = table.current_player().copied();
+            match table.time_out(action_id) {
+                Ok(true) => {},
+                Ok(false) => return,
+                Err(e) => {
+                    println!("Failed to time out poker player: {e}");
+                    return;
+                }
+            }
+            if let Some(player_id) = timed_out_player {
+                let _ = channel_id.say(&http, format!("⏰ {} ran out of time\n{}", player_id.mention(), poker_status(&table))).await;
+            }
+            if table.street() == Street::Waiting {
+                return;
+            }
+            action_id = table.action_id();
+        }
+    });
+}
+
+#[command]
+#[only_in(guilds)]
+async fn pksit(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let buy_in = match args.rest().trim().parse::<u128>() {
+        Ok(buy_in) => buy_in,
+        Err(_) => {
+            reply(ctx, msg, "Usage: `!pksit <buy-in>`").await;
+            return Ok(());
+        }
+    };
+    let roulette_state = get_roulette_state(ctx, msg.channel_id).await;
+    let poker_table = get_poker_table(ctx, msg.channel_id).await;
+    let mut roulette_state_mut = roulette_state.lock().await;
+    let mut table = poker_table.lock().await;
+    roulette_state_mut.register_player(msg.author.id, &msg.author.name);
+    match table.sit(msg.author.id, buy_in, &mut roulette_state_mut) {
+        Ok(()) => {
+            let balance = roulette_state_mut.get_balance(msg.author.id).unwrap_or(0);
+            reply(ctx, msg, format!("```\nBought in for {buy_in}. Your remaining balance is {balance}\n```")).await;
+        },
+        Err(e) => reply(ctx, msg, format!("Couldn't sit down:\n{e}")).await,
+    }
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn pkleave(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let roulette_state = get_roulette_state(ctx, msg.channel_id).await;
+    let poker_table = get_poker_table(ctx, msg.channel_id).await;
+    let mut roulette_state_mut = roulette_state.lock().await;
+    let mut table = poker_table.lock().await;
+    roulette_state_mut.register_player(msg.author.id, &msg.author.name);
+    match table.leave(msg.author.id, &mut roulette_state_mut) {
+        Ok(stack) => {
+            let balance = roulette_state_mut.get_balance(msg.author.id).unwrap_or(0);
+            reply(ctx, msg, format!("```\nCashed out {stack}. Your new balance is {balance}\n```")).await;
+        },
+        Err(e) => reply(ctx, msg, format!("Couldn't cash out:\n{e}")).await,
+    }
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn pkdeal(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let poker_table = get_poker_table(ctx, msg.channel_id).await;
+    let mut table = poker_table.lock().await;
+    if let Err(e) = table.start_hand() {
+        reply(ctx, msg, format!("Couldn't deal:\n{e}")).await;
+        return Ok(());
+    }
+    let players: Vec<UserId> = table.seats().iter().filter(|seat| seat.in_hand).map(|seat| seat.player_id).collect();
+    for player_id in players {
+        send_hole_cards(&ctx.http, msg.channel_id, &table, player_id).await;
+    }
+    check_msg(msg.channel_id.say(&ctx.http, poker_status(&table)).await);
+    if table.street() != Street::Waiting {
+        schedule_poker_timer(ctx.http.clone(), msg.channel_id, poker_table.clone(), table.action_id());
+    }
+    Ok(())
+}
+
+enum PokerAction {
+    Check,
+    Call,
+    RaiseTo(u128),
+    AllIn,
+    Fold
+}
+
+async fn poker_action(ctx: &Context, msg: &Message, action: PokerAction) -> CommandResult {
+    let poker_table = get_poker_table(ctx, msg.channel_id).await;
+    let mut table = poker_table.lock().await;
+    let player_id = msg.author.id;
+    let result = match action {
+        PokerAction::Check => table.check(player_id),
+        PokerAction::Call => table.call(player_id),
+        PokerAction::RaiseTo(amount) => table.raise_to(player_id, amount),
+        PokerAction::AllIn => table.all_in(player_id),
+        PokerAction::Fold => table.fold(player_id),
+    };
+    match result {
+        Ok(()) => {
+            check_msg(msg.channel_id.say(&ctx.http, poker_status(&table)).await);
+            if table.street() != Street::Waiting {
+                schedule_poker_timer(ctx.http.clone(), msg.channel_id, poker_table.clone(), table.action_id());
+            }
+        },
+        Err(e) => reply(ctx, msg, format!("```\n{e}\n```")).await,
+    }
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn pkcheck(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    poker_action(ctx, msg, PokerAction::Check).await
+}
+
+#[command]
+#[only_in(guilds)]
+async fn pkcall(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    poker_action(ctx, msg, PokerAction::Call).await
+}
+
+#[command]
+#[aliases("pkbet")]
+#[only_in(guilds)]
+async fn pkraise(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    match args.rest().trim().parse::<u128>() {
+        Ok(amount) => poker_action(ctx, msg, PokerAction::RaiseTo(amount)).await,
+        Err(_) => {
+            reply(ctx, msg, "Usage: `!pkraise <total bet>`").await;
+            Ok(())
+        }
+    }
+}
+
+#[command]
+#[only_in(guilds)]
+async fn pkallin(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    poker_action(ctx, msg, PokerAction::AllIn).await
+}
+
+#[command]
+#[only_in(guilds)]
+async fn pkfold(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    poker_action(ctx, msg, PokerAction::Fold).await
+}
+
+#[command]
+#[only_in(guilds)]
+async fn pktable(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let poker_table = get_poker_table(ctx, msg.channel_id).await;
+    let table = poker_table.lock().await;
+    reply(ctx, msg, poker_status(&table)).await;
+    Ok(())
+}
+
+/// Resend your hole cards by direct message
+#[command]
+#[only_in(guilds)]
+async fn pkcards(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let poker_table = get_poker_table(ctx, msg.channel_id).await;
+    let table = poker_table.lock().await;
+    if table.hole_cards(&msg.author.id).is_none_or(|cards| cards.is_empty()) {
+        reply(ctx, msg, "You aren't in the current hand").await;
+        return Ok(());
+    }
+    send_hole_cards(&ctx.http, msg.channel_id, &table, msg.author.id).await;
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn pkblinds(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let (small_blind, big_blind) = match (args.single::<u128>(), args.single::<u128>()) {
+        (Ok(small_blind), Ok(big_blind)) => (small_blind, big_blind),
+        _ => {
+            reply(ctx, msg, "Usage: `!pkblinds <small blind> <big blind>`").await;
+            return Ok(());
+        }
+    };
+    let poker_table = get_poker_table(ctx, msg.channel_id).await;
+    let mut table = poker_table.lock().await;
+    match table.set_blinds(small_blind, big_blind) {
+        Ok(()) => reply(ctx, msg, format!("```\nBlinds are now {small_blind}/{big_blind}\n```")).await,
+        Err(e) => reply(ctx, msg, format!("Unable to change blinds:\n{e}")).await,
+    }
+    Ok(())
+}
+
+struct SlotsData;
+
+impl TypeMapKey for SlotsData {
+    type Value = Arc<SlotMachine>;
+}
+
+/// Spin the slot machine with `!slots <amount>`, or see its paytable with `!slots info`
+#[command]
+#[only_in(guilds)]
+async fn slots(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let machine = match ctx.data.read().await.get::<SlotsData>() {
+        Some(machine) => machine.clone(),
+        None => {
+            reply(ctx, msg, "❌ The slot machine is out of order").await;
+            return Ok(());
+        }
+    };
+    let argument = args.rest().trim();
+
+    if argument == "info" {
+        let (paid, combinations) = machine.return_to_player();
+        let mut info = String::from("Paytable (multiples of the stake on each line):\n");
+        for entry in machine.paytable.iter() {
+            info += &format!("{}  pays {}\n", entry.symbols.join(" "), entry.pays);
+        }
+        info += &format!(
+            "{} paylines, so stakes must be a multiple of {}\nReturn to player: {:.4}% ({paid}/{combinations})",
+            machine.paylines.len(),
+            machine.paylines.len(),
+            paid as f64 * 100.0 / combinations as f64
+        );
+        reply(ctx, msg, format!("```\n{info}\n```")).await;
+        return Ok(());
+    }
+
+    let amount = match argument.parse::<u128>() {
+        Ok(amount) => amount,
+        Err(_) => {
+            reply(ctx, msg, "Usage: `!slots <amount>` or `!slots info`").await;
+            return Ok(());
+        }
+    };
+    let roulette_state = get_roulette_state(ctx, msg.channel_id).await;
+    let mut roulette_state_mut = roulette_state.lock().await;
+    roulette_state_mut.register_player(msg.author.id, &msg.author.name);
+    let spin = machine.validate_stake(amount)
+        .and_then(|_| roulette_state_mut.withdraw(msg.author.id, amount))
+        .and_then(|_| machine.spin(amount, &mut rand::thread_rng()));
+    let spin = match spin {
+        Ok(spin) => spin,
+        Err(e) => {
+            reply(ctx, msg, format!("Couldn't spin:\n{e}")).await;
+            return Ok(());
+        }
+    };
+    if spin.payout > 0 {
+        if let Err(e) = roulette_state_mut.deposit(msg.author.id, spin.payout) {
+            reply(ctx, msg, format!("Unable to pay out your winnings: {e}")).await;
+        }
+    }
+
+    let grid: Vec<String> = spin.grid.iter().map(|row| row.join(" ")).collect();
+    let mut response = format!("🎰\n{}\n", grid.join("\n"));
+    if spin.line_wins.is_empty() {
+        response += "No luck this time.";
+    }
+    else {
+        for (line, pays) in spin.line_wins.iter() {
+            response += &format!("Line {} pays {pays}x\n", line + 1);
+        }
+        response += &format!("You won **{}**!", spin.payout);
+    }
+    if let Ok(balance) = roulette_state_mut.get_balance(msg.author.id) {
+        response += &format!("\n```\nYour new balance is {balance}\n```");
+    }
+    reply(ctx, msg, response).await;
+    Ok(())
+}
+
+struct CrapsData {
+    channel_state: HashMap<ChannelId, Arc<Mutex<CrapsState<UserId>>>>
+}
+
+impl TypeMapKey for CrapsData {
+    type Value = CrapsData;
+}
+
+async fn get_craps_state(ctx: &Context, channel_id: ChannelId) -> Arc<Mutex<CrapsState<UserId>>> {
+    let mut ctx_data = ctx.data.write().await;
+    let craps_data = ctx_data.entry::<CrapsData>().or_insert(CrapsData{ channel_state: Default::default() });
+    craps_data.channel_state.entry(channel_id).or_insert(
+        Arc::new(Mutex::new(CrapsState::new()))
+    ).clone()
+}
+
+fn craps_bets_message(craps_state: &CrapsState<UserId>, player_id: UserId) -> String {
+    let bets: Vec<String> = craps_state.get_bets(&player_id).into_iter()
+        .map(|bet| format!("- {} ({})", bet.bet, bet.amount))
+        .collect();
+    let point = match craps_state.point() {
+        Some(point) => format!("The point is {point}"),
+        None => "Coming out".to_string(),
+    };
+    format!("{point}. Current bets:\n```\n{}\n```", bets.join("\n"))
+}
+
+/// Bet on craps, e.g. `!cbet 10 pass`, `!cbet 20 odds pass`, `!cbet 6 place 8` or `!cbet 5 hard 10`
+#[command]
+#[only_in(guilds)]
+async fn cbet(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let amount = args.single::<u128>();
+    let bet = CrapsBet::from_string(args.rest());
+    let (amount, bet) = match (amount, bet) {
+        (Ok(amount), Ok(bet)) => (amount, bet),
+        (Err(_), _) => {
+            reply(ctx, msg, "Usage: `!cbet <amount> <pass|dontpass|come|dontcome|odds ...|place <n>|field|hard <n>>`").await;
+            return Ok(());
+        },
+        (_, Err(e)) => {
+            reply(ctx, msg, format!("Betting failed:\n{e}")).await;
+            return Ok(());
+        }
+    };
+    let roulette_state = get_roulette_state(ctx, msg.channel_id).await;
+    let craps_state = get_craps_state(ctx, msg.channel_id).await;
+    let mut roulette_state_mut = roulette_state.lock().await;
+    let mut craps_state_mut = craps_state.lock().await;
+    let player_id = msg.author.id;
+    roulette_state_mut.register_player(player_id, &msg.author.name);
+    if let Err(e) = craps_state_mut.bet(player_id, bet, amount, &mut roulette_state_mut) {
+        reply(ctx, msg, format!("Betting failed:\n{e}")).await;
+    }
+    reply(ctx, msg, craps_bets_message(&craps_state_mut, player_id)).await;
+    if let Ok(current_balance) = roulette_state_mut.get_balance(player_id) {
+        reply(ctx, msg, format!("```\nYour new balance is {current_balance}\n```")).await;
+    }
+
+    if !craps_state_mut.roll_scheduled {
+        craps_state_mut.roll_scheduled = true;
+        let shooter = craps_state_mut.shooter().map(|shooter| shooter.mention().to_string()).unwrap_or_default();
+        let _ = msg.channel_id.say(ctx, format!("{shooter} is shooting. The dice roll in 25 seconds. Place your bets!")).await;
+        let http = ctx.http.clone();
+        let roulette_state = roulette_state.clone();
+        let craps_state = craps_state.clone();
+        let channel_id = msg.channel_id;
+        tokio::spawn(async move {
+            tokio::time::sleep(tokio::time::Duration::from_secs(15)).await;
+            let _ = channel_id.say(&http, "```\nThe dice roll in 10 seconds. Finalize your bets!\n```").await;
+            tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
+            let mut roulette_state = roulette_state.lock().await;
+            let mut craps_state = craps_state.lock().await;
+            craps_state.lock_bets();
+            let _ = channel_id.say(&http, "Bets have been finalized!").await;
+            let roll = craps_state.roll(&mut roulette_state, &mut rand::thread_rng());
+
+            let shooter = roll.shooter.map(|shooter| shooter.mention().to_string()).unwrap_or("The shooter".into());
+            let mut message = format!("🎲 {shooter} rolled **{}** ({} + {})", roll.total(), roll.dice.0, roll.dice.1);
+            match (roll.point, roll.new_point) {
+                (None, Some(point)) => message += &format!("\nThe point is **{point}**"),
+                (Some(point), None) if !roll.seven_out => message += &format!("\nThe shooter hit the point of {point}!"),
+                _ => {},
+            }
+            if roll.seven_out {
+                message += "\n**Seven out!**";
+                if let Some(next_shooter) = craps_state.shooter() {
+                    message += &format!(" The dice pass to {}", next_shooter.mention());
+                }
+            }
+            for outcome in roll.outcomes.iter() {
+                let result = match outcome.result {
+                    BetResult::Win(payout) => format!("won {}", payout - outcome.amount),
+                    BetResult::Lose => format!("lost {}", outcome.amount),
+                    BetResult::Push => "pushed".to_string(),
+                    BetResult::Moved(point) => format!("moves to {point}"),
+                };
+                message += &format!("\n- {} {} ({}): {result}", outcome.player_id.mention(), outcome.bet, outcome.amount);
+            }
+            let _ = channel_id.say(&http, message).await;
+        });
+    }
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn cbets(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let craps_state = get_craps_state(ctx, msg.channel_id).await;
+    let craps_state = craps_state.lock().await;
+    reply(ctx, msg, craps_bets_message(&craps_state, msg.author.id)).await;
+    Ok(())
+}
+
+/// Take a place bet, hardway or odds off the table, e.g. `!ctakedown place 8` or `!ctakedown odds come 6`
+#[command]
+#[only_in(guilds)]
+async fn ctakedown(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let bet = match CrapsBet::from_string(args.rest()) {
+        Ok(bet) => bet,
+        Err(e) => {
+            reply(ctx, msg, format!("Usage: `!ctakedown <place <n>|hard <n>|odds ...>`\n{e}")).await;
+            return Ok(());
+        }
+    };
+    let roulette_state = get_roulette_state(ctx, msg.channel_id).await;
+    let craps_state = get_craps_state(ctx, msg.channel_id).await;
+    let mut roulette_state_mut = roulette_state.lock().await;
+    let mut craps_state_mut = craps_state.lock().await;
+    let player_id = msg.author.id;
+    match craps_state_mut.take_down(&player_id, bet, &mut roulette_state_mut) {
+        Ok(amount) => {
+            let balance = roulette_state_mut.get_balance(player_id).unwrap_or(0);
+            reply(ctx, msg, format!("Took down {bet} ({amount})\n{}\n```\nYour new balance is {balance}\n```", craps_bets_message(&craps_state_mut, player_id))).await;
+        },
+        Err(e) => reply(ctx, msg, format!("Couldn't take that down:\n{e}")).await,
+    }
+    Ok(())
+}
+
+struct CrashData {
+    channel_state: HashMap<ChannelId, Arc<Mutex<CrashGame<UserId>>>>
+}
+
+impl TypeMapKey for CrashData {
+    type Value = CrashData;
+}
+
+/// Custom id of the button players press to cash out of a crash round
+const CRASH_CASH_OUT_BUTTON: &str = "crash_cash_out";
+
+/// How fast the crash multiplier grows, per second
+const CRASH_GROWTH_RATE: f64 = 0.07;
+
+async fn get_crash_game(ctx: &Context, channel_id: ChannelId) -> Arc<Mutex<CrashGame<UserId>>> {
+    let mut ctx_data = ctx.data.write().await;
+    let crash_data = ctx_data.entry::<CrashData>().or_insert(CrashData{ channel_state: Default::default() });
+    crash_data.channel_state.entry(channel_id).or_insert(
+        Arc::new(Mutex::new(CrashGame::new(100)))
+    ).clone()
+}
+
+fn crash_status(game: &CrashGame<UserId>, multiplier: u64) -> String {
+    let mut status = format!("🚀 **{}**", crash::format_multiplier(multiplier));
+    for (player_id, bet) in game.bets() {
+        match bet.cashed_out_at {
+            Some(cashed_out_at) => status += &format!("\n- {} cashed out at {} for {}", player_id.mention(), crash::format_multiplier(cashed_out_at), bet.payout),
+            None => status += &format!("\n- {} is riding {}", player_id.mention(), bet.amount),
+        }
+    }
+    status
+}
+
+/// Run a crash round after its betting window, editing the multiplier into one message until it crashes
+async fn run_crash_round(http: Arc<Http>, channel_id: ChannelId, roulette_state: Arc<Mutex<RouletteState<UserId>>>, crash_game: Arc<Mutex<CrashGame<UserId>>>) {
+    tokio::time::sleep(tokio::time::Duration::from_secs(15)).await;
+    let status = {
+        let mut game = crash_game.lock().await;
+        if let Err(e) = game.launch() {
+            let _ = channel_id.say(&http, format!("Couldn't launch: {e}")).await;
+            return;
+        }
+        crash_status(&game, crash::ONE_X)
+    };
+    let message = channel_id.send_message(&http, |m| {
+        m.content(status).components(|c| {
+            c.create_action_row(|row| {
+                row.create_button(|button| button.custom_id(CRASH_CASH_OUT_BUTTON).label("Cash out").style(ButtonStyle::Success))
+            })
+        })
+    }).await;
+    let mut message = match message {
+        Ok(message) => Some(message),
+        Err(e) => {
+            println!("Error sending message: {:?}", e);
+            None
+        }
+    };
+
+    let started = tokio::time::Instant::now();
+    loop {
+        let multiplier = (100.0 * (CRASH_GROWTH_RATE * started.elapsed().as_secs_f64()).exp()) as u64;
+        let tick = {
+            let mut roulette_state = roulette_state.lock().await;
+            let mut game = crash_game.lock().await;
+            match game.advance(multiplier, &mut roulette_state) {
+                Ok(CrashTick::Climbing { multiplier }) => Ok(crash_status(&game, multiplier)),
+                Ok(CrashTick::Crashed(summary)) => Err(Some(summary)),
+                Err(e) => {
+                    println!("Crash round failed: {e}");
+                    Err(None)
+                }
+            }
+        };
+        match tick {
+            Ok(status) => {
+                if let Some(message) = message.as_mut() {
+                    let _ = message.edit(&http, |m| m.content(status)).await;
+                }
+            },
+            Err(summary) => {
+                let summary = match summary {
+                    Some(summary) => summary,
+                    None => return,
+                };
+                let mut result = format!("💥 **Crashed at {}**", crash::format_multiplier(summary.crash_point));
+                for (player_id, bet) in summary.bets.iter() {
+                    match bet.cashed_out_at {
+                        Some(cashed_out_at) => result += &format!("\n- {} cashed out at {} and won {}", player_id.mention(), crash::format_multiplier(cashed_out_at), bet.payout),
+                        None => result += &format!("\n- {} lost {}", player_id.mention(), bet.amount),
+                    }
+                }
+                result += &format!(
+                    "\nRound {} seed: `{}` with a house edge of {}. Check it with `!crashverify <seed> <round> <edge>`",
+                    summary.round,
+                    summary.server_seed,
+                    crash::format_house_edge(summary.house_edge_bps)
+                );
+                if let Some(message) = message.as_mut() {
+                    let _ = message.edit(&http, |m| m.content(&result).components(|c| c)).await;
+                }
+                else {
+                    let _ = channel_id.say(&http, result).await;
+                }
+                return;
+            }
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+    }
+}
+
+/// Bet on the next crash round with `!crash <amount> [auto cash out multiplier]`
+#[command]
+#[only_in(guilds)]
+async fn crash(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let amount = match args.single::<u128>() {
+        Ok(amount) => amount,
+        Err(_) => {
+            reply(ctx, msg, "Usage: `!crash <amount> [auto cash out, e.g. 2.5x]`").await;
+            return Ok(());
+        }
+    };
+    let auto_cash_out = match args.single::<String>() {
+        Ok(auto_cash_out) => match crash::parse_multiplier(&auto_cash_out) {
+            Ok(auto_cash_out) => Some(auto_cash_out),
+            Err(e) => {
+                reply(ctx, msg, format!("Betting failed:\n{e}")).await;
+                return Ok(());
+            }
+        },
+        Err(_) => None,
+    };
+    let roulette_state = get_roulette_state(ctx, msg.channel_id).await;
+    let crash_game = get_crash_game(ctx, msg.channel_id).await;
+    let mut roulette_state_mut = roulette_state.lock().await;
+    let mut game = crash_game.lock().await;
+    roulette_state_mut.register_player(msg.author.id, &msg.author.name);
+    let bet_result = game.bet(msg.author.id, amount, auto_cash_out, &mut roulette_state_mut, &mut rand::thread_rng());
+    let opened = match bet_result {
+        Ok(opened) => opened,
+        Err(e) => {
+            reply(ctx, msg, format!("Betting failed:\n{e}")).await;
+            return Ok(());
+        }
+    };
+    if let Ok(current_balance) = roulette_state_mut.get_balance(msg.author.id) {
+        reply(ctx, msg, format!("```\nYou bet {amount}. Your new balance is {current_balance}\n```")).await;
+    }
+    if opened {
+        let _ = msg.channel_id.say(ctx, format!(
+            "```\nCrash round {} launches in 15 seconds. Place your bets with !crash!\nSeed commitment (SHA-256): {}\n```",
+            game.round(),
+            game.commitment()
+        )).await;
+        tokio::spawn(run_crash_round(ctx.http.clone(), msg.channel_id, roulette_state.clone(), crash_game.clone()));
+    }
+    Ok(())
+}
+
+async fn crash_cash_out(ctx: &Context, channel_id: ChannelId, player_id: UserId, player_name: &str) -> String {
+    let roulette_state = get_roulette_state(ctx, channel_id).await;
+    let crash_game = get_crash_game(ctx, channel_id).await;
+    let mut roulette_state_mut = roulette_state.lock().await;
+    let mut game = crash_game.lock().await;
+    roulette_state_mut.register_player(player_id, player_name);
+    let multiplier = game.multiplier();
+    match game.cash_out(player_id, &mut roulette_state_mut) {
+        Ok(payout) => {
+            let balance = roulette_state_mut.get_balance(player_id).unwrap_or(0);
+            format!("Cashed out at {} for {payout}. Your new balance is {balance}", crash::format_multiplier(multiplier))
+        },
+        Err(e) => format!("Couldn't cash out: {e}"),
+    }
+}
+
+#[command]
+#[only_in(guilds)]
+async fn cashout(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let response = crash_cash_out(ctx, msg.channel_id, msg.author.id, &msg.author.name).await;
+    reply(ctx, msg, response).await;
+    Ok(())
+}
+
+async fn crash_cash_out_button(ctx: &Context, component: &MessageComponentInteraction) {
+    let response = crash_cash_out(ctx, component.channel_id, component.user.id, &component.user.name).await;
+    let result = component.create_interaction_response(&ctx.http, |r| {
+        r.kind(InteractionResponseType::ChannelMessageWithSource)
+            .interaction_response_data(|data| data.content(response).ephemeral(true))
+    }).await;
+    if let Err(why) = result {
+        println!("Error responding to interaction: {:?}", why);
+    }
+}
+
+/// Recompute a past round's crash point from its revealed seed and the house edge it was played with,
+/// e.g. `!crashverify <seed> 12 1%`
+#[command]
+async fn crashverify(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let (seed, round, house_edge) = match (args.single::<String>(), args.single::<u64>(), args.single::<String>()) {
+        (Ok(seed), Ok(round), Ok(house_edge)) => (seed, round, house_edge),
+        _ => {
+            reply(ctx, msg, "Usage: `!crashverify <seed> <round> <edge>`").await;
+            return Ok(());
+        }
+    };
+    let house_edge = match crash::parse_house_edge(&house_edge) {
+        Ok(house_edge) => house_edge,
+        Err(e) => {
+            reply(ctx, msg, format!("Invalid house edge: {e}")).await;
+            return Ok(());
+        }
+    };
+    let seed = match hex::decode(seed.trim()) {
+        Ok(seed) => seed,
+        Err(e) => {
+            reply(ctx, msg, format!("Invalid seed: {e}")).await;
+            return Ok(());
+        }
+    };
+    reply(ctx, msg, format!(
+        "```\nCommitment: {}\nCrash point: {} (house edge {})\n```",
+        crash::seed_commitment(&seed),
+        crash::format_multiplier(crash::crash_point(&seed, round, house_edge)),
+        crash::format_house_edge(house_edge)
+    )).await;
+    Ok(())
+}
+
+/// Set the crash house edge in percent, e.g. `!crashedge 1.5`
+#[command]
+#[only_in(guilds)]
+async fn crashedge(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    if !is_admin(ctx, msg).await {
+        reply(ctx, msg, "❌ Only administrators can set the house edge").await;
+        return Ok(());
+    }
+    let house_edge = match crash::parse_house_edge(args.rest()) {
+        Ok(house_edge) => house_edge,
+        Err(e) => {
+            reply(ctx, msg, format!("Usage: `!crashedge <percent>`\n{e}")).await;
+            return Ok(());
+        }
+    };
+    let crash_game = get_crash_game(ctx, msg.channel_id).await;
+    let mut game = crash_game.lock().await;
+    if game.phase() != CrashPhase::Idle {
+        reply(ctx, msg, "The house edge can only be changed between rounds").await;
+        return Ok(());
+    }
+    match game.set_house_edge(house_edge) {
+        Ok(()) => reply(ctx, msg, format!("```\nCrash house edge is now {}\n```", crash::format_house_edge(house_edge))).await,
+        Err(e) => reply(ctx, msg, format!("Unable to change the house edge:\n{e}")).await,
+    }
+    Ok(())
+}
+
+struct DuelData {
+    channel_state: HashMap<ChannelId, Arc<Mutex<DuelLedger<UserId>>>>
+}
+
+impl TypeMapKey for DuelData {
+    type Value = DuelData;
+}
+
+/// Where pending duel stakes are recorded, so they can be refunded after a restart
+/// How long a duel challenge stays open
+const DUEL_EXPIRY_SECONDS: u64 = 60;
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+async fn get_duel_ledger(ctx: &Context, channel_id: ChannelId) -> Arc<Mutex<DuelLedger<UserId>>> {
+    let mut ctx_data = ctx.data.write().await;
+    let duel_data = ctx_data.entry::<DuelData>().or_insert(DuelData{ channel_state: Default::default() });
+    duel_data.channel_state.entry(channel_id).or_insert(
+        Arc::new(Mutex::new(DuelLedger::new()))
+    ).clone()
+}
+
+/// Write a file through a temporary file and a rename, so stopping part way through can't leave it half written
+async fn write_atomically(path: impl AsRef<std::path::Path>, contents: &str) -> std::io::Result<()> {
+    let path = path.as_ref();
+    let temp = path.with_extension("tmp");
+    tokio::fs::write(&temp, contents).await?;
+    tokio::fs::rename(&temp, path).await
+}
+
+fn save_json(path: &str, value: &impl serde::Serialize) -> anyhow::Result<()> {
+    let json = serde_json::to_string_pretty(value)?;
+    std::fs::write(path, json)?;
+    Ok(())
+}
+
+/// Whether the author of a message is an administrator of the server it was sent in
+async fn is_admin(ctx: &Context, msg: &Message) -> bool {
+    match msg.member(ctx).await {
+        Ok(member) => member.permissions(ctx).map(|permissions| permissions.administrator()).unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
+/// Flip a coin against the house with `!coinflip <amount> <heads|tails>`
+#[command]
+#[only_in(guilds)]
+async fn coinflip(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let (amount, call) = match (args.single::<u128>(), args.single::<String>().map(|call| call.parse::<CoinSide>())) {
+        (Ok(amount), Ok(Ok(call))) => (amount, call),
+        _ => {
+            reply(ctx, msg, "Usage: `!coinflip <amount> <heads|tails>`").await;
+            return Ok(());
+        }
+    };
+    let roulette_state = get_roulette_state(ctx, msg.channel_id).await;
+    let mut roulette_state_mut = roulette_state.lock().await;
+    roulette_state_mut.register_player(msg.author.id, &msg.author.name);
+    let flip_result = duel::coin_flip(msg.author.id, call, amount, &mut roulette_state_mut, &mut rand::thread_rng());
+    match flip_result {
+        Ok((landed, payout)) => {
+            let outcome = if payout > 0 { format!("You won **{payout}**!") } else { format!("You lost {amount}.") };
+            let balance = roulette_state_mut.get_balance(msg.author.id).unwrap_or(0);
+            reply(ctx, msg, format!("🪙 **{landed}**! {outcome}\n```\nYour new balance is {balance}\n```")).await;
+        },
+        Err(e) => reply(ctx, msg, format!("Couldn't flip:\n{e}")).await,
+    }
+    Ok(())
+}
+
+/// Challenge someone to a coin flip for a stake with `!duel @user <amount>`
+#[command]
+#[only_in(guilds)]
+async fn duel(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let (challenged, amount) = match (args.single::<UserId>(), args.single::<u128>()) {
+        (Ok(challenged), Ok(amount)) => (challenged, amount),
+        _ => {
+            reply(ctx, msg, "Usage: `!duel @user <amount>`").await;
+            return Ok(());
+        }
+    };
+    let roulette_state = get_roulette_state(ctx, msg.channel_id).await;
+    let duel_ledger = get_duel_ledger(ctx, msg.channel_id).await;
+    {
+        let mut roulette_state_mut = roulette_state.lock().await;
+        let mut ledger = duel_ledger.lock().await;
+        roulette_state_mut.register_player(msg.author.id, &msg.author.name);
+        let expires_at = unix_now() + DUEL_EXPIRY_SECONDS;
+        if let Err(e) = ledger.challenge(msg.author.id, &msg.author.name, challenged, amount, expires_at, &mut roulette_state_mut) {
+            reply(ctx, msg, format!("Couldn't start a duel:\n{e}")).await;
+            return Ok(());
+        }
+    }
+    save_balances(&ctx.data).await;
+    check_msg(msg.channel_id.say(&ctx.http, format!(
+        "⚔️ {} challenges {} to a coin flip for **{amount}**! `!accept` or `!decline` within {DUEL_EXPIRY_SECONDS} seconds",
+        msg.author.id.mention(),
+        challenged.mention()
+    )).await);
+
+    let http = ctx.http.clone();
+    let data = ctx.data.clone();
+    let channel_id = msg.channel_id;
+    tokio::spawn(async move {
+        tokio::time::sleep(tokio::time::Duration::from_secs(DUEL_EXPIRY_SECONDS)).await;
+        let expired = {
+            let mut roulette_state = roulette_state.lock().await;
+            let mut ledger = duel_ledger.lock().await;
+            ledger.expire(unix_now(), &mut roulette_state)
+        };
+        if expired.is_empty() {
+            return;
+        }
+        save_balances(&data).await;
+        for duel in expired {
+            let _ = channel_id.say(&http, format!(
+                "⌛ {}'s challenge to {} expired. The {} stake has been refunded",
+                duel.challenger.mention(),
+                duel.challenged.mention(),
+                duel.amount
+            )).await;
+        }
+    });
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn accept(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let roulette_state = get_roulette_state(ctx, msg.channel_id).await;
+    let duel_ledger = get_duel_ledger(ctx, msg.channel_id).await;
+    let result = {
+        let mut roulette_state_mut = roulette_state.lock().await;
+        let mut ledger = duel_ledger.lock().await;
+        roulette_state_mut.register_player(msg.author.id, &msg.author.name);
+        ledger.accept(msg.author.id, &mut roulette_state_mut, &mut rand::thread_rng())
+    };
+    match result {
+        Ok(result) => {
+            save_balances(&ctx.data).await;
+            let rake = if result.rake > 0 { format!(" (house rake {})", result.rake) } else { String::new() };
+            check_msg(msg.channel_id.say(&ctx.http, format!(
+                "🪙 {} is heads, {} is tails... **{}**! {} wins **{}**{rake}",
+                result.duel.challenger.mention(),
+                result.duel.challenged.mention(),
+                result.landed,
+                result.winner.mention(),
+                result.payout
+            )).await);
+        },
+        Err(e) => reply(ctx, msg, format!("Couldn't accept:\n{e}")).await,
+    }
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn decline(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let roulette_state = get_roulette_state(ctx, msg.channel_id).await;
+    let duel_ledger = get_duel_ledger(ctx, msg.channel_id).await;
+    let result = {
+        let mut roulette_state_mut = roulette_state.lock().await;
+        let mut ledger = duel_ledger.lock().await;
+        ledger.decline(msg.author.id, &mut roulette_state_mut)
+    };
+    match result {
+        Ok(duel) => {
+            save_balances(&ctx.data).await;
+            reply(ctx, msg, format!("You declined {}'s duel. Their {} stake has been refunded", duel.challenger.mention(), duel.amount)).await;
+        },
+        Err(e) => reply(ctx, msg, format!("Couldn't decline:\n{e}")).await,
+    }
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn duelcancel(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let roulette_state = get_roulette_state(ctx, msg.channel_id).await;
+    let duel_ledger = get_duel_ledger(ctx, msg.channel_id).await;
+    let result = {
+        let mut roulette_state_mut = roulette_state.lock().await;
+        let mut ledger = duel_ledger.lock().await;
+        ledger.cancel(msg.author.id, &mut roulette_state_mut)
+    };
+    match result {
+        Ok(duel) => {
+            save_balances(&ctx.data).await;
+            reply(ctx, msg, format!("```\nChallenge withdrawn. Your {} stake has been refunded\n```", duel.amount)).await;
+        },
+        Err(e) => reply(ctx, msg, format!("Couldn't cancel:\n{e}")).await,
+    }
+    Ok(())
+}
+
+/// Set the house cut of duel pots in percent, e.g. `!duelrake 2.5`
+#[command]
+#[only_in(guilds)]
+async fn duelrake(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    if !is_admin(ctx, msg).await {
+        reply(ctx, msg, "❌ Only administrators can set the duel rake").await;
+        return Ok(());
+    }
+    // Percentages parse the same way as crash multipliers, in hundredths
+    let rake = match crash::parse_multiplier(args.rest().trim_end_matches('%')).map(u32::try_from) {
+        Ok(Ok(rake)) => rake,
+        Ok(Err(_)) => {
+            reply(ctx, msg, "Unable to change the rake:\nThe rake can't be more than 50%").await;
+            return Ok(());
+        },
+        Err(_) => {
+            reply(ctx, msg, "Usage: `!duelrake <percent>`").await;
+            return Ok(());
+        }
+    };
+    let duel_ledger = get_duel_ledger(ctx, msg.channel_id).await;
+    let mut ledger = duel_ledger.lock().await;
+    match ledger.set_rake(rake) {
+        Ok(()) => reply(ctx, msg, format!("```\nDuel rake is now {}.{:02}%\n```", rake / 100, rake % 100)).await,
+        Err(e) => reply(ctx, msg, format!("Unable to change the rake:\n{e}")).await,
+    }
+    Ok(())
+}
+
+struct LotteryData {
+    channel_state: HashMap<ChannelId, Arc<Mutex<LotteryState<UserId>>>>
+}
+
+impl TypeMapKey for LotteryData {
+    type Value = LotteryData;
+}
+
+/// Where lottery tickets and jackpots are kept between restarts
+const LOTTERY_FILE: &str = "lottery.json";
+
+async fn get_lottery(ctx: &Context, channel_id: ChannelId) -> Arc<Mutex<LotteryState<UserId>>> {
+    let mut ctx_data = ctx.data.write().await;
+    let lottery_data = ctx_data.entry::<LotteryData>().or_insert(LotteryData{ channel_state: Default::default() });
+    lottery_data.channel_state.entry(channel_id).or_insert(
+        Arc::new(Mutex::new(LotteryState::new(unix_now())))
+    ).clone()
+}
+
+async fn lotteries(data: &Arc<RwLock<TypeMap>>) -> Vec<(ChannelId, Arc<Mutex<LotteryState<UserId>>>)> {
+    match data.read().await.get::<LotteryData>() {
+        Some(lottery_data) => lottery_data.channel_state.iter().map(|(channel_id, lottery)| (*channel_id, lottery.clone())).collect(),
+        None => vec![],
+    }
+}
+
+/// Write every channel's lottery to the lottery file. Don't call this while holding a lottery lock
+async fn save_lotteries(data: &Arc<RwLock<TypeMap>>) {
+    let mut states: HashMap<u64, LotteryState<UserId>> = HashMap::new();
+    for (channel_id, lottery) in lotteries(data).await {
+        states.insert(channel_id.0, lottery.lock().await.clone());
+    }
+    if let Err(e) = save_json(LOTTERY_FILE, &states) {
+        println!("Failed to save lotteries: {e}");
+    }
+}
+
+async fn load_lotteries(data: &Arc<RwLock<TypeMap>>) -> anyhow::Result<()> {
+    let json = match std::fs::read_to_string(LOTTERY_FILE) {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let states: HashMap<u64, LotteryState<UserId>> = serde_json::from_str(&json)?;
+    let channel_state = states.into_iter()
+        .map(|(channel_id, state)| (ChannelId(channel_id), Arc::new(Mutex::new(state))))
+        .collect();
+    data.write().await.insert::<LotteryData>(LotteryData { channel_state });
+    Ok(())
+}
+
+async fn draw_lottery(http: &Arc<Http>, data: &Arc<RwLock<TypeMap>>, channel_id: ChannelId, lottery: &Arc<Mutex<LotteryState<UserId>>>) {
+    let roulette_state = {
+        let mut ctx_data = data.write().await;
+        let roulette_data = ctx_data.entry::<RouletteData>().or_insert(RouletteData{ channel_state: Default::default() });
+        roulette_data.channel_state.entry(channel_id).or_insert(
+            Arc::new(Mutex::new(RouletteState::new()))
+        ).clone()
+    };
+    let result = {
+        let mut roulette_state = roulette_state.lock().await;
+        let mut lottery = lottery.lock().await;
+        lottery.draw(&mut roulette_state, &mut rand::thread_rng(), unix_now())
+    };
+    let result = match result {
+        Ok(result) => result,
+        Err(e) => {
+            println!("Failed to draw the lottery in {channel_id}: {e}");
+            let _ = channel_id.say(http, format!("Couldn't draw the lottery:\n{e}\nAn administrator can fix the schedule with `!lottery schedule <hours>`")).await;
+            return;
+        }
+    };
+    save_balances(data).await;
+    save_lotteries(data).await;
+
+    let mut announcement = format!(
+        "🎟️ **Lottery draw {}**\nThe winning numbers are **{}** ({} tickets sold)",
+        result.round,
+        lottery::format_numbers(&result.numbers),
+        result.tickets_sold
+    );
+    if result.winners.is_empty() {
+        announcement += &format!("\nNobody won! The jackpot rolls over at **{}**", result.jackpot);
+    }
+    for (ticket, prize) in result.winners.iter() {
+        announcement += &format!("\n- {} wins **{prize}**!", ticket.owner.mention());
+    }
+    let _ = channel_id.say(http, announcement).await;
+}
+
+/// Check for lottery draws that are due, once a minute
+async fn run_lottery_draws(http: Arc<Http>, data: Arc<RwLock<TypeMap>>) {
+    loop {
+        tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
+        for (channel_id, lottery) in lotteries(&data).await {
+            let due = lottery.lock().await.is_due(unix_now());
+            if due {
+                draw_lottery(&http, &data, channel_id, &lottery).await;
+            }
+        }
+    }
+}
+
+/// `!lottery buy <count>`, `!lottery tickets` or `!lottery info`.
+/// Admins can also use `!lottery draw`, `!lottery schedule <hours>` and `!lottery price <amount>`
+#[command]
+#[only_in(guilds)]
+async fn lottery(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let subcommand = args.single::<String>().unwrap_or_else(|_| "info".into());
+    let lottery = get_lottery(ctx, msg.channel_id).await;
+    match subcommand.as_str() {
+        "buy" => {
+            let count = args.single::<u32>().unwrap_or(1);
+            let roulette_state = get_roulette_state(ctx, msg.channel_id).await;
+            let response = {
+                let mut roulette_state_mut = roulette_state.lock().await;
+                let mut lottery = lottery.lock().await;
+                roulette_state_mut.register_player(msg.author.id, &msg.author.name);
+                let bought = lottery.buy(msg.author.id, &msg.author.name, count, &mut roulette_state_mut, &mut rand::thread_rng());
+                match bought {
+                    Ok(tickets) => {
+                        let numbers: Vec<String> = tickets.iter().map(|ticket| format!("- {}", lottery::format_numbers(&ticket.numbers))).collect();
+                        let balance = roulette_state_mut.get_balance(msg.author.id).unwrap_or(0);
+                        format!(
+                            "🎟️ Your tickets:\n```\n{}\n```The jackpot is now **{}**\n```\nYour new balance is {balance}\n```",
+                            numbers.join("\n"),
+                            lottery.jackpot
+                        )
+                    },
+                    Err(e) => format!("Couldn't buy tickets:\n{e}"),
+                }
+            };
+            save_lotteries(&ctx.data).await;
+            reply(ctx, msg, response).await;
+        },
+        "tickets" => {
+            let lottery = lottery.lock().await;
+            let tickets: Vec<String> = lottery.tickets_for(&msg.author.id).iter()
+                .map(|ticket| format!("- {}", lottery::format_numbers(&ticket.numbers)))
+                .collect();
+            if tickets.is_empty() {
+                reply(ctx, msg, "You don't have any tickets for this draw. Buy some with `!lottery buy <count>`").await;
+            }
+            else {
+                reply(ctx, msg, format!("Your tickets for draw {}:\n```\n{}\n```", lottery.round, tickets.join("\n"))).await;
+            }
+        },
+        "info" => {
+            let lottery = lottery.lock().await;
+            reply(ctx, msg, format!(
+                "```\nDraw {}: jackpot {}, {} tickets sold\nTickets cost {} and pick {} numbers from 1 to {}\nDraws every {} hours, next in {} minutes\n```",
+                lottery.round,
+                lottery.jackpot,
+                lottery.tickets().len(),
+                lottery.ticket_price,
+                lottery::NUMBERS_PER_TICKET,
+                lottery::HIGHEST_NUMBER,
+                lottery.draw_interval_hours,
+                lottery.next_draw.saturating_sub(unix_now()) / 60
+            )).await;
+        },
+        "draw" | "schedule" | "price" if !is_admin(ctx, msg).await => {
+            reply(ctx, msg, "❌ Only administrators can run the lottery").await;
+        },
+        "draw" => {
+            draw_lottery(&ctx.http, &ctx.data, msg.channel_id, &lottery).await;
+        },
+        "schedule" => {
+            let result = match args.single::<u64>() {
+                Ok(hours) => lottery.lock().await.set_schedule(hours, unix_now()),
+                Err(_) => Err(anyhow::anyhow!("Usage: `!lottery schedule <hours between draws>`")),
+            };
+            save_lotteries(&ctx.data).await;
+            match result {
+                Ok(()) => reply(ctx, msg, "```\nLottery schedule updated\n```").await,
+                Err(e) => reply(ctx, msg, format!("Unable to change the schedule:\n{e}")).await,
+            }
+        },
+        "price" => {
+            let result = match args.single::<u128>() {
+                Ok(price) => lottery.lock().await.set_ticket_price(price),
+                Err(_) => Err(anyhow::anyhow!("Usage: `!lottery price <amount>`")),
+            };
+            save_lotteries(&ctx.data).await;
+            match result {
+                Ok(()) => reply(ctx, msg, "```\nTicket price updated\n```").await,
+                Err(e) => reply(ctx, msg, format!("Unable to change the ticket price:\n{e}")).await,
+            }
+        },
+        _ => reply(ctx, msg, "Usage: `!lottery buy <count>`, `!lottery tickets` or `!lottery info`").await,
+    }
+    Ok(())
+}
+
+type GuildShop = Arc<Mutex<Shop<UserId, RoleId, ChannelId>>>;
+
+struct ShopData {
+    guild_state: HashMap<GuildId, GuildShop>
+}
+
+impl TypeMapKey for ShopData {
+    type Value = ShopData;
+}
+
+/// Where every server's shop and what players own is kept
+const SHOP_FILE: &str = "shop.json";
+
+async fn get_shop(ctx: &Context, guild_id: GuildId) -> GuildShop {
+    let mut ctx_data = ctx.data.write().await;
+    let shop_data = ctx_data.entry::<ShopData>().or_insert(ShopData{ guild_state: Default::default() });
+    shop_data.guild_state.entry(guild_id).or_insert(
+        Arc::new(Mutex::new(Shop::new()))
+    ).clone()
+}
+
+/// Write every server's shop to the shop file. Don't call this while holding a shop lock
+async fn save_shops(data: &Arc<RwLock<TypeMap>>) {
+    let shops: Vec<(GuildId, GuildShop)> = match data.read().await.get::<ShopData>() {
+        Some(shop_data) => shop_data.guild_state.iter().map(|(guild_id, shop)| (*guild_id, shop.clone())).collect(),
+        None => return,
+    };
+    let mut states: HashMap<u64, Shop<UserId, RoleId, ChannelId>> = HashMap::new();
+    for (guild_id, shop) in shops {
+        states.insert(guild_id.0, shop.lock().await.clone());
+    }
+    if let Err(e) = save_json(SHOP_FILE, &states) {
+        println!("Failed to save shops: {e}");
+    }
+}
+
+async fn load_shops(data: &Arc<RwLock<TypeMap>>) -> anyhow::Result<()> {
+    let json = match std::fs::read_to_string(SHOP_FILE) {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let states: HashMap<u64, Shop<UserId, RoleId, ChannelId>> = serde_json::from_str(&json)?;
+    let guild_state = states.into_iter()
+        .map(|(guild_id, shop)| (GuildId(guild_id), Arc::new(Mutex::new(shop))))
+        .collect();
+    data.write().await.insert::<ShopData>(ShopData { guild_state });
+    Ok(())
+}
+
+/// Reads an optional leading count followed by an item name, like `3 Golden Ticket`
+fn count_and_item(args: &mut Args) -> (u32, String) {
+    let count = args.single::<u32>().unwrap_or(1);
+    (count, args.rest().trim().to_string())
+}
+
+/// `!shop` lists what's for sale. Admins can also use `!shop add <price> <stock|unlimited> [@role] <name>`,
+/// `!shop remove <name>` and `!shop restock <stock|unlimited> <name>`
+#[command]
+#[only_in(guilds)]
+async fn shop(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let shop = get_shop(ctx, guild_id).await;
+    let subcommand = args.single::<String>().unwrap_or_default();
+    if !subcommand.is_empty() && !is_admin(ctx, msg).await {
+        reply(ctx, msg, "❌ Only administrators can change the shop").await;
+        return Ok(());
+    }
+    let parse_stock = |stock: String| match stock.to_lowercase().as_str() {
+        "unlimited" | "-" => Ok(None),
+        stock => stock.parse::<u32>().map(Some),
+    };
+
+    let result = match subcommand.as_str() {
+        "" => {
+            let shop = shop.lock().await;
+            let items: Vec<String> = shop.items()
+                .map(|item| {
+                    let stock = item.stock.map(|stock| format!("{stock} left")).unwrap_or_else(|| "unlimited".into());
+                    let role = item.role.map(|role| format!(", grants {}", role.mention())).unwrap_or_default();
+                    format!("- **{}** for {} ({stock}{role})", item.name, item.price)
+                })
+                .collect();
+            if items.is_empty() {
+                reply(ctx, msg, "The shop is empty").await;
+            }
+            else {
+                reply(ctx, msg, format!("🛒 **Shop**\n{}\nBuy with `!buy [count] <item>`", items.join("\n"))).await;
+            }
+            return Ok(());
+        },
+        "add" => {
+            let price = args.single::<u128>();
+            let stock = args.single::<String>().map(parse_stock);
+            // Only a mention is a role, so names can start with a number
+            let role = args.current().and_then(serenity::utils::parse_role).map(RoleId);
+            if role.is_some() {
+                args.advance();
+            }
+            let name = args.rest().trim().to_string();
+            match (price, stock) {
+                (Ok(price), Ok(Ok(stock))) => shop.lock().await.add_item(&name, price, stock, role).map(|_| format!("Added {name} to the shop")),
+                _ => Err(anyhow::anyhow!("Usage: `!shop add <price> <stock|unlimited> [@role] <name>`")),
+            }
+        },
+        "remove" => {
+            shop.lock().await.remove_item(args.rest().trim()).map(|item| format!("Removed {} from the shop", item.name))
+        },
+        "restock" => {
+            match args.single::<String>().map(parse_stock) {
+                Ok(Ok(stock)) => shop.lock().await.restock(args.rest().trim(), stock).map(|_| "Restocked".to_string()),
+                _ => Err(anyhow::anyhow!("Usage: `!shop restock <stock|unlimited> <name>`")),
+            }
+        },
+        _ => Err(anyhow::anyhow!("Usage: `!shop`, `!shop add`, `!shop remove` or `!shop restock`")),
+    };
+    match result {
+        Ok(response) => {
+            save_shops(&ctx.data).await;
+            reply(ctx, msg, format!("```\n{response}\n```")).await;
+        },
+        Err(e) => reply(ctx, msg, format!("Unable to change the shop:\n{e}")).await,
+    }
+    Ok(())
+}
+
+/// Buy from the server's shop with `!buy [count] <item>`
+#[command]
+#[only_in(guilds)]
+async fn buy(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let (count, name) = count_and_item(&mut args);
+    let roulette_state = get_roulette_state(ctx, msg.channel_id).await;
+    let shop = get_shop(ctx, msg.guild_id.unwrap()).await;
+    let mut roulette_state_mut = roulette_state.lock().await;
+    let mut shop_mut = shop.lock().await;
+    roulette_state_mut.register_player(msg.author.id, &msg.author.name);
+    let purchase = match shop_mut.buy(msg.author.id, &msg.author.name, &name, count, msg.channel_id, &mut roulette_state_mut) {
+        Ok(purchase) => purchase,
+        Err(e) => {
+            reply(ctx, msg, format!("Couldn't buy that:\n{e}")).await;
+            return Ok(());
+        }
+    };
+
+    if let Some(role) = purchase.item.role {
+        let granted = match msg.member(ctx).await {
+            Ok(mut member) => member.add_role(&ctx.http, role).await.map_err(anyhow::Error::from),
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = granted {
+            println!("Failed to grant role {role} to {}: {e}", msg.author.id);
+            if let Err(e) = shop_mut.refund(msg.author.id, &purchase, &msg.channel_id, &mut roulette_state_mut) {
+                println!("Failed to refund {}: {e}", msg.author.id);
+            }
+            drop(shop_mut);
+            drop(roulette_state_mut);
+            save_shops(&ctx.data).await;
+            reply(ctx, msg, "I couldn't give you that item's role, so you've been refunded").await;
+            return Ok(());
+        }
+    }
+
+    let balance = roulette_state_mut.get_balance(msg.author.id).unwrap_or(0);
+    drop(shop_mut);
+    drop(roulette_state_mut);
+    save_shops(&ctx.data).await;
+    reply(ctx, msg, format!(
+        "🛍️ You bought {} **{}** for {}\n```\nYour new balance is {balance}\n```",
+        purchase.quantity,
+        purchase.item.name,
+        purchase.cost
+    )).await;
+    Ok(())
+}
+
+/// Sell items back to the shop for half what was paid with `!sell [count] <item>`, in the channel they were bought in
+#[command]
+#[only_in(guilds)]
+async fn sell(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let (count, name) = count_and_item(&mut args);
+    let roulette_state = get_roulette_state(ctx, msg.channel_id).await;
+    let shop = get_shop(ctx, msg.guild_id.unwrap()).await;
+    let (sale, balance, still_owned) = {
+        let mut roulette_state_mut = roulette_state.lock().await;
+        let mut shop_mut = shop.lock().await;
+        roulette_state_mut.register_player(msg.author.id, &msg.author.name);
+        match shop_mut.sell(msg.author.id, &name, count, &msg.channel_id, &mut roulette_state_mut) {
+            Ok(sale) => {
+                let still_owned = shop_mut.quantity(&msg.author.id, &sale.item.name);
+                (sale, roulette_state_mut.get_balance(msg.author.id).unwrap_or(0), still_owned)
+            },
+            Err(e) => {
+                reply(ctx, msg, format!("Couldn't sell that:\n{e}")).await;
+                return Ok(());
+            }
+        }
+    };
+    save_shops(&ctx.data).await;
+
+    if let (Some(role), 0) = (sale.item.role, still_owned) {
+        let removed = match msg.member(ctx).await {
+            Ok(mut member) => member.remove_role(&ctx.http, role).await.map_err(anyhow::Error::from),
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = removed {
+            println!("Failed to take role {role} from {}: {e}", msg.author.id);
+        }
+    }
+    reply(ctx, msg, format!(
+        "💰 You sold {} **{}** for {}\n```\nYour new balance is {balance}\n```",
+        sale.quantity,
+        sale.item.name,
+        sale.cost
+    )).await;
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn inventory(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let shop = get_shop(ctx, msg.guild_id.unwrap()).await;
+    let holdings: Vec<String> = shop.lock().await.inventory(&msg.author.id).iter()
+        .map(|holding| format!("- {} x{} (bought in {})", holding.item, holding.quantity, holding.bank.mention()))
+        .collect();
+    if holdings.is_empty() {
+        reply(ctx, msg, "Your inventory is empty. See what's for sale with `!shop`").await;
+    }
+    else {
+        reply(ctx, msg, format!("🎒 **Inventory**\n{}", holdings.join("\n"))).await;
+    }
+    Ok(())
+}
+
+type GuildMacros = Arc<Mutex<MacroBook<UserId>>>;
+
+struct MacroData {
+    guild_state: HashMap<GuildId, GuildMacros>
+}
+
+impl TypeMapKey for MacroData {
+    type Value = MacroData;
+}
+
+/// Where saved macros and roll variables are kept
+const MACROS_FILE: &str = "macros.json";
+
+async fn get_macros(ctx: &Context, guild_id: GuildId) -> GuildMacros {
+    let mut ctx_data = ctx.data.write().await;
+    let macro_data = ctx_data.entry::<MacroData>().or_insert(MacroData{ guild_state: Default::default() });
+    macro_data.guild_state.entry(guild_id).or_insert(
+        Arc::new(Mutex::new(MacroBook::new()))
+    ).clone()
+}
+
+/// Write every server's macros to the macros file. Don't call this while holding a macro book lock
+async fn save_macros(data: &Arc<RwLock<TypeMap>>) {
+    let books: Vec<(GuildId, GuildMacros)> = match data.read().await.get::<MacroData>() {
+        Some(macro_data) => macro_data.guild_state.iter().map(|(guild_id, book)| (*guild_id, book.clone())).collect(),
+        None => return,
+    };
+    let mut states: HashMap<u64, MacroBook<UserId>> = HashMap::new();
+    for (guild_id, book) in books {
+        states.insert(guild_id.0, book.lock().await.clone());
+    }
+    if let Err(e) = save_json(MACROS_FILE, &states) {
+        println!("Failed to save macros: {e}");
+    }
+}
+
+async fn load_macros(data: &Arc<RwLock<TypeMap>>) -> anyhow::Result<()> {
+    let json = match std::fs::read_to_string(MACROS_FILE) {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let states: HashMap<u64, MacroBook<UserId>> = serde_json::from_str(&json)?;
+    let guild_state = states.into_iter()
+        .map(|(guild_id, book)| (GuildId(guild_id), Arc::new(Mutex::new(book))))
+        .collect();
+    data.write().await.insert::<MacroData>(MacroData { guild_state });
+    Ok(())
+}
+
+/// `!macro save <name> <expression>`, `!macro delete <name>`, `!macro list`,
+/// `!macro set <variable> <value>` and `!macro unset <variable>`.
+/// GMs can share macros with everyone using `!macro guild save <name> <expression>` and `!macro guild delete <name>`
+#[command("macro")]
+#[only_in(guilds)]
+async fn macro_command(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let book = get_macros(ctx, msg.guild_id.unwrap()).await;
+    let mut subcommand = args.single::<String>().unwrap_or_default().to_lowercase();
+    let shared = subcommand == "guild";
+    if shared {
+        subcommand = args.single::<String>().unwrap_or_default().to_lowercase();
+        if !is_gm(ctx, msg).await {
+            reply(ctx, msg, "❌ Only the GM can change the server's macros").await;
+            return Ok(());
+        }
+    }
+    let owner = if shared { None } else { Some(msg.author.id) };
+
+    let result = match subcommand.as_str() {
+        "save" => {
+            let name = args.single::<String>().unwrap_or_default();
+            book.lock().await.save(owner, &name, args.rest()).map(|_| format!("Saved macro '{name}'. Roll it with `!r {name}`"))
+        },
+        "delete" => {
+            let name = args.rest().trim().to_string();
+            book.lock().await.delete(owner, &name).map(|_| format!("Deleted macro '{name}'"))
+        },
+        "set" if !shared => {
+            match (args.single::<String>(), args.single::<i64>()) {
+                (Ok(name), Ok(value)) => book.lock().await.set_variable(msg.author.id, &name, value).map(|_| format!("@{name} is now {value}")),
+                _ => Err(anyhow::anyhow!("Usage: `!macro set <variable> <value>`")),
+            }
+        },
+        "unset" if !shared => {
+            let name = args.rest().trim().trim_start_matches('@').to_string();
+            book.lock().await.unset_variable(&msg.author.id, &name).map(|_| format!("@{name} has been removed"))
+        },
+        "list" | "" => {
+            let book = book.lock().await;
+            let format_macros = |owner: Option<&UserId>| {
+                let macros: Vec<String> = book.macros(owner).iter().map(|roll_macro| format!("- `{}`: `{}`", roll_macro.name, roll_macro.expression)).collect();
+                if macros.is_empty() { "None".to_string() } else { macros.join("\n") }
+            };
+            let variables: Vec<String> = book.variables(&msg.author.id).iter().map(|variable| format!("@{} = {}", variable.name, variable.value)).collect();
+            let variables = if variables.is_empty() { "None".to_string() } else { variables.join(", ") };
+            reply(ctx, msg, format!(
+                "**Your macros**\n{}\n**Server macros**\n{}\n**Your variables**\n{variables}",
+                format_macros(Some(&msg.author.id)),
+                format_macros(None)
+            )).await;
+            return Ok(());
+        },
+        _ => Err(anyhow::anyhow!("Usage: `!macro save <name> <expression>`, `!macro delete <name>`, `!macro list`, `!macro set <variable> <value>` or `!macro unset <variable>`")),
+    };
+    match result {
+        Ok(response) => {
+            save_macros(&ctx.data).await;
+            reply(ctx, msg, format!("```\n{response}\n```")).await;
+        },
+        Err(e) => reply(ctx, msg, format!("Unable to change macros:\n{e}")).await,
+    }
+    Ok(())
+}
+
+/// Roll a saved macro with `!r <name>`, or any expression using your `@variables`.
+/// Variables you haven't set come from your character sheet, like `@dex` or `@stealth`
+#[command]
+#[only_in(guilds)]
+async fn r(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    let sheet = {
+        let characters = get_characters(ctx, guild_id).await;
+        let characters = characters.lock().await;
+        characters.sheet(&msg.author.id, characters.campaign(&msg.channel_id)).ok().cloned()
+    };
+    let book = get_macros(ctx, guild_id).await;
+    let expanded = book.lock().await.expand(&msg.author.id, args.rest(), |name| sheet.as_ref().and_then(|sheet| sheet.variable(name)));
+    match expanded {
+        Ok(expression) => roll_and_reply(ctx, msg, &expression).await,
+        Err(e) => reply(ctx, msg, format!("Couldn't roll that:\n{e}")).await,
+    }
+    Ok(())
+}
+
+/// The role whose members run games on each server
+struct GmRoleData {
+    guild_roles: HashMap<GuildId, RoleId>
+}
+
+impl TypeMapKey for GmRoleData {
+    type Value = GmRoleData;
+}
+
+const GM_ROLES_FILE: &str = "gm_roles.json";
+
+async fn load_gm_roles(data: &Arc<RwLock<TypeMap>>) -> anyhow::Result<()> {
+    let json = match std::fs::read_to_string(GM_ROLES_FILE) {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let roles: HashMap<u64, u64> = serde_json::from_str(&json)?;
+    let guild_roles = roles.into_iter().map(|(guild_id, role_id)| (GuildId(guild_id), RoleId(role_id))).collect();
+    data.write().await.insert::<GmRoleData>(GmRoleData { guild_roles });
+    Ok(())
+}
+
+async fn get_gm_role(ctx: &Context, guild_id: GuildId) -> Option<RoleId> {
+    ctx.data.read().await.get::<GmRoleData>()?.guild_roles.get(&guild_id).copied()
+}
+
+/// Whether the author of a message is an administrator or has the server's GM role
+async fn is_gm(ctx: &Context, msg: &Message) -> bool {
+    if is_admin(ctx, msg).await {
+        return true;
+    }
+    let (Some(guild_id), Ok(member)) = (msg.guild_id, msg.member(ctx).await) else {
+        return false;
+    };
+    match get_gm_role(ctx, guild_id).await {
+        Some(role) => member.roles.contains(&role),
+        None => false,
+    }
+}
+
+/// Everyone with the server's GM role, paging through all of the server's members.
+/// Listing members needs the server members intent turned on for the bot
+async fn gm_members(ctx: &Context, guild_id: GuildId) -> anyhow::Result<Vec<UserId>> {
+    let Some(role) = get_gm_role(ctx, guild_id).await else {
+        return Ok(vec![]);
+    };
+    let mut gms = vec![];
+    let mut members = guild_id.members_iter(&ctx.http).boxed();
+    while let Some(member) = members.next().await {
+        let member = member.map_err(|e| {
+            println!("Failed to list members of {guild_id}: {e}");
+            anyhow::anyhow!("I couldn't look through the server's members to find the GMs: {e}
+The bot needs the Server Members Intent turned on in the Discord developer portal")
+        })?;
+        if member.roles.contains(&role) {
+            gms.push(member.user.id);
+        }
+    }
+    Ok(gms)
+}
+
+/// Roll and send the result only by DM, to the GMs and unless it's a blind roll, the roller
+async fn private_roll(ctx: &Context, msg: &Message, roll_expression: &str, blind: bool) {
+    let parts: Vec<&str> = roll_expression.split(';').map(str::trim).filter(|part| !part.is_empty()).collect();
+    if parts.is_empty() {
+        reply(ctx, msg, "Give an expression to roll, like `!groll 1d20+4`").await;
+        return;
+    }
+    let guild_id = msg.guild_id.unwrap();
+    let gms = match gm_members(ctx, guild_id).await {
+        Ok(gms) => gms,
+        Err(e) => {
+            reply(ctx, msg, format!("Couldn't send that roll:\n{e}")).await;
+            return;
+        }
+    };
+    let mut recipients = gms.clone();
+    if blind && recipients.is_empty() {
+        reply(ctx, msg, "There's no GM to see a blind roll. An administrator can choose the GM role with `!gmrole @role`").await;
+        return;
+    }
+    if !blind && !recipients.contains(&msg.author.id) {
+        recipients.push(msg.author.id);
+    }
+
+    let lines: Vec<String> = parts.into_iter().take(MAX_INLINE_ROLLS).map(|part| roll_line(part).0).collect();
+    let kind = if blind { "blind" } else { "private" };
+    let channel_name = msg.channel_id.name(&ctx.cache).await.unwrap_or_else(|| msg.channel_id.to_string());
+    let response = format!("🔒 {} made a {kind} roll in #{channel_name}\n{}", msg.author.name, lines.join("\n"));
+    let mut sent_to = 0;
+    let mut gms_reached = 0;
+    for recipient in recipients {
+        let sent = match recipient.create_dm_channel(&ctx.http).await {
+            Ok(dm) => dm.say(&ctx.http, &response).await.map(|_| ()),
+            Err(e) => Err(e),
+        };
+        match sent {
+            Ok(()) => {
+                sent_to += 1;
+                if gms.contains(&recipient) {
+                    gms_reached += 1;
+                }
+            },
+            Err(e) => println!("Failed to send a {kind} roll to {recipient}: {e}"),
+        }
+    }
+    if sent_to == 0 {
+        reply(ctx, msg, format!("❌ Couldn't DM that {kind} roll to anyone, so nobody saw it. Check that DMs from server members are allowed")).await;
+        return;
+    }
+    let mut announcement = match blind {
+        true => format!("🙈 {} made a blind roll for the GM", msg.author.id.mention()),
+        false => format!("🔒 {} rolled privately", msg.author.id.mention()),
+    };
+    if !gms.is_empty() && gms_reached == 0 {
+        announcement += "\n⚠️ None of the GMs could be sent the roll. Check that they allow DMs from server members";
+    }
+    check_msg(msg.channel_id.say(&ctx.http, announcement).await);
+}
+
+/// Roll with the result sent by DM to you and the GMs, with `!groll <expression>`
+#[command]
+#[only_in(guilds)]
+async fn groll(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    private_roll(ctx, msg, args.rest(), false).await;
+    Ok(())
+}
+
+/// Roll with the result sent only to the GMs, with `!broll <expression>`
+#[command]
+#[only_in(guilds)]
+async fn broll(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    private_roll(ctx, msg, args.rest(), true).await;
+    Ok(())
+}
+
+/// Choose the GM role with `!gmrole @role`, or stop using one with `!gmrole clear`
+#[command]
+#[only_in(guilds)]
+async fn gmrole(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    if !is_admin(ctx, msg).await {
+        reply(ctx, msg, "❌ Only administrators can choose the GM role").await;
+        return Ok(());
+    }
+    let guild_id = msg.guild_id.unwrap();
+    if args.is_empty() {
+        match get_gm_role(ctx, guild_id).await {
+            Some(role) => reply(ctx, msg, format!("The GM role is {}", role.mention())).await,
+            None => reply(ctx, msg, "There's no GM role. Choose one with `!gmrole @role`").await,
+        }
+        return Ok(());
+    }
+    let role = match args.single::<RoleId>() {
+        Ok(role) => Some(role),
+        Err(_) if args.rest().trim().eq_ignore_ascii_case("clear") => None,
+        Err(_) => {
+            reply(ctx, msg, "Usage: `!gmrole @role` or `!gmrole clear`").await;
+            return Ok(());
+        }
+    };
+    let roles: HashMap<u64, u64> = {
+        let mut ctx_data = ctx.data.write().await;
+        let gm_role_data = ctx_data.entry::<GmRoleData>().or_insert(GmRoleData{ guild_roles: Default::default() });
+        match role {
+            Some(role) => gm_role_data.guild_roles.insert(guild_id, role),
+            None => gm_role_data.guild_roles.remove(&guild_id),
+        };
+        gm_role_data.guild_roles.iter().map(|(guild_id, role)| (guild_id.0, role.0)).collect()
+    };
+    if let Err(e) = save_json(GM_ROLES_FILE, &roles) {
+        println!("Failed to save GM roles: {e}");
+    }
+    match role {
+        Some(role) => reply(ctx, msg, format!("{} can now see private and blind rolls", role.mention())).await,
+        None => reply(ctx, msg, "```\nThe GM role has been cleared\n```").await,
+    }
+    Ok(())
+}
+
+struct InitiativeData {
+    channel_state: HashMap<ChannelId, Arc<Mutex<Encounter<UserId>>>>
+}
+
+impl TypeMapKey for InitiativeData {
+    type Value = InitiativeData;
+}
+
+async fn get_encounter(ctx: &Context, channel_id: ChannelId) -> Arc<Mutex<Encounter<UserId>>> {
+    let mut ctx_data = ctx.data.write().await;
+    let initiative_data = ctx_data.entry::<InitiativeData>().or_insert(InitiativeData{ channel_state: Default::default() });
+    initiative_data.channel_state.entry(channel_id).or_insert(
+        Arc::new(Mutex::new(Encounter::new()))
+    ).clone()
+}
+
+/// Whether the author runs this encounter, either by starting it or having the GM role
+async fn runs_encounter(ctx: &Context, msg: &Message, encounter: &Arc<Mutex<Encounter<UserId>>>) -> bool {
+    if encounter.lock().await.gm == Some(msg.author.id) {
+        return true;
+    }
+    is_gm(ctx, msg).await
+}
+
+fn initiative_order(encounter: &Encounter<UserId>) -> String {
+    if encounter.combatants().is_empty() {
+        return "Nobody has rolled initiative yet. Join with `!init join <modifier>`".into();
+    }
+    let current = encounter.current().map(|combatant| combatant.id);
+    let lines: Vec<String> = encounter.combatants().iter()
+        .map(|combatant| {
+            let marker = if Some(combatant.id) == current { "▶" } else { " " };
+            let mut line = format!("{marker} {:>3}  {} ({:+})", combatant.initiative, combatant.name, combatant.modifier);
+            if combatant.delaying {
+                line += " ⏸ delaying";
+            }
+            if let Some(action) = &combatant.readied {
+                line += &format!(" ⚡ readied: {action}");
+            }
+            for condition in combatant.conditions.iter() {
+                match condition.ends.and_then(|(id, round)| encounter.name_of(id).map(|name| (name, round))) {
+                    Some((name, round)) => line += &format!(" [{} until {name}'s turn in round {round}]", condition.name),
+                    None => line += &format!(" [{}]", condition.name),
+                }
+            }
+            line
+        })
+        .collect();
+    let heading = match encounter.has_started() {
+        true => format!("Round {}", encounter.round),
+        false => "Waiting to start. The GM starts the first round with `!next`".into(),
+    };
+    format!("```\n{heading}\n{}\n```", lines.join("\n"))
+}
+
+/// Who to ping for a combatant's turn: their player, or the GM for NPCs
+fn combatant_mention(encounter: &Encounter<UserId>, combatant: &Combatant<UserId>) -> String {
+    match (combatant.player, encounter.gm) {
+        (Some(player), _) | (None, Some(player)) => player.mention().to_string(),
+        (None, None) => String::new(),
+    }
+}
+
+/// Describe a change of turn, pinging whoever's turn it is now
+fn turn_announcement(encounter: &Encounter<UserId>, change: &TurnChange) -> String {
+    let mut lines = vec![];
+    if change.new_round {
+        lines.push(format!("⚔️ **Round {}**", encounter.round));
+    }
+    for expiry in change.expired.iter() {
+        lines.push(format!("{} is no longer {}", expiry.combatant, expiry.condition));
+    }
+    if !change.skipped.is_empty() {
+        lines.push(format!("⏸ Still delaying: {}", change.skipped.join(", ")));
+    }
+    if let Some(current) = encounter.current() {
+        if let Some(action) = &change.lapsed {
+            lines.push(format!("{}'s readied action lapsed: {action}", current.name));
+        }
+        let mut turn = format!("🎲 It's **{}**'s turn {}", current.name, combatant_mention(encounter, current));
+        if !current.conditions.is_empty() {
+            let conditions: Vec<&str> = current.conditions.iter().map(|condition| condition.name.as_str()).collect();
+            turn += &format!(" ({})", conditions.join(", "));
+        }
+        lines.push(turn);
+        if let Some(next) = encounter.up_next().filter(|next| next.id != current.id) {
+            lines.push(format!("Up next: {}", next.name));
+        }
+    }
+    lines.join("\n")
+}
+
+/// Find the combatant someone is acting as: the named one, or their own if no name is given.
+/// Only the GM can act for NPCs and other players
+fn acting_combatant(encounter: &Encounter<UserId>, author: UserId, runs_encounter: bool, name: &str) -> anyhow::Result<usize> {
+    let name = name.trim();
+    if name.is_empty() {
+        return encounter.find_player(&author);
+    }
+    let index = match serenity::utils::parse_username(name) {
+        Some(user_id) => encounter.find_player(&UserId(user_id)).map_err(|_| anyhow::anyhow!("They're not in this encounter"))?,
+        None => encounter.find(name)?,
+    };
+    if !runs_encounter && encounter.combatants()[index].player != Some(author) {
+        anyhow::bail!("Only the GM can act for {}", encounter.combatants()[index].name);
+    }
+    Ok(index)
+}
+
+/// The combatant whose turn it is, if the author controls them
+fn current_combatant(encounter: &Encounter<UserId>, author: UserId, runs_encounter: bool) -> anyhow::Result<usize> {
+    let current = encounter.current().ok_or_else(|| anyhow::anyhow!("The fight hasn't started yet"))?;
+    if current.player != Some(author) && !(runs_encounter && current.player.is_none()) {
+        anyhow::bail!("It's not your turn, it's {}'s", current.name);
+    }
+    encounter.find(&current.name)
+}
+
+/// Run combat in this channel:
+/// `!init start`, `!init join <modifier>`, `!init add <name> <modifier> [count]`, `!init set <name> <initiative>`,
+/// `!init remove <name>` and `!init end`. `!init` on its own shows the turn order
+#[command]
+#[only_in(guilds)]
+async fn init(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let subcommand = args.single::<String>().unwrap_or_else(|_| "list".into()).to_lowercase();
+    let encounter = get_encounter(ctx, msg.channel_id).await;
+    let runs_encounter = runs_encounter(ctx, msg, &encounter).await;
+    match subcommand.as_str() {
+        "list" | "order" => {
+            let encounter = encounter.lock().await;
+            match encounter.is_running() {
+                true => reply(ctx, msg, initiative_order(&encounter)).await,
+                false => reply(ctx, msg, "There's no encounter running in this channel. Start one with `!init start`").await,
+            }
+        },
+        "start" => {
+            let result = encounter.lock().await.start(msg.author.id);
+            match result {
+                Ok(()) => reply(ctx, msg, format!(
+                    "⚔️ {} started an encounter! Roll initiative with `!init join <modifier>`",
+                    msg.author.id.mention()
+                )).await,
+                Err(e) => reply(ctx, msg, format!("Couldn't start an encounter:\n{e}")).await,
+            }
+        },
+        "join" => {
+            let modifier = match args.single::<i64>() {
+                Ok(modifier) => modifier,
+                Err(_) if args.is_empty() => 0,
+                Err(_) => {
+                    reply(ctx, msg, "Usage: `!init join <modifier>`, like `!init join +3`").await;
+                    return Ok(());
+                }
+            };
+            let name = msg.author_nick(&ctx.http).await.unwrap_or_else(|| msg.author.name.clone());
+            let response = {
+                let mut encounter = encounter.lock().await;
+                match encounter.join(msg.author.id, &name, modifier, &mut rand::thread_rng()) {
+                    Ok(combatant) => format!(
+                        "🎲 {} rolled **{}** for initiative (1d20: {}{:+})",
+                        combatant.name,
+                        combatant.initiative,
+                        combatant.roll.unwrap_or_default(),
+                        combatant.modifier
+                    ),
+                    Err(e) => format!("Couldn't join the encounter:\n{e}"),
+                }
+            };
+            reply(ctx, msg, response).await;
+        },
+        "add" | "set" | "remove" | "end" if !runs_encounter => {
+            reply(ctx, msg, "❌ Only the GM running this encounter can do that").await;
+        },
+        "add" => {
+            let (Ok(name), Ok(modifier)) = (args.single_quoted::<String>(), args.single::<i64>()) else {
+                reply(ctx, msg, "Usage: `!init add <name> <modifier> [count]`, like `!init add Goblin +2 3`. Put names with spaces in quotes").await;
+                return Ok(());
+            };
+            let count = args.single::<u32>().unwrap_or(1);
+            let response = {
+                let mut encounter = encounter.lock().await;
+                match encounter.add_npcs(&name, modifier, count, &mut rand::thread_rng()) {
+                    Ok(added) => {
+                        let rolls: Vec<String> = added.iter()
+                            .map(|combatant| format!("{} rolled **{}** (1d20: {}{:+})", combatant.name, combatant.initiative, combatant.roll.unwrap_or_default(), combatant.modifier))
+                            .collect();
+                        format!("🎲 {}\n{}", rolls.join("\n"), initiative_order(&encounter))
+                    },
+                    Err(e) => format!("Couldn't add NPCs:\n{e}"),
+                }
+            };
+            reply(ctx, msg, response).await;
+        },
+        "set" => {
+            let (Ok(name), Ok(initiative)) = (args.single_quoted::<String>(), args.single::<i64>()) else {
+                reply(ctx, msg, "Usage: `!init set <name> <initiative>`").await;
+                return Ok(());
+            };
+            let response = {
+                let mut encounter = encounter.lock().await;
+                match encounter.set_initiative(&name, initiative) {
+                    Ok(()) => initiative_order(&encounter),
+                    Err(e) => format!("Couldn't change their initiative:\n{e}"),
+                }
+            };
+            reply(ctx, msg, response).await;
+        },
+        "remove" => {
+            let name = args.rest().trim().trim_matches('"');
+            let response = {
+                let mut encounter = encounter.lock().await;
+                match encounter.remove(name) {
+                    Ok(removed) => format!("{} has left the fight\n{}", removed.name, initiative_order(&encounter)),
+                    Err(e) => format!("Couldn't remove them:\n{e}"),
+                }
+            };
+            reply(ctx, msg, response).await;
+        },
+        "end" => {
+            let result = encounter.lock().await.end();
+            match result {
+                Ok(()) => reply(ctx, msg, "🏁 The encounter is over").await,
+                Err(e) => reply(ctx, msg, format!("Couldn't end the encounter:\n{e}")).await,
+            }
+        },
+        _ => reply(ctx, msg, "Usage: `!init [start|join|add|set|remove|end]`").await,
+    }
+    Ok(())
+}
+
+/// End the current turn and ping whoever's next. The GM uses this to start the first round
+#[command]
+#[only_in(guilds)]
+async fn next(ctx: &Context, msg: &Message) -> CommandResult {
+    let encounter = get_encounter(ctx, msg.channel_id).await;
+    let runs_encounter = runs_encounter(ctx, msg, &encounter).await;
+    let response = {
+        let mut encounter = encounter.lock().await;
+        let allowed = match encounter.current() {
+            Some(current) => runs_encounter || current.player == Some(msg.author.id),
+            None => runs_encounter,
+        };
+        if !allowed {
+            "❌ Only the GM or whoever's turn it is can move on to the next turn".to_string()
+        }
+        else {
+            match encounter.next_turn() {
+                Ok(change) => turn_announcement(&encounter, &change),
+                Err(e) => format!("Couldn't move on:\n{e}"),
+            }
+        }
+    };
+    reply(ctx, msg, response).await;
+    Ok(())
+}
+
+/// Hold your turn to act later in the round, then come back in with `!rejoin`
+#[command]
+#[only_in(guilds)]
+async fn delay(ctx: &Context, msg: &Message) -> CommandResult {
+    let encounter = get_encounter(ctx, msg.channel_id).await;
+    let runs_encounter = runs_encounter(ctx, msg, &encounter).await;
+    let response = {
+        let mut encounter = encounter.lock().await;
+        let result = current_combatant(&encounter, msg.author.id, runs_encounter)
+            .and_then(|index| encounter.delay(index).map(|change| (index, change)));
+        match result {
+            Ok((index, change)) => format!("⏸ {} delays\n{}", encounter.combatants()[index].name, turn_announcement(&encounter, &change)),
+            Err(e) => format!("Couldn't delay:\n{e}"),
+        }
+    };
+    reply(ctx, msg, response).await;
+    Ok(())
+}
+
+/// Come back in after delaying, taking your turn right after the current one. The GM can name an NPC
+#[command]
+#[only_in(guilds)]
+async fn rejoin(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let encounter = get_encounter(ctx, msg.channel_id).await;
+    let runs_encounter = runs_encounter(ctx, msg, &encounter).await;
+    let response = {
+        let mut encounter = encounter.lock().await;
+        let result = acting_combatant(&encounter, msg.author.id, runs_encounter, args.rest())
+            .and_then(|index| encounter.rejoin(index));
+        match result {
+            Ok(change) => turn_announcement(&encounter, &change),
+            Err(e) => format!("Couldn't rejoin:\n{e}"),
+        }
+    };
+    reply(ctx, msg, response).await;
+    Ok(())
+}
+
+/// Ready an action to take before your next turn, like `!ready attack whoever opens the door`
+#[command]
+#[only_in(guilds)]
+async fn ready(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let encounter = get_encounter(ctx, msg.channel_id).await;
+    let runs_encounter = runs_encounter(ctx, msg, &encounter).await;
+    let response = {
+        let mut encounter = encounter.lock().await;
+        let result = current_combatant(&encounter, msg.author.id, runs_encounter)
+            .and_then(|index| encounter.ready(index, args.rest()).map(|change| (index, change)));
+        match result {
+            Ok((index, change)) => format!(
+                "⚡ {} readies: {}\n{}",
+                encounter.combatants()[index].name,
+                args.rest().trim(),
+                turn_announcement(&encounter, &change)
+            ),
+            Err(e) => format!("Couldn't ready an action:\n{e}"),
+        }
+    };
+    reply(ctx, msg, response).await;
+    Ok(())
+}
+
+/// Take your readied action now. The GM can name an NPC
+#[command]
+#[only_in(guilds)]
+async fn trigger(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let encounter = get_encounter(ctx, msg.channel_id).await;
+    let runs_encounter = runs_encounter(ctx, msg, &encounter).await;
+    let response = {
+        let mut encounter = encounter.lock().await;
+        let result = acting_combatant(&encounter, msg.author.id, runs_encounter, args.rest())
+            .and_then(|index| encounter.trigger(index).map(|action| (index, action)));
+        match result {
+            Ok((index, action)) => format!("⚡ {} takes their readied action: {action}", encounter.combatants()[index].name),
+            Err(e) => format!("Couldn't take a readied action:\n{e}"),
+        }
+    };
+    reply(ctx, msg, response).await;
+    Ok(())
+}
+
+/// Give someone a condition with `!condition <name> <condition> [rounds]`, or take it away with
+/// `!condition remove <name> <condition>`. Durations count from the current turn. Only the GM can do this
+#[command]
+#[only_in(guilds)]
+async fn condition(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let removing = args.current().is_some_and(|arg| arg.eq_ignore_ascii_case("remove"));
+    if removing {
+        args.advance();
+    }
+    let (Ok(who), Ok(condition)) = (args.single_quoted::<String>(), args.single_quoted::<String>()) else {
+        reply(ctx, msg, "Usage: `!condition <name> <condition> [rounds]` or `!condition remove <name> <condition>`").await;
+        return Ok(());
+    };
+    let rounds = args.single::<u32>().ok();
+    let encounter = get_encounter(ctx, msg.channel_id).await;
+    if !runs_encounter(ctx, msg, &encounter).await {
+        reply(ctx, msg, "❌ Only the GM running this encounter can do that").await;
+        return Ok(());
+    }
+    let response = {
+        let mut encounter = encounter.lock().await;
+        let index = match serenity::utils::parse_username(&who) {
+            Some(user_id) => encounter.find_player(&UserId(user_id)).map_err(|_| anyhow::anyhow!("They're not in this encounter")),
+            None => encounter.find(&who),
+        };
+        let result = index.and_then(|index| match removing {
+            true => encounter.remove_condition(index, &condition),
+            false => encounter.add_condition(index, &condition, rounds),
+        });
+        match result {
+            Ok(()) => initiative_order(&encounter),
+            Err(e) => format!("Couldn't change conditions:\n{e}"),
+        }
+    };
+    reply(ctx, msg, response).await;
+    Ok(())
+}
+
+type GuildCharacters = Arc<Mutex<CharacterBook<UserId, ChannelId>>>;
+
+struct CharacterData {
+    guild_state: HashMap<GuildId, GuildCharacters>
+}
+
+impl TypeMapKey for CharacterData {
+    type Value = CharacterData;
+}
+
+/// Where character sheets and each channel's campaign are kept
+const CHARACTERS_FILE: &str = "characters.json";
+
+/// Largest character sheet that can be imported, in bytes
+const MAX_SHEET_SIZE: u64 = 64 * 1024;
+
+async fn get_characters(ctx: &Context, guild_id: GuildId) -> GuildCharacters {
+    let mut ctx_data = ctx.data.write().await;
+    let character_data = ctx_data.entry::<CharacterData>().or_insert(CharacterData{ guild_state: Default::default() });
+    character_data.guild_state.entry(guild_id).or_insert(
+        Arc::new(Mutex::new(CharacterBook::new()))
+    ).clone()
+}
+
+/// Write every server's character sheets to the characters file. Don't call this while holding a character book lock
+async fn save_characters(data: &Arc<RwLock<TypeMap>>) {
+    let books: Vec<(GuildId, GuildCharacters)> = match data.read().await.get::<CharacterData>() {
+        Some(character_data) => character_data.guild_state.iter().map(|(guild_id, book)| (*guild_id, book.clone())).collect(),
+        None => return,
+    };
+    let mut states: HashMap<u64, CharacterBook<UserId, ChannelId>> = HashMap::new();
+    for (guild_id, book) in books {
+        states.insert(guild_id.0, book.lock().await.clone());
+    }
+    if let Err(e) = save_json(CHARACTERS_FILE, &states) {
+        println!("Failed to save character sheets: {e}");
+    }
+}
+
+async fn load_characters(data: &Arc<RwLock<TypeMap>>) -> anyhow::Result<()> {
+    let json = match std::fs::read_to_string(CHARACTERS_FILE) {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let states: HashMap<u64, CharacterBook<UserId, ChannelId>> = serde_json::from_str(&json)?;
+    let guild_state = states.into_iter()
+        .map(|(guild_id, book)| (GuildId(guild_id), Arc::new(Mutex::new(book))))
+        .collect();
+    data.write().await.insert::<CharacterData>(CharacterData { guild_state });
+    Ok(())
+}
+
+/// The JSON for `!sheet import`, from an attachment or pasted after the command
+async fn sheet_json(msg: &Message, pasted: &str) -> anyhow::Result<String> {
+    if let Some(attachment) = msg.attachments.first() {
+        if attachment.size > MAX_SHEET_SIZE {
+            anyhow::bail!("Character sheets can be at most {} KB", MAX_SHEET_SIZE / 1024);
+        }
+        let bytes = attachment.download().await?;
+        return Ok(String::from_utf8(bytes)?);
+    }
+    let pasted = pasted.trim().trim_start_matches("```json").trim_matches('`').trim();
+    if pasted.is_empty() {
+        anyhow::bail!("Paste the sheet's JSON after `!sheet import`, or attach it as a file");
+    }
+    Ok(pasted.to_string())
+}
+
+async fn send_sheet(ctx: &Context, msg: &Message, owner: UserId, campaign: &str, sheet: &Sheet) {
+    let (saves, skills) = sheet.describe_proficiencies();
+    let hp = match sheet.hp.temp {
+        0 => format!("{}/{}", sheet.current_hp(), sheet.hp.max),
+        temp => format!("{}/{} (+{temp} temporary)", sheet.current_hp(), sheet.hp.max),
+    };
+    check_msg(msg.channel_id.send_message(&ctx.http, |m| {
+        m.reference_message(msg).embed(|e| {
+            e.title(format!("📜 {}", sheet.name))
+                .description(format!("Played by {} in the '{campaign}' campaign", owner.mention()))
+                .field("HP", hp, true)
+                .field("AC", sheet.ac, true)
+                .field("Level", format!("{} (proficiency {:+})", sheet.level, sheet.proficiency_bonus()), true)
+                .field("Abilities", sheet.describe_abilities(), false)
+                .field("Saves", truncate_field(saves), false)
+                .field("Skills", truncate_field(skills), false)
+                .colour(Colour::DARK_GOLD)
+        })
+    }).await);
+}
+
+/// `!sheet` shows your character in this channel's campaign, or someone else's with `!sheet @player`.
+/// `!sheet import` takes the sheet's JSON pasted after it or attached as a file, and there's also
+/// `!sheet export`, `!sheet delete` and `!sheet list`
+#[command]
+#[only_in(guilds)]
+async fn sheet(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let characters = get_characters(ctx, msg.guild_id.unwrap()).await;
+    let subcommand = args.current().unwrap_or_default().to_lowercase();
+    if !subcommand.is_empty() {
+        args.advance();
+    }
+    match subcommand.as_str() {
+        "import" => {
+            let json = match sheet_json(msg, args.rest()).await {
+                Ok(json) => json,
+                Err(e) => {
+                    reply(ctx, msg, format!("Couldn't read the sheet:\n{e}")).await;
+                    return Ok(());
+                }
+            };
+            let imported = match Sheet::from_json(&json) {
+                Ok(sheet) => {
+                    let mut characters = characters.lock().await;
+                    let campaign = characters.campaign(&msg.channel_id).to_string();
+                    characters.import(msg.author.id, &campaign, sheet.clone());
+                    Ok((campaign, sheet))
+                },
+                Err(e) => Err(e),
+            };
+            match imported {
+                Ok((campaign, sheet)) => {
+                    save_characters(&ctx.data).await;
+                    send_sheet(ctx, msg, msg.author.id, &campaign, &sheet).await;
+                },
+                Err(e) => reply(ctx, msg, format!("Couldn't import the sheet:\n{e:#}")).await,
+            }
+        },
+        "export" => {
+            let response = {
+                let characters = characters.lock().await;
+                match characters.sheet(&msg.author.id, characters.campaign(&msg.channel_id)) {
+                    Ok(sheet) => match serde_json::to_string_pretty(sheet) {
+                        Ok(json) => format!("```json\n{json}\n```"),
+                        Err(e) => format!("Couldn't export the sheet:\n{e}"),
+                    },
+                    Err(e) => e.to_string(),
+                }
+            };
+            reply(ctx, msg, response).await;
+        },
+        "delete" => {
+            let result = {
+                let mut characters = characters.lock().await;
+                let campaign = characters.campaign(&msg.channel_id).to_string();
+                characters.delete(&msg.author.id, &campaign)
+            };
+            match result {
+                Ok(sheet) => {
+                    save_characters(&ctx.data).await;
+                    reply(ctx, msg, format!("```\nDeleted {}\n```", sheet.name)).await;
+                },
+                Err(e) => reply(ctx, msg, format!("Couldn't delete the sheet:\n{e}")).await,
+            }
+        },
+        "list" => {
+            let response = {
+                let characters = characters.lock().await;
+                let campaign = characters.campaign(&msg.channel_id);
+                let lines: Vec<String> = characters.characters(campaign).iter()
+                    .map(|character| format!(
+                        "- **{}** ({}): HP {}/{}, AC {}",
+                        character.sheet.name,
+                        character.owner.mention(),
+                        character.sheet.current_hp(),
+                        character.sheet.hp.max,
+                        character.sheet.ac
+                    ))
+                    .collect();
+                match lines.is_empty() {
+                    true => format!("Nobody has a character in the '{campaign}' campaign yet. Import one with `!sheet import`"),
+                    false => format!("**Characters in the '{campaign}' campaign**\n{}", lines.join("\n")),
+                }
+            };
+            reply(ctx, msg, response).await;
+        },
+        _ => {
+            let owner = match serenity::utils::parse_username(&subcommand) {
+                Some(user_id) => UserId(user_id),
+                None if subcommand.is_empty() => msg.author.id,
+                None => {
+                    reply(ctx, msg, "Usage: `!sheet [@player]`, `!sheet import`, `!sheet export`, `!sheet delete` or `!sheet list`").await;
+                    return Ok(());
+                }
+            };
+            let found = {
+                let characters = characters.lock().await;
+                let campaign = characters.campaign(&msg.channel_id).to_string();
+                characters.sheet(&owner, &campaign).cloned().map(|sheet| (campaign, sheet))
+            };
+            match found {
+                Ok((campaign, sheet)) => send_sheet(ctx, msg, owner, &campaign, &sheet).await,
+                Err(e) => reply(ctx, msg, e.to_string()).await,
+            }
+        },
+    }
+    Ok(())
+}
+
+/// See which campaign this channel is playing, or as the GM change it with `!campaign <name>`.
+/// Everyone has a separate character sheet in each campaign
+#[command]
+#[only_in(guilds)]
+async fn campaign(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let characters = get_characters(ctx, msg.guild_id.unwrap()).await;
+    let name = args.rest().trim();
+    if name.is_empty() {
+        let campaign = characters.lock().await.campaign(&msg.channel_id).to_string();
+        reply(ctx, msg, format!("This channel is playing the '{campaign}' campaign")).await;
+        return Ok(());
+    }
+    if !is_gm(ctx, msg).await {
+        reply(ctx, msg, "❌ Only the GM can change the campaign").await;
+        return Ok(());
+    }
+    let result = characters.lock().await.set_campaign(msg.channel_id, name);
+    match result {
+        Ok(campaign) => {
+            save_characters(&ctx.data).await;
+            reply(ctx, msg, format!("```\nThis channel is now playing the '{campaign}' campaign\n```")).await;
+        },
+        Err(e) => reply(ctx, msg, format!("Couldn't change the campaign:\n{e}")).await,
+    }
+    Ok(())
+}
+
+/// Roll a d20 check or save from the author's character sheet, with advantage or disadvantage if it ends with `adv` or `dis`
+async fn sheet_roll(ctx: &Context, msg: &Message, input: &str, save: bool) {
+    let mut words: Vec<&str> = input.split_whitespace().collect();
+    let d20 = match words.last().map(|word| word.to_lowercase()).as_deref() {
+        Some("adv") | Some("advantage") => "2d20kh1",
+        Some("dis") | Some("disadvantage") => "2d20kl1",
+        _ => "1d20",
+    };
+    if d20 != "1d20" {
+        words.pop();
+    }
+    let name = words.join(" ");
+    if name.is_empty() {
+        let usage = match save {
+            true => "Usage: `!save <ability> [adv|dis]`, like `!save dex`",
+            false => "Usage: `!check <skill or ability> [adv|dis]`, like `!check stealth`",
+        };
+        reply(ctx, msg, usage).await;
+        return;
+    }
+    let expression = {
+        let characters = get_characters(ctx, msg.guild_id.unwrap()).await;
+        let characters = characters.lock().await;
+        characters.sheet(&msg.author.id, characters.campaign(&msg.channel_id)).and_then(|sheet| {
+            let (roll_name, modifier) = if save { sheet.save(&name)? } else { sheet.check(&name)? };
+            Ok(format!("{d20}{modifier:+} [{}: {roll_name}]", sheet.name))
+        })
+    };
+    match expression {
+        Ok(expression) => roll_and_reply(ctx, msg, &expression).await,
+        Err(e) => reply(ctx, msg, format!("Couldn't roll that:\n{e}")).await,
+    }
+}
+
+/// Roll a skill or ability check from your character sheet, like `!check stealth` or `!check str adv`
+#[command]
+#[only_in(guilds)]
+async fn check(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    sheet_roll(ctx, msg, args.rest(), false).await;
+    Ok(())
+}
+
+/// Roll a saving throw from your character sheet, like `!save dex` or `!save wis dis`
+#[command]
+#[only_in(guilds)]
+async fn save(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    sheet_roll(ctx, msg, args.rest(), true).await;
+    Ok(())
+}
+
+/// Show your HP with `!hp`. The GM can use `!hp damage <who> <amount>`, `!hp heal <who> <amount>`
+/// and `!hp temp <who> <amount>`, where `who` is a player or a character's name
+#[command]
+#[only_in(guilds)]
+async fn hp(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let characters = get_characters(ctx, msg.guild_id.unwrap()).await;
+    let subcommand = args.single::<String>().unwrap_or_default().to_lowercase();
+    if subcommand.is_empty() {
+        let response = {
+            let characters = characters.lock().await;
+            match characters.sheet(&msg.author.id, characters.campaign(&msg.channel_id)) {
+                Ok(sheet) => format!("❤️ {}: **{}/{}** HP, {} temporary", sheet.name, sheet.current_hp(), sheet.hp.max, sheet.hp.temp),
+                Err(e) => e.to_string(),
+            }
+        };
+        reply(ctx, msg, response).await;
+        return Ok(());
+    }
+    if !matches!(subcommand.as_str(), "damage" | "heal" | "temp") {
+        reply(ctx, msg, "Usage: `!hp`, or `!hp damage|heal|temp <who> <amount>`").await;
+        return Ok(());
+    }
+    if !is_gm(ctx, msg).await {
+        reply(ctx, msg, "❌ Only the GM can change HP").await;
+        return Ok(());
+    }
+    let rest = args.rest().trim();
+    let (who, amount) = match rest.rsplit_once(' ').map(|(who, amount)| (who.trim().trim_matches('"'), amount.parse::<i64>())) {
+        Some((who, Ok(amount))) if !who.is_empty() && (0..=10_000).contains(&amount) => (who, amount),
+        _ => {
+            reply(ctx, msg, format!("Usage: `!hp {subcommand} <who> <amount>`, with an amount between 0 and 10000")).await;
+            return Ok(());
+        }
+    };
+    let result = {
+        let mut characters = characters.lock().await;
+        let campaign = characters.campaign(&msg.channel_id).to_string();
+        let character = match serenity::utils::parse_username(who) {
+            Some(user_id) => characters.find_owner_mut(&UserId(user_id), &campaign),
+            None => characters.find_mut(&campaign, who),
+        };
+        character.map(|character| {
+            let sheet = &mut character.sheet;
+            let change = match subcommand.as_str() {
+                "damage" => {
+                    sheet.damage(amount);
+                    format!("💥 {} takes {amount} damage", sheet.name)
+                },
+                "heal" => {
+                    sheet.heal(amount);
+                    format!("💚 {} heals {amount}", sheet.name)
+                },
+                _ => {
+                    sheet.add_temp(amount);
+                    format!("🛡️ {} gains {amount} temporary HP", sheet.name)
+                },
+            };
+            let down = if sheet.current_hp() == 0 { " **Down!**" } else { "" };
+            format!("{change}, now at **{}/{}** HP ({} temporary){down}", sheet.current_hp(), sheet.hp.max, sheet.hp.temp)
+        })
+    };
+    match result {
+        Ok(response) => {
+            save_characters(&ctx.data).await;
+            reply(ctx, msg, response).await;
+        },
+        Err(e) => reply(ctx, msg, format!("Couldn't change HP:\n{e}")).await,
+    }
+    Ok(())
+}
+
+/// Rolls made in each channel since its session started
+struct SessionData {
+    channel_state: HashMap<ChannelId, Arc<Mutex<Session<UserId>>>>
+}
+
+impl TypeMapKey for SessionData {
+    type Value = SessionData;
+}
+
+/// Where each channel's roll history is kept, in a file per channel
+const SESSIONS_DIR: &str = "sessions";
+
+/// Most rolls `!rolls` will list
+const MAX_LISTED_ROLLS: usize = 50;
+
+async fn get_session(ctx: &Context, channel_id: ChannelId) -> Arc<Mutex<Session<UserId>>> {
+    let mut ctx_data = ctx.data.write().await;
+    let session_data = ctx_data.entry::<SessionData>().or_insert(SessionData{ channel_state: Default::default() });
+    session_data.channel_state.entry(channel_id).or_insert(
+        Arc::new(Mutex::new(Session::new(unix_now())))
+    ).clone()
+}
+
+fn session_path(channel_id: ChannelId) -> std::path::PathBuf {
+    std::path::Path::new(SESSIONS_DIR).join(format!("{channel_id}.json"))
+}
+
+/// Write one channel's roll history to its file without blocking the async runtime.
+/// The session stays locked until it's written, so two saves can't land out of order
+async fn save_session(channel_id: ChannelId, session: &Mutex<Session<UserId>>) {
+    let session = session.lock().await;
+    // Not pretty printed, since this is written after every roll
+    let json = match serde_json::to_string(&*session) {
+        Ok(json) => json,
+        Err(e) => {
+            println!("Failed to save roll history for channel {channel_id}: {e}");
+            return;
+        }
+    };
+    let result = match tokio::fs::create_dir_all(SESSIONS_DIR).await {
+        Ok(()) => write_atomically(session_path(channel_id), &json).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        println!("Failed to save roll history for channel {channel_id}: {e}");
+    }
+}
+
+/// Load every channel's roll history. A file that can't be read is skipped, so it only loses that channel's history
+async fn load_sessions(data: &Arc<RwLock<TypeMap>>) -> anyhow::Result<()> {
+    let mut channel_state: HashMap<ChannelId, Arc<Mutex<Session<UserId>>>> = HashMap::new();
+    let entries = match std::fs::read_dir(SESSIONS_DIR) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_none_or(|extension| extension != "json") {
+            continue;
+        }
+        let Some(channel_id) = path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| stem.parse::<u64>().ok()) else {
+            continue;
+        };
+        let session = std::fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| serde_json::from_str::<Session<UserId>>(&json).map_err(anyhow::Error::from));
+        match session {
+            Ok(session) => {
+                channel_state.insert(ChannelId(channel_id), Arc::new(Mutex::new(session)));
+            },
+            Err(e) => println!("Skipping unreadable roll history {}: {e}", path.display()),
         }
     }
+    data.write().await.insert::<SessionData>(SessionData { channel_state });
     Ok(())
 }
 
+/// Add rolls made by the author of a message to the channel's session
+async fn record_rolls(ctx: &Context, msg: &Message, rolled: Vec<Rolled>) {
+    if rolled.is_empty() {
+        return;
+    }
+    let session = get_session(ctx, msg.channel_id).await;
+    {
+        let mut session = session.lock().await;
+        let now = unix_now();
+        for rolled in rolled {
+            session.record(RollRecord::new(msg.author.id, &msg.author.name, rolled, now));
+        }
+    }
+    save_session(msg.channel_id, &session).await;
+}
+
+/// List recent rolls in this channel's session, like `!rolls last 20`, or only one player's with `!rolls last 20 @player`
 #[command]
 #[only_in(guilds)]
-async fn rbets(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+async fn rolls(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    if args.current().is_some_and(|arg| arg.eq_ignore_ascii_case("last")) {
+        args.advance();
+    }
+    let count = args.single::<usize>().unwrap_or(10).clamp(1, MAX_LISTED_ROLLS);
+    let player = args.single::<UserId>().ok();
+    let session = get_session(ctx, msg.channel_id).await;
+    let lines: Vec<String> = session.lock().await.last(count, player.as_ref()).iter()
+        .map(|record| format!(
+            "<t:{}:T> **{}** `{}` → **{}** ({})",
+            record.timestamp,
+            record.player_name,
+            record.expression,
+            record.result,
+            record.describe_dice()
+        ))
+        .collect();
+    if lines.is_empty() {
+        reply(ctx, msg, "Nobody has rolled in this session yet").await;
+        return Ok(());
+    }
+    // Drop the oldest rolls until the list fits in a message
+    let mut shown = lines.len();
+    let mut response = lines.join("\n");
+    while response.chars().count() > MAX_MESSAGE_LENGTH && shown > 1 {
+        shown -= 1;
+        response = lines[lines.len() - shown..].join("\n");
+    }
+    reply(ctx, msg, truncate_message(response)).await;
+    Ok(())
+}
+
+/// How lucky everyone's dice have been this session
+#[command]
+#[only_in(guilds)]
+async fn luck(ctx: &Context, msg: &Message) -> CommandResult {
+    let session = get_session(ctx, msg.channel_id).await;
+    let luck = session.lock().await.luck();
+    if luck.is_empty() {
+        reply(ctx, msg, "Nobody has rolled in this session yet").await;
+        return Ok(());
+    }
+    let lines: Vec<String> = luck.iter()
+        .map(|luck| {
+            let mut line = format!(
+                "- **{}**: {:.0}% luck over {} dice in {} roll{}",
+                luck.player_name,
+                luck.average,
+                luck.dice,
+                luck.rolls,
+                if luck.rolls == 1 { "" } else { "s" }
+            );
+            if luck.d20s > 0 {
+                line += &format!(", {} natural 20s and {} natural 1s from {} d20s", luck.natural_20s, luck.natural_1s, luck.d20s);
+            }
+            line
+        })
+        .collect();
+    reply(ctx, msg, truncate_message(format!(
+        "🍀 **Luck this session** (50% is average, 100% would be every die landing on its highest face)\n{}",
+        lines.join("\n")
+    ))).await;
+    Ok(())
+}
+
+/// `!session` shows when this channel's session started. `!session export` sends its rolls as
+/// a Markdown file, or JSON with `!session export json`, and the GM can begin a new one with `!session start`
+#[command]
+#[only_in(guilds)]
+async fn session(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let subcommand = args.single::<String>().unwrap_or_default().to_lowercase();
+    let session = get_session(ctx, msg.channel_id).await;
+    match subcommand.as_str() {
+        "" | "info" => {
+            let (started_at, count) = {
+                let session = session.lock().await;
+                (session.started_at, session.rolls().len())
+            };
+            reply(ctx, msg, format!("This session started <t:{started_at}:R> and has {count} roll{}", if count == 1 { "" } else { "s" })).await;
+        },
+        "start" => {
+            if !is_gm(ctx, msg).await {
+                reply(ctx, msg, "❌ Only the GM can start a new session").await;
+                return Ok(());
+            }
+            *session.lock().await = Session::new(unix_now());
+            save_session(msg.channel_id, &session).await;
+            reply(ctx, msg, "📖 A new session has started. Earlier rolls have been cleared").await;
+        },
+        "export" => {
+            let format = args.single::<String>().unwrap_or_else(|_| "markdown".into()).to_lowercase();
+            let channel_name = msg.channel_id.name(&ctx.cache).await.unwrap_or_else(|| msg.channel_id.to_string());
+            let exported = {
+                let session = session.lock().await;
+                match format.as_str() {
+                    "markdown" | "md" => Ok((session.to_markdown(&format!("Session log for #{channel_name}")), "md")),
+                    "json" => serde_json::to_string_pretty(&*session).map(|json| (json, "json")).map_err(anyhow::Error::from),
+                    _ => Err(anyhow::anyhow!("Sessions can be exported as `markdown` or `json`")),
+                }
+            };
+            match exported {
+                Ok((contents, extension)) => {
+                    let filename = format!("session-{channel_name}.{extension}");
+                    check_msg(msg.channel_id.send_message(&ctx.http, |m| {
+                        m.reference_message(msg)
+                            .content("📖 Here's this session's log")
+                            .add_file(AttachmentType::Bytes { data: contents.into_bytes().into(), filename })
+                    }).await);
+                },
+                Err(e) => reply(ctx, msg, format!("Couldn't export the session:\n{e}")).await,
+            }
+        },
+        _ => reply(ctx, msg, "Usage: `!session`, `!session export [markdown|json]` or `!session start`").await,
+    }
+    Ok(())
+}
+
+/// Tables that come with the bot, loaded from the tables directory
+struct BuiltinTablesData;
+
+impl TypeMapKey for BuiltinTablesData {
+    type Value = Arc<TableLibrary>;
+}
+
+type GuildTables = Arc<Mutex<TableLibrary>>;
+
+/// Tables each server has uploaded
+struct TableData {
+    guild_state: HashMap<GuildId, GuildTables>
+}
+
+impl TypeMapKey for TableData {
+    type Value = TableData;
+}
+
+/// Where the tables that come with the bot are
+const TABLES_DIR: &str = "tables";
+
+/// Where tables uploaded by each server are kept
+const GUILD_TABLES_FILE: &str = "guild_tables.json";
+
+/// Largest table file that can be uploaded, in bytes
+const MAX_TABLE_SIZE: u64 = 64 * 1024;
+
+/// Most times a table can be rolled on at once
+const MAX_TABLE_ROLLS: usize = 10;
+
+async fn get_guild_tables(ctx: &Context, guild_id: GuildId) -> GuildTables {
     let mut ctx_data = ctx.data.write().await;
-    let roulette_data = ctx_data.entry::<RouletteData>().or_insert(RouletteData{ channel_state: Default::default() });
-    let roulette_state = roulette_data.channel_state.entry(msg.channel_id).or_insert(
-        Arc::new(Mutex::new(RouletteState::new()))
-    );
-    let player_id = msg.author.id;
-    let player_name = msg.author.name.clone();
-    let mut roulette_state_mut = roulette_state.lock().await;
-    roulette_state_mut.register_player(player_id, &player_name);
-    let current_bets = roulette_state_mut.get_bets(player_id);
-    match current_bets {
-        Ok(current_bets) => {
-            let bets: Vec<String> = current_bets.into_iter().map(|bet| format!("- {bet:?}")).collect();
-            reply(ctx, msg, format!("Current bets:\n```\n{}\n```", bets.join("\n"))).await;
+    let table_data = ctx_data.entry::<TableData>().or_insert(TableData{ guild_state: Default::default() });
+    table_data.guild_state.entry(guild_id).or_insert(
+        Arc::new(Mutex::new(TableLibrary::new()))
+    ).clone()
+}
+
+async fn get_builtin_tables(ctx: &Context) -> Arc<TableLibrary> {
+    match ctx.data.read().await.get::<BuiltinTablesData>() {
+        Some(tables) => tables.clone(),
+        None => Arc::new(TableLibrary::new()),
+    }
+}
+
+/// Write every server's uploaded tables to the tables file. Don't call this while holding a table library lock
+async fn save_guild_tables(data: &Arc<RwLock<TypeMap>>) {
+    let libraries: Vec<(GuildId, GuildTables)> = match data.read().await.get::<TableData>() {
+        Some(table_data) => table_data.guild_state.iter().map(|(guild_id, library)| (*guild_id, library.clone())).collect(),
+        None => return,
+    };
+    let mut states: HashMap<u64, TableLibrary> = HashMap::new();
+    for (guild_id, library) in libraries {
+        states.insert(guild_id.0, library.lock().await.clone());
+    }
+    if let Err(e) = save_json(GUILD_TABLES_FILE, &states) {
+        println!("Failed to save uploaded tables: {e}");
+    }
+}
+
+async fn load_guild_tables(data: &Arc<RwLock<TypeMap>>) -> anyhow::Result<()> {
+    let json = match std::fs::read_to_string(GUILD_TABLES_FILE) {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let states: HashMap<u64, TableLibrary> = serde_json::from_str(&json)?;
+    let guild_state = states.into_iter()
+        .map(|(guild_id, library)| (GuildId(guild_id), Arc::new(Mutex::new(library))))
+        .collect();
+    data.write().await.insert::<TableData>(TableData { guild_state });
+    Ok(())
+}
+
+/// Parse a table attached to a message, named after the file unless a name is given
+async fn uploaded_table(msg: &Message, name: &str) -> anyhow::Result<RandomTable> {
+    let attachment = msg.attachments.first()
+        .ok_or_else(|| anyhow::anyhow!("Attach the table as a `.csv`, `.yaml` or `.yml` file"))?;
+    let format = Format::from_filename(&attachment.filename)
+        .ok_or_else(|| anyhow::anyhow!("Tables have to be `.csv`, `.yaml` or `.yml` files"))?;
+    if attachment.size > MAX_TABLE_SIZE {
+        anyhow::bail!("Tables can be at most {} KB", MAX_TABLE_SIZE / 1024);
+    }
+    let text = String::from_utf8(attachment.download().await?)?;
+    let name = match name.trim() {
+        "" => attachment.filename.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(&attachment.filename),
+        name => name,
+    };
+    RandomTable::parse(name, &text, format)
+}
+
+/// Roll on random tables with `!table roll <name> [times]`, see them with `!table list` and `!table show <name>`.
+/// GMs can upload a table as a CSV or YAML attachment with `!table upload [name]` and remove it with `!table delete <name>`
+#[command]
+#[only_in(guilds)]
+async fn table(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let subcommand = args.single::<String>().unwrap_or_else(|_| "list".into()).to_lowercase();
+    let guild_tables = get_guild_tables(ctx, msg.guild_id.unwrap()).await;
+    let builtin_tables = get_builtin_tables(ctx).await;
+    match subcommand.as_str() {
+        "list" => {
+            let guild_tables = guild_tables.lock().await;
+            let names = |library: &TableLibrary| {
+                let names: Vec<String> = library.tables().map(|table| format!("`{}`", table.name)).collect();
+                if names.is_empty() { "None".to_string() } else { names.join(", ") }
+            };
+            reply(ctx, msg, truncate_message(format!(
+                "**Server tables**\n{}\n**Built in tables**\n{}",
+                names(&guild_tables),
+                names(&builtin_tables)
+            ))).await;
+        },
+        "roll" => {
+            let name = args.single::<String>().unwrap_or_default();
+            let times = args.single::<usize>().unwrap_or(1).clamp(1, MAX_TABLE_ROLLS);
+            let response = {
+                let guild_tables = guild_tables.lock().await;
+                let lookup = |name: &str| guild_tables.get(name).or_else(|| builtin_tables.get(name));
+                match lookup(&name) {
+                    Some(table) => {
+                        let mut rng = rand::thread_rng();
+                        let results: anyhow::Result<Vec<String>> = (0..times)
+                            .map(|_| tables::roll(table, &lookup, &mut rng).map(|rolled| {
+                                let rolls: Vec<String> = rolled.rolls.iter().map(|(table, total)| format!("{table} {total}")).collect();
+                                format!("🎲 {} *({})*", rolled.text, rolls.join(" → "))
+                            }))
+                            .collect();
+                        match results {
+                            Ok(results) => format!("**{}** ({})\n{}", table.name, table.dice, results.join("\n")),
+                            Err(e) => format!("Couldn't roll on '{name}':\n{e}"),
+                        }
+                    },
+                    None => format!("There's no table called '{name}'. See them all with `!table list`"),
+                }
+            };
+            reply(ctx, msg, truncate_message(response)).await;
+        },
+        "show" => {
+            let name = args.single::<String>().unwrap_or_default();
+            let response = {
+                let guild_tables = guild_tables.lock().await;
+                match guild_tables.get(&name).or_else(|| builtin_tables.get(&name)) {
+                    Some(table) => {
+                        let rows: Vec<String> = table.rows.iter()
+                            .map(|row| match row.low == row.high {
+                                true => format!("{:>7} {}", row.low, row.result),
+                                false => format!("{:>7} {}", format!("{}-{}", row.low, row.high), row.result),
+                            })
+                            .collect();
+                        format!("**{}** ({})\n```\n{}\n```", table.name, table.dice, rows.join("\n"))
+                    },
+                    None => format!("There's no table called '{name}'. See them all with `!table list`"),
+                }
+            };
+            if response.chars().count() > MAX_MESSAGE_LENGTH {
+                reply(ctx, msg, format!("'{name}' is too long to show here")).await;
+            }
+            else {
+                reply(ctx, msg, response).await;
+            }
+        },
+        "upload" | "delete" if !is_gm(ctx, msg).await => {
+            reply(ctx, msg, "❌ Only the GM can change the server's tables").await;
+        },
+        "upload" => {
+            let table = match uploaded_table(msg, args.rest()).await {
+                Ok(table) => table,
+                Err(e) => {
+                    reply(ctx, msg, format!("Couldn't upload the table:\n{e}")).await;
+                    return Ok(());
+                }
+            };
+            let response = {
+                let mut guild_tables = guild_tables.lock().await;
+                if guild_tables.get(&table.name).is_none() && guild_tables.tables().count() >= tables::MAX_GUILD_TABLES {
+                    Err(anyhow::anyhow!("Servers can have at most {} tables", tables::MAX_GUILD_TABLES))
+                }
+                else {
+                    let response = format!("Saved '{}' with {} rows. Roll on it with `!table roll {}`", table.name, table.rows.len(), table.name);
+                    guild_tables.add(table);
+                    Ok(response)
+                }
+            };
+            match response {
+                Ok(response) => {
+                    save_guild_tables(&ctx.data).await;
+                    reply(ctx, msg, format!("```\n{response}\n```")).await;
+                },
+                Err(e) => reply(ctx, msg, format!("Couldn't upload the table:\n{e}")).await,
+            }
+        },
+        "delete" => {
+            let name = args.rest().trim().to_string();
+            let result = guild_tables.lock().await.remove(&name);
+            match result {
+                Ok(table) => {
+                    save_guild_tables(&ctx.data).await;
+                    reply(ctx, msg, format!("```\nDeleted '{}'\n```", table.name)).await;
+                },
+                Err(e) => reply(ctx, msg, format!("Couldn't delete the table:\n{e}")).await,
+            }
+        },
+        _ => reply(ctx, msg, "Usage: `!table list`, `!table roll <name> [times]`, `!table show <name>`, `!table upload [name]` or `!table delete <name>`").await,
+    }
+    Ok(())
+}
+
+/// The total modifier below which each server flags rolled ability scores for a reroll
+struct RerollThresholdData {
+    guild_thresholds: HashMap<GuildId, i64>
+}
+
+impl TypeMapKey for RerollThresholdData {
+    type Value = RerollThresholdData;
+}
+
+const REROLL_THRESHOLDS_FILE: &str = "reroll_thresholds.json";
+
+/// Point buys players are part way through, by server and player
+struct PointBuyData {
+    point_buys: HashMap<(GuildId, UserId), PointBuy>
+}
+
+impl TypeMapKey for PointBuyData {
+    type Value = PointBuyData;
+}
+
+/// Step through a point buy with `!stats pointbuy`:
+/// `[points] [scores...]` starts over, `<ability> <score|+n|-n>` changes one ability, `reset` puts everything back to 8 and `done` finishes
+async fn point_buy_command(ctx: &Context, guild_id: GuildId, player_id: UserId, words: &[&str]) -> String {
+    let usage = "Change a score with `!stats pointbuy <ability> <score|+1|-1>`, start over with `!stats pointbuy reset` and finish with `!stats pointbuy done`";
+    let mut ctx_data = ctx.data.write().await;
+    let point_buys = &mut ctx_data.entry::<PointBuyData>().or_insert(PointBuyData{ point_buys: Default::default() }).point_buys;
+    let key = (guild_id, player_id);
+    match words {
+        ["done"] => match point_buys.remove(&key) {
+            Some(point_buy) => format!("**Final point buy with {} points**\n```\n{}\n```", point_buy.budget, point_buy.describe()),
+            None => "You don't have a point buy going. Start one with `!stats pointbuy [points]`".to_string(),
+        },
+        ["reset"] => {
+            let budget = point_buys.get(&key).map(|point_buy| point_buy.budget).unwrap_or(stats::DEFAULT_POINT_BUY);
+            let point_buy = point_buys.entry(key).or_insert(PointBuy::new(budget).expect("The budget was already checked"));
+            point_buy.scores = [8; 6];
+            format!("**Point buy with {budget} points**\n```\n{}\n```\n{usage}", point_buy.describe())
+        },
+        [ability, change] if sheets::ability_key(ability).is_some() => {
+            let Some(point_buy) = point_buys.get_mut(&key) else {
+                return "You don't have a point buy going. Start one with `!stats pointbuy [points]`".to_string();
+            };
+            let ability_key = sheets::ability_key(ability).expect("The ability was already checked");
+            let index = sheets::ABILITIES.iter().position(|(key, _)| *key == ability_key).expect("Ability keys come from the list of abilities");
+            let result = match change.parse::<i64>() {
+                Ok(steps) if change.starts_with(['+', '-']) => point_buy.adjust(index, steps),
+                Ok(score) => point_buy.set(index, score),
+                Err(_) => Err(anyhow::anyhow!("'{change}' should be a score like 14 or a change like +1 or -1")),
+            };
+            match result {
+                Ok(()) => format!("**Point buy with {} points**\n```\n{}\n```", point_buy.budget, point_buy.describe()),
+                Err(e) => format!("❌ {e}\n```\n{}\n```", point_buy.describe()),
+            }
+        },
+        [] if point_buys.contains_key(&key) => {
+            let point_buy = &point_buys[&key];
+            format!("**Point buy with {} points**\n```\n{}\n```\n{usage}", point_buy.budget, point_buy.describe())
+        },
+        words => {
+            let budget = match words.first().map(|budget| budget.parse::<i64>()) {
+                Some(Ok(budget)) => budget,
+                None => stats::DEFAULT_POINT_BUY,
+                Some(Err(_)) => return format!("Usage: `!stats pointbuy [points] [STR] [DEX] [CON] [INT] [WIS] [CHA]`\n{usage}"),
+            };
+            let mut point_buy = match PointBuy::new(budget) {
+                Ok(point_buy) => point_buy,
+                Err(e) => return format!("❌ {e}"),
+            };
+            // Scores given up front are bought one at a time, in ability order
+            let scores = words.iter().skip(1);
+            if scores.len() > sheets::ABILITIES.len() {
+                return format!("❌ There are only {} abilities, but {} scores were given", sheets::ABILITIES.len(), scores.len());
+            }
+            for (index, score) in scores.enumerate() {
+                let result = score.parse::<i64>()
+                    .map_err(|_| anyhow::anyhow!("'{score}' isn't a score"))
+                    .and_then(|score| point_buy.set(index, score));
+                if let Err(e) = result {
+                    return format!("❌ {e}\n```\n{}\n```", point_buy.describe());
+                }
+            }
+            let response = format!(
+                "**Point buy with {budget} points**\nScores cost:\n```\n{}\n```\n```\n{}\n```\n{usage}",
+                stats::point_buy_costs(),
+                point_buy.describe()
+            );
+            point_buys.insert(key, point_buy);
+            response
+        },
+    }
+}
+
+async fn load_reroll_thresholds(data: &Arc<RwLock<TypeMap>>) -> anyhow::Result<()> {
+    let json = match std::fs::read_to_string(REROLL_THRESHOLDS_FILE) {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let thresholds: HashMap<u64, i64> = serde_json::from_str(&json)?;
+    let guild_thresholds = thresholds.into_iter().map(|(guild_id, threshold)| (GuildId(guild_id), threshold)).collect();
+    data.write().await.insert::<RerollThresholdData>(RerollThresholdData { guild_thresholds });
+    Ok(())
+}
+
+async fn get_reroll_threshold(ctx: &Context, guild_id: GuildId) -> i64 {
+    match ctx.data.read().await.get::<RerollThresholdData>() {
+        Some(data) => data.guild_thresholds.get(&guild_id).copied().unwrap_or(stats::DEFAULT_REROLL_THRESHOLD),
+        None => stats::DEFAULT_REROLL_THRESHOLD,
+    }
+}
+
+/// Roll ability scores with `!stats`, or another way like `!stats 3d6` or `!stats 4d6dl1 x3` for three sets.
+/// `!stats array` shows the standard array, and `!stats pointbuy 27` starts a point buy that's changed a score at a time.
+/// GMs choose the total modifier below which rolled scores should be rerolled with `!stats threshold <modifier>`
+#[command]
+#[only_in(guilds)]
+async fn stats(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap();
+    match args.current().map(|arg| arg.to_lowercase()).as_deref() {
+        Some("pointbuy") => {
+            args.advance();
+            let words: Vec<String> = args.rest().split_whitespace().map(str::to_lowercase).collect();
+            let words: Vec<&str> = words.iter().map(String::as_str).collect();
+            let response = point_buy_command(ctx, guild_id, msg.author.id, &words).await;
+            reply(ctx, msg, response).await;
+        },
+        Some("threshold") => {
+            args.advance();
+            let Ok(threshold) = args.single::<i64>() else {
+                reply(ctx, msg, format!("Rolled scores with a total modifier below {:+} are flagged for a reroll", get_reroll_threshold(ctx, guild_id).await)).await;
+                return Ok(());
+            };
+            if !is_gm(ctx, msg).await {
+                reply(ctx, msg, "❌ Only the GM can change the reroll threshold").await;
+                return Ok(());
+            }
+            let thresholds: HashMap<u64, i64> = {
+                let mut ctx_data = ctx.data.write().await;
+                let threshold_data = ctx_data.entry::<RerollThresholdData>().or_insert(RerollThresholdData{ guild_thresholds: Default::default() });
+                threshold_data.guild_thresholds.insert(guild_id, threshold);
+                threshold_data.guild_thresholds.iter().map(|(guild_id, threshold)| (guild_id.0, *threshold)).collect()
+            };
+            if let Err(e) = save_json(REROLL_THRESHOLDS_FILE, &thresholds) {
+                println!("Failed to save reroll thresholds: {e}");
+            }
+            reply(ctx, msg, format!("```\nRolled scores with a total modifier below {threshold:+} will be flagged for a reroll\n```")).await;
+        },
+        _ => {
+            let input = args.rest().trim();
+            let (input, sets) = match input.rsplit_once(' ') {
+                Some((method, sets)) if sets.starts_with(['x', 'X']) && sets[1..].parse::<usize>().is_ok() => (method, sets[1..].parse::<usize>().unwrap()),
+                _ => (input, 1),
+            };
+            let method = match Method::parse(input) {
+                Ok(method) => method,
+                Err(e) => {
+                    reply(ctx, msg, format!("Invalid roll expression: {}\n```\n{}\n```", e.message, e.pointer(input))).await;
+                    return Ok(());
+                }
+            };
+            let sets = match method {
+                Method::StandardArray => 1,
+                Method::Roll(_) => sets.clamp(1, stats::MAX_SETS),
+            };
+            let threshold = get_reroll_threshold(ctx, guild_id).await;
+            let arrays: Result<Vec<_>, _> = {
+                let mut rng = rand::thread_rng();
+                (0..sets).map(|_| method.generate(&mut rng)).collect()
+            };
+            let arrays = match arrays {
+                Ok(arrays) => arrays,
+                Err(e) => {
+                    reply(ctx, msg, format!("Couldn't roll: {e}")).await;
+                    return Ok(());
+                }
+            };
+            let title = match &method {
+                Method::Roll(_) if input.is_empty() => format!("Ability scores rolled with `{}`", stats::DEFAULT_ROLL),
+                Method::Roll(_) => format!("Ability scores rolled with `{input}`"),
+                Method::StandardArray => "The standard array".to_string(),
+            };
+            let mut response = format!("**{title}**");
+            for (index, array) in arrays.iter().enumerate() {
+                if sets > 1 {
+                    response += &format!("\n__Set {}__", index + 1);
+                }
+                response += &format!("\n{}\nTotal {}, modifiers {:+}", array.describe(), array.total(), array.total_modifier());
+                if array.needs_reroll(threshold) {
+                    response += &format!("\n⚠️ Below the reroll threshold of {threshold:+}, so this set can be rerolled");
+                }
+            }
+            let rolled = arrays.iter()
+                .flat_map(|array| array.scores.iter())
+                .filter_map(|score| score.roll.as_ref().map(Rolled::from_breakdown))
+                .collect();
+            reply(ctx, msg, truncate_message(response)).await;
+            record_rolls(ctx, msg, rolled).await;
         },
-        Err(e) => {
-            reply(ctx, msg, format!("Unable to get current bets: {}", e.to_string())).await;
-        }
     }
     Ok(())
 }
@@ -0,0 +1,245 @@
+use std::collections::BTreeMap;
+
+/// Most distinct results a distribution can have before we stop working it out
+const MAX_OUTCOMES: usize = 100_000;
+
+/// Most rows in a histogram, after which results are grouped into ranges
+const MAX_HISTOGRAM_ROWS: usize = 30;
+
+/// Width of the longest bar in a histogram
+const HISTOGRAM_WIDTH: usize = 25;
+
+/// Steps left for working out one set of odds, shared by every part of an expression
+#[derive(Debug)]
+pub struct Budget {
+    remaining: usize
+}
+
+impl Budget {
+    pub fn new(steps: usize) -> Self {
+        Budget { remaining: steps }
+    }
+
+    /// Use up some steps, failing once there aren't enough left
+    pub fn spend(&mut self, steps: usize, message: &str) -> Result<(), String> {
+        self.remaining = self.remaining.checked_sub(steps).ok_or(message)?;
+        Ok(())
+    }
+}
+
+/// Every result something can have, and how likely each is
+#[derive(Debug, Clone)]
+pub struct Distribution {
+    outcomes: BTreeMap<i64, f64>
+}
+
+impl Distribution {
+    pub fn constant(value: i64) -> Self {
+        Distribution { outcomes: BTreeMap::from([(value, 1.0)]) }
+    }
+
+    /// Build a distribution from results and their probabilities, adding up repeated results
+    pub fn from_weights(weights: impl IntoIterator<Item = (i64, f64)>) -> Self {
+        let mut outcomes = BTreeMap::new();
+        for (value, probability) in weights {
+            if probability > 0.0 {
+                *outcomes.entry(value).or_insert(0.0) += probability;
+            }
+        }
+        Distribution { outcomes }
+    }
+
+    pub fn outcomes(&self) -> &BTreeMap<i64, f64> {
+        &self.outcomes
+    }
+
+    /// Apply `op` to every pair of results from two independent distributions, spending a step on each pair.
+    /// Fails if `op` fails for any pair, or there would be too many results
+    pub fn combine(&self, other: &Distribution, op: impl Fn(i64, i64) -> Option<i64>, budget: &mut Budget) -> Result<Self, String> {
+        budget.spend(self.outcomes.len().saturating_mul(other.outcomes.len()), "There are too many possible results to work out the odds")?;
+        let mut outcomes: BTreeMap<i64, f64> = BTreeMap::new();
+        for (left, left_probability) in self.outcomes.iter() {
+            for (right, right_probability) in other.outcomes.iter() {
+                let value = op(*left, *right).ok_or("The result could be too large")?;
+                *outcomes.entry(value).or_insert(0.0) += left_probability * right_probability;
+            }
+            if outcomes.len() > MAX_OUTCOMES {
+                return Err("There are too many possible results to work out the odds".into());
+            }
+        }
+        Ok(Distribution { outcomes })
+    }
+
+    /// The distribution of adding up `count` independent copies of this one.
+    /// Copies are added up in doubling batches, so it takes a handful of combines rather than one per copy
+    pub fn repeat_sum(&self, count: i64, budget: &mut Budget) -> Result<Self, String> {
+        let mut total = Distribution::constant(0);
+        let mut batch = self.clone();
+        let mut remaining = count.max(0);
+        while remaining > 0 {
+            if remaining & 1 == 1 {
+                total = total.combine(&batch, i64::checked_add, budget)?;
+            }
+            remaining >>= 1;
+            if remaining > 0 {
+                batch = batch.combine(&batch, i64::checked_add, budget)?;
+            }
+        }
+        Ok(total)
+    }
+
+    pub fn map(&self, f: impl Fn(i64) -> Option<i64>) -> Result<Self, String> {
+        let mut weights = vec![];
+        for (value, probability) in self.outcomes.iter() {
+            weights.push((f(*value).ok_or("The result could be too large")?, *probability));
+        }
+        Ok(Distribution::from_weights(weights))
+    }
+
+    pub fn probability(&self, matches: impl Fn(i64) -> bool) -> f64 {
+        self.outcomes.iter().filter(|(value, _)| matches(**value)).map(|(_, probability)| probability).sum()
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.outcomes.iter().map(|(value, probability)| *value as f64 * probability).sum()
+    }
+
+    pub fn standard_deviation(&self) -> f64 {
+        let mean = self.mean();
+        let variance: f64 = self.outcomes.iter().map(|(value, probability)| (*value as f64 - mean).powi(2) * probability).sum();
+        variance.sqrt()
+    }
+
+    /// Bar chart of the results, one row per result or per range of results.
+    /// With a target, bars for results that meet it are solid and the rest are shaded.
+    /// Results far too unlikely to show up as a bar are left off the ends
+    pub fn histogram(&self, target: Option<&dyn Fn(i64) -> bool>) -> String {
+        let most_likely = self.outcomes.values().fold(0.0, |most, probability| probability.max(most));
+        let visible = || self.outcomes.iter().filter(|(_, probability)| **probability >= most_likely / 1000.0).map(|(value, _)| value);
+        let (Some(lowest), Some(highest)) = (visible().next(), visible().next_back()) else {
+            return String::new();
+        };
+        let span = (*highest as i128 - *lowest as i128 + 1) as u128;
+        let bucket_size = span.div_ceil(MAX_HISTOGRAM_ROWS as u128).max(1) as i128;
+
+        // Each row is the range it covers, its probability and the share of that which meets the target
+        let mut rows: Vec<(i64, i64, f64, f64)> = vec![];
+        for (value, probability) in self.outcomes.range(lowest..=highest) {
+            let start = (*lowest as i128 + (*value as i128 - *lowest as i128) / bucket_size * bucket_size) as i64;
+            let meets = if target.is_some_and(|target| target(*value)) { *probability } else { 0.0 };
+            match rows.last_mut() {
+                Some(row) if row.0 == start => {
+                    row.1 = *value;
+                    row.2 += probability;
+                    row.3 += meets;
+                },
+                _ => rows.push((start, *value, *probability, meets)),
+            }
+        }
+
+        let labels: Vec<String> = rows.iter()
+            .map(|(start, end, _, _)| if start == end { start.to_string() } else { format!("{start}-{end}") })
+            .collect();
+        let label_width = labels.iter().map(|label| label.len()).max().unwrap_or(0);
+        let most_likely = rows.iter().map(|row| row.2).fold(0.0, f64::max);
+        rows.iter().zip(labels)
+            .map(|((_, _, probability, meets), label)| {
+                let length = ((probability / most_likely) * HISTOGRAM_WIDTH as f64).round().max(1.0) as usize;
+                let bar = match target {
+                    None => "█",
+                    Some(_) if *meets >= *probability * 0.999 => "█",
+                    Some(_) if *meets <= 0.0 => "░",
+                    Some(_) => "▒",
+                };
+                format!("{label:>label_width$} | {} {:.2}%", bar.repeat(length), probability * 100.0)
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dice::Expression;
+
+    fn odds(expression: &str) -> Distribution {
+        Expression::parse(expression).unwrap().distribution().unwrap()
+    }
+
+    fn die(sides: i64) -> Distribution {
+        Distribution::from_weights((1..=sides).map(|value| (value, 1.0 / sides as f64)))
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{actual} isn't {expected}");
+    }
+
+    fn assert_same(actual: &Distribution, expected: &Distribution) {
+        assert_eq!(actual.outcomes().keys().collect::<Vec<_>>(), expected.outcomes().keys().collect::<Vec<_>>());
+        for (value, probability) in expected.outcomes() {
+            assert_close(actual.outcomes()[value], *probability);
+        }
+    }
+
+    #[test]
+    fn three_d6() {
+        let distribution = odds("3d6");
+        assert_close(distribution.probability(|total| total >= 13), 56.0 / 216.0);
+        assert_close(distribution.probability(|total| total >= 14), 35.0 / 216.0);
+        assert_close(distribution.probability(|total| total == 3), 1.0 / 216.0);
+        assert_close(distribution.probability(|_| true), 1.0);
+        assert_close(distribution.mean(), 10.5);
+        assert_close(distribution.standard_deviation(), (35.0f64 / 4.0).sqrt());
+    }
+
+    #[test]
+    fn repeat_sum_matches_repeated_combine() {
+        for count in [0, 1, 2, 5, 8, 13] {
+            let mut expected = Distribution::constant(0);
+            for _ in 0..count {
+                expected = expected.combine(&die(6), i64::checked_add, &mut Budget::new(usize::MAX)).unwrap();
+            }
+            let summed = die(6).repeat_sum(count, &mut Budget::new(usize::MAX)).unwrap();
+            assert_same(&summed, &expected);
+        }
+    }
+
+    #[test]
+    fn keeping_and_dropping() {
+        // Four sixes, or three sixes and anything else in any of four places
+        assert_close(odds("4d6kh3").probability(|total| total == 18), 21.0 / 1296.0);
+        assert_close(odds("4d6dl1").mean(), 15869.0 / 1296.0);
+        assert_close(odds("2d20kh1").probability(|total| total == 20), 39.0 / 400.0);
+        assert_close(odds("2d20kl1").probability(|total| total == 1), 39.0 / 400.0);
+        assert_close(odds("2d20kh1").mean(), 13.825);
+        assert_same(&odds("3d6dh1"), &odds("3d6kl2"));
+    }
+
+    #[test]
+    fn rerolls() {
+        let once = odds("1d6ro1");
+        assert_close(once.probability(|total| total == 1), 1.0 / 36.0);
+        assert_close(once.probability(|total| total == 4), 7.0 / 36.0);
+        assert_same(&odds("1d6r1"), &Distribution::from_weights((2..=6).map(|value| (value, 0.2))));
+    }
+
+    #[test]
+    fn exploding() {
+        let exploding = odds("1d6!");
+        assert_close(exploding.probability(|total| total == 6), 0.0);
+        assert_close(exploding.probability(|total| total == 7), 1.0 / 36.0);
+        assert_close(exploding.mean(), 4.2);
+    }
+
+    #[test]
+    fn running_out_of_budget() {
+        assert!(die(6).combine(&die(6), i64::checked_add, &mut Budget::new(35)).is_err());
+        assert!(die(6).combine(&die(6), i64::checked_add, &mut Budget::new(36)).is_ok());
+        let mut budget = Budget::new(100);
+        assert!(die(6).repeat_sum(100, &mut budget).is_err());
+
+        let error = Expression::parse("1000d10000").unwrap().distribution().unwrap_err();
+        assert_eq!(error.message, "There are too many possible results to work out the odds");
+    }
+}
@@ -0,0 +1,805 @@
+use std::{fmt::Display, hash::Hash};
+use anyhow::{Context, Result};
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::cards::{format_cards, Card, Shoe};
+use crate::roulette::RouletteState;
+
+pub const MAX_SEATS: usize = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HandCategory {
+    HighCard,
+    Pair,
+    TwoPair,
+    ThreeOfAKind,
+    Straight,
+    Flush,
+    FullHouse,
+    FourOfAKind,
+    StraightFlush
+}
+
+impl Display for HandCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            HandCategory::HighCard => "High card",
+            HandCategory::Pair => "Pair",
+            HandCategory::TwoPair => "Two pair",
+            HandCategory::ThreeOfAKind => "Three of a kind",
+            HandCategory::Straight => "Straight",
+            HandCategory::Flush => "Flush",
+            HandCategory::FullHouse => "Full house",
+            HandCategory::FourOfAKind => "Four of a kind",
+            HandCategory::StraightFlush => "Straight flush",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Strength of a five card poker hand. Compares the way poker hands do
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct HandValue {
+    pub category: HandCategory,
+    /// Card ranks (aces high as 14) in the order they break ties
+    pub tiebreakers: Vec<u8>
+}
+
+fn poker_rank(card: &Card) -> u8 {
+    if card.is_ace() { 14 } else { card.rank }
+}
+
+fn evaluate_five(cards: &[Card]) -> HandValue {
+    let mut ranks: Vec<u8> = cards.iter().map(poker_rank).collect();
+    ranks.sort_unstable_by(|a, b| b.cmp(a));
+    let is_flush = cards.iter().all(|card| card.suit == cards[0].suit);
+    let mut distinct = ranks.clone();
+    distinct.dedup();
+    let straight_high = if distinct.len() == 5 && distinct[0] - distinct[4] == 4 {
+        Some(distinct[0])
+    }
+    else if distinct == [14, 5, 4, 3, 2] {
+        // The wheel, where the ace plays low
+        Some(5)
+    }
+    else {
+        None
+    };
+
+    // Group ranks by how often they appear, biggest groups then highest ranks first
+    let mut groups: Vec<(usize, u8)> = distinct.iter()
+        .map(|rank| (ranks.iter().filter(|r| *r == rank).count(), *rank))
+        .collect();
+    groups.sort_unstable_by(|a, b| b.cmp(a));
+    let grouped_ranks: Vec<u8> = groups.iter().map(|(_, rank)| *rank).collect();
+
+    let (category, tiebreakers) = match (straight_high, is_flush, groups[0].0, groups.get(1).map(|g| g.0)) {
+        (Some(high), true, _, _) => (HandCategory::StraightFlush, vec![high]),
+        (_, _, 4, _) => (HandCategory::FourOfAKind, grouped_ranks),
+        (_, _, 3, Some(2)) => (HandCategory::FullHouse, grouped_ranks),
+        (_, true, _, _) => (HandCategory::Flush, ranks),
+        (Some(high), _, _, _) => (HandCategory::Straight, vec![high]),
+        (_, _, 3, _) => (HandCategory::ThreeOfAKind, grouped_ranks),
+        (_, _, 2, Some(2)) => (HandCategory::TwoPair, grouped_ranks),
+        (_, _, 2, _) => (HandCategory::Pair, grouped_ranks),
+        _ => (HandCategory::HighCard, ranks),
+    };
+    HandValue { category, tiebreakers }
+}
+
+/// Best five card hand that can be made from any number of cards (at least five)
+pub fn evaluate(cards: &[Card]) -> HandValue {
+    assert!(cards.len() >= 5, "A poker hand needs at least five cards");
+    let n = cards.len();
+    let mut best: Option<HandValue> = None;
+    // Try every way of leaving out all but five cards
+    for a in 0..n {
+        for b in a + 1..n {
+            for c in b + 1..n {
+                for d in c + 1..n {
+                    for e in d + 1..n {
+                        let value = evaluate_five(&[cards[a], cards[b], cards[c], cards[d], cards[e]]);
+                        if best.as_ref().is_none_or(|best| value > *best) {
+                            best = Some(value);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    best.expect("At least one five card combination exists")
+}
+
+/// A pot and the players that can still win it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pot<T> {
+    pub amount: u128,
+    pub eligible: Vec<T>
+}
+
+/// Split everyone's chips into a main pot and side pots.
+/// `contributions` holds each player's total chips put in this hand, and whether they folded
+pub fn side_pots<T: Clone>(contributions: &[(T, u128, bool)]) -> Vec<Pot<T>> {
+    let mut levels: Vec<u128> = contributions.iter()
+        .filter(|(_, amount, folded)| !folded && *amount > 0)
+        .map(|(_, amount, _)| *amount)
+        .collect();
+    levels.sort_unstable();
+    levels.dedup();
+
+    let mut pots: Vec<Pot<T>> = vec![];
+    let mut previous_level = 0;
+    for (index, level) in levels.iter().enumerate() {
+        // Chips above the last live player's level also go in the top pot
+        let is_top = index == levels.len() - 1;
+        let amount: u128 = contributions.iter()
+            .map(|(_, amount, _)| {
+                let upper = if is_top { *amount } else { (*amount).min(*level) };
+                upper.saturating_sub(previous_level)
+            })
+            .sum();
+        let eligible: Vec<T> = contributions.iter()
+            .filter(|(_, amount, folded)| !folded && amount >= level)
+            .map(|(player, _, _)| player.clone())
+            .collect();
+        previous_level = *level;
+        pots.push(Pot { amount, eligible });
+    }
+    pots
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Street {
+    /// No hand is being played
+    Waiting,
+    Preflop,
+    Flop,
+    Turn,
+    River
+}
+
+pub struct PokerSeat<T> {
+    pub player_id: T,
+    pub stack: u128,
+    pub hole_cards: Vec<Card>,
+    /// Chips put in during the current betting round
+    pub street_bet: u128,
+    /// Chips put in during the whole hand
+    pub committed: u128,
+    pub in_hand: bool,
+    pub folded: bool,
+    pub all_in: bool,
+    acted: bool
+}
+
+impl<T> PokerSeat<T> {
+    fn can_act(&self) -> bool {
+        self.in_hand && !self.folded && !self.all_in
+    }
+
+    fn is_live(&self) -> bool {
+        self.in_hand && !self.folded
+    }
+
+    /// Move chips from the stack into the pot, going all in if the stack runs out
+    fn put_in(&mut self, amount: u128) -> u128 {
+        let amount = amount.min(self.stack);
+        self.stack -= amount;
+        self.street_bet += amount;
+        self.committed += amount;
+        if self.stack == 0 {
+            self.all_in = true;
+        }
+        amount
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PotResult<T> {
+    pub amount: u128,
+    pub winners: Vec<T>,
+    /// The winning hand, if the pot went to a showdown
+    pub hand: Option<HandValue>
+}
+
+#[derive(Debug, Clone)]
+pub struct HandSummary<T> {
+    pub board: Vec<Card>,
+    /// Hole cards of everyone who went to showdown
+    pub shown: Vec<(T, Vec<Card>, HandValue)>,
+    pub pots: Vec<PotResult<T>>
+}
+
+pub struct PokerTable<T> {
+    small_blind: u128,
+    big_blind: u128,
+    seats: Vec<PokerSeat<T>>,
+    board: Vec<Card>,
+    deck: Shoe,
+    rng: StdRng,
+    button: usize,
+    street: Street,
+    current: usize,
+    current_bet: u128,
+    min_raise: u128,
+    /// Changes every time someone acts, so stale turn timers can tell they have expired
+    action_id: u64,
+    last_hand: Option<HandSummary<T>>
+}
+
+impl<T> PokerTable<T> where T: Display + Eq + Hash + Clone + Ord {
+    pub fn new(small_blind: u128, big_blind: u128) -> Self {
+        Self::with_rng(small_blind, big_blind, StdRng::from_entropy())
+    }
+
+    /// Create a table that deals from a specific RNG, e.g. a seeded one
+    pub fn with_rng(small_blind: u128, big_blind: u128, mut rng: StdRng) -> Self {
+        let deck = Shoe::new(1, &mut rng);
+        PokerTable {
+            small_blind,
+            big_blind,
+            seats: vec![],
+            board: vec![],
+            deck,
+            rng,
+            button: 0,
+            street: Street::Waiting,
+            current: 0,
+            current_bet: 0,
+            min_raise: big_blind,
+            action_id: 0,
+            last_hand: None
+        }
+    }
+
+    pub fn blinds(&self) -> (u128, u128) {
+        (self.small_blind, self.big_blind)
+    }
+
+    pub fn set_blinds(&mut self, small_blind: u128, big_blind: u128) -> Result<()> {
+        if self.street != Street::Waiting {
+            anyhow::bail!("The blinds can only be changed between hands");
+        }
+        if small_blind == 0 || big_blind < small_blind {
+            anyhow::bail!("The big blind must be at least the small blind, which can't be zero");
+        }
+        self.small_blind = small_blind;
+        self.big_blind = big_blind;
+        self.min_raise = big_blind;
+        Ok(())
+    }
+
+    pub fn seats(&self) -> &[PokerSeat<T>] {
+        &self.seats
+    }
+
+    pub fn board(&self) -> &[Card] {
+        &self.board
+    }
+
+    pub fn street(&self) -> Street {
+        self.street
+    }
+
+    pub fn action_id(&self) -> u64 {
+        self.action_id
+    }
+
+    pub fn current_bet(&self) -> u128 {
+        self.current_bet
+    }
+
+    pub fn pot(&self) -> u128 {
+        self.seats.iter().map(|seat| seat.committed).sum()
+    }
+
+    pub fn button_player(&self) -> Option<&T> {
+        self.seats.get(self.button).map(|seat| &seat.player_id)
+    }
+
+    /// Summary of the most recently finished hand
+    pub fn last_hand(&self) -> Option<&HandSummary<T>> {
+        self.last_hand.as_ref()
+    }
+
+    pub fn current_player(&self) -> Option<&T> {
+        match self.street {
+            Street::Waiting => None,
+            _ => self.seats.get(self.current).map(|seat| &seat.player_id),
+        }
+    }
+
+    pub fn hole_cards(&self, player_id: &T) -> Option<&[Card]> {
+        self.seats.iter()
+            .find(|seat| seat.player_id == *player_id && seat.in_hand)
+            .map(|seat| seat.hole_cards.as_slice())
+    }
+
+    /// Buy in (or top up between hands) from the player's balance
+    pub fn sit(&mut self, player_id: T, buy_in: u128, bank: &mut RouletteState<T>) -> Result<()> {
+        if buy_in == 0 {
+            anyhow::bail!("You cannot buy in for zero!");
+        }
+        let seat_index = self.seats.iter().position(|seat| seat.player_id == player_id);
+        if let Some(seat_index) = seat_index {
+            if self.seats[seat_index].is_live() && self.street != Street::Waiting {
+                anyhow::bail!("You can't top up in the middle of a hand");
+            }
+        }
+        else if self.seats.len() >= MAX_SEATS {
+            anyhow::bail!("The table is full ({MAX_SEATS} players)");
+        }
+        bank.withdraw(player_id.clone(), buy_in)?;
+        match seat_index {
+            Some(seat_index) => self.seats[seat_index].stack += buy_in,
+            None => self.seats.push(PokerSeat {
+                player_id,
+                stack: buy_in,
+                hole_cards: vec![],
+                street_bet: 0,
+                committed: 0,
+                in_hand: false,
+                folded: false,
+                all_in: false,
+                acted: false
+            }),
+        }
+        Ok(())
+    }
+
+    /// Get up from the table, returning the player's stack to their balance
+    pub fn leave(&mut self, player_id: T, bank: &mut RouletteState<T>) -> Result<u128> {
+        let seat_index = self.seats.iter().position(|seat| seat.player_id == player_id)
+            .context("You don't have a seat at this table")?;
+        if self.street != Street::Waiting {
+            anyhow::bail!("You can cash out once the current hand is over");
+        }
+        let seat = self.seats.remove(seat_index);
+        if seat_index < self.button {
+            self.button -= 1;
+        }
+        if self.button >= self.seats.len() {
+            self.button = 0;
+        }
+        bank.deposit(player_id, seat.stack)?;
+        Ok(seat.stack)
+    }
+
+    fn next_seat(&self, from: usize, predicate: impl Fn(&PokerSeat<T>) -> bool) -> Option<usize> {
+        let n = self.seats.len();
+        (1..=n).map(|offset| (from + offset) % n).find(|index| predicate(&self.seats[*index]))
+    }
+
+    /// Shuffle up, move the button, post blinds and deal hole cards
+    pub fn start_hand(&mut self) -> Result<()> {
+        if self.street != Street::Waiting {
+            anyhow::bail!("A hand is already in progress");
+        }
+        let players = self.seats.iter().filter(|seat| seat.stack > 0).count();
+        if players < 2 {
+            anyhow::bail!("At least two players with chips are needed to play");
+        }
+        self.deck.shuffle(&mut self.rng);
+        self.board.clear();
+        self.last_hand = None;
+        for seat in self.seats.iter_mut() {
+            seat.hole_cards.clear();
+            seat.street_bet = 0;
+            seat.committed = 0;
+            seat.in_hand = seat.stack > 0;
+            seat.folded = false;
+            seat.all_in = false;
+            seat.acted = false;
+        }
+        self.button = self.next_seat(self.button, |seat| seat.in_hand).expect("Two players are in the hand");
+
+        // Heads up, the button posts the small blind
+        let small_blind_seat = if players == 2 {
+            self.button
+        }
+        else {
+            self.next_seat(self.button, |seat| seat.in_hand).expect("Two players are in the hand")
+        };
+        let big_blind_seat = self.next_seat(small_blind_seat, |seat| seat.in_hand).expect("Two players are in the hand");
+        self.seats[small_blind_seat].put_in(self.small_blind);
+        self.seats[big_blind_seat].put_in(self.big_blind);
+        self.current_bet = self.big_blind;
+        self.min_raise = self.big_blind;
+
+        for _ in 0..2 {
+            for index in 0..self.seats.len() {
+                if self.seats[index].in_hand {
+                    let card = self.deck.draw(&mut self.rng);
+                    self.seats[index].hole_cards.push(card);
+                }
+            }
+        }
+        self.street = Street::Preflop;
+        self.current = big_blind_seat;
+        self.advance();
+        Ok(())
+    }
+
+    fn acting_seat(&mut self, player_id: &T) -> Result<&mut PokerSeat<T>> {
+        if self.street == Street::Waiting {
+            anyhow::bail!("No hand is being played");
+        }
+        let seat = &mut self.seats[self.current];
+        if seat.player_id != *player_id {
+            anyhow::bail!("It is not your turn");
+        }
+        Ok(seat)
+    }
+
+    pub fn fold(&mut self, player_id: T) -> Result<()> {
+        let seat = self.acting_seat(&player_id)?;
+        seat.folded = true;
+        seat.acted = true;
+        self.advance();
+        Ok(())
+    }
+
+    pub fn check(&mut self, player_id: T) -> Result<()> {
+        let current_bet = self.current_bet;
+        let seat = self.acting_seat(&player_id)?;
+        if seat.street_bet < current_bet {
+            anyhow::bail!("You can't check, there is a bet of {current_bet} to call");
+        }
+        seat.acted = true;
+        self.advance();
+        Ok(())
+    }
+
+    pub fn call(&mut self, player_id: T) -> Result<()> {
+        let current_bet = self.current_bet;
+        let seat = self.acting_seat(&player_id)?;
+        if seat.street_bet >= current_bet {
+            anyhow::bail!("There is nothing to call, check instead");
+        }
+        seat.put_in(current_bet - seat.street_bet);
+        seat.acted = true;
+        self.advance();
+        Ok(())
+    }
+
+    /// Bet or raise so the player's total bet this round becomes `amount`
+    pub fn raise_to(&mut self, player_id: T, amount: u128) -> Result<()> {
+        let current_bet = self.current_bet;
+        let min_raise = self.min_raise;
+        let seat = self.acting_seat(&player_id)?;
+        let all_in_amount = seat.street_bet + seat.stack;
+        if amount > all_in_amount {
+            anyhow::bail!("You only have {} chips behind", seat.stack);
+        }
+        if amount <= current_bet {
+            anyhow::bail!("A raise must be to more than the current bet of {current_bet}");
+        }
+        if amount - current_bet < min_raise && amount != all_in_amount {
+            anyhow::bail!("The minimum raise is to {}", current_bet + min_raise);
+        }
+        // Someone who already acted only gets to raise again if they face a full raise
+        if seat.acted {
+            anyhow::bail!("The last raise was a short all in, which doesn't reopen the betting. You can only call or fold");
+        }
+        seat.put_in(amount - seat.street_bet);
+        seat.acted = true;
+        // A short all in doesn't change the minimum raise or let anyone who already acted raise again,
+        // though they still have to call it
+        if amount - current_bet >= min_raise {
+            self.min_raise = amount - current_bet;
+            let current = self.current;
+            for (index, seat) in self.seats.iter_mut().enumerate() {
+                if index != current && seat.can_act() {
+                    seat.acted = false;
+                }
+            }
+        }
+        self.current_bet = amount;
+        self.advance();
+        Ok(())
+    }
+
+    pub fn all_in(&mut self, player_id: T) -> Result<()> {
+        let current_bet = self.current_bet;
+        let seat = self.acting_seat(&player_id)?;
+        let all_in_amount = seat.street_bet + seat.stack;
+        if all_in_amount <= current_bet {
+            self.call(player_id)
+        }
+        else {
+            self.raise_to(player_id, all_in_amount)
+        }
+    }
+
+    /// Check or fold for a player whose turn timer ran out.
+    /// Returns whether anything happened, which it won't if they acted in time
+    pub fn time_out(&mut self, action_id: u64) -> Result<bool> {
+        if action_id != self.action_id || self.street == Street::Waiting {
+            return Ok(false);
+        }
+        let seat = &self.seats[self.current];
+        let player_id = seat.player_id.clone();
+        if seat.street_bet >= self.current_bet {
+            self.check(player_id)?;
+        }
+        else {
+            self.fold(player_id)?;
+        }
+        Ok(true)
+    }
+
+    fn round_complete(&self) -> bool {
+        self.seats.iter()
+            .filter(|seat| seat.can_act())
+            .all(|seat| seat.acted && seat.street_bet == self.current_bet)
+    }
+
+    /// Move play along after an action: to the next player, the next street or the showdown
+    fn advance(&mut self) {
+        self.action_id += 1;
+        if self.seats.iter().filter(|seat| seat.is_live()).count() == 1 {
+            self.finish_hand();
+            return;
+        }
+        if !self.round_complete() {
+            if let Some(next) = self.next_seat(self.current, |seat| seat.can_act() && (!seat.acted || seat.street_bet < self.current_bet)) {
+                self.current = next;
+                return;
+            }
+        }
+        loop {
+            match self.street {
+                Street::Preflop => self.deal_board(3, Street::Flop),
+                Street::Flop => self.deal_board(1, Street::Turn),
+                Street::Turn => self.deal_board(1, Street::River),
+                Street::River | Street::Waiting => {
+                    self.finish_hand();
+                    return;
+                },
+            }
+            // Keep dealing if nobody is left to bet against each other
+            if self.seats.iter().filter(|seat| seat.can_act()).count() >= 2 {
+                break;
+            }
+        }
+        self.current = self.next_seat(self.button, |seat| seat.can_act()).expect("Two players can still act");
+    }
+
+    fn deal_board(&mut self, cards: usize, street: Street) {
+        for _ in 0..cards {
+            let card = self.deck.draw(&mut self.rng);
+            self.board.push(card);
+        }
+        for seat in self.seats.iter_mut() {
+            seat.street_bet = 0;
+            seat.acted = false;
+        }
+        self.current_bet = 0;
+        self.min_raise = self.big_blind;
+        self.street = street;
+    }
+
+    fn finish_hand(&mut self) {
+        let contributions: Vec<(usize, u128, bool)> = self.seats.iter().enumerate()
+            .filter(|(_, seat)| seat.in_hand)
+            .map(|(index, seat)| (index, seat.committed, seat.folded))
+            .collect();
+        let pots = side_pots(&contributions);
+        let live_players = self.seats.iter().filter(|seat| seat.is_live()).count();
+        let showdown = live_players > 1;
+
+        let mut shown = vec![];
+        let mut values = vec![None; self.seats.len()];
+        if showdown {
+            for (index, seat) in self.seats.iter().enumerate() {
+                if seat.is_live() {
+                    let mut cards = seat.hole_cards.clone();
+                    cards.extend_from_slice(&self.board);
+                    let value = evaluate(&cards);
+                    shown.push((seat.player_id.clone(), seat.hole_cards.clone(), value.clone()));
+                    values[index] = Some(value);
+                }
+            }
+        }
+
+        let mut results = vec![];
+        for pot in pots {
+            let winners: Vec<usize> = if showdown {
+                let best = pot.eligible.iter().filter_map(|index| values[*index].clone()).max();
+                pot.eligible.iter().copied().filter(|index| values[*index] == best).collect()
+            }
+            else {
+                pot.eligible.clone()
+            };
+            // Odd chips go to the first winner left of the button
+            let mut ordered_winners = winners.clone();
+            let n = self.seats.len();
+            ordered_winners.sort_by_key(|index| (index + n - self.button - 1) % n);
+            let share = pot.amount / winners.len() as u128;
+            let remainder = pot.amount % winners.len() as u128;
+            for (position, index) in ordered_winners.iter().enumerate() {
+                let bonus = if position == 0 { remainder } else { 0 };
+                self.seats[*index].stack += share + bonus;
+            }
+            results.push(PotResult {
+                amount: pot.amount,
+                winners: ordered_winners.iter().map(|index| self.seats[*index].player_id.clone()).collect(),
+                hand: if showdown { values[ordered_winners[0]].clone() } else { None }
+            });
+        }
+
+        for seat in self.seats.iter_mut() {
+            seat.street_bet = 0;
+            seat.committed = 0;
+        }
+        self.last_hand = Some(HandSummary { board: self.board.clone(), shown, pots: results });
+        self.street = Street::Waiting;
+        self.current_bet = 0;
+        self.action_id += 1;
+    }
+}
+
+impl Display for HandValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rank_name = |rank: u8| match rank {
+            14 => "A".to_string(),
+            13 => "K".to_string(),
+            12 => "Q".to_string(),
+            11 => "J".to_string(),
+            rank => rank.to_string(),
+        };
+        let ranks: Vec<String> = self.tiebreakers.iter().map(|rank| rank_name(*rank)).collect();
+        write!(f, "{} ({})", self.category, ranks.join(" "))
+    }
+}
+
+pub fn describe_cards(cards: &[Card]) -> String {
+    if cards.is_empty() {
+        "-".into()
+    }
+    else {
+        format_cards(cards)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cards::Suit;
+
+    /// Cards written like `As Kd 10h 2c`
+    fn cards(text: &str) -> Vec<Card> {
+        text.split_whitespace()
+            .map(|card| {
+                let (rank, suit) = card.split_at(card.len() - 1);
+                let rank = match rank {
+                    "A" => 1,
+                    "J" => 11,
+                    "Q" => 12,
+                    "K" => 13,
+                    rank => rank.parse().unwrap(),
+                };
+                let suit = match suit {
+                    "c" => Suit::Clubs,
+                    "d" => Suit::Diamonds,
+                    "h" => Suit::Hearts,
+                    _ => Suit::Spades,
+                };
+                Card::new(rank, suit)
+            })
+            .collect()
+    }
+
+    fn value(text: &str) -> HandValue {
+        evaluate(&cards(text))
+    }
+
+    #[test]
+    fn categories() {
+        let cases = [
+            ("As Ks Qs Js 10s 2d 3c", HandCategory::StraightFlush, vec![14]),
+            ("9h 9d 9s 9c Kd 2c 3c", HandCategory::FourOfAKind, vec![9, 13]),
+            ("9h 9d 9s Kc Kd Ac 3c", HandCategory::FullHouse, vec![9, 13]),
+            ("2h 7h 9h Jh Kh Ac 3c", HandCategory::Flush, vec![13, 11, 9, 7, 2]),
+            ("5d 6h 7s 8c 9d Kc 2c", HandCategory::Straight, vec![9]),
+            ("9h 9d 9s Kc Qd 2c 3c", HandCategory::ThreeOfAKind, vec![9, 13, 12]),
+            ("9h 9d Ks Kc Qd 2c 3c", HandCategory::TwoPair, vec![13, 9, 12]),
+            ("9h 9d As Kc Qd 2c 3c", HandCategory::Pair, vec![9, 14, 13, 12]),
+            ("9h 7d As Kc Qd 2c 3c", HandCategory::HighCard, vec![14, 13, 12, 9, 7]),
+        ];
+        for (text, category, tiebreakers) in cases {
+            assert_eq!(value(text), HandValue { category, tiebreakers }, "{text}");
+        }
+    }
+
+    #[test]
+    fn the_wheel() {
+        let wheel = value("Ad 2c 3h 4s 5d Kc 9c");
+        assert_eq!(wheel, HandValue { category: HandCategory::Straight, tiebreakers: vec![5] });
+        // The ace plays low, so the wheel is the lowest straight
+        assert!(wheel < value("2c 3h 4s 5d 6d Kc 9c"));
+        assert!(wheel > value("Ad Ac As 4s 5d Kc 9c"));
+        assert_eq!(value("Ad 2d 3d 4d 5d Kc 9c"), HandValue { category: HandCategory::StraightFlush, tiebreakers: vec![5] });
+        // A-K-Q-J-10 is the top straight, but K-A-2 doesn't wrap around
+        assert_eq!(value("Ad Kc Qh Js 10d 2c 3c").tiebreakers, vec![14]);
+        assert_eq!(value("Qd Kc Ah 2s 3d 8c 9c").category, HandCategory::HighCard);
+    }
+
+    #[test]
+    fn ties_and_kickers() {
+        let board = "Kh Kd 8s 5c 2d";
+        let hand = |hole: &str| value(&format!("{hole} {board}"));
+        // The kicker decides a shared pair
+        assert!(hand("As 3c") > hand("Qs Jc"));
+        // Kickers that don't play don't count
+        assert_eq!(hand("As 3c"), hand("Ac 4c"));
+        // Two pair goes to the higher second pair, then the kicker
+        assert!(hand("8c 3c") > hand("5s Ac"));
+        assert!(hand("8c Ac") > hand("8d Qc"));
+        // Playing the board is a tie
+        assert_eq!(value("2c 3d Ah Ad As Kc Kd"), value("4c 6d Ah Ad As Kc Kd"));
+        // Flushes compare every card
+        assert!(value("Ah 9h 7h 4h 3h 2c Kc") > value("Ah 9h 7h 4h 2h 2c 3c"));
+    }
+
+    #[test]
+    fn multi_way_all_ins() {
+        let pots = side_pots(&[("a", 100, false), ("b", 300, false), ("c", 300, false), ("d", 50, false)]);
+        assert_eq!(pots, vec![
+            Pot { amount: 200, eligible: vec!["a", "b", "c", "d"] },
+            Pot { amount: 150, eligible: vec!["a", "b", "c"] },
+            Pot { amount: 400, eligible: vec!["b", "c"] },
+        ]);
+    }
+
+    #[test]
+    fn folded_contributors() {
+        // Folded chips go into the pots without making their owner eligible
+        let pots = side_pots(&[("a", 100, true), ("b", 50, false), ("c", 200, false)]);
+        assert_eq!(pots, vec![
+            Pot { amount: 150, eligible: vec!["b", "c"] },
+            Pot { amount: 200, eligible: vec!["c"] },
+        ]);
+        // Everyone else folding leaves one pot for the last player
+        let pots = side_pots(&[("a", 10, true), ("b", 30, true), ("c", 20, false)]);
+        assert_eq!(pots, vec![Pot { amount: 60, eligible: vec!["c"] }]);
+    }
+
+    /// A 5/10 table with players 1, 2 and 3 seated in that order, with 1 on the big blind and 2 first to act
+    fn table(stacks: [u128; 3]) -> PokerTable<u64> {
+        let mut table = PokerTable::with_rng(5, 10, StdRng::seed_from_u64(1));
+        let mut bank = RouletteState::new();
+        for (player_id, stack) in (1..).zip(stacks) {
+            bank.register_player(player_id, "player");
+            bank.set_balance(player_id, stack).unwrap();
+            table.sit(player_id, stack, &mut bank).unwrap();
+        }
+        table.start_hand().unwrap();
+        assert_eq!(table.current_player(), Some(&2));
+        table
+    }
+
+    #[test]
+    fn short_all_in_does_not_reopen_betting() {
+        let mut table = table([1000, 1000, 15]);
+        table.call(2).unwrap();
+        // 3 is all in for 15, only 5 more than the big blind
+        table.all_in(3).unwrap();
+        table.call(1).unwrap();
+        assert_eq!(table.current_player(), Some(&2));
+        assert!(table.raise_to(2, 100).is_err());
+        table.call(2).unwrap();
+        assert_eq!(table.street(), Street::Flop);
+        assert_eq!(table.pot(), 45);
+    }
+
+    #[test]
+    fn full_raise_reopens_betting() {
+        let mut table = table([1000, 1000, 1000]);
+        table.call(2).unwrap();
+        table.raise_to(3, 30).unwrap();
+        table.call(1).unwrap();
+        table.raise_to(2, 100).unwrap();
+        assert_eq!(table.current_player(), Some(&3));
+    }
+}
@@ -0,0 +1,728 @@
+use anyhow::{Context, Result};
+use rand::Rng;
+
+use crate::dice::DieResult;
+
+/// Most dice one pool can have
+const MAX_POOL: u32 = 100;
+
+/// How many times one die can explode before we give up
+const MAX_AGAINS: usize = 100;
+
+/// Roughly how well a pool went, whatever the system calls it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tier {
+    Critical,
+    Success,
+    Partial,
+    Failure,
+    Disaster
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolSystem {
+    /// d10 pools, rolling another die for each one at or above `again`.
+    /// With `botch`, ones cancel successes and a roll with only ones botches
+    WorldOfDarkness {
+        again: Option<i64>,
+        difficulty: i64,
+        botch: bool
+    },
+    /// d6 pools hitting on 5 and 6, glitching when over half the dice show ones.
+    /// With `edge`, sixes explode
+    Shadowrun {
+        edge: bool
+    },
+    /// d6 pools where only the highest die counts
+    Blades,
+    /// Call of Cthulhu percentile rolls against an optional skill.
+    /// A positive `bonus` rolls that many extra tens dice and keeps the best, a negative one keeps the worst
+    CallOfCthulhu {
+        skill: Option<i64>,
+        bonus: i64
+    },
+    /// Genesys and Star Wars narrative dice, counted per kind in the order of `NARRATIVE_DICE`
+    Narrative([u32; 7])
+}
+
+/// Narrative dice symbols, one letter each
+const SUCCESS: char = 'S';
+const FAILURE: char = 'F';
+const ADVANTAGE: char = 'A';
+const THREAT: char = 'T';
+const TRIUMPH: char = 'R';
+const DESPAIR: char = 'D';
+const LIGHT: char = 'L';
+const DARK: char = 'N';
+
+struct NarrativeDie {
+    name: &'static str,
+    /// Letter used to ask for this die, like the `g` in `2g1p`
+    letter: char,
+    /// The symbols on each face
+    faces: &'static [&'static str]
+}
+
+const NARRATIVE_DICE: [NarrativeDie; 7] = [
+    NarrativeDie { name: "Boost", letter: 'b', faces: &["", "", "S", "SA", "AA", "A"] },
+    NarrativeDie { name: "Setback", letter: 'k', faces: &["", "", "F", "F", "T", "T"] },
+    NarrativeDie { name: "Ability", letter: 'g', faces: &["", "S", "S", "SS", "A", "A", "SA", "AA"] },
+    NarrativeDie { name: "Difficulty", letter: 'p', faces: &["", "F", "FF", "T", "T", "T", "TT", "FT"] },
+    NarrativeDie { name: "Proficiency", letter: 'y', faces: &["", "S", "S", "SS", "SS", "A", "SA", "SA", "SA", "AA", "AA", "R"] },
+    NarrativeDie { name: "Challenge", letter: 'r', faces: &["", "F", "F", "FF", "FF", "T", "T", "FT", "FT", "TT", "TT", "D"] },
+    NarrativeDie { name: "Force", letter: 'w', faces: &["N", "N", "N", "N", "N", "N", "NN", "L", "L", "LL", "LL", "LL"] },
+];
+
+fn symbol_name(symbol: char) -> &'static str {
+    match symbol {
+        SUCCESS => "Success",
+        FAILURE => "Failure",
+        ADVANTAGE => "Advantage",
+        THREAT => "Threat",
+        TRIUMPH => "Triumph",
+        DESPAIR => "Despair",
+        LIGHT => "Light",
+        DARK => "Dark",
+        _ => "?",
+    }
+}
+
+/// Parse narrative dice like `2g 1y 3p` or `ggyppp`
+fn parse_narrative(s: &str) -> Result<[u32; 7]> {
+    let mut counts = [0u32; 7];
+    let mut count: Option<u32> = None;
+    for c in s.chars().filter(|c| !c.is_whitespace()) {
+        if let Some(digit) = c.to_digit(10) {
+            let next = count.unwrap_or(0).saturating_mul(10).saturating_add(digit);
+            if next > MAX_POOL {
+                anyhow::bail!("Roll between 1 and {MAX_POOL} narrative dice");
+            }
+            count = Some(next);
+            continue;
+        }
+        let index = NARRATIVE_DICE.iter().position(|die| die.letter == c.to_ascii_lowercase())
+            .context(format!("'{c}' isn't a narrative die. Use b, k, g, p, y, r or w"))?;
+        counts[index] = counts[index].checked_add(count.take().unwrap_or(1)).context(format!("Roll between 1 and {MAX_POOL} narrative dice"))?;
+    }
+    if count.is_some() {
+        anyhow::bail!("A count needs a die after it, like `2g`");
+    }
+    let total = counts.iter().try_fold(0u32, |total, count| total.checked_add(*count)).unwrap_or(u32::MAX);
+    if total == 0 || total > MAX_POOL {
+        anyhow::bail!("Roll between 1 and {MAX_POOL} narrative dice");
+    }
+    Ok(counts)
+}
+
+/// Whether the words after a `d%` are only bonus and penalty dice, like `bonus` or `penalty 2`.
+/// Anything else is a normal dice expression using `d%`
+fn only_bonus_and_penalty(words: &[&str]) -> bool {
+    let is_option = |word: &str| word.eq_ignore_ascii_case("bonus") || word.eq_ignore_ascii_case("penalty");
+    words.iter().enumerate().all(|(index, word)| {
+        is_option(word) || (index > 0 && is_option(words[index - 1]) && word.parse::<i64>().is_ok())
+    })
+}
+
+/// Parse a Call of Cthulhu roll like `65 bonus`, `40 penalty 2` or nothing at all
+fn parse_cthulhu<'a>(words: impl Iterator<Item = &'a str>) -> Result<PoolSystem> {
+    let mut words = words.peekable();
+    let skill = match words.peek().and_then(|word| word.parse::<i64>().ok()) {
+        Some(skill) => {
+            words.next();
+            Some(skill)
+        },
+        None => None,
+    };
+    let mut bonus = 0;
+    while let Some(word) = words.next() {
+        let sign = match word.to_lowercase().as_str() {
+            "bonus" => 1,
+            "penalty" => -1,
+            word => anyhow::bail!("'{word}' isn't an option for a Call of Cthulhu roll"),
+        };
+        let count = match words.peek().and_then(|word| word.parse::<i64>().ok()) {
+            Some(count) => {
+                words.next();
+                count
+            },
+            None => 1,
+        };
+        bonus += sign * count;
+    }
+    if !(-2..=2).contains(&bonus) {
+        anyhow::bail!("You can have at most two bonus or penalty dice");
+    }
+    Ok(PoolSystem::CallOfCthulhu { skill, bonus })
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Pool {
+    pub system: PoolSystem,
+    pub dice: u32
+}
+
+#[derive(Debug, Clone)]
+pub struct PoolResult {
+    pub system: &'static str,
+    pub dice: Vec<DieResult>,
+    /// What the system calls the result, like "3 hits" or "Partial success"
+    pub outcome: String,
+    /// How to show the dice, when listing their faces isn't enough
+    pub detail: Option<String>,
+    pub tier: Tier,
+    /// Whether the dice show numbers from 1 up to their sides, rather than percentile digits or symbols
+    pub numeric: bool
+}
+
+fn die(sides: u32, value: i64, kept: bool, score: Option<i64>) -> DieResult {
+    DieResult { sides, value, fudge: false, kept, score }
+}
+
+impl Pool {
+    /// Parse a pool like `wod 8 9again`, `sr 12 edge`, `blades 2`, `coc 65 bonus` or `genesys 2g1y2p`.
+    /// Returns `None` if it doesn't name a system, so it can be rolled as a normal expression instead
+    pub fn parse(s: &str) -> Option<Result<Self>> {
+        let mut words = s.split_whitespace();
+        let mut system = match words.next()?.to_lowercase().as_str() {
+            "coc" | "cthulhu" => {
+                return Some(parse_cthulhu(words).map(|system| Pool { system, dice: 1 }));
+            },
+            "d%" => {
+                let options: Vec<&str> = words.collect();
+                if !only_bonus_and_penalty(&options) {
+                    return None;
+                }
+                return Some(parse_cthulhu(options.into_iter()).map(|system| Pool { system, dice: 1 }));
+            },
+            "genesys" | "swrpg" | "narrative" => {
+                let dice = words.collect::<Vec<&str>>().join(" ");
+                // The total is checked against MAX_POOL while parsing, so it can't overflow
+                return Some(parse_narrative(&dice).map(|counts| Pool { system: PoolSystem::Narrative(counts), dice: counts.iter().sum() }));
+            },
+            "wod" | "cofd" | "nwod" => PoolSystem::WorldOfDarkness { again: Some(10), difficulty: 8, botch: false },
+            "owod" => PoolSystem::WorldOfDarkness { again: None, difficulty: 6, botch: true },
+            "sr" | "shadowrun" => PoolSystem::Shadowrun { edge: false },
+            "blades" | "bitd" | "fitd" => PoolSystem::Blades,
+            _ => return None,
+        };
+        let parsed = (|| {
+            let dice = words.next()
+                .and_then(|dice| dice.parse::<u32>().ok())
+                .context("Give the number of dice in the pool, like `wod 8`")?;
+            if dice > MAX_POOL {
+                anyhow::bail!("Pools can have at most {MAX_POOL} dice");
+            }
+            while let Some(option) = words.next() {
+                match (&mut system, option.to_lowercase().as_str()) {
+                    (PoolSystem::WorldOfDarkness { again, .. }, "10again") => *again = Some(10),
+                    (PoolSystem::WorldOfDarkness { again, .. }, "9again") => *again = Some(9),
+                    (PoolSystem::WorldOfDarkness { again, .. }, "8again") => *again = Some(8),
+                    (PoolSystem::WorldOfDarkness { again, .. }, "noagain") => *again = None,
+                    (PoolSystem::WorldOfDarkness { botch, .. }, "botch") => *botch = true,
+                    (PoolSystem::WorldOfDarkness { difficulty, .. }, "diff") => {
+                        *difficulty = words.next()
+                            .and_then(|difficulty| difficulty.parse::<i64>().ok())
+                            .filter(|difficulty| (2..=10).contains(difficulty))
+                            .context("Difficulty must be between 2 and 10, like `diff 7`")?;
+                    },
+                    (PoolSystem::Shadowrun { edge }, "edge") => *edge = true,
+                    (_, option) => anyhow::bail!("'{option}' isn't an option for this kind of pool"),
+                }
+            }
+            // Chronicles and Blades have their own rules for rolling with no dice
+            let needs_dice = match system {
+                PoolSystem::WorldOfDarkness { botch, .. } => botch,
+                PoolSystem::Shadowrun { .. } => true,
+                PoolSystem::Blades | PoolSystem::CallOfCthulhu { .. } | PoolSystem::Narrative(_) => false,
+            };
+            if dice == 0 && needs_dice {
+                anyhow::bail!("This pool needs at least one die");
+            }
+            Ok(Pool { system, dice })
+        })();
+        Some(parsed)
+    }
+
+    pub fn roll(&self, rng: &mut impl Rng) -> PoolResult {
+        match self.system {
+            PoolSystem::WorldOfDarkness { again, difficulty, botch } => self.roll_world_of_darkness(again, difficulty, botch, rng),
+            PoolSystem::Shadowrun { edge } => self.roll_shadowrun(edge, rng),
+            PoolSystem::Blades => self.roll_blades(rng),
+            PoolSystem::CallOfCthulhu { skill, bonus } => roll_cthulhu(skill, bonus, rng),
+            PoolSystem::Narrative(counts) => roll_narrative(counts, rng),
+        }
+    }
+
+    fn roll_world_of_darkness(&self, again: Option<i64>, difficulty: i64, botch: bool, rng: &mut impl Rng) -> PoolResult {
+        let system = "World of Darkness";
+        // With no dice left, a single chance die succeeds only on a 10 and fails dramatically on a 1
+        if self.dice == 0 {
+            let value = rng.gen_range(1..=10);
+            let (outcome, tier, score) = match value {
+                10 => ("1 success on a chance die", Tier::Success, 1),
+                1 => ("Dramatic failure!", Tier::Disaster, -1),
+                _ => ("Failure", Tier::Failure, 0),
+            };
+            return PoolResult { system, dice: vec![die(10, value, true, Some(score))], outcome: outcome.into(), detail: None, tier, numeric: true };
+        }
+
+        let mut dice = vec![];
+        let mut pending = self.dice as usize;
+        while pending > 0 && dice.len() < self.dice as usize + MAX_AGAINS {
+            pending -= 1;
+            let value = rng.gen_range(1..=10);
+            if again.is_some_and(|again| value >= again) {
+                pending += 1;
+            }
+            let score = if value >= difficulty { 1 } else if botch && value == 1 { -1 } else { 0 };
+            dice.push(die(10, value, true, Some(score)));
+        }
+        let successes = dice.iter().filter(|die| die.score == Some(1)).count() as i64;
+        let ones = dice.iter().filter(|die| die.score == Some(-1)).count() as i64;
+        let net = successes - ones;
+
+        let (outcome, tier) = if botch && successes == 0 && ones > 0 {
+            ("Botch!".to_string(), Tier::Disaster)
+        }
+        else if net >= 5 {
+            (format!("Exceptional success! {net} successes"), Tier::Critical)
+        }
+        else if net > 0 {
+            (format!("{net} success{}", if net == 1 { "" } else { "es" }), Tier::Success)
+        }
+        else {
+            ("Failure".to_string(), Tier::Failure)
+        };
+        PoolResult { system, dice, outcome, detail: None, tier, numeric: true }
+    }
+
+    fn roll_shadowrun(&self, edge: bool, rng: &mut impl Rng) -> PoolResult {
+        let mut dice = vec![];
+        let mut ones = 0;
+        for _ in 0..self.dice {
+            let mut value = rng.gen_range(1..=6);
+            if value == 1 {
+                ones += 1;
+            }
+            // Rule of six: with edge, sixes are rerolled and the new die counts too
+            for _ in 0..MAX_AGAINS {
+                let score = match value {
+                    5.. => 1,
+                    1 => -1,
+                    _ => 0,
+                };
+                dice.push(die(6, value, true, Some(score)));
+                if !(edge && value == 6) {
+                    break;
+                }
+                value = rng.gen_range(1..=6);
+            }
+        }
+        let hits = dice.iter().filter(|die| die.score == Some(1)).count();
+        let glitch = ones * 2 > self.dice;
+
+        let (outcome, tier) = match (glitch, hits) {
+            (true, 0) => ("Critical glitch!".to_string(), Tier::Disaster),
+            (true, hits) => (format!("Glitch! {hits} hit{}", if hits == 1 { "" } else { "s" }), Tier::Partial),
+            (false, 0) => ("No hits".to_string(), Tier::Failure),
+            (false, hits) => (format!("{hits} hit{}", if hits == 1 { "" } else { "s" }), Tier::Success),
+        };
+        PoolResult { system: "Shadowrun", dice, outcome, detail: None, tier, numeric: true }
+    }
+
+    fn roll_blades(&self, rng: &mut impl Rng) -> PoolResult {
+        // With no dice, roll two and take the lowest, which can't crit
+        let zero_dice = self.dice == 0;
+        let count = if zero_dice { 2 } else { self.dice };
+        let values: Vec<i64> = (0..count).map(|_| rng.gen_range(1..=6)).collect();
+        let result = if zero_dice { *values.iter().min().unwrap() } else { *values.iter().max().unwrap() };
+        let sixes = values.iter().filter(|value| **value == 6).count();
+
+        let mut counted = false;
+        let dice = values.iter()
+            .map(|value| {
+                let kept = !zero_dice || (*value == result && !std::mem::replace(&mut counted, true));
+                die(6, *value, kept, None)
+            })
+            .collect();
+        let (outcome, tier) = match result {
+            6 if sixes >= 2 && !zero_dice => ("Critical success", Tier::Critical),
+            6 => ("Full success", Tier::Success),
+            4 | 5 => ("Partial success", Tier::Partial),
+            _ => ("Bad outcome", Tier::Failure),
+        };
+        PoolResult { system: "Blades in the Dark", dice, outcome: outcome.into(), detail: None, tier, numeric: true }
+    }
+}
+
+/// Roll percentile dice as a units die and one or more tens dice, where 00 and 0 make 100
+fn roll_cthulhu(skill: Option<i64>, bonus: i64, rng: &mut impl Rng) -> PoolResult {
+    let units = rng.gen_range(0..=9);
+    let tens: Vec<i64> = (0..=bonus.abs()).map(|_| rng.gen_range(0..=9)).collect();
+    cthulhu_result(skill, bonus, &tens, units)
+}
+
+/// Pick the tens die that counts and grade the roll against the skill
+fn cthulhu_result(skill: Option<i64>, bonus: i64, tens: &[i64], units: i64) -> PoolResult {
+    let total = |tens: i64| match tens * 10 + units {
+        0 => 100,
+        total => total,
+    };
+    let best = match bonus {
+        1.. => tens.iter().copied().min_by_key(|tens| total(*tens)).unwrap(),
+        _ => tens.iter().copied().max_by_key(|tens| total(*tens)).unwrap(),
+    };
+    let result = total(best);
+
+    let mut counted = false;
+    let tens_faces: Vec<String> = tens.iter()
+        .map(|tens| {
+            let face = format!("{:02}", tens * 10);
+            if *tens == best && !std::mem::replace(&mut counted, true) { face } else { format!("~~{face}~~") }
+        })
+        .collect();
+    let detail = format!("Tens: {} · Units: {units} → **{result}**", tens_faces.join(", "));
+    let mut dice: Vec<DieResult> = tens.iter().map(|tens| die(10, *tens, *tens == best, None)).collect();
+    dice.push(die(10, units, true, None));
+
+    let (outcome, tier) = match skill {
+        None => (format!("{result}"), Tier::Success),
+        Some(skill) => {
+            let (level, tier) = if result == 1 {
+                ("Critical success!", Tier::Critical)
+            }
+            else if result == 100 || (skill < 50 && result >= 96) {
+                ("Fumble!", Tier::Disaster)
+            }
+            else if result <= skill / 5 {
+                ("Extreme success", Tier::Critical)
+            }
+            else if result <= skill / 2 {
+                ("Hard success", Tier::Success)
+            }
+            else if result <= skill {
+                ("Regular success", Tier::Success)
+            }
+            else {
+                ("Failure", Tier::Failure)
+            };
+            (format!("{level} ({result} against {skill})"), tier)
+        },
+    };
+    PoolResult { system: "Call of Cthulhu", dice, outcome, detail: Some(detail), tier, numeric: false }
+}
+
+/// Roll narrative dice and cancel successes against failures and advantage against threat
+fn roll_narrative(counts: [u32; 7], rng: &mut impl Rng) -> PoolResult {
+    let rolled: Vec<Vec<usize>> = NARRATIVE_DICE.iter().zip(counts)
+        .map(|(kind, count)| (0..count).map(|_| rng.gen_range(0..kind.faces.len())).collect())
+        .collect();
+    narrative_result(&rolled)
+}
+
+/// Count up the symbols on narrative dice that have already been rolled, given as face indexes per kind of die
+fn narrative_result(rolled: &[Vec<usize>]) -> PoolResult {
+    let mut dice = vec![];
+    let mut symbols = String::new();
+    let mut lines = vec![];
+    for (kind, indexes) in NARRATIVE_DICE.iter().zip(rolled) {
+        if indexes.is_empty() {
+            continue;
+        }
+        let mut faces = vec![];
+        for index in indexes.iter().copied() {
+            let face = kind.faces[index];
+            symbols.push_str(face);
+            dice.push(die(kind.faces.len() as u32, index as i64 + 1, true, None));
+            faces.push(match face {
+                "" => "Blank".to_string(),
+                face => face.chars().map(symbol_name).collect::<Vec<_>>().join(" + "),
+            });
+        }
+        lines.push(format!("{}: {}", kind.name, faces.join(", ")));
+    }
+
+    let count = |symbol: char| symbols.chars().filter(|c| *c == symbol).count() as i64;
+    let (triumphs, despairs) = (count(TRIUMPH), count(DESPAIR));
+    // Triumphs and despairs also count as a success or failure
+    let successes = count(SUCCESS) + triumphs - count(FAILURE) - despairs;
+    let advantage = count(ADVANTAGE) - count(THREAT);
+    let checked = rolled[..6].iter().any(|indexes| !indexes.is_empty());
+
+    let mut parts = vec![];
+    if checked {
+        parts.push(match successes {
+            1.. => format!("Success ({successes} net)"),
+            _ => "Failure".to_string(),
+        });
+    }
+    match advantage {
+        1.. => parts.push(format!("{advantage} advantage")),
+        0 => {},
+        _ => parts.push(format!("{} threat", -advantage)),
+    }
+    if triumphs > 0 {
+        parts.push(format!("{triumphs} triumph"));
+    }
+    if despairs > 0 {
+        parts.push(format!("{despairs} despair"));
+    }
+    if !rolled[6].is_empty() {
+        parts.push(format!("{} light side, {} dark side", count(LIGHT), count(DARK)));
+    }
+
+    let tier = match (checked, successes > 0) {
+        (false, _) => Tier::Success,
+        (true, true) if triumphs > 0 => Tier::Critical,
+        (true, true) => Tier::Success,
+        (true, false) if despairs > 0 => Tier::Disaster,
+        (true, false) if advantage > 0 => Tier::Partial,
+        (true, false) => Tier::Failure,
+    };
+    PoolResult { system: "Narrative dice", dice, outcome: parts.join(", "), detail: Some(lines.join("\n")), tier, numeric: false }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    const SEEDS: u64 = 500;
+
+    /// Roll a pool once for each seed
+    fn rolls(pool: &str) -> Vec<PoolResult> {
+        let pool = Pool::parse(pool).unwrap().unwrap();
+        (0..SEEDS).map(|seed| pool.roll(&mut StdRng::seed_from_u64(seed))).collect()
+    }
+
+    fn values(roll: &PoolResult) -> Vec<i64> {
+        roll.dice.iter().map(|die| die.value).collect()
+    }
+
+    #[test]
+    fn again_chains() {
+        for (pool, again) in [("wod 5", Some(10)), ("wod 5 9again", Some(9)), ("wod 5 8again", Some(8)), ("wod 5 noagain", None)] {
+            let mut chained = false;
+            for roll in rolls(pool) {
+                // Every die at or above the again number adds one more die
+                let agains = values(&roll).iter().filter(|value| again.is_some_and(|again| **value >= again)).count();
+                assert_eq!(roll.dice.len(), 5 + agains, "{pool}: {:?}", values(&roll));
+                chained |= roll.dice.len() > 6;
+            }
+            assert_eq!(chained, again.is_some(), "{pool}");
+        }
+    }
+
+    #[test]
+    fn successes_against_difficulty() {
+        for roll in rolls("wod 6 diff 7 noagain") {
+            let successes = values(&roll).iter().filter(|value| **value >= 7).count();
+            let expected = match successes {
+                0 => Tier::Failure,
+                1..=4 => Tier::Success,
+                _ => Tier::Critical,
+            };
+            assert_eq!(roll.tier, expected, "{:?}", values(&roll));
+        }
+    }
+
+    #[test]
+    fn ones_cancel_and_botch() {
+        let (mut cancelled, mut botched) = (false, false);
+        for roll in rolls("owod 4") {
+            let successes = values(&roll).iter().filter(|value| **value >= 6).count() as i64;
+            let ones = values(&roll).iter().filter(|value| **value == 1).count() as i64;
+            let expected = match (successes, successes - ones) {
+                // Only a roll with no successes at all botches, however many ones cancel them
+                (0, _) if ones > 0 => Tier::Disaster,
+                (_, 5..) => Tier::Critical,
+                (_, 1..) => Tier::Success,
+                _ => Tier::Failure,
+            };
+            assert_eq!(roll.tier, expected, "{:?}", values(&roll));
+            cancelled |= successes > 0 && ones >= successes;
+            botched |= roll.tier == Tier::Disaster;
+        }
+        assert!(cancelled && botched);
+    }
+
+    #[test]
+    fn chance_die() {
+        let mut tiers = vec![];
+        for roll in rolls("wod 0") {
+            assert_eq!(roll.dice.len(), 1);
+            let expected = match roll.dice[0].value {
+                10 => Tier::Success,
+                1 => Tier::Disaster,
+                _ => Tier::Failure,
+            };
+            assert_eq!(roll.tier, expected);
+            tiers.push(roll.tier);
+        }
+        assert!([Tier::Success, Tier::Disaster, Tier::Failure].iter().all(|tier| tiers.contains(tier)));
+        assert!(Pool::parse("owod 0").unwrap().is_err());
+    }
+
+    #[test]
+    fn shadowrun_glitches() {
+        let mut exactly_half = false;
+        for roll in rolls("sr 4") {
+            let hits = values(&roll).iter().filter(|value| **value >= 5).count();
+            let ones = values(&roll).iter().filter(|value| **value == 1).count();
+            // A glitch needs more than half the dice to show ones
+            let expected = match (ones > 2, hits) {
+                (true, 0) => Tier::Disaster,
+                (true, _) => Tier::Partial,
+                (false, 0) => Tier::Failure,
+                (false, _) => Tier::Success,
+            };
+            assert_eq!(roll.tier, expected, "{:?}", values(&roll));
+            exactly_half |= ones == 2;
+        }
+        assert!(exactly_half);
+    }
+
+    #[test]
+    fn rule_of_six() {
+        for roll in rolls("sr 3 edge") {
+            let sixes = values(&roll).iter().filter(|value| **value == 6).count();
+            assert_eq!(roll.dice.len(), 3 + sixes, "{:?}", values(&roll));
+        }
+    }
+
+    #[test]
+    fn blades() {
+        let mut critical = false;
+        for roll in rolls("blades 3") {
+            let highest = *values(&roll).iter().max().unwrap();
+            let sixes = values(&roll).iter().filter(|value| **value == 6).count();
+            let expected = match highest {
+                6 if sixes >= 2 => Tier::Critical,
+                6 => Tier::Success,
+                4 | 5 => Tier::Partial,
+                _ => Tier::Failure,
+            };
+            assert_eq!(roll.tier, expected, "{:?}", values(&roll));
+            critical |= expected == Tier::Critical;
+        }
+        assert!(critical);
+
+        // With no dice, two are rolled and the lowest counts, which can never crit
+        for roll in rolls("blades 0") {
+            assert_eq!(roll.dice.len(), 2);
+            assert_eq!(roll.dice.iter().filter(|die| die.kept).count(), 1);
+            let lowest = *values(&roll).iter().min().unwrap();
+            let expected = match lowest {
+                6 => Tier::Success,
+                4 | 5 => Tier::Partial,
+                _ => Tier::Failure,
+            };
+            assert_eq!(roll.tier, expected, "{:?}", values(&roll));
+        }
+    }
+
+    fn system(s: &str) -> Option<PoolSystem> {
+        Pool::parse(s).map(|pool| pool.unwrap().system)
+    }
+
+    #[test]
+    fn percentile_rolls_and_dice_expressions() {
+        assert_eq!(system("d%"), Some(PoolSystem::CallOfCthulhu { skill: None, bonus: 0 }));
+        assert_eq!(system("d% bonus 2"), Some(PoolSystem::CallOfCthulhu { skill: None, bonus: 2 }));
+        assert_eq!(system("d% penalty"), Some(PoolSystem::CallOfCthulhu { skill: None, bonus: -1 }));
+        assert_eq!(system("coc 65 bonus"), Some(PoolSystem::CallOfCthulhu { skill: Some(65), bonus: 1 }));
+        assert!(Pool::parse("d% + 5").is_none());
+        assert!(Pool::parse("d% 5").is_none());
+        assert!(Pool::parse("coc 65 lucky").unwrap().is_err());
+    }
+
+    #[test]
+    fn narrative_pool_sizes() {
+        assert_eq!(parse_narrative("2g 1y3p").unwrap(), [0, 0, 2, 3, 1, 0, 0]);
+        assert_eq!(parse_narrative("ggp").unwrap(), [0, 0, 2, 1, 0, 0, 0]);
+        for dice in ["4294967295g 2p", "101g", "60g 50p", "", "2"] {
+            assert!(parse_narrative(dice).is_err(), "{dice}");
+        }
+    }
+
+    /// Grade a single percentile result with no bonus or penalty dice
+    fn graded(skill: i64, result: i64) -> (String, Tier) {
+        let (tens, units) = match result {
+            100 => (0, 0),
+            result => (result / 10, result % 10),
+        };
+        let roll = cthulhu_result(Some(skill), 0, &[tens], units);
+        (roll.outcome, roll.tier)
+    }
+
+    #[test]
+    fn bonus_and_penalty_dice() {
+        // 00 and 0 make 100, so a bonus die keeps the 50 and a penalty die keeps the 100
+        assert_eq!(cthulhu_result(None, 1, &[0, 5], 0).outcome, "50");
+        assert_eq!(cthulhu_result(None, -1, &[0, 5], 0).outcome, "100");
+        assert_eq!(cthulhu_result(None, 1, &[0, 5], 3).outcome, "3");
+        assert_eq!(cthulhu_result(None, -2, &[2, 7, 4], 3).outcome, "73");
+        let kept: Vec<bool> = cthulhu_result(None, 2, &[2, 7, 4], 3).dice.iter().map(|die| die.kept).collect();
+        assert_eq!(kept, [true, false, false, true]);
+    }
+
+    #[test]
+    fn skill_cut_offs() {
+        let cases = [
+            (50, 1, "Critical success!", Tier::Critical),
+            (50, 10, "Extreme success", Tier::Critical),
+            (50, 11, "Hard success", Tier::Success),
+            (50, 25, "Hard success", Tier::Success),
+            (50, 26, "Regular success", Tier::Success),
+            (50, 50, "Regular success", Tier::Success),
+            (50, 51, "Failure", Tier::Failure),
+            (50, 96, "Failure", Tier::Failure),
+            (50, 100, "Fumble!", Tier::Disaster),
+            (49, 9, "Extreme success", Tier::Critical),
+            (49, 95, "Failure", Tier::Failure),
+            (49, 96, "Fumble!", Tier::Disaster),
+        ];
+        for (skill, result, level, tier) in cases {
+            let (outcome, graded_tier) = graded(skill, result);
+            assert_eq!(outcome, format!("{level} ({result} against {skill})"));
+            assert_eq!(graded_tier, tier, "{result} against {skill}");
+        }
+    }
+
+    /// Face indexes for each kind of narrative die, in the order of `NARRATIVE_DICE`
+    fn narrative(faces: &[(char, usize)]) -> PoolResult {
+        let mut rolled = vec![vec![]; NARRATIVE_DICE.len()];
+        for (letter, index) in faces {
+            let kind = NARRATIVE_DICE.iter().position(|die| die.letter == *letter).unwrap();
+            rolled[kind].push(*index);
+        }
+        narrative_result(&rolled)
+    }
+
+    #[test]
+    fn narrative_cancellation() {
+        // A triumph is also a success, cancelled here by a failure
+        let roll = narrative(&[('y', 11), ('p', 1)]);
+        assert_eq!((roll.outcome.as_str(), roll.tier), ("Failure, 1 triumph", Tier::Failure));
+        let roll = narrative(&[('y', 11)]);
+        assert_eq!((roll.outcome.as_str(), roll.tier), ("Success (1 net), 1 triumph", Tier::Critical));
+
+        // A despair is also a failure
+        let roll = narrative(&[('g', 1), ('r', 11)]);
+        assert_eq!((roll.outcome.as_str(), roll.tier), ("Failure, 1 despair", Tier::Disaster));
+        let roll = narrative(&[('g', 3), ('r', 11)]);
+        assert_eq!((roll.outcome.as_str(), roll.tier), ("Success (1 net), 1 despair", Tier::Success));
+
+        // Advantage and threat cancel too
+        let roll = narrative(&[('b', 3), ('k', 4)]);
+        assert_eq!(roll.outcome, "Success (1 net)");
+        let roll = narrative(&[('g', 4), ('p', 1)]);
+        assert_eq!((roll.outcome.as_str(), roll.tier), ("Failure, 1 advantage", Tier::Partial));
+    }
+
+    #[test]
+    fn force_dice_alone() {
+        let roll = narrative(&[('w', 0), ('w', 9)]);
+        assert_eq!((roll.outcome.as_str(), roll.tier), ("2 light side, 1 dark side", Tier::Success));
+    }
+}
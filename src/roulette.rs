@@ -1,6 +1,7 @@
 use std::{collections::BTreeMap, fmt::Display, hash::Hash, str::FromStr};
 use anyhow::{Context, Result};
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 pub fn is_red(number: u8) -> bool {
     // In number ranges from 1 to 10 and 19 to 28,
@@ -89,6 +90,14 @@ pub struct PlayerBet {
 }
 
 impl PlayerBet {
+    pub fn bet(&self) -> Bet {
+        self.bet
+    }
+
+    pub fn amount(&self) -> u128 {
+        self.amount
+    }
+
     pub fn get_payout(&self, spin_result: u8) -> u128 {
         if self.bet.is_correct(spin_result) {
             self.bet.get_payout_ratio() * self.amount
@@ -154,15 +163,34 @@ impl<T> Player<T> where T: Display {
     }
 }
 
+/// A player's balance as it's kept between restarts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedBalance<T> {
+    pub player_id: T,
+    pub player_name: String,
+    pub balance: u128
+}
+
 pub struct RouletteState<T> {
     players: BTreeMap<T, Player<T>>,
     can_change_bets: bool,
     pub spin_scheduled: bool
 }
 
-pub struct SpinResult {
+/// How a single player fared on a spin
+#[derive(Debug, Clone)]
+pub struct PlayerOutcome<T> {
+    pub player_id: T,
+    /// Total payout minus everything the player staked on this spin
+    pub net: i128,
+    pub balance: u128,
+    pub winning_bets: Vec<PlayerBet>,
+}
+
+pub struct SpinResult<T> {
     pub result: u8,
-    pub payouts: BTreeMap<String, (u128, u128)>,
+    /// Outcomes for every player that had bets on the table, biggest winners first
+    pub outcomes: Vec<PlayerOutcome<T>>,
 }
 
 impl<T> RouletteState<T> where T: Display + Eq + Hash + Clone + Ord {
@@ -247,29 +275,46 @@ impl<T> RouletteState<T> where T: Display + Eq + Hash + Clone + Ord {
         }
     }
 
-    pub fn spin(&mut self) -> SpinResult {
-        let mut thread_rng = rand::thread_rng();
-        let mut payouts: BTreeMap<String, (u128, u128)> = BTreeMap::default();
-        let result = thread_rng.gen_range(0..36) as u8;
+    pub fn spin(&mut self) -> SpinResult<T> {
+        self.spin_with_rng(&mut rand::thread_rng())
+    }
+
+    /// Spin using a specific RNG, e.g. a seeded one
+    pub fn spin_with_rng(&mut self, rng: &mut impl Rng) -> SpinResult<T> {
+        let mut outcomes: Vec<PlayerOutcome<T>> = vec![];
+        let result = rng.gen_range(0..36) as u8;
         println!("Spin result: {result}");
         for player in self.players.values_mut() {
+            if player.bets.is_empty() {
+                continue;
+            }
             let mut total_payout: u128 = 0;
+            let mut total_staked: u128 = 0;
+            let mut winning_bets = vec![];
             for player_bet in player.bets.iter() {
-                total_payout += player_bet.get_payout(result);
-            }
-            if player.bets.len() > 0 {
-                player.bets.clear();
-                player.balance += total_payout;
-                println!("Player {} (id={}) received payout of {total_payout}", player.player_name, player.player_id);
-                let player_key = format!("{} (id={})", player.player_name, player.player_id);
-                payouts.insert(player_key, (total_payout, player.balance));
+                let payout = player_bet.get_payout(result);
+                if payout > 0 {
+                    winning_bets.push(*player_bet);
+                }
+                total_payout += payout;
+                total_staked += player_bet.amount;
             }
+            player.bets.clear();
+            player.balance += total_payout;
+            println!("Player {} (id={}) received payout of {total_payout}", player.player_name, player.player_id);
+            outcomes.push(PlayerOutcome {
+                player_id: player.player_id.clone(),
+                net: total_payout as i128 - total_staked as i128,
+                balance: player.balance,
+                winning_bets,
+            });
         }
+        outcomes.sort_by_key(|outcome| std::cmp::Reverse(outcome.net));
         self.can_change_bets = true;
         self.spin_scheduled = false;
         SpinResult {
             result,
-            payouts,
+            outcomes,
         }
     }
 
@@ -282,6 +327,53 @@ impl<T> RouletteState<T> where T: Display + Eq + Hash + Clone + Ord {
         }
     }
 
+    /// Take money out of a player's balance to stake on another game
+    pub fn withdraw(&mut self, player_id: T, amount: u128) -> Result<()> {
+        if let Some(player) = self.players.get_mut(&player_id) {
+            if amount > player.balance {
+                anyhow::bail!("Balance of {} is too low to stake {amount}", player.balance);
+            }
+            player.balance -= amount;
+            println!("Withdrew {amount} from player {} (id={player_id})", player.player_name);
+            Ok(())
+        }
+        else {
+            anyhow::bail!("Player with id {player_id} is not registered to play roulette!")
+        }
+    }
+
+    /// Pay money from another game back into a player's balance
+    pub fn deposit(&mut self, player_id: T, amount: u128) -> Result<()> {
+        if let Some(player) = self.players.get_mut(&player_id) {
+            player.balance += amount;
+            println!("Deposited {amount} to player {} (id={player_id})", player.player_name);
+            Ok(())
+        }
+        else {
+            anyhow::bail!("Player with id {player_id} is not registered to play roulette!")
+        }
+    }
+
+    /// Every player's balance, counting bets still on the table since those are given back after a restart
+    pub fn saved_balances(&self) -> Vec<SavedBalance<T>> {
+        self.players.values()
+            .map(|player| SavedBalance {
+                player_id: player.player_id.clone(),
+                player_name: player.player_name.clone(),
+                balance: player.balance + player.bets.iter().map(|bet| bet.amount).sum::<u128>()
+            })
+            .collect()
+    }
+
+    /// Bring back balances saved before a restart
+    pub fn restore_balances(&mut self, saved: Vec<SavedBalance<T>>) {
+        for saved in saved {
+            let mut player = Player::new(saved.player_id.clone(), &saved.player_name);
+            player.balance = saved.balance;
+            self.players.insert(saved.player_id, player);
+        }
+    }
+
     pub fn set_balance(&mut self, player_id: T, balance: u128) -> Result<()> {
         if let Some(player) = self.players.get_mut(&player_id) {
             println!("Set player {} (id={player_id})'s balance to {balance}", player.player_name);
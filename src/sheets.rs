@@ -0,0 +1,457 @@
+use std::collections::BTreeMap;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// Campaign used in channels that haven't been given one
+pub const DEFAULT_CAMPAIGN: &str = "default";
+
+/// Ability names, with the short forms players type
+pub const ABILITIES: [(&str, &str); 6] = [
+    ("str", "Strength"),
+    ("dex", "Dexterity"),
+    ("con", "Constitution"),
+    ("int", "Intelligence"),
+    ("wis", "Wisdom"),
+    ("cha", "Charisma")
+];
+
+/// Every skill and the ability it uses
+pub const SKILLS: [(&str, &str); 18] = [
+    ("acrobatics", "dex"),
+    ("animal-handling", "wis"),
+    ("arcana", "int"),
+    ("athletics", "str"),
+    ("deception", "cha"),
+    ("history", "int"),
+    ("insight", "wis"),
+    ("intimidation", "cha"),
+    ("investigation", "int"),
+    ("medicine", "wis"),
+    ("nature", "int"),
+    ("perception", "wis"),
+    ("performance", "cha"),
+    ("persuasion", "cha"),
+    ("religion", "int"),
+    ("sleight-of-hand", "dex"),
+    ("stealth", "dex"),
+    ("survival", "wis")
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Proficiency {
+    None,
+    /// Half the proficiency bonus, rounded down, like Jack of All Trades
+    Half,
+    Proficient,
+    /// Double the proficiency bonus
+    Expertise
+}
+
+impl Proficiency {
+    fn bonus(&self, proficiency: i64) -> i64 {
+        match self {
+            Proficiency::None => 0,
+            Proficiency::Half => proficiency / 2,
+            Proficiency::Proficient => proficiency,
+            Proficiency::Expertise => proficiency * 2,
+        }
+    }
+}
+
+fn default_score() -> i64 {
+    10
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Abilities {
+    #[serde(default = "default_score", alias = "str")]
+    pub strength: i64,
+    #[serde(default = "default_score", alias = "dex")]
+    pub dexterity: i64,
+    #[serde(default = "default_score", alias = "con")]
+    pub constitution: i64,
+    #[serde(default = "default_score", alias = "int")]
+    pub intelligence: i64,
+    #[serde(default = "default_score", alias = "wis")]
+    pub wisdom: i64,
+    #[serde(default = "default_score", alias = "cha")]
+    pub charisma: i64
+}
+
+impl Abilities {
+    fn scores(&self) -> [i64; 6] {
+        [self.strength, self.dexterity, self.constitution, self.intelligence, self.wisdom, self.charisma]
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HitPoints {
+    pub max: i64,
+    /// Filled up to `max` when a sheet is imported without it
+    #[serde(default)]
+    pub current: Option<i64>,
+    #[serde(default)]
+    pub temp: i64
+}
+
+/// A character as imported from JSON
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sheet {
+    pub name: String,
+    #[serde(default = "default_level")]
+    pub level: i64,
+    /// Worked out from the level if it's left out
+    #[serde(default)]
+    pub proficiency: Option<i64>,
+    pub abilities: Abilities,
+    /// Skills with any proficiency, keyed by name
+    #[serde(default)]
+    pub skills: BTreeMap<String, Proficiency>,
+    /// Abilities the character is proficient in saving with
+    #[serde(default)]
+    pub saves: Vec<String>,
+    pub hp: HitPoints,
+    pub ac: i64
+}
+
+fn default_level() -> i64 {
+    1
+}
+
+/// The short name of an ability, from either its short or full name
+pub fn ability_key(name: &str) -> Option<&'static str> {
+    let name = name.trim().to_lowercase();
+    ABILITIES.iter()
+        .find(|(key, full)| name == *key || name == full.to_lowercase())
+        .map(|(key, _)| *key)
+}
+
+fn ability_name(key: &str) -> &'static str {
+    ABILITIES.iter().find(|(short, _)| *short == key).map(|(_, full)| *full).unwrap_or("Unknown")
+}
+
+/// A skill's name as it's stored, so `Sleight of Hand` and `sleight_of_hand` both work
+fn skill_key(name: &str) -> String {
+    name.trim().to_lowercase().replace([' ', '_'], "-")
+}
+
+/// Turn a skill key like `sleight-of-hand` into `Sleight of Hand`
+fn skill_name(key: &str) -> String {
+    key.split('-')
+        .enumerate()
+        .map(|(index, word)| match (index, word) {
+            (i, "of") if i > 0 => word.to_string(),
+            _ => word[..1].to_uppercase() + &word[1..],
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+impl Sheet {
+    /// Parse and check a sheet, tidying up skill and save names
+    pub fn from_json(json: &str) -> Result<Self> {
+        let mut sheet: Sheet = serde_json::from_str(json).context("That isn't a valid character sheet")?;
+        sheet.name = sheet.name.trim().to_string();
+        if sheet.name.is_empty() || sheet.name.len() > 64 || sheet.name.contains(['[', ']', '`']) {
+            anyhow::bail!("Character names must be 1 to 64 characters long, without brackets or backticks");
+        }
+        if !(1..=30).contains(&sheet.level) {
+            anyhow::bail!("Level must be between 1 and 30");
+        }
+        if sheet.abilities.scores().iter().any(|score| !(1..=30).contains(score)) {
+            anyhow::bail!("Ability scores must be between 1 and 30");
+        }
+        if sheet.proficiency.is_some_and(|proficiency| !(0..=10).contains(&proficiency)) {
+            anyhow::bail!("The proficiency bonus must be between 0 and 10");
+        }
+        if !(1..=10_000).contains(&sheet.hp.max) || !(0..=10_000).contains(&sheet.hp.temp) || !(0..=100).contains(&sheet.ac) {
+            anyhow::bail!("Max HP must be between 1 and 10000, temporary HP between 0 and 10000 and AC between 0 and 100");
+        }
+        sheet.hp.current = Some(sheet.hp.current.unwrap_or(sheet.hp.max).clamp(0, sheet.hp.max));
+
+        let mut skills = BTreeMap::new();
+        for (name, proficiency) in sheet.skills {
+            let key = skill_key(&name);
+            if !SKILLS.iter().any(|(skill, _)| *skill == key) {
+                anyhow::bail!("There's no skill called '{name}'");
+            }
+            skills.insert(key, proficiency);
+        }
+        sheet.skills = skills;
+
+        let mut saves = vec![];
+        for name in sheet.saves {
+            let key = ability_key(&name).context(format!("There's no ability called '{name}'"))?;
+            if !saves.iter().any(|save| save == key) {
+                saves.push(key.to_string());
+            }
+        }
+        sheet.saves = saves;
+        Ok(sheet)
+    }
+
+    pub fn proficiency_bonus(&self) -> i64 {
+        self.proficiency.unwrap_or(2 + (self.level - 1) / 4)
+    }
+
+    pub fn score(&self, ability: &str) -> i64 {
+        let index = ABILITIES.iter().position(|(key, _)| *key == ability).unwrap_or(0);
+        self.abilities.scores()[index]
+    }
+
+    pub fn ability_modifier(&self, ability: &str) -> i64 {
+        (self.score(ability) - 10).div_euclid(2)
+    }
+
+    /// The name and modifier for a check with a skill, or a plain ability check
+    pub fn check(&self, name: &str) -> Result<(String, i64)> {
+        if let Some(ability) = ability_key(name) {
+            return Ok((format!("{} check", ability_name(ability)), self.ability_modifier(ability)));
+        }
+        let key = skill_key(name);
+        let (skill, ability) = SKILLS.iter()
+            .find(|(skill, _)| *skill == key)
+            .context(format!("There's no skill or ability called '{name}'"))?;
+        let proficiency = self.skills.get(*skill).copied().unwrap_or(Proficiency::None);
+        Ok((format!("{} check", skill_name(skill)), self.ability_modifier(ability) + proficiency.bonus(self.proficiency_bonus())))
+    }
+
+    /// The name and modifier for a saving throw
+    pub fn save(&self, name: &str) -> Result<(String, i64)> {
+        let ability = ability_key(name).context(format!("There's no ability called '{name}'"))?;
+        let proficiency = if self.saves.iter().any(|save| save == ability) { self.proficiency_bonus() } else { 0 };
+        Ok((format!("{} save", ability_name(ability)), self.ability_modifier(ability) + proficiency))
+    }
+
+    /// Values for `@variables` in rolls: ability modifiers, skill modifiers, `@prof`, `@ac` and `@level`
+    pub fn variable(&self, name: &str) -> Option<i64> {
+        match name {
+            "prof" | "proficiency" => Some(self.proficiency_bonus()),
+            "ac" => Some(self.ac),
+            "level" => Some(self.level),
+            _ => self.check(name).ok().map(|(_, modifier)| modifier),
+        }
+    }
+
+    pub fn current_hp(&self) -> i64 {
+        self.hp.current.unwrap_or(self.hp.max)
+    }
+
+    /// Take damage, using up temporary hit points first. HP doesn't go below 0
+    pub fn damage(&mut self, amount: i64) {
+        let absorbed = amount.min(self.hp.temp);
+        self.hp.temp -= absorbed;
+        self.hp.current = Some((self.current_hp() - (amount - absorbed)).max(0));
+    }
+
+    /// Heal up to max HP
+    pub fn heal(&mut self, amount: i64) {
+        self.hp.current = Some((self.current_hp() + amount).min(self.hp.max));
+    }
+
+    /// Temporary hit points don't stack, so only the larger amount is kept
+    pub fn add_temp(&mut self, amount: i64) {
+        self.hp.temp = self.hp.temp.max(amount);
+    }
+
+    pub fn describe_abilities(&self) -> String {
+        ABILITIES.iter()
+            .map(|(key, _)| format!("{} {} ({:+})", key.to_uppercase(), self.score(key), self.ability_modifier(key)))
+            .collect::<Vec<String>>()
+            .join(" · ")
+    }
+
+    /// Saves and skills the character is proficient in, with their modifiers
+    pub fn describe_proficiencies(&self) -> (String, String) {
+        let saves: Vec<String> = self.saves.iter()
+            .filter_map(|save| self.save(save).ok())
+            .map(|(name, modifier)| format!("{} {modifier:+}", name.trim_end_matches(" save")))
+            .collect();
+        let skills: Vec<String> = self.skills.iter()
+            .filter(|(_, proficiency)| **proficiency != Proficiency::None)
+            .filter_map(|(skill, _)| self.check(skill).ok())
+            .map(|(name, modifier)| format!("{} {modifier:+}", name.trim_end_matches(" check")))
+            .collect();
+        let or_none = |list: Vec<String>| if list.is_empty() { "None".to_string() } else { list.join(", ") };
+        (or_none(saves), or_none(skills))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Character<T> {
+    pub owner: T,
+    pub campaign: String,
+    pub sheet: Sheet
+}
+
+/// Which campaign a channel is playing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelCampaign<C> {
+    pub channel: C,
+    pub campaign: String
+}
+
+/// Every character on a server, one per player per campaign
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CharacterBook<T, C> {
+    characters: Vec<Character<T>>,
+    campaigns: Vec<ChannelCampaign<C>>
+}
+
+impl<T, C> CharacterBook<T, C> where T: Eq + Clone, C: Eq + Clone {
+    pub fn new() -> Self {
+        CharacterBook { characters: vec![], campaigns: vec![] }
+    }
+
+    pub fn campaign(&self, channel: &C) -> &str {
+        self.campaigns.iter()
+            .find(|campaign| campaign.channel == *channel)
+            .map(|campaign| campaign.campaign.as_str())
+            .unwrap_or(DEFAULT_CAMPAIGN)
+    }
+
+    pub fn set_campaign(&mut self, channel: C, campaign: &str) -> Result<String> {
+        let campaign = campaign.trim().to_lowercase();
+        if campaign.is_empty() || campaign.len() > 32 || !campaign.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            anyhow::bail!("Campaign names can only use letters, numbers, '-' and '_', and be up to 32 characters long");
+        }
+        self.campaigns.retain(|existing| existing.channel != channel);
+        if campaign != DEFAULT_CAMPAIGN {
+            self.campaigns.push(ChannelCampaign { channel, campaign: campaign.clone() });
+        }
+        Ok(campaign)
+    }
+
+    /// Save a player's sheet for a campaign, replacing the one they had
+    pub fn import(&mut self, owner: T, campaign: &str, sheet: Sheet) {
+        self.characters.retain(|character| !(character.owner == owner && character.campaign == campaign));
+        self.characters.push(Character { owner, campaign: campaign.into(), sheet });
+    }
+
+    pub fn delete(&mut self, owner: &T, campaign: &str) -> Result<Sheet> {
+        let index = self.characters.iter().position(|character| character.owner == *owner && character.campaign == campaign)
+            .context(format!("There's no character sheet in the '{campaign}' campaign. Import one with `!sheet import`"))?;
+        Ok(self.characters.remove(index).sheet)
+    }
+
+    pub fn sheet(&self, owner: &T, campaign: &str) -> Result<&Sheet> {
+        self.characters.iter()
+            .find(|character| character.owner == *owner && character.campaign == campaign)
+            .map(|character| &character.sheet)
+            .context(format!("There's no character sheet in the '{campaign}' campaign. Import one with `!sheet import`"))
+    }
+
+    /// Find a character in a campaign by name, or the start of one
+    pub fn find_mut(&mut self, campaign: &str, name: &str) -> Result<&mut Character<T>> {
+        let name = name.trim().to_lowercase();
+        let matches: Vec<usize> = (0..self.characters.len())
+            .filter(|index| self.characters[*index].campaign == campaign)
+            .filter(|index| self.characters[*index].sheet.name.to_lowercase().starts_with(&name))
+            .collect();
+        let exact = matches.iter().find(|index| self.characters[**index].sheet.name.to_lowercase() == name);
+        match (exact, matches.as_slice()) {
+            (Some(index), _) | (None, [index]) => Ok(&mut self.characters[*index]),
+            (None, []) => anyhow::bail!("There's nobody called '{name}' in the '{campaign}' campaign"),
+            (None, _) => anyhow::bail!("'{name}' could be more than one character"),
+        }
+    }
+
+    pub fn find_owner_mut(&mut self, owner: &T, campaign: &str) -> Result<&mut Character<T>> {
+        self.characters.iter_mut()
+            .find(|character| character.owner == *owner && character.campaign == campaign)
+            .context(format!("They don't have a character sheet in the '{campaign}' campaign"))
+    }
+
+    pub fn characters(&self, campaign: &str) -> Vec<&Character<T>> {
+        self.characters.iter().filter(|character| character.campaign == campaign).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHEET: &str = r#"{
+        "name": " Vex ",
+        "level": 5,
+        "abilities": {"str": 16, "dexterity": 14, "wis": 13, "int": 8},
+        "skills": {"Sleight of Hand": "expertise", "animal_handling": "half", "Athletics": "proficient", "stealth": "none"},
+        "saves": ["Strength", "dex", "str"],
+        "hp": {"max": 20, "current": 12, "temp": 5},
+        "ac": 15
+    }"#;
+
+    fn sheet(replace: &str, with: &str) -> Result<Sheet> {
+        assert!(SHEET.contains(replace));
+        Sheet::from_json(&SHEET.replacen(replace, with, 1))
+    }
+
+    #[test]
+    fn importing_sheets() {
+        let sheet = Sheet::from_json(SHEET).unwrap();
+        assert_eq!(sheet.name, "Vex");
+        assert_eq!(sheet.describe_abilities(), "STR 16 (+3) · DEX 14 (+2) · CON 10 (+0) · INT 8 (-1) · WIS 13 (+1) · CHA 10 (+0)");
+        assert_eq!(sheet.proficiency_bonus(), 3);
+        assert_eq!(sheet.skills.keys().collect::<Vec<_>>(), ["animal-handling", "athletics", "sleight-of-hand", "stealth"]);
+        assert_eq!(sheet.saves, ["str", "dex"]);
+
+        let sheet = Sheet::from_json(r#"{"name": "Tad", "abilities": {}, "hp": {"max": 8, "current": 30}, "ac": 10}"#).unwrap();
+        assert_eq!((sheet.level, sheet.proficiency_bonus(), sheet.current_hp()), (1, 2, 8));
+        assert_eq!(sheet.score("cha"), 10);
+    }
+
+    #[test]
+    fn invalid_sheets() {
+        assert!(Sheet::from_json("{}").is_err());
+        assert!(sheet(r#"" Vex ""#, r#""  ""#).is_err());
+        assert!(sheet(r#"" Vex ""#, r#""[Vex]""#).is_err());
+        assert!(sheet(r#""level": 5"#, r#""level": 31"#).is_err());
+        assert!(sheet(r#""str": 16"#, r#""str": 31"#).is_err());
+        assert!(sheet(r#""str": 16"#, r#""str": 0"#).is_err());
+        assert!(sheet(r#""level": 5"#, r#""level": 5, "proficiency": 11"#).is_err());
+        assert!(sheet(r#""Athletics""#, r#""Basket Weaving""#).is_err());
+        assert!(sheet(r#""expertise""#, r#""double""#).is_err());
+        assert!(sheet(r#""Strength""#, r#""Luck""#).is_err());
+        assert!(sheet(r#""max": 20"#, r#""max": 0"#).is_err());
+        assert!(sheet(r#""temp": 5"#, r#""temp": -5"#).is_err());
+        assert!(sheet(r#""ac": 15"#, r#""ac": 101"#).is_err());
+    }
+
+    #[test]
+    fn checks_and_saves() {
+        let sheet = Sheet::from_json(SHEET).unwrap();
+        assert_eq!(sheet.check("sleight of hand").unwrap(), ("Sleight of Hand check".to_string(), 2 + 6));
+        assert_eq!(sheet.check("Animal-Handling").unwrap(), ("Animal Handling check".to_string(), 1 + 1));
+        assert_eq!(sheet.check("athletics").unwrap().1, 3 + 3);
+        assert_eq!(sheet.check("stealth").unwrap().1, 2);
+        assert_eq!(sheet.check("arcana").unwrap().1, -1);
+        assert_eq!(sheet.check("Intelligence").unwrap(), ("Intelligence check".to_string(), -1));
+        assert!(sheet.check("juggling").is_err());
+
+        assert_eq!(sheet.save("STR").unwrap(), ("Strength save".to_string(), 3 + 3));
+        assert_eq!(sheet.save("dexterity").unwrap().1, 2 + 3);
+        assert_eq!(sheet.save("wis").unwrap().1, 1);
+        assert!(sheet.save("athletics").is_err());
+
+        assert_eq!(sheet.variable("prof"), Some(3));
+        assert_eq!(sheet.variable("sleight_of_hand"), Some(8));
+        assert_eq!(sheet.variable("luck"), None);
+    }
+
+    #[test]
+    fn temporary_hit_points_go_first() {
+        let mut sheet = Sheet::from_json(SHEET).unwrap();
+        sheet.damage(3);
+        assert_eq!((sheet.hp.temp, sheet.current_hp()), (2, 12));
+        sheet.damage(10);
+        assert_eq!((sheet.hp.temp, sheet.current_hp()), (0, 4));
+        sheet.damage(50);
+        assert_eq!(sheet.current_hp(), 0);
+        sheet.heal(50);
+        assert_eq!(sheet.current_hp(), 20);
+        sheet.add_temp(4);
+        sheet.add_temp(2);
+        assert_eq!(sheet.hp.temp, 4);
+    }
+}
@@ -0,0 +1,252 @@
+use std::{collections::BTreeMap, fmt::Display, hash::Hash};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::roulette::RouletteState;
+
+/// Share of the price paid back when an item is sold to the shop, in percent
+pub const SELL_BACK_PERCENT: u128 = 50;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShopItem<R> {
+    pub name: String,
+    pub price: u128,
+    /// How many are left, or `None` for no limit
+    pub stock: Option<u32>,
+    /// Role given to whoever owns one
+    pub role: Option<R>
+}
+
+/// Some of an item that a player owns. `B` is the balance it was paid from, like a channel's
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Holding<T, R, B> {
+    pub owner: T,
+    /// Name of the item, as listed in the shop
+    pub item: String,
+    pub quantity: u32,
+    /// Which balance paid for it, which is the only one it can be sold back to
+    pub bank: B,
+    /// What was paid for all of them, so they can still be sold back once the shop stops selling the item
+    pub paid: u128,
+    /// The role that came with the item
+    pub role: Option<R>
+}
+
+#[derive(Debug, Clone)]
+pub struct Purchase<R> {
+    pub item: ShopItem<R>,
+    pub quantity: u32,
+    pub cost: u128
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Shop<T, R, B> {
+    /// Items keyed by their lowercased name
+    items: BTreeMap<String, ShopItem<R>>,
+    holdings: Vec<Holding<T, R, B>>
+}
+
+impl<T, R, B> Shop<T, R, B> where T: Display + Eq + Hash + Clone + Ord, R: Clone, B: Clone + PartialEq {
+    pub fn new() -> Self {
+        Shop { items: Default::default(), holdings: vec![] }
+    }
+
+    pub fn items(&self) -> impl Iterator<Item = &ShopItem<R>> {
+        self.items.values()
+    }
+
+    pub fn item(&self, name: &str) -> Result<&ShopItem<R>> {
+        self.items.get(&name.to_lowercase()).context(format!("The shop doesn't sell '{name}'"))
+    }
+
+    /// Add an item, or replace the one with the same name
+    pub fn add_item(&mut self, name: &str, price: u128, stock: Option<u32>, role: Option<R>) -> Result<()> {
+        let name = name.trim();
+        if name.is_empty() {
+            anyhow::bail!("Items need a name");
+        }
+        if price == 0 {
+            anyhow::bail!("Items can't be free");
+        }
+        self.items.insert(name.to_lowercase(), ShopItem { name: name.into(), price, stock, role });
+        Ok(())
+    }
+
+    /// Stop selling an item. Anyone who already owns it keeps it
+    pub fn remove_item(&mut self, name: &str) -> Result<ShopItem<R>> {
+        self.items.remove(&name.to_lowercase()).context(format!("The shop doesn't sell '{name}'"))
+    }
+
+    pub fn restock(&mut self, name: &str, stock: Option<u32>) -> Result<()> {
+        let item = self.items.get_mut(&name.to_lowercase()).context(format!("The shop doesn't sell '{name}'"))?;
+        item.stock = stock;
+        Ok(())
+    }
+
+    pub fn inventory(&self, owner: &T) -> Vec<&Holding<T, R, B>> {
+        self.holdings.iter().filter(|holding| holding.owner == *owner).collect()
+    }
+
+    /// How many of an item a player owns, whichever balances paid for them
+    pub fn quantity(&self, owner: &T, name: &str) -> u32 {
+        self.holdings.iter()
+            .filter(|holding| holding.owner == *owner && holding.item.eq_ignore_ascii_case(name))
+            .map(|holding| holding.quantity)
+            .sum()
+    }
+
+    fn holding_index(&self, owner: &T, name: &str, bank_id: &B) -> Option<usize> {
+        self.holdings.iter().position(|holding| {
+            holding.owner == *owner && holding.item.eq_ignore_ascii_case(name) && holding.bank == *bank_id
+        })
+    }
+
+    /// Buy `quantity` of an item with the balance `bank_id` names. Items that grant a role can only be owned once
+    pub fn buy(&mut self, owner: T, owner_name: &str, name: &str, quantity: u32, bank_id: B, bank: &mut RouletteState<T>) -> Result<Purchase<R>> {
+        if quantity == 0 {
+            anyhow::bail!("You have to buy at least one");
+        }
+        let item = self.item(name)?.clone();
+        let owned = self.quantity(&owner, &item.name).checked_add(quantity).context("That's more than anyone could own")?;
+        if item.role.is_some() && owned > 1 {
+            anyhow::bail!("You can only own one '{}'", item.name);
+        }
+        if let Some(stock) = item.stock {
+            if stock < quantity {
+                anyhow::bail!("There are only {stock} '{}' left", item.name);
+            }
+        }
+        let cost = item.price.checked_mul(quantity as u128).context("That costs more than anyone could have")?;
+        bank.withdraw(owner.clone(), cost)?;
+        if let Some(stock) = self.items.get_mut(&item.name.to_lowercase()).and_then(|item| item.stock.as_mut()) {
+            *stock -= quantity;
+        }
+        let existing = self.holdings.iter_mut()
+            .find(|holding| holding.owner == owner && holding.item.eq_ignore_ascii_case(&item.name) && holding.bank == bank_id);
+        match existing {
+            Some(holding) => {
+                holding.quantity += quantity;
+                holding.paid += cost;
+            },
+            None => self.holdings.push(Holding {
+                owner: owner.clone(),
+                item: item.name.clone(),
+                quantity,
+                bank: bank_id,
+                paid: cost,
+                role: item.role.clone()
+            }),
+        }
+        println!("Player {owner_name} (id={owner}) bought {quantity} '{}' for {cost}", item.name);
+        Ok(Purchase { item, quantity, cost })
+    }
+
+    /// Sell items back to the shop for part of what was paid for them, into the balance they were bought with.
+    /// Returns what was paid back
+    pub fn sell(&mut self, owner: T, name: &str, quantity: u32, bank_id: &B, bank: &mut RouletteState<T>) -> Result<Purchase<R>> {
+        if quantity == 0 {
+            anyhow::bail!("You have to sell at least one");
+        }
+        let Some(index) = self.holding_index(&owner, name, bank_id) else {
+            if self.quantity(&owner, name) > 0 {
+                anyhow::bail!("You bought your '{name}' with a balance from another channel, so it can only be sold back there");
+            }
+            anyhow::bail!("You don't own any '{name}'");
+        };
+        let holding = &self.holdings[index];
+        if holding.quantity < quantity {
+            anyhow::bail!("You only own {} '{}' bought here", holding.quantity, holding.item);
+        }
+        let listing = self.items.get(&holding.item.to_lowercase()).cloned();
+        // What these ones cost, out of everything paid for the holding
+        let cost = holding.paid * quantity as u128 / holding.quantity as u128;
+        let item = match listing {
+            Some(listing) => ShopItem { role: holding.role.clone().or(listing.role.clone()), ..listing },
+            None => ShopItem { name: holding.item.clone(), price: cost / quantity as u128, stock: None, role: holding.role.clone() },
+        };
+        let paid_back = cost * SELL_BACK_PERCENT / 100;
+        bank.deposit(owner, paid_back)?;
+
+        let holding = &mut self.holdings[index];
+        holding.quantity -= quantity;
+        holding.paid -= cost;
+        if holding.quantity == 0 {
+            self.holdings.remove(index);
+        }
+        if let Some(stock) = self.items.get_mut(&item.name.to_lowercase()).and_then(|item| item.stock.as_mut()) {
+            *stock += quantity;
+        }
+        Ok(Purchase { item, quantity, cost: paid_back })
+    }
+
+    /// Undo a purchase in full, for when its role couldn't be granted
+    pub fn refund(&mut self, owner: T, purchase: &Purchase<R>, bank_id: &B, bank: &mut RouletteState<T>) -> Result<()> {
+        let index = self.holding_index(&owner, &purchase.item.name, bank_id).context("The purchase has already been undone")?;
+        let holding = &mut self.holdings[index];
+        holding.quantity -= purchase.quantity.min(holding.quantity);
+        holding.paid = holding.paid.saturating_sub(purchase.cost);
+        if holding.quantity == 0 {
+            self.holdings.remove(index);
+        }
+        bank.deposit(owner, purchase.cost)?;
+        if let Some(stock) = self.items.get_mut(&purchase.item.name.to_lowercase()).and_then(|item| item.stock.as_mut()) {
+            *stock += purchase.quantity;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAYER: u64 = 1;
+
+    /// A shop selling unlimited hats for 100, and two channels' balances for one player
+    fn shop() -> (Shop<u64, u64, u64>, RouletteState<u64>, RouletteState<u64>) {
+        let mut shop = Shop::new();
+        shop.add_item("Hat", 100, None, None).unwrap();
+        let mut first = RouletteState::new();
+        first.register_player(PLAYER, "player");
+        let mut second = RouletteState::new();
+        second.register_player(PLAYER, "player");
+        (shop, first, second)
+    }
+
+    #[test]
+    fn sells_back_to_the_balance_that_paid() {
+        let (mut shop, mut first, mut second) = shop();
+        let start = first.get_balance(PLAYER).unwrap();
+        shop.buy(PLAYER, "player", "hat", 2, 1, &mut first).unwrap();
+        assert_eq!(first.get_balance(PLAYER).unwrap(), start - 200);
+
+        assert!(shop.sell(PLAYER, "hat", 1, &2, &mut second).is_err());
+        assert_eq!(second.get_balance(PLAYER).unwrap(), start);
+
+        let sale = shop.sell(PLAYER, "hat", 1, &1, &mut first).unwrap();
+        assert_eq!(sale.cost, 50);
+        assert_eq!(first.get_balance(PLAYER).unwrap(), start - 150);
+        assert_eq!(shop.quantity(&PLAYER, "hat"), 1);
+    }
+
+    #[test]
+    fn sells_at_the_price_paid() {
+        let (mut shop, mut first, _) = shop();
+        shop.buy(PLAYER, "player", "hat", 1, 1, &mut first).unwrap();
+        shop.add_item("Hat", 1000, None, None).unwrap();
+        assert_eq!(shop.sell(PLAYER, "hat", 1, &1, &mut first).unwrap().cost, 50);
+    }
+
+    #[test]
+    fn removed_items_can_be_sold() {
+        let (mut shop, mut first, _) = shop();
+        let start = first.get_balance(PLAYER).unwrap();
+        shop.buy(PLAYER, "player", "hat", 1, 1, &mut first).unwrap();
+        shop.remove_item("hat").unwrap();
+        let sale = shop.sell(PLAYER, "hat", 1, &1, &mut first).unwrap();
+        assert_eq!(sale.item.name, "Hat");
+        assert_eq!(first.get_balance(PLAYER).unwrap(), start - 50);
+        assert!(shop.inventory(&PLAYER).is_empty());
+        assert!(shop.item("hat").is_err());
+    }
+}
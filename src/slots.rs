@@ -0,0 +1,214 @@
+use std::collections::BTreeMap;
+use anyhow::{Context, Result};
+use rand::Rng;
+use serde::Deserialize;
+
+/// Symbol in a paytable entry that matches anything
+const WILDCARD: &str = "*";
+
+/// The machine used when no `slots.json` is found next to the bot
+const DEFAULT_MACHINE: &str = include_str!("../slots.json");
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PaytableEntry {
+    /// One symbol per reel, or `*` for any symbol
+    pub symbols: Vec<String>,
+    /// Multiple of the line stake paid when a payline shows these symbols
+    pub pays: u128
+}
+
+impl PaytableEntry {
+    fn matches(&self, line: &[&str]) -> bool {
+        self.symbols.iter().zip(line.iter()).all(|(symbol, shown)| symbol == WILDCARD || symbol == shown)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SlotMachine {
+    /// How many symbols of each reel are visible
+    pub rows: usize,
+    /// Reel strips, which wrap around
+    pub reels: Vec<Vec<String>>,
+    /// For every payline, the visible row it passes through on each reel
+    pub paylines: Vec<Vec<usize>>,
+    pub paytable: Vec<PaytableEntry>
+}
+
+#[derive(Debug, Clone)]
+pub struct SlotSpin {
+    /// Visible symbols, indexed by row and then by reel
+    pub grid: Vec<Vec<String>>,
+    /// Paylines that won, with how many line stakes each paid
+    pub line_wins: Vec<(usize, u128)>,
+    pub payout: u128
+}
+
+impl SlotMachine {
+    /// Load `path`, falling back to the built in machine if the file doesn't exist
+    pub fn load(path: &str) -> Result<Self> {
+        let data = match std::fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => DEFAULT_MACHINE.to_string(),
+            Err(e) => return Err(e).context(format!("Unable to read slot machine from '{path}'")),
+        };
+        Self::from_json(&data)
+    }
+
+    pub fn from_json(data: &str) -> Result<Self> {
+        let machine: SlotMachine = serde_json::from_str(data).context("Invalid slot machine definition")?;
+        machine.validate()?;
+        Ok(machine)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.reels.is_empty() || self.reels.iter().any(|reel| reel.is_empty()) {
+            anyhow::bail!("Every slot machine needs at least one reel, and reels can't be empty");
+        }
+        if self.rows == 0 {
+            anyhow::bail!("A slot machine must show at least one row");
+        }
+        if self.paylines.is_empty() {
+            anyhow::bail!("A slot machine needs at least one payline");
+        }
+        for (index, payline) in self.paylines.iter().enumerate() {
+            if payline.len() != self.reels.len() {
+                anyhow::bail!("Payline {} has {} positions but there are {} reels", index + 1, payline.len(), self.reels.len());
+            }
+            if payline.iter().any(|row| *row >= self.rows) {
+                anyhow::bail!("Payline {} passes through a row that isn't visible", index + 1);
+            }
+        }
+        for entry in self.paytable.iter() {
+            if entry.symbols.len() != self.reels.len() {
+                anyhow::bail!("Paytable entry {:?} doesn't have one symbol per reel", entry.symbols);
+            }
+        }
+        Ok(())
+    }
+
+    /// Cost of one spin must split evenly across the paylines
+    pub fn validate_stake(&self, amount: u128) -> Result<()> {
+        let lines = self.paylines.len() as u128;
+        if amount == 0 || !amount.is_multiple_of(lines) {
+            anyhow::bail!("The stake must be a positive multiple of {lines}, one share per payline");
+        }
+        Ok(())
+    }
+
+    /// Best paytable multiplier for the symbols on one payline
+    fn line_pays(&self, line: &[&str]) -> u128 {
+        self.paytable.iter()
+            .filter(|entry| entry.matches(line))
+            .map(|entry| entry.pays)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn spin(&self, amount: u128, rng: &mut impl Rng) -> Result<SlotSpin> {
+        self.validate_stake(amount)?;
+        let stops: Vec<usize> = self.reels.iter().map(|reel| rng.gen_range(0..reel.len())).collect();
+        let grid: Vec<Vec<String>> = (0..self.rows)
+            .map(|row| {
+                self.reels.iter().zip(stops.iter())
+                    .map(|(reel, stop)| reel[(stop + row) % reel.len()].clone())
+                    .collect()
+            })
+            .collect();
+
+        let mut line_wins = vec![];
+        for (index, payline) in self.paylines.iter().enumerate() {
+            let line: Vec<&str> = payline.iter().enumerate().map(|(reel, row)| grid[*row][reel].as_str()).collect();
+            let pays = self.line_pays(&line);
+            if pays > 0 {
+                line_wins.push((index, pays));
+            }
+        }
+        let line_stake = amount / self.paylines.len() as u128;
+        let payout = line_wins.iter().map(|(_, pays)| pays * line_stake).sum();
+        Ok(SlotSpin { grid, line_wins, payout })
+    }
+
+    /// Exact return to player as a fraction, worked out over every combination of symbols.
+    /// Each payline sees each reel's symbols with the same odds, so one line is enough
+    pub fn return_to_player(&self) -> (u128, u128) {
+        let reel_odds: Vec<Vec<(&str, u128)>> = self.reels.iter()
+            .map(|reel| {
+                let mut counts: BTreeMap<&str, u128> = BTreeMap::new();
+                for symbol in reel {
+                    *counts.entry(symbol.as_str()).or_default() += 1;
+                }
+                counts.into_iter().collect()
+            })
+            .collect();
+        let total_combinations: u128 = self.reels.iter().map(|reel| reel.len() as u128).product();
+
+        let mut paid: u128 = 0;
+        let mut line: Vec<&str> = vec![""; self.reels.len()];
+        self.sum_line_pays(&reel_odds, 0, 1, &mut line, &mut paid);
+        (paid, total_combinations)
+    }
+
+    fn sum_line_pays<'a>(&self, reel_odds: &[Vec<(&'a str, u128)>], reel: usize, ways: u128, line: &mut Vec<&'a str>, paid: &mut u128) {
+        if reel == reel_odds.len() {
+            *paid += ways * self.line_pays(line);
+            return;
+        }
+        for (symbol, count) in reel_odds[reel].iter() {
+            line[reel] = symbol;
+            self.sum_line_pays(reel_odds, reel + 1, ways * count, line, paid);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine(reels: &str, paylines: &str, paytable: &str) -> Result<SlotMachine> {
+        SlotMachine::from_json(&format!(r#"{{"rows": 2, "reels": {reels}, "paylines": {paylines}, "paytable": {paytable}}}"#))
+    }
+
+    #[test]
+    fn return_to_player() {
+        // AA comes up 2 ways in 6 paying 5, AB 2 ways paying 2, BA never pays and BB once,
+        // where the best entry pays 10 rather than the wildcard's 2
+        let machine = machine(
+            r#"[["A", "A", "B"], ["A", "B"]]"#,
+            "[[0, 0], [1, 1]]",
+            r#"[{"symbols": ["A", "A"], "pays": 5}, {"symbols": ["*", "B"], "pays": 2}, {"symbols": ["B", "B"], "pays": 10}]"#,
+        ).unwrap();
+        assert_eq!(machine.return_to_player(), (2 * 5 + 2 * 2 + 10, 6));
+    }
+
+    #[test]
+    fn default_machine_loads() {
+        let machine = SlotMachine::from_json(DEFAULT_MACHINE).unwrap();
+        let (paid, total) = machine.return_to_player();
+        assert!(paid < total);
+    }
+
+    #[test]
+    fn invalid_machines() {
+        let paytable = r#"[{"symbols": ["A", "A"], "pays": 5}]"#;
+        assert!(machine(r#"[["A"], ["A"]]"#, "[[0, 1]]", paytable).is_ok());
+        assert!(machine("[]", "[[0, 1]]", paytable).is_err());
+        assert!(machine(r#"[["A"], []]"#, "[[0, 1]]", paytable).is_err());
+        assert!(machine(r#"[["A"], ["A"]]"#, "[]", paytable).is_err());
+        assert!(machine(r#"[["A"], ["A"]]"#, "[[0]]", paytable).is_err());
+        assert!(machine(r#"[["A"], ["A"]]"#, "[[0, 0, 0]]", paytable).is_err());
+        assert!(machine(r#"[["A"], ["A"]]"#, "[[0, 2]]", paytable).is_err());
+        assert!(machine(r#"[["A"], ["A"]]"#, "[[0, 1]]", r#"[{"symbols": ["A"], "pays": 5}]"#).is_err());
+        assert!(SlotMachine::from_json(r#"{"rows": 0, "reels": [["A"]], "paylines": [[0]], "paytable": []}"#).is_err());
+    }
+
+    #[test]
+    fn stakes_split_across_paylines() {
+        let machine = machine(r#"[["A"], ["A"]]"#, "[[0, 0], [1, 1]]", r#"[{"symbols": ["A", "A"], "pays": 3}]"#).unwrap();
+        assert!(machine.validate_stake(0).is_err());
+        assert!(machine.validate_stake(3).is_err());
+        let mut rng = rand::thread_rng();
+        let spin = machine.spin(10, &mut rng).unwrap();
+        assert_eq!(spin.line_wins, [(0, 3), (1, 3)]);
+        assert_eq!(spin.payout, 30);
+    }
+}
@@ -0,0 +1,299 @@
+use anyhow::{Context, Result};
+use rand::Rng;
+
+use crate::dice::{self, DiceError, Expression, RollBreakdown};
+use crate::sheets::ABILITIES;
+
+/// How ability scores are rolled when no method is given
+pub const DEFAULT_ROLL: &str = "4d6dl1";
+
+/// The standard array, highest first
+pub const STANDARD_ARRAY: [i64; 6] = [15, 14, 13, 12, 10, 8];
+
+/// Arrays whose modifiers add up to less than this are flagged for a reroll, unless a server chooses otherwise
+pub const DEFAULT_REROLL_THRESHOLD: i64 = 1;
+
+/// Most sets of scores that can be rolled at once
+pub const MAX_SETS: usize = 5;
+
+/// Points most commonly given for point buy
+pub const DEFAULT_POINT_BUY: i64 = 27;
+
+/// Most points that can be given for point buy, which is enough to buy 15 in everything
+pub const MAX_POINT_BUY: i64 = 54;
+
+/// The cost of each score that can be bought, from 8 to 15
+const POINT_BUY_COSTS: [(i64, i64); 8] = [(8, 0), (9, 1), (10, 2), (11, 3), (12, 4), (13, 5), (14, 7), (15, 9)];
+
+/// The modifier for an ability score
+pub fn modifier(score: i64) -> i64 {
+    (score - 10).div_euclid(2)
+}
+
+/// One ability score, with the roll it came from if it was rolled
+#[derive(Debug, Clone)]
+pub struct Score {
+    pub value: i64,
+    pub roll: Option<RollBreakdown>
+}
+
+/// Six ability scores
+#[derive(Debug, Clone)]
+pub struct StatArray {
+    pub scores: Vec<Score>
+}
+
+impl StatArray {
+    pub fn total(&self) -> i64 {
+        self.scores.iter().map(|score| score.value).sum()
+    }
+
+    pub fn total_modifier(&self) -> i64 {
+        self.scores.iter().map(|score| modifier(score.value)).sum()
+    }
+
+    pub fn needs_reroll(&self, threshold: i64) -> bool {
+        self.total_modifier() < threshold
+    }
+
+    /// Each score with its modifier and the dice behind it, one per line
+    pub fn describe(&self) -> String {
+        let lines: Vec<String> = self.scores.iter()
+            .map(|score| {
+                let dice: Vec<String> = score.roll.iter()
+                    .flat_map(|roll| roll.terms.iter())
+                    .filter_map(|term| match term {
+                        dice::TermResult::Dice { dice, .. } => Some(dice::format_dice(dice)),
+                        dice::TermResult::Modifier(_) => None,
+                    })
+                    .collect();
+                match dice.is_empty() {
+                    true => format!("**{}** ({:+})", score.value, modifier(score.value)),
+                    false => format!("**{}** ({:+}) from {}", score.value, modifier(score.value), dice.join("; ")),
+                }
+            })
+            .collect();
+        lines.join("\n")
+    }
+}
+
+/// A way of coming up with ability scores
+#[derive(Debug, Clone)]
+pub enum Method {
+    /// Roll each score with a dice expression, like `4d6dl1`
+    Roll(Box<Expression>),
+    StandardArray
+}
+
+impl Method {
+    pub fn parse(input: &str) -> Result<Self, DiceError> {
+        match input.trim() {
+            "" => Expression::parse(DEFAULT_ROLL).map(|expression| Method::Roll(Box::new(expression))),
+            input if input.eq_ignore_ascii_case("array") => Ok(Method::StandardArray),
+            input => Expression::parse(input).map(|expression| Method::Roll(Box::new(expression))),
+        }
+    }
+
+    pub fn generate(&self, rng: &mut impl Rng) -> Result<StatArray, DiceError> {
+        let scores = match self {
+            Method::Roll(expression) => (0..ABILITIES.len())
+                .map(|_| expression.roll(rng).map(|roll| Score { value: roll.total, roll: Some(roll) }))
+                .collect::<Result<_, _>>()?,
+            Method::StandardArray => STANDARD_ARRAY.iter().map(|value| Score { value: *value, roll: None }).collect(),
+        };
+        Ok(StatArray { scores })
+    }
+}
+
+pub fn point_cost(score: i64) -> Option<i64> {
+    POINT_BUY_COSTS.iter().find(|(value, _)| *value == score).map(|(_, cost)| *cost)
+}
+
+/// The scores and costs that can be bought, as a table
+pub fn point_buy_costs() -> String {
+    let costs: Vec<String> = POINT_BUY_COSTS.iter().map(|(score, cost)| format!("{score:>2} ({:+}) costs {cost}", modifier(*score))).collect();
+    costs.join("\n")
+}
+
+/// A point buy in progress, with a score for every ability in ability order. Every ability starts at 8
+#[derive(Debug, Clone)]
+pub struct PointBuy {
+    pub budget: i64,
+    pub scores: [i64; 6]
+}
+
+impl PointBuy {
+    pub fn new(budget: i64) -> Result<Self> {
+        if !(0..=MAX_POINT_BUY).contains(&budget) {
+            anyhow::bail!("Point buy budgets go from 0 to {MAX_POINT_BUY}");
+        }
+        Ok(PointBuy { budget, scores: [8; 6] })
+    }
+
+    pub fn spent(&self) -> i64 {
+        self.scores.iter().filter_map(|score| point_cost(*score)).sum()
+    }
+
+    pub fn remaining(&self) -> i64 {
+        self.budget - self.spent()
+    }
+
+    /// Buy a score for an ability, as long as it can be bought and the points are there to pay for it
+    pub fn set(&mut self, ability: usize, score: i64) -> Result<()> {
+        let (key, _) = ABILITIES.get(ability).context("There's no such ability")?;
+        let cost = point_cost(score).with_context(|| format!("Scores can only be bought from 8 to 15, not {score}"))?;
+        let refund = point_cost(self.scores[ability]).unwrap_or_default();
+        let remaining = self.remaining() + refund;
+        if cost > remaining {
+            anyhow::bail!("{} {score} costs {cost}, but only {remaining} points are left for it", key.to_uppercase());
+        }
+        self.scores[ability] = score;
+        Ok(())
+    }
+
+    /// Raise or lower an ability by a number of steps
+    pub fn adjust(&mut self, ability: usize, steps: i64) -> Result<()> {
+        let score = self.scores.get(ability).context("There's no such ability")?.saturating_add(steps);
+        self.set(ability, score)
+    }
+
+    /// Every ability with its score, and what's left to spend
+    pub fn describe(&self) -> String {
+        let mut lines: Vec<String> = ABILITIES.iter().zip(self.scores)
+            .map(|((key, _), score)| format!("{} {score:>2} ({:+}) costs {}", key.to_uppercase(), modifier(score), point_cost(score).unwrap_or_default()))
+            .collect();
+        let total_modifier: i64 = self.scores.iter().map(|score| modifier(*score)).sum();
+        lines.push(format!("Total modifier {total_modifier:+}, {} of {} points spent", self.spent(), self.budget));
+        let remaining = self.remaining();
+        lines.push(match remaining {
+            0 => "✅ Every point is spent".to_string(),
+            1 => "1 point left to spend".to_string(),
+            _ => format!("{remaining} points left to spend"),
+        });
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn modifiers() {
+        let cases = [(1, -5), (7, -2), (8, -1), (9, -1), (10, 0), (11, 0), (12, 1), (15, 2), (18, 4), (20, 5)];
+        for (score, expected) in cases {
+            assert_eq!(modifier(score), expected, "{score}");
+        }
+    }
+
+    #[test]
+    fn rolling_drops_the_lowest_die() {
+        let method = Method::parse("").unwrap();
+        for seed in 0..100 {
+            let array = method.generate(&mut StdRng::seed_from_u64(seed)).unwrap();
+            assert_eq!(array.scores.len(), ABILITIES.len());
+            for score in array.scores.iter() {
+                let roll = score.roll.as_ref().unwrap();
+                let dice::TermResult::Dice { dice, .. } = &roll.terms[0] else {
+                    panic!("4d6dl1 rolls dice");
+                };
+                assert_eq!(dice.len(), 4);
+                let lowest = dice.iter().map(|die| die.value).min().unwrap();
+                let kept: Vec<i64> = dice.iter().filter(|die| die.kept).map(|die| die.value).collect();
+                assert_eq!(kept.len(), 3);
+                assert_eq!(score.value, dice.iter().map(|die| die.value).sum::<i64>() - lowest);
+                assert!((3..=18).contains(&score.value));
+            }
+        }
+    }
+
+    #[test]
+    fn standard_array() {
+        let array = Method::parse("Array").unwrap().generate(&mut StdRng::seed_from_u64(0)).unwrap();
+        let scores: Vec<i64> = array.scores.iter().map(|score| score.value).collect();
+        assert_eq!(scores, STANDARD_ARRAY);
+        assert!(array.scores.iter().all(|score| score.roll.is_none()));
+        assert_eq!(array.total(), 72);
+        assert_eq!(array.total_modifier(), 5);
+    }
+
+    #[test]
+    fn reroll_threshold() {
+        let array = |values: [i64; 6]| StatArray { scores: values.iter().map(|value| Score { value: *value, roll: None }).collect() };
+        // Modifiers of -1, -1, 0, 0, 1, 1 add up to 0
+        let low = array([8, 9, 10, 11, 12, 13]);
+        assert_eq!(low.total_modifier(), 0);
+        assert!(low.needs_reroll(DEFAULT_REROLL_THRESHOLD));
+        assert!(!low.needs_reroll(0));
+        assert!(!array(STANDARD_ARRAY).needs_reroll(DEFAULT_REROLL_THRESHOLD));
+        assert!(array(STANDARD_ARRAY).needs_reroll(6));
+
+        // Seeded rolls flag exactly the sets under the threshold
+        let method = Method::parse("3d6").unwrap();
+        let mut rng = StdRng::seed_from_u64(7);
+        let arrays: Vec<StatArray> = (0..50).map(|_| method.generate(&mut rng).unwrap()).collect();
+        assert!(arrays.iter().any(|array| array.needs_reroll(DEFAULT_REROLL_THRESHOLD)));
+        assert!(arrays.iter().any(|array| !array.needs_reroll(DEFAULT_REROLL_THRESHOLD)));
+        for array in arrays {
+            assert_eq!(array.needs_reroll(DEFAULT_REROLL_THRESHOLD), array.total_modifier() < DEFAULT_REROLL_THRESHOLD);
+        }
+    }
+
+    #[test]
+    fn point_buy_costs() {
+        let costs: Vec<Option<i64>> = (7..=16).map(point_cost).collect();
+        assert_eq!(costs, vec![None, Some(0), Some(1), Some(2), Some(3), Some(4), Some(5), Some(7), Some(9), None]);
+
+        // The standard array costs exactly 27 points
+        let mut point_buy = PointBuy::new(DEFAULT_POINT_BUY).unwrap();
+        for (ability, score) in STANDARD_ARRAY.iter().enumerate() {
+            point_buy.set(ability, *score).unwrap();
+        }
+        assert_eq!(point_buy.spent(), 27);
+        assert_eq!(point_buy.remaining(), 0);
+
+        // Everything at 15 takes the most points there can be
+        let mut point_buy = PointBuy::new(MAX_POINT_BUY).unwrap();
+        for ability in 0..ABILITIES.len() {
+            point_buy.set(ability, 15).unwrap();
+        }
+        assert_eq!(point_buy.remaining(), 0);
+    }
+
+    #[test]
+    fn point_buy_steps() {
+        let mut point_buy = PointBuy::new(DEFAULT_POINT_BUY).unwrap();
+        assert_eq!(point_buy.scores, [8; 6]);
+        point_buy.adjust(0, 7).unwrap();
+        point_buy.adjust(1, 6).unwrap();
+        point_buy.adjust(2, 5).unwrap();
+        assert_eq!(point_buy.remaining(), 6);
+        // Going from 14 to 15 costs 2, and lowering a score gives its points back
+        point_buy.adjust(1, 1).unwrap();
+        assert_eq!(point_buy.remaining(), 4);
+        point_buy.adjust(1, -1).unwrap();
+        assert_eq!(point_buy.remaining(), 6);
+        assert!(point_buy.adjust(0, 1).is_err());
+        assert!(point_buy.adjust(3, -1).is_err());
+    }
+
+    #[test]
+    fn point_buy_over_budget() {
+        let mut point_buy = PointBuy::new(DEFAULT_POINT_BUY).unwrap();
+        point_buy.set(0, 15).unwrap();
+        point_buy.set(1, 15).unwrap();
+        point_buy.set(2, 15).unwrap();
+        assert_eq!(point_buy.remaining(), 0);
+        let error = point_buy.set(3, 9).unwrap_err();
+        assert_eq!(error.to_string(), "INT 9 costs 1, but only 0 points are left for it");
+        // A failed step leaves the scores as they were
+        assert_eq!(point_buy.scores, [15, 15, 15, 8, 8, 8]);
+        // Swapping a score for a cheaper one is fine even with nothing left
+        point_buy.set(0, 14).unwrap();
+        assert_eq!(point_buy.remaining(), 2);
+
+        assert!(PointBuy::new(MAX_POINT_BUY + 1).is_err());
+        assert!(PointBuy::new(-1).is_err());
+    }
+}
@@ -0,0 +1,499 @@
+use std::collections::BTreeMap;
+use anyhow::{Context, Result};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::dice::{self, Expression};
+
+/// Most rows one table can have
+const MAX_ROWS: usize = 1_000;
+
+/// How many tables deep `{other-table}` references can go, so tables that refer to each other can't loop forever
+const MAX_NESTING: usize = 10;
+
+/// Most tables one roll can roll on, counting every reference, so rows that refer to a table many times can't blow up
+const MAX_TABLE_ROLLS: usize = 500;
+
+/// Longest result one roll can build, in bytes
+const MAX_RESULT_LENGTH: usize = 4_000;
+
+/// Most that a weighted table's weights can add up to, since it's rolled with a die that has a side for each
+const MAX_TOTAL_WEIGHT: i64 = 1_000_000;
+
+/// Most tables one server can upload
+pub const MAX_GUILD_TABLES: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Yaml
+}
+
+impl Format {
+    /// Work out the format from a file name, like `wild-magic.csv`
+    pub fn from_filename(filename: &str) -> Option<Self> {
+        let extension = filename.rsplit_once('.')?.1.to_lowercase();
+        match extension.as_str() {
+            "csv" => Some(Format::Csv),
+            "yaml" | "yml" => Some(Format::Yaml),
+            _ => None,
+        }
+    }
+}
+
+/// A row that's picked when the table's roll lands between `low` and `high`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TableRow {
+    pub low: i64,
+    pub high: i64,
+    /// Can refer to other tables like `{treasure}` and roll dice like `[[2d6]]`
+    pub result: String
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RandomTable {
+    pub name: String,
+    /// What's rolled to pick a row, like `d100`. Weighted tables roll one die with a side for each unit of weight
+    pub dice: String,
+    pub rows: Vec<TableRow>
+}
+
+/// A row as it's written, before weights are turned into ranges
+enum RowKey {
+    Weight(i64),
+    Range(i64, i64)
+}
+
+/// The dice a table file names, if any, and its rows as written
+type ParsedRows = (Option<String>, Vec<(RowKey, String)>);
+
+pub fn validate_name(name: &str) -> Result<String> {
+    let name = name.trim().to_lowercase();
+    if name.is_empty() || name.len() > 32 || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        anyhow::bail!("Table names can only use letters, numbers, '-' and '_', and be up to 32 characters long");
+    }
+    Ok(name)
+}
+
+/// Parse a row's range, like `01-05`, `6` or `96-00`, where `00` means 100
+fn parse_range(text: &str) -> Option<(i64, i64)> {
+    let number = |text: &str| -> Option<i64> {
+        let text = text.trim();
+        match text {
+            "00" => Some(100),
+            _ => text.parse().ok(),
+        }
+    };
+    let text = text.trim().replace(['–', '—'], "-");
+    match text.split_once('-') {
+        Some((low, high)) => Some((number(low)?, number(high)?)),
+        None => number(&text).map(|value| (value, value)),
+    }
+}
+
+/// A CSV row that starts with a range, like `01-05,A goblin`
+#[derive(Deserialize)]
+struct RangeCsvRow {
+    range: String,
+    result: String
+}
+
+/// A CSV row that starts with a weight, like `3,A goblin`
+#[derive(Deserialize)]
+struct WeightCsvRow {
+    weight: i64,
+    result: String
+}
+
+/// Parse a table from CSV. Each row is a range and then the result, like `01-05,A goblin`.
+/// A header row is optional. It can name the dice, like `d100,Result`, or say `weight,Result` to make each row a weight instead.
+/// Results with commas in them need quotes
+fn parse_csv(text: &str) -> Result<ParsedRows> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(text.as_bytes());
+    let mut records = reader.records().peekable();
+
+    let mut dice = None;
+    let mut weighted = false;
+    if let Some(Ok(header)) = records.peek() {
+        let key = header.get(0).unwrap_or_default();
+        if key.eq_ignore_ascii_case("weight") {
+            weighted = true;
+            records.next();
+        }
+        else if parse_range(key).is_none() {
+            // Any other header, like `Roll,Result`, just names the columns
+            if key.to_lowercase().contains('d') && Expression::parse(key).is_ok() {
+                dice = Some(key.to_string());
+            }
+            records.next();
+        }
+    }
+
+    let mut rows = vec![];
+    for record in records {
+        let record = record?;
+        let line = record.position().map(|position| position.line()).unwrap_or_default();
+        if record.iter().all(str::is_empty) {
+            continue;
+        }
+        if record.len() != 2 {
+            anyhow::bail!("Line {line} should be a range or weight, then a comma and the result. Put results with commas in them in quotes");
+        }
+        if weighted {
+            let row: WeightCsvRow = record.deserialize(None).ok().with_context(|| format!("Line {line} should start with a weight, not '{}'", &record[0]))?;
+            rows.push((RowKey::Weight(row.weight), row.result));
+        }
+        else {
+            let row: RangeCsvRow = record.deserialize(None)?;
+            let (low, high) = parse_range(&row.range).with_context(|| format!("Line {line} should start with a range like 01-05, not '{}'", row.range))?;
+            rows.push((RowKey::Range(low, high), row.result));
+        }
+    }
+    Ok((dice, rows))
+}
+
+/// A table written in YAML:
+///
+/// ```yaml
+/// dice: d100
+/// rows:
+///   - range: 01-05
+///     result: A goblin
+///   - weight: 2
+///     result: Two goblins
+///   - Plain rows have a weight of 1
+/// ```
+#[derive(Deserialize)]
+struct YamlTable {
+    dice: Option<String>,
+    #[serde(alias = "entries")]
+    rows: Vec<YamlRow>
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum YamlRow {
+    Plain(String),
+    Detailed {
+        range: Option<YamlRange>,
+        weight: Option<i64>,
+        result: String
+    }
+}
+
+/// A range can be written as a single number, like `range: 7`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum YamlRange {
+    Single(i64),
+    Text(String)
+}
+
+fn parse_yaml(text: &str) -> Result<ParsedRows> {
+    let table: YamlTable = serde_yaml::from_str(text)?;
+    let mut rows = vec![];
+    for (index, row) in table.rows.into_iter().enumerate() {
+        let (key, result) = match row {
+            YamlRow::Plain(result) => (RowKey::Weight(1), result),
+            YamlRow::Detailed { range: Some(YamlRange::Single(value)), result, .. } => (RowKey::Range(value, value), result),
+            YamlRow::Detailed { range: Some(YamlRange::Text(range)), result, .. } => {
+                let (low, high) = parse_range(&range).with_context(|| format!("Row {} has an invalid range '{range}'", index + 1))?;
+                (RowKey::Range(low, high), result)
+            },
+            YamlRow::Detailed { range: None, weight, result } => (RowKey::Weight(weight.unwrap_or(1)), result),
+        };
+        rows.push((key, result));
+    }
+    Ok((table.dice, rows))
+}
+
+impl RandomTable {
+    pub fn parse(name: &str, text: &str, format: Format) -> Result<Self> {
+        let name = validate_name(name)?;
+        let (dice, rows) = match format {
+            Format::Csv => parse_csv(text)?,
+            Format::Yaml => parse_yaml(text)?,
+        };
+        if rows.is_empty() {
+            anyhow::bail!("The table doesn't have any rows");
+        }
+        if rows.len() > MAX_ROWS {
+            anyhow::bail!("Tables can have at most {MAX_ROWS} rows");
+        }
+        let weighted = rows.iter().any(|(key, _)| matches!(key, RowKey::Weight(_)));
+        if weighted && rows.iter().any(|(key, _)| matches!(key, RowKey::Range(..))) {
+            anyhow::bail!("A table's rows must all have weights or all have ranges");
+        }
+
+        let mut table_rows = vec![];
+        let mut total = 0i64;
+        for (key, result) in rows {
+            let (low, high) = match key {
+                RowKey::Weight(weight) if !(1..=MAX_TOTAL_WEIGHT).contains(&weight) => anyhow::bail!("Weights must be between 1 and {MAX_TOTAL_WEIGHT}"),
+                RowKey::Weight(weight) => (total + 1, total + weight),
+                RowKey::Range(low, high) if low > high => anyhow::bail!("The range {low}-{high} is backwards"),
+                RowKey::Range(low, high) => (low, high),
+            };
+            total = total.max(high);
+            table_rows.push(TableRow { low, high, result });
+        }
+        if weighted && total > MAX_TOTAL_WEIGHT {
+            anyhow::bail!("Weights can add up to at most {MAX_TOTAL_WEIGHT}");
+        }
+        table_rows.sort_by_key(|row| row.low);
+        if let Some(pair) = table_rows.windows(2).find(|pair| pair[1].low <= pair[0].high) {
+            anyhow::bail!("The ranges {}-{} and {}-{} overlap", pair[0].low, pair[0].high, pair[1].low, pair[1].high);
+        }
+
+        let dice = match (weighted, dice) {
+            (true, _) => format!("1d{total}"),
+            (false, Some(dice)) => dice,
+            (false, None) => format!("1d{total}"),
+        };
+        let table = RandomTable { name, dice, rows: table_rows };
+        table.check_coverage()?;
+        Ok(table)
+    }
+
+    /// Check that every total from the lowest to the highest the table's dice can roll picks a row
+    fn check_coverage(&self) -> Result<()> {
+        let expression = Expression::parse(&self.dice).map_err(|e| anyhow::anyhow!("Invalid dice '{}': {e}", self.dice))?;
+        let (low, high) = expression.bounds().map_err(|e| anyhow::anyhow!("Invalid dice '{}': {e}", self.dice))?;
+        // The rows are sorted and don't overlap, so walk along them looking for the first gap
+        let mut next = low;
+        for row in self.rows.iter().skip_while(|row| row.high < low) {
+            if row.low > next || next > high {
+                break;
+            }
+            next = row.high.saturating_add(1);
+        }
+        if next <= high {
+            anyhow::bail!("Rolling {} can give {next}, but no row covers it", self.dice);
+        }
+        Ok(())
+    }
+
+    pub fn row(&self, total: i64) -> Option<&TableRow> {
+        self.rows.iter().find(|row| (row.low..=row.high).contains(&total))
+    }
+}
+
+/// What came of rolling on a table
+#[derive(Debug, Clone)]
+pub struct TableRoll {
+    /// The result, with references to other tables and dice rolled and filled in
+    pub text: String,
+    /// Each table rolled on and the total that picked its row, starting with the first one
+    pub rolls: Vec<(String, i64)>
+}
+
+/// Roll on a table, following any references to other tables in the result.
+/// `lookup` finds tables by name
+pub fn roll<'a>(table: &'a RandomTable, lookup: &impl Fn(&str) -> Option<&'a RandomTable>, rng: &mut impl Rng) -> Result<TableRoll> {
+    let mut rolls = vec![];
+    let text = roll_nested(table, lookup, rng, &mut rolls, 0)?;
+    Ok(TableRoll { text, rolls })
+}
+
+fn roll_nested<'a>(table: &'a RandomTable, lookup: &impl Fn(&str) -> Option<&'a RandomTable>, rng: &mut impl Rng, rolls: &mut Vec<(String, i64)>, depth: usize) -> Result<String> {
+    if depth > MAX_NESTING {
+        anyhow::bail!("Tables refer to each other more than {MAX_NESTING} deep. Do two of them refer to each other?");
+    }
+    if rolls.len() >= MAX_TABLE_ROLLS {
+        anyhow::bail!("That would roll on more than {MAX_TABLE_ROLLS} tables. Do rows refer to the same table many times?");
+    }
+    let total = dice::roll(&table.dice, rng).map_err(|e| anyhow::anyhow!("Couldn't roll {} for '{}': {e}", table.dice, table.name))?.total;
+    rolls.push((table.name.clone(), total));
+    let row = table.row(total).with_context(|| format!("'{}' has no row for {total}", table.name))?;
+
+    let mut text = String::new();
+    let mut rest = row.result.as_str();
+    loop {
+        let next_table = rest.find('{');
+        let next_roll = rest.find("[[");
+        match (next_table, next_roll) {
+            (Some(start), roll_start) if roll_start.is_none_or(|roll_start| start < roll_start) => {
+                let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+                    break;
+                };
+                let name = rest[start + 1..end].trim().to_lowercase();
+                let nested = lookup(&name).with_context(|| format!("'{}' refers to a table called '{name}' that doesn't exist", table.name))?;
+                text += &rest[..start];
+                text += &roll_nested(nested, lookup, rng, rolls, depth + 1)?;
+                rest = &rest[end + 1..];
+                if text.len() > MAX_RESULT_LENGTH {
+                    anyhow::bail!("The result would be longer than {MAX_RESULT_LENGTH} characters");
+                }
+            },
+            (_, Some(start)) => {
+                let Some(end) = rest[start..].find("]]").map(|end| start + end) else {
+                    break;
+                };
+                let expression = &rest[start + 2..end];
+                let rolled = dice::roll(expression, rng).map_err(|e| anyhow::anyhow!("'{}' has an invalid roll [[{expression}]]: {e}", table.name))?;
+                text += &rest[..start];
+                text += &rolled.total.to_string();
+                rest = &rest[end + 2..];
+            },
+            _ => break,
+        }
+    }
+    text += rest;
+    if text.len() > MAX_RESULT_LENGTH {
+        anyhow::bail!("The result would be longer than {MAX_RESULT_LENGTH} characters");
+    }
+    Ok(text)
+}
+
+/// A collection of tables, like those a server has uploaded
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TableLibrary {
+    tables: BTreeMap<String, RandomTable>
+}
+
+impl TableLibrary {
+    pub fn new() -> Self {
+        TableLibrary { tables: BTreeMap::new() }
+    }
+
+    /// Load every `.csv`, `.yaml` and `.yml` file in a directory, named after the file.
+    /// Tables that can't be parsed are skipped with a warning
+    pub fn load_directory(path: &str) -> Result<Self> {
+        let mut library = TableLibrary::new();
+        let entries = match std::fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(library),
+            Err(e) => return Err(e.into()),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            let filename = path.file_name().and_then(|name| name.to_str()).unwrap_or_default().to_string();
+            let Some(format) = Format::from_filename(&filename) else {
+                continue;
+            };
+            let name = filename.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(&filename);
+            match std::fs::read_to_string(&path).map_err(anyhow::Error::from).and_then(|text| RandomTable::parse(name, &text, format)) {
+                Ok(table) => library.add(table),
+                Err(e) => println!("Skipping table {}: {e}", path.display()),
+            }
+        }
+        Ok(library)
+    }
+
+    pub fn add(&mut self, table: RandomTable) {
+        self.tables.insert(table.name.clone(), table);
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<RandomTable> {
+        self.tables.remove(&name.to_lowercase()).with_context(|| format!("There's no table called '{name}'"))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&RandomTable> {
+        self.tables.get(&name.to_lowercase())
+    }
+
+    pub fn tables(&self) -> impl Iterator<Item = &RandomTable> {
+        self.tables.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(table: &RandomTable) -> Vec<(i64, i64, &str)> {
+        table.rows.iter().map(|row| (row.low, row.high, row.result.as_str())).collect()
+    }
+
+    #[test]
+    fn csv_without_a_header_is_ranges() {
+        let table = RandomTable::parse("plain", "1,a\n2,b\n3,c\n", Format::Csv).unwrap();
+        assert_eq!(table.dice, "1d3");
+        assert_eq!(ranges(&table), vec![(1, 1, "a"), (2, 2, "b"), (3, 3, "c")]);
+    }
+
+    #[test]
+    fn csv_headers() {
+        let table = RandomTable::parse("dice", "d4,Result\n1-2,\"a, b\"\n3-4,c\n", Format::Csv).unwrap();
+        assert_eq!(table.dice, "d4");
+        assert_eq!(ranges(&table), vec![(1, 2, "a, b"), (3, 4, "c")]);
+
+        let table = RandomTable::parse("weights", "weight,Result\n3,a\n1,b\n", Format::Csv).unwrap();
+        assert_eq!(table.dice, "1d4");
+        assert_eq!(ranges(&table), vec![(1, 3, "a"), (4, 4, "b")]);
+
+        let table = RandomTable::parse("named", "Roll,Result\n96-00,a\n01-95,b\n", Format::Csv).unwrap();
+        assert_eq!(ranges(&table), vec![(1, 95, "b"), (96, 100, "a")]);
+    }
+
+    #[test]
+    fn csv_errors() {
+        assert!(RandomTable::parse("commas", "1,a, b\n2,c\n", Format::Csv).is_err());
+        assert!(RandomTable::parse("weights", "weight,Result\nthree,a\n", Format::Csv).is_err());
+        assert!(RandomTable::parse("gap", "d6,Result\n1-2,a\n4-6,b\n", Format::Csv).is_err());
+        assert!(RandomTable::parse("overlap", "1-3,a\n3-4,b\n", Format::Csv).is_err());
+    }
+
+    #[test]
+    fn yaml_rows() {
+        let text = "dice: d6\nrows:\n  - range: 1-3\n    result: \"Room #3 has a trap\"\n  - range: 4\n    result: b\n  - range: 5-6\n    result: c # a comment\n";
+        let table = RandomTable::parse("rooms", text, Format::Yaml).unwrap();
+        assert_eq!(ranges(&table), vec![(1, 3, "Room #3 has a trap"), (4, 4, "b"), (5, 6, "c")]);
+
+        let table = RandomTable::parse("flow", "rows: [a, b, c]", Format::Yaml).unwrap();
+        assert_eq!(table.dice, "1d3");
+        assert_eq!(ranges(&table), vec![(1, 1, "a"), (2, 2, "b"), (3, 3, "c")]);
+
+        let table = RandomTable::parse("weights", "entries:\n  - weight: 2\n    result: a\n  - b\n", Format::Yaml).unwrap();
+        assert_eq!(ranges(&table), vec![(1, 2, "a"), (3, 3, "b")]);
+    }
+
+    #[test]
+    fn large_weights_and_dice() {
+        let table = RandomTable::parse("heavy", "weight,Result\n9000,a\n9000,b\n", Format::Csv).unwrap();
+        assert_eq!(table.dice, "1d18000");
+        assert!(RandomTable::parse("too-heavy", "weight,Result\n1000000,a\n1,b\n", Format::Csv).is_err());
+
+        let table = RandomTable::parse("many", "1000d100,Result\n1000-50000,a\n50001-100000,b\n", Format::Csv).unwrap();
+        assert_eq!(table.row(100_000).unwrap().result, "b");
+        assert!(RandomTable::parse("short", "1000d100,Result\n1000-99999,a\n", Format::Csv).is_err());
+    }
+
+    #[test]
+    fn builtin_tables_parse() {
+        RandomTable::parse("wilderness", include_str!("../tables/wilderness.csv"), Format::Csv).unwrap();
+        RandomTable::parse("trinket", include_str!("../tables/trinket.yaml"), Format::Yaml).unwrap();
+    }
+
+    #[test]
+    fn nested_rolls() {
+        let mut library = TableLibrary::new();
+        library.add(RandomTable::parse("inner", "1,[[2+3]] coins", Format::Csv).unwrap());
+        let outer = RandomTable::parse("outer", "1,You find {inner}", Format::Csv).unwrap();
+        let rolled = roll(&outer, &|name| library.get(name), &mut rand::thread_rng()).unwrap();
+        assert_eq!(rolled.text, "You find 5 coins");
+        assert_eq!(rolled.rolls, vec![("outer".to_string(), 1), ("inner".to_string(), 1)]);
+    }
+
+    #[test]
+    fn rolls_that_blow_up() {
+        // Each table refers to the next six times, which is over a thousand rolls only five deep
+        let mut library = TableLibrary::new();
+        for (name, next) in [("a", "b"), ("b", "c"), ("c", "d"), ("d", "e")] {
+            let row = format!("1,{}", format!("{{{next}}}").repeat(6));
+            library.add(RandomTable::parse(name, &row, Format::Csv).unwrap());
+        }
+        library.add(RandomTable::parse("e", "1,x", Format::Csv).unwrap());
+        let error = roll(library.get("a").unwrap(), &|name| library.get(name), &mut rand::thread_rng()).unwrap_err();
+        assert!(error.to_string().contains("more than 500 tables"), "{error}");
+
+        library.add(RandomTable::parse("long", &format!("1,{}", "x".repeat(3_000)), Format::Csv).unwrap());
+        let twice = RandomTable::parse("twice", "1,{long} {long}", Format::Csv).unwrap();
+        let error = roll(&twice, &|name| library.get(name), &mut rand::thread_rng()).unwrap_err();
+        assert!(error.to_string().contains("longer than"), "{error}");
+    }
+}